@@ -0,0 +1,15 @@
+use std::process::Command;
+
+/// Set `RUST_TEXT_EDITOR_VERSION` to the output of `git describe` if the crate is built from a
+/// git repository with a version tag, or to the package version otherwise.
+fn main() {
+    let git_describe = ["describe", "--tags", "--match=v*", "--dirty"];
+    let version = match Command::new("git").args(git_describe).output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout[1..])
+            .trim()
+            .replacen('-', ".r", 1)
+            .replace('-', "."),
+        _ => String::from(env!("CARGO_PKG_VERSION")),
+    };
+    println!("cargo:rustc-env=RUST_TEXT_EDITOR_VERSION={}", version);
+}
@@ -9,6 +9,11 @@ pub(crate) const RESET_FMT: &str = "\x1b[m";
 /// Invert foreground and background color
 pub(crate) const REVERSE_VIDEO: &str = "\x1b[7m";
 
+/// Underline the following characters
+pub(crate) const UNDERLINE: &str = "\x1b[4m";
+/// Stop underlining, keeping the colors
+pub(crate) const NO_UNDERLINE: &str = "\x1b[24m";
+
 /// Move the cursor to 1:1
 pub const MOVE_CURSOR_TO_START: &str = "\x1b[H";
 
@@ -25,3 +30,20 @@ pub(crate) const DEVICE_STATUS_REPORT: &str = "\x1b[6n";
 
 /// Reposition the cursor to the end of the window
 pub(crate) const REPOSITION_CURSOR_END: &str = "\x1b[999C\x1b[999B";
+
+/// Enable mouse reporting: button presses (1000), using the SGR extended format (1006)
+pub(crate) const ENABLE_MOUSE_REPORTING: &str = "\x1b[?1000h\x1b[?1006h";
+/// Disable mouse reporting
+pub(crate) const DISABLE_MOUSE_REPORTING: &str = "\x1b[?1006l\x1b[?1000l";
+
+/// Save the cursor and switch to the alternate screen buffer (1049)
+pub(crate) const ENTER_ALTERNATE_SCREEN: &str = "\x1b[?1049h";
+/// Switch back to the main screen buffer and restore the cursor
+pub(crate) const LEAVE_ALTERNATE_SCREEN: &str = "\x1b[?1049l";
+
+/// Enable bracketed paste (2004): pasted text is wrapped in `<ESC>[200~` and `<ESC>[201~`
+pub(crate) const ENABLE_BRACKETED_PASTE: &str = "\x1b[?2004h";
+/// Disable bracketed paste
+pub(crate) const DISABLE_BRACKETED_PASTE: &str = "\x1b[?2004l";
+/// The sequence sent after the pasted text when bracketed paste is enabled
+pub(crate) const BRACKETED_PASTE_END: &str = "\x1b[201~";
@@ -2,15 +2,21 @@
 //!
 //! Utilities to configure the text editor.
 
-use std::fmt::{Display, format};
+use std::fmt::Display;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
-use std::path::Path;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
+use crate::snippets::Snippets;
+use crate::sys::conf_dirs as cdirs;
+use crate::theme::Theme;
 use crate::{Error, Error::Config as ConfErr};
 
+/// Values of `quit_times` above this limit are accepted, but reported as suspicious.
+const MAX_SENSIBLE_QUIT_TIMES: usize = 100;
+
 #[derive(Debug, PartialEq)]
 pub struct Config {
     /// The size of a tab. Must be > 0.
@@ -22,12 +28,325 @@ pub struct Config {
     pub message_dur: Duration,
     /// Whether to display line numbers.
     pub show_line_num: bool,
+    /// How line numbers are displayed: absolute, relative to the cursor, or both.
+    pub line_number_style: LineNumberStyle,
+    /// The maximum width of the rows. If not 0, a ruler is drawn in the next column, and the
+    /// characters beyond it are highlighted.
+    pub ruler_column: usize,
+    /// Whether long rows are wrapped on several lines of the screen, preferably at word
+    /// boundaries, instead of scrolling horizontally.
+    pub wrap: bool,
+    /// The minimum number of lines kept above and below the cursor when scrolling vertically, if
+    /// the document has enough lines. Limited to half the height of the screen.
+    pub scrolloff: usize,
+    /// The minimum number of columns kept on the left and on the right of the cursor when
+    /// scrolling horizontally. Limited to half the width of the screen.
+    pub sidescrolloff: usize,
+    /// Whether new lines should start with the indentation of the line they were split from.
+    pub auto_indent: bool,
+    /// Whether pressing Tab inserts spaces up to the next tab stop instead of a tab character, in
+    /// which case Backspace deletes the spaces back to the previous tab stop at once. Set with
+    /// `soft_tabs` or with `indent_style` (`tab` or `space`). The indentation copied by
+    /// `auto_indent` is left as is, so it can also be removed one tab stop at a time.
+    pub soft_tabs: bool,
+    /// Whether Home moves the cursor to the first non-blank character of the row, or to the start
+    /// of the row if it is already there. If disabled, Home always moves to the start of the row.
+    pub smart_home: bool,
+    /// Whether to remember the cursor position for each file, and restore it when the file is
+    /// opened again.
+    pub remember_cursor: bool,
+    /// The number of recently opened files remembered for the quick-open prompt. Disabled if zero.
+    pub recent_files: usize,
+    /// Whether files are opened in read-only mode, where editing and saving are disabled.
+    pub read_only: bool,
+    /// Whether to highlight trailing spaces and tabs with a red background.
+    pub highlight_trailing_whitespace: bool,
+    /// Whether to show spaces as `·` and tabs as `→`. This can be toggled at runtime.
+    pub show_whitespace: bool,
+    /// Whether to tint the background of the row of the cursor, across the whole width of the
+    /// pane. The color is set with `cursorline` in `theme.ini`. This can be toggled at runtime.
+    pub cursorline: bool,
+    /// Whether to draw `<` and `>` on the first and last columns of the rows that continue beyond
+    /// the left or right edge of the screen when scrolling horizontally. Ignored when wrapping.
+    pub scroll_markers: bool,
+    /// Whether to draw a scrollbar in the rightmost column of each pane, showing the position of
+    /// the visible rows in the file.
+    pub scrollbar: bool,
+    /// Whether to draw a vertical line at each tab stop of the indentation of the rows.
+    pub indent_guides: bool,
+    /// Whether to underline the occurrences of the word under the cursor in the rows shown on the
+    /// screen.
+    pub highlight_word: bool,
+    /// Whether to show a welcome message in the middle of the screen when the buffer is empty.
+    pub show_welcome: bool,
+    /// The welcome message, instead of the name and version of the editor if not empty. Lines are
+    /// separated with `\n`.
+    pub welcome_message: String,
+    /// The interval between automatic saves of a modified file. Auto-save is disabled if zero.
+    pub autosave_interval: Duration,
+    /// Whether tabs are converted into spaces, up to the next tab stop, when saving. The rows in
+    /// memory are not modified.
+    pub expand_tabs_on_save: bool,
+    /// Whether trailing spaces and tabs are removed from each row when saving.
+    pub trim_trailing_whitespace_on_save: bool,
+    /// Whether the trailing whitespace removed when saving is also removed from the rows in
+    /// memory, so that the change is visible. Only used if `trim_trailing_whitespace_on_save` is
+    /// enabled.
+    pub trim_trailing_whitespace_in_buffer: bool,
+    /// Whether a line ending is written after the last row when saving, if that row is not empty,
+    /// so that the file ends with exactly one line ending.
+    pub insert_final_newline: bool,
+    /// Files of at least this size, in MiB, are opened in paged mode: only the rows around the
+    /// visible ones are kept in memory, and editing is disabled. If 0, all files are opened in
+    /// paged mode, as with the `--huge` command-line option.
+    pub large_file_size: u64,
+    /// Where the output of the commands run with the execute key is shown: inserted at the cursor
+    /// position, or in the status bar.
+    pub execute_output: ExecuteOutput,
+    /// The format of the date inserted by the `date` command and in the `{date}` placeholders of
+    /// snippets, e.g. `%Y-%m-%d %H:%M`. The date is in UTC.
+    pub date_format: String,
+    /// Whether to enable mouse reporting, to move the cursor with clicks and scroll with the mouse
+    /// wheel. Disabled by default, since some terminals do not support it properly.
+    pub mouse: bool,
+    /// Whether to draw on the alternate screen buffer of the terminal, so that its previous content
+    /// is restored when quitting. Can be disabled for terminals that do not support it.
+    pub alternate_screen: bool,
+    /// The keys bound to the editor actions.
+    pub keys: KeyBindings,
+    /// The true-color theme, loaded from `theme.ini`.
+    pub theme: Theme,
+    /// The snippets, loaded from `snippets.ini`.
+    pub snippets: Snippets,
 }
 
 impl Default for Config {
     /// Default configuration.
     fn default() -> Self {
-        Self { tab_stop: 4, quit_times: 2, message_dur: Duration::new(3, 0), show_line_num: true }
+        Self {
+            tab_stop: 4,
+            quit_times: 2,
+            message_dur: Duration::new(3, 0),
+            show_line_num: true,
+            line_number_style: LineNumberStyle::default(),
+            ruler_column: 0,
+            wrap: false,
+            scrolloff: 0,
+            sidescrolloff: 0,
+            auto_indent: true,
+            soft_tabs: false,
+            smart_home: true,
+            remember_cursor: true,
+            recent_files: 20,
+            read_only: false,
+            highlight_trailing_whitespace: false,
+            show_whitespace: false,
+            cursorline: false,
+            scroll_markers: true,
+            scrollbar: false,
+            indent_guides: false,
+            highlight_word: false,
+            show_welcome: true,
+            welcome_message: String::new(),
+            autosave_interval: Duration::ZERO,
+            expand_tabs_on_save: false,
+            trim_trailing_whitespace_on_save: false,
+            trim_trailing_whitespace_in_buffer: false,
+            insert_final_newline: true,
+            large_file_size: 256,
+            execute_output: ExecuteOutput::default(),
+            date_format: String::from("%Y-%m-%d"),
+            mouse: false,
+            alternate_screen: true,
+            keys: KeyBindings::default(),
+            theme: Theme::default(),
+            snippets: Snippets::default(),
+        }
+    }
+}
+
+/// The style of the line numbers shown on the left of the rows.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum LineNumberStyle {
+    /// The line number of each row.
+    #[default]
+    Absolute,
+    /// The distance between each row and the row of the cursor.
+    Relative,
+    /// The line number of the row of the cursor, and the distance to it for the other rows.
+    Hybrid,
+}
+
+impl FromStr for LineNumberStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "absolute" => Ok(Self::Absolute),
+            "relative" => Ok(Self::Relative),
+            "hybrid" => Ok(Self::Hybrid),
+            _ => Err(format!("{} (expected absolute, relative or hybrid)", s)),
+        }
+    }
+}
+
+/// Where the output of an executed command is shown.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ExecuteOutput {
+    /// The output is inserted at the cursor position, and may span several rows.
+    #[default]
+    Insert,
+    /// The output is shown in the status bar, with its lines separated by `|`.
+    Status,
+}
+
+impl FromStr for ExecuteOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "insert" => Ok(Self::Insert),
+            "status" => Ok(Self::Status),
+            _ => Err(format!("{} (expected insert or status)", s)),
+        }
+    }
+}
+
+/// Return the byte sent by the terminal when `key` is pressed together with Ctrl.
+pub const fn ctrl_key(key: u8) -> u8 { key & 0x1f }
+
+/// Return the name of a control character, for instance `Ctrl-S`.
+pub fn ctrl_key_name(key: u8) -> String {
+    match key {
+        // Terminals send Ctrl-/ as Ctrl-_
+        k if k == ctrl_key(b'_') => String::from("Ctrl-/"),
+        _ => format!("Ctrl-{}", (key | 0x40) as char),
+    }
+}
+
+/// The control characters bound to the editor actions. They can be overridden in `config.ini`,
+/// using the action name prefixed with `key_` as the key and the character pressed together with
+/// Ctrl as the value, for instance `key_quit = x`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct KeyBindings {
+    pub quit: u8,
+    pub save: u8,
+    pub find: u8,
+    pub replace: u8,
+    pub goto: u8,
+    pub duplicate: u8,
+    pub execute: u8,
+    pub remove_line: u8,
+    pub refresh: u8,
+    pub comment: u8,
+    pub match_bracket: u8,
+    pub line_ending: u8,
+    pub whitespace: u8,
+    pub window: u8,
+    pub command: u8,
+    pub revert: u8,
+    pub fold: u8,
+    pub pipe: u8,
+    pub cursorline: u8,
+    pub open: u8,
+    pub find_file: u8,
+    pub goto_symbol: u8,
+    pub snippet: u8,
+    pub join_lines: u8,
+    // Toggles of the Find prompt
+    pub whole_word: u8,
+    pub regex: u8,
+}
+
+impl Default for KeyBindings {
+    /// Default key bindings.
+    fn default() -> Self {
+        Self {
+            quit: ctrl_key(b'Q'),
+            save: ctrl_key(b'S'),
+            find: ctrl_key(b'F'),
+            replace: ctrl_key(b'T'),
+            goto: ctrl_key(b'G'),
+            duplicate: ctrl_key(b'D'),
+            execute: ctrl_key(b'E'),
+            remove_line: ctrl_key(b'R'),
+            refresh: ctrl_key(b'L'),
+            comment: ctrl_key(b'_'),
+            match_bracket: ctrl_key(b']'),
+            line_ending: ctrl_key(b'N'),
+            whitespace: ctrl_key(b'B'),
+            window: ctrl_key(b'W'),
+            command: ctrl_key(b'P'),
+            revert: ctrl_key(b'U'),
+            fold: ctrl_key(b'A'),
+            pipe: ctrl_key(b'\\'),
+            cursorline: ctrl_key(b'^'),
+            open: ctrl_key(b'O'),
+            find_file: ctrl_key(b'K'),
+            goto_symbol: ctrl_key(b'Y'),
+            snippet: ctrl_key(b'X'),
+            join_lines: ctrl_key(b'J'),
+            whole_word: ctrl_key(b'C'),
+            regex: ctrl_key(b'Z'),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Return the actions names and the corresponding keys.
+    fn bindings_mut(&mut self) -> [(&'static str, &mut u8); 26] {
+        [
+            ("quit", &mut self.quit),
+            ("save", &mut self.save),
+            ("find", &mut self.find),
+            ("replace", &mut self.replace),
+            ("goto", &mut self.goto),
+            ("duplicate", &mut self.duplicate),
+            ("execute", &mut self.execute),
+            ("remove_line", &mut self.remove_line),
+            ("refresh", &mut self.refresh),
+            ("comment", &mut self.comment),
+            ("match_bracket", &mut self.match_bracket),
+            ("line_ending", &mut self.line_ending),
+            ("whitespace", &mut self.whitespace),
+            ("window", &mut self.window),
+            ("command", &mut self.command),
+            ("revert", &mut self.revert),
+            ("fold", &mut self.fold),
+            ("pipe", &mut self.pipe),
+            ("cursorline", &mut self.cursorline),
+            ("open", &mut self.open),
+            ("find_file", &mut self.find_file),
+            ("goto_symbol", &mut self.goto_symbol),
+            ("snippet", &mut self.snippet),
+            ("join_lines", &mut self.join_lines),
+            ("whole_word", &mut self.whole_word),
+            ("regex", &mut self.regex),
+        ]
+    }
+
+    /// Bind `action` to the key given as an INI value, for instance `s` for Ctrl-S. Return an error
+    /// if the action does not exist, if the key is invalid or reserved, or if it is already bound
+    /// to another action.
+    fn set(&mut self, action: &str, value: &str) -> Result<(), String> {
+        let key = match value.trim().as_bytes() {
+            [b'/'] => ctrl_key(b'_'),
+            [c @ (b'a'..=b'z' | b'A'..=b'Z' | b'@' | b'\\' | b']' | b'^' | b'_')] => ctrl_key(*c),
+            _ => return Err(format!("Invalid key binding: {}", value.trim())),
+        };
+        // Backspace (Ctrl-H), Tab (Ctrl-I) and Enter (Ctrl-M)
+        if [b'H', b'I', b'M'].map(ctrl_key).contains(&key) {
+            return Err(format!("{} is reserved", ctrl_key_name(key)));
+        }
+        let bindings = self.bindings_mut();
+        let Some(i) = bindings.iter().position(|(a, _)| *a == action) else {
+            return Err(format!("Invalid action: {}", action));
+        };
+        if let Some((other, _)) = bindings.iter().find(|(a, k)| **k == key && *a != action) {
+            return Err(format!("{} is already bound to {}", ctrl_key_name(key), other));
+        }
+        *bindings[i].1 = key;
+        Ok(())
     }
 }
 
@@ -37,7 +356,7 @@ impl Config {
     ///   - On Linux, macOS and other *nix systems:
     ///     - `/etc/rust-text-editor` (system-wide configuration).
     ///     - `$XDG_CONFIG_HOME/rust-text-editor` if environment variable `$XDG_CONFIG_HOME` is defined,
-    ///         `$HOME/.config/rust-text-editor` otherwise (user-level configuration).
+    ///       `$HOME/.config/rust-text-editor` otherwise (user-level configuration).
     ///   - On Windows:
     ///     - `%APPDATA%\rust-text-editor`
     ///
@@ -45,36 +364,107 @@ impl Config {
     ///
     /// Will return `Err` if one of the configuration file cannot be parsed properly.
     pub fn load() -> Result<Self, Error> {
-        let mut conf = Self::default();
-
         let paths: Vec<_> = cdirs()
             .iter()
-            .map(|d| Path::from(d).join("config.ini"))
+            .map(|d| Path::new(d).join("config.ini"))
             .collect();
 
-        for path in paths
-            .iter()
-            .filter(|p| p.is_file())
-            .rev()
-        {
-            process_ini_file(path, &mut |key, value| {
-                match key {
-                    "tab_stop" => match parse_value(value)? {
-                        0 => return Err("tab_stop must be > 0".into()),
-                        tab_stop => conf.tab_stop = tab_stop
-                    },
-                    "quit_times" => conf.quit_times = parse_value(value)?,
-                    "message_duration" =>
-                        conf.message_dur = Duration::from_secs_f32(parse_value(value)?),
-                    "show_line_numbers" => conf.show_line_num = parse_value(value)?,
-                    _ => return Err(format!("Invalid key: {}", key))
-                };
-                Ok(())
-            })?;
+        // Missing configuration files are ignored
+        let paths: Vec<_> = paths.into_iter().filter(|p| p.is_file()).collect();
+        Self::load_from(&paths)
+    }
+
+    /// Load the configuration from the given INI files. Files that come first take precedence
+    /// over the following ones. Contrary to `load`, every file must exist.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if one of the configuration files cannot be read or parsed properly.
+    /// Invalid lines do not stop the parsing: if there are several of them, they are all returned
+    /// in an `Error::ConfigErrors`, so that they can be fixed at once.
+    pub fn load_from(paths: &[PathBuf]) -> Result<Self, Error> {
+        let (mut conf, mut errors) = (Self::default(), Vec::new());
+        for path in paths.iter().rev() {
+            collect_ini_errors(path, &mut |key, value| conf.set(key, value), &mut errors)?;
+        }
+        match errors.len() {
+            0 => (),
+            1 => return Err(errors.remove(0)),
+            _ => return Err(Error::ConfigErrors(errors)),
         }
+        conf.theme = Theme::load()?;
+        conf.snippets = Snippets::load()?;
 
         Ok(conf)
     }
+
+    /// Return warnings about values that are valid, but probably not what the user intended.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.quit_times > MAX_SENSIBLE_QUIT_TIMES {
+            warnings.push(format!("quit_times = {} is unusually large", self.quit_times));
+        }
+        warnings
+    }
+
+    /// Set a configuration option, given as an INI key and value. This is used both when loading
+    /// `config.ini` files and by the `set` command of the editor.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "tab_stop" => match parse_value(value)? {
+                0 => return Err("tab_stop must be > 0".into()),
+                tab_stop => self.tab_stop = tab_stop
+            },
+            "quit_times" => self.quit_times = parse_value(value)?,
+            "message_duration" => match Duration::try_from_secs_f32(parse_value(value)?) {
+                Ok(duration) => self.message_dur = duration,
+                Err(_) => return Err("message_duration must be a number of seconds >= 0".into()),
+            },
+            "show_line_numbers" => self.show_line_num = parse_value(value)?,
+            "line_number_style" => self.line_number_style = parse_value(value)?,
+            "ruler_column" => self.ruler_column = parse_value(value)?,
+            "wrap" => self.wrap = parse_value(value)?,
+            "scrolloff" => self.scrolloff = parse_value(value)?,
+            "sidescrolloff" => self.sidescrolloff = parse_value(value)?,
+            "auto_indent" => self.auto_indent = parse_value(value)?,
+            "soft_tabs" => self.soft_tabs = parse_value(value)?,
+            "smart_home" => self.smart_home = parse_value(value)?,
+            "indent_style" => self.soft_tabs = match value.trim() {
+                "tab" => false,
+                "space" => true,
+                v => return Err(format!("Parser error: {} (expected tab or space)", v)),
+            },
+            "remember_cursor" => self.remember_cursor = parse_value(value)?,
+            "recent_files" => self.recent_files = parse_value(value)?,
+            "read_only" => self.read_only = parse_value(value)?,
+            "highlight_trailing_whitespace" =>
+                self.highlight_trailing_whitespace = parse_value(value)?,
+            "show_whitespace" => self.show_whitespace = parse_value(value)?,
+            "cursorline" => self.cursorline = parse_value(value)?,
+            "scroll_markers" => self.scroll_markers = parse_value(value)?,
+            "scrollbar" => self.scrollbar = parse_value(value)?,
+            "indent_guides" => self.indent_guides = parse_value(value)?,
+            "highlight_word" => self.highlight_word = parse_value(value)?,
+            "show_welcome" => self.show_welcome = parse_value(value)?,
+            "welcome_message" => self.welcome_message = value.trim().replace("\\n", "\n"),
+            "autosave_interval" =>
+                self.autosave_interval = Duration::from_secs(parse_value(value)?),
+            "expand_tabs_on_save" => self.expand_tabs_on_save = parse_value(value)?,
+            "trim_trailing_whitespace_on_save" =>
+                self.trim_trailing_whitespace_on_save = parse_value(value)?,
+            "trim_trailing_whitespace_in_buffer" =>
+                self.trim_trailing_whitespace_in_buffer = parse_value(value)?,
+            "insert_final_newline" => self.insert_final_newline = parse_value(value)?,
+            "large_file_size" => self.large_file_size = parse_value(value)?,
+            "execute_output" => self.execute_output = parse_value(value)?,
+            "date_format" => self.date_format = String::from(value.trim()),
+            "mouse" => self.mouse = parse_value(value)?,
+            "alternate_screen" => self.alternate_screen = parse_value(value)?,
+            k if k.starts_with("key_") => self.keys.set(&k[4..], value)?,
+            _ => return Err(format!("Invalid key: {}", key))
+        };
+        Ok(())
+    }
 }
 
 /// Process an INI file.
@@ -83,15 +473,57 @@ impl Config {
 /// function will update a configuration instance.
 pub fn process_ini_file<F>(path: &Path, kv_fn: &mut F) -> Result<(), Error>
     where F: FnMut(&str, &str) -> Result<(), String> {
-    let file = File::open(path).map(|e| ConfErr(path.into(), 0, e.to_string()))?;
+    process_ini_sections(path, &mut |_| Err(String::from("No '='")), kv_fn)
+}
+
+/// Process an INI file containing sections, i.e. lines such as `[name]`.
+///
+/// The `section_fn` function will be called with the name of each section, and the `kv_fn`
+/// function for each key-value pair in the file.
+pub fn process_ini_sections<S, F>(
+    path: &Path, section_fn: &mut S, kv_fn: &mut F,
+) -> Result<(), Error>
+    where S: FnMut(&str) -> Result<(), String>, F: FnMut(&str, &str) -> Result<(), String> {
+    process_ini_lines(path, section_fn, kv_fn, &mut Err)
+}
+
+/// Process an INI file like `process_ini_file`, but without stopping at the first invalid line:
+/// the errors of all the lines are appended to `errors`. Only the errors reading the file are
+/// returned.
+pub fn collect_ini_errors<F>(
+    path: &Path, kv_fn: &mut F, errors: &mut Vec<Error>,
+) -> Result<(), Error>
+    where F: FnMut(&str, &str) -> Result<(), String> {
+    let on_error = &mut |err| {
+        errors.push(err);
+        Ok(())
+    };
+    process_ini_lines(path, &mut |_| Err(String::from("No '='")), kv_fn, on_error)
+}
+
+/// Process the lines of an INI file. The error of each invalid line is passed to `on_error`, which
+/// decides whether the processing should go on.
+fn process_ini_lines<S, F, E>(
+    path: &Path, section_fn: &mut S, kv_fn: &mut F, on_error: &mut E,
+) -> Result<(), Error>
+    where S: FnMut(&str) -> Result<(), String>,
+          F: FnMut(&str, &str) -> Result<(), String>,
+          E: FnMut(Error) -> Result<(), Error> {
+    let file = File::open(path).map_err(|e| ConfErr(path.into(), 0, e.to_string()))?;
     for (i, line) in BufReader::new(file).lines().enumerate() {
         let (i, line) = (i + 1, line?);
         let mut parts = line.trim_start().splitn(2, '=');
-        match (parts.next(), parts.next()) {
-            (Some(comment_line), _) if comment_line.starts_with(&['#', ';'][..]) => (),
-            (Some(k), Some(v)) => kv_fn(k.trim_end(), v).map_err(|r| ConfErr(path.into(), i, r))?,
-            (Some(""), None) | (None, _) => (), // Empty line.
-            (Some(_), None) => return Err(ConfErr(path.into(), i, String::from("No '='")))
+        let result = match (parts.next(), parts.next()) {
+            (Some(comment_line), _) if comment_line.starts_with(&['#', ';'][..]) => Ok(()),
+            (Some(k), Some(v)) => kv_fn(k.trim_end(), v),
+            (Some(""), None) | (None, _) => Ok(()), // Empty line.
+            (Some(l), None) if l.starts_with('[') && l.trim_end().ends_with(']') => {
+                section_fn(&l.trim_end()[1..l.trim_end().len() - 1])
+            }
+            (Some(_), None) => Err(String::from("No '='")),
+        };
+        if let Err(r) = result {
+            on_error(ConfErr(path.into(), i, r))?;
         }
     }
     Ok(())
@@ -109,12 +541,12 @@ pub fn parse_values<T: FromStr<Err=E>, E: Display>(value: &str) -> Result<Vec<T>
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use std::ffi::{OsStr, OsString};
     use std::{env, fs};
 
     use serial_test::serial;
-    use templife::TempDir;
+    use tempfile::TempDir;
 
     use super::*;
 
@@ -202,13 +634,70 @@ mod tests {
         }
     }
 
-    struct TempEnvVar {
+    #[test]
+    fn key_bindings() {
+        let mut keys = KeyBindings::default();
+        keys.set("quit", " v").unwrap();
+        keys.set("comment", "/").unwrap();
+        assert_eq!((keys.quit, keys.comment), (ctrl_key(b'V'), ctrl_key(b'_')));
+
+        let err = |s: &str| Err(String::from(s));
+        assert_eq!(keys.set("save", "f"), err("Ctrl-F is already bound to find"));
+        assert_eq!(keys.set("save", "m"), err("Ctrl-M is reserved"));
+        assert_eq!(keys.set("whole_word", "w"), err("Ctrl-W is already bound to window"));
+        assert_eq!(keys.set("regex", "r"), err("Ctrl-R is already bound to remove_line"));
+        assert_eq!(keys.set("save", "ab"), err("Invalid key binding: ab"));
+        assert_eq!(keys.set("unknown", "c"), err("Invalid action: unknown"));
+
+        let kv_fn = &mut |key: &str, value: &str| keys.set(&key[4..], value);
+        match ini_processing_helper("key_save = q\nkey_find = q\n", kv_fn) {
+            Err(Error::Config(_, 2, s)) if s == "Ctrl-Q is already bound to save" => (),
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn load_from_paths() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let (user, system) = (tmp_dir.path().join("user.ini"), tmp_dir.path().join("system.ini"));
+        fs::write(&user, "tab_stop = 2\n").unwrap();
+        fs::write(&system, "tab_stop = 8\nquit_times = 5\nalternate_screen = false\n").unwrap();
+        let config = Config::load_from(&[user.clone(), system.clone()]).unwrap();
+        assert_eq!((config.tab_stop, config.quit_times), (2, 5));
+        assert!(Config::default().alternate_screen && !config.alternate_screen);
+
+        // All the invalid lines are reported
+        let ini_content = "tab_stop = 0\nquit_times = 500\nmessage_duration = -1\nwrap\n";
+        fs::write(&system, ini_content).unwrap();
+        let lines: Vec<_> = match Config::load_from(&[user.clone(), system.clone()]) {
+            Err(Error::ConfigErrors(errors)) => errors.iter().map(|err| match err {
+                Error::Config(path, line, _) if *path == system => *line,
+                err => panic!("Unexpected error {:?}", err),
+            }).collect(),
+            r => panic!("Unexpected result {:?}", r),
+        };
+        assert_eq!(lines, [1, 3, 4]);
+        let mut config = Config::default();
+        assert!(config.set("message_duration", "NaN").is_err());
+        assert!(config.warnings().is_empty());
+        config.set("quit_times", "500").unwrap();
+        assert_eq!(config.warnings(), ["quit_times = 500 is unusually large"]);
+
+        let missing = tmp_dir.path().join("missing.ini");
+        match Config::load_from(&[user, missing.clone()]) {
+            Err(Error::Config(path, 0, _)) if path == missing => (),
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    /// An environment variable set for the duration of a test, and restored when dropped.
+    pub(crate) struct TempEnvVar {
         key: OsString,
         orig_value: Option<OsString>,
     }
 
     impl TempEnvVar {
-        fn new(key: &OsStr, value: Option<&OsStr>) -> TempEnvVar {
+        pub(crate) fn new(key: &OsStr, value: Option<&OsStr>) -> TempEnvVar {
             let orig_value = env::var_os(key);
             match value {
                 Some(value) => env::set_var(key, value),
@@ -236,7 +725,7 @@ mod tests {
             custom_config.tab_stop, custom_config.quit_times
         );
 
-        fs::create_dir_all(&config_home).unwrap();
+        fs::create_dir_all(config_home).unwrap();
 
         fs::write(config_home.join("config.ini"), ini_content)
             .expect("Could not write INI file");
@@ -1,49 +1,208 @@
 #![allow(clippy::wildcard_imports)]
 
+use std::ascii;
+use std::borrow::Cow;
+use std::env;
 use std::io::{
-    self, BufRead, BufReader, ErrorKind::InvalidInput, ErrorKind::NotFound, Read, Seek, Write,
+    self, BufRead, BufReader, ErrorKind::NotFound, Read, Seek, Write,
 };
-use std::iter::{self, repeat, successors};
-use std::{fmt::Display, fs::File, path::Path, process::Command, thread, time::Instant};
+use std::cmp::Reverse;
+use std::iter::{self, repeat_n, successors};
+use std::mem;
+use std::{fmt::Display, fs::File, num::ParseIntError, ops::Range, path::Path, path::PathBuf};
+use std::process::Stdio;
+use std::sync::{Mutex, Once};
+use std::{panic, thread, time::Duration, time::Instant, time::SystemTime};
 
-use crate::row::{HlState, Row};
-use crate::{ansi_escape::*, syntax::Conf as SyntaxConf, sys, terminal, Config, Error};
+#[cfg(feature = "regex")]
+use regex::bytes::{Regex, RegexBuilder};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-const fn ctrl_key(key: u8) -> u8 { key & 0x1f }
+use crate::row::{trailing_whitespace_len, Row};
+use crate::config::{ctrl_key, ctrl_key_name, ExecuteOutput, KeyBindings, LineNumberStyle};
+use crate::editorconfig::Properties;
+use crate::paged::PagedFile;
+use crate::text_buffer::{CursorState, LineEnding, TextBuffer};
+use crate::{
+    ansi_escape::*, snippets, state, syntax::Conf as SyntaxConf, sys, terminal, Config, Error,
+};
 
-const EXIT: u8 = ctrl_key(b'Q');
 const DELETE_BIS: u8 = ctrl_key(b'H');
-const REFRESH_SCREEN: u8 = ctrl_key(b'L');
-const SAVE: u8 = ctrl_key(b'S');
-const FIND: u8 = ctrl_key(b'F');
-const GOTO: u8 = ctrl_key(b'G');
-const DUPLICATE: u8 = ctrl_key(b'D');
-const EXECUTE: u8 = ctrl_key(b'E');
-const REMOVE_LINE: u8 = ctrl_key(b'R');
+const TOGGLE_CASE: u8 = ctrl_key(b'I');
 const BACKSPACE: u8 = 127;
 
-const HELP_MESSAGE: &str =
-    "Ctrl-S = save | Ctrl-Q = quit | Ctrl-F = find | Ctrl-G = go to | Ctrl-D = duplicate | Ctrl-E = execute";
+/// The number of rows scrolled by each step of the mouse wheel.
+const MOUSE_SCROLL_ROWS: isize = 3;
+
+/// In paged mode, the number of rows kept in memory above and below the visible ones.
+const PAGE_MARGIN: usize = 1000;
+
+/// The minimum interval between two writes of the swap file.
+const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+
+/// The maximum number of matches counted by the Find prompt, and the maximum duration of the
+/// count, so that typing stays responsive in large documents.
+const MAX_COUNTED_MATCHES: usize = 10_000;
+const MAX_COUNT_DURATION: Duration = Duration::from_millis(50);
+
+/// The maximum duration of a command the text is piped through. The command is killed afterwards.
+const PIPE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The minimum duration between two updates of the progress of a long operation, which is shown
+/// in the message bar.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+/// The frames of the spinner shown while waiting for an operation whose progress is unknown.
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// The maximum depth of the directories, and the maximum number of files, scanned by the file
+/// finder, so that it stays responsive in large directory trees.
+const FIND_FILE_MAX_DEPTH: usize = 8;
+const MAX_SCANNED_FILES: usize = 10_000;
+/// The maximum number of candidates listed above the prompt of the file finder.
+const MAX_SHOWN_CANDIDATES: usize = 10;
+
+/// The window size (rows, columns) used if it cannot be obtained at startup.
+const DEFAULT_WINDOW_SIZE: (usize, usize) = (24, 80);
+
+/// The maximum number of queries kept in the history of a prompt. When this number is reached,
+/// the oldest queries are dropped.
+const MAX_HISTORY: usize = 100;
+
+/// The number of editors that have enabled raw mode, and the terminal mode restored by the panic
+/// hook, i.e. the original mode saved by the first of them, along with whether it switched to the
+/// alternate screen. Editors created while another one is running see the raw mode as their
+/// original mode, which must not be restored.
+static PANIC_TERM_MODE: Mutex<(usize, Option<(sys::TermMode, bool)>)> = Mutex::new((0, None));
+
+/// Return the window size (rows, columns) given by the `LINES` and `COLUMNS` environment
+/// variables, if both are set to positive numbers.
+fn env_window_size() -> Option<(usize, usize)> {
+    let var = |name| env::var(name).ok()?.trim().parse().ok().filter(|n| *n > 0);
+    Some((var("LINES")?, var("COLUMNS")?))
+}
+
+/// Return the description of each key binding, for instance `Ctrl-S = save`.
+fn key_bindings_help(keys: &KeyBindings) -> Vec<String> {
+    [
+        (keys.save, "save"),
+        (keys.quit, "quit"),
+        (keys.find, "find"),
+        (keys.replace, "replace"),
+        (keys.goto, "go to"),
+        (keys.duplicate, "duplicate (with Alt: above)"),
+        (keys.execute, "execute"),
+        (keys.comment, "comment"),
+        (keys.match_bracket, "matching bracket"),
+        (keys.line_ending, "LF/CRLF"),
+        (keys.whitespace, "whitespace"),
+        (keys.window, "split"),
+        (keys.command, "command"),
+        (keys.revert, "revert"),
+        (keys.fold, "fold"),
+        (keys.pipe, "pipe"),
+        (keys.cursorline, "cursorline"),
+        (keys.open, "open recent"),
+        (keys.find_file, "find file"),
+        (keys.goto_symbol, "go to symbol"),
+        (keys.snippet, "expand snippet"),
+        (keys.join_lines, "join lines"),
+    ]
+    .map(|(key, action)| format!("{} = {}", ctrl_key_name(key), action))
+    .to_vec()
+}
+
+/// Return the options that the state of the editor depends on, with their values. They are
+/// compared when the configuration is reloaded, to apply and report the changes.
+fn applied_options(config: &Config) -> [(&'static str, String); 8] {
+    [
+        ("tab_stop", config.tab_stop.to_string()),
+        ("show_line_numbers", config.show_line_num.to_string()),
+        ("line_number_style", format!("{:?}", config.line_number_style)),
+        ("quit_times", config.quit_times.to_string()),
+        ("message_duration", format!("{:?}", config.message_dur)),
+        ("read_only", config.read_only.to_string()),
+        ("mouse", config.mouse.to_string()),
+        ("scrollbar", config.scrollbar.to_string()),
+    ]
+}
+
+/// Return the keys toggling the options of the Find prompt, as shown in the prompt.
+fn find_toggles(keys: &KeyBindings) -> String {
+    let toggles = format!("Ctrl-I = case, {} = word", ctrl_key_name(keys.whole_word));
+    #[cfg(feature = "regex")]
+    let toggles = format!("{}, {} = regex", toggles, ctrl_key_name(keys.regex));
+    toggles
+}
 
-/// `set_status!` sets a formatted status message for the editor.
-/// Example usage: `set_status!(editor, "{} written to {}", file_size, file_name)`
+/// `set_status!` sets a formatted status message for the editor. The severity of the message may
+/// precede the format string; it is `Info` by default.
+/// Example usage: `set_status!(editor, "{} written to {}", file_size, file_name)`,
+/// `set_status!(editor, Error: "Can't save! {}", err)`
 macro_rules! set_status {
-    ($editor:expr, $($arg:expr),*) => ($editor.status_msg = Some(StatusMessage::new(format!($($arg),*))))
+    ($editor:expr, $severity:ident: $($arg:expr),*) => ($editor.status_msg =
+        Some(StatusMessage::new(format!($($arg),*), Severity::$severity)));
+    ($editor:expr, $($arg:expr),*) => ($editor.status_msg =
+        Some(StatusMessage::new(format!($($arg),*), Severity::Info)))
 }
 
 /// Enum of input keys
+#[derive(Debug)]
 enum Key {
     Arrow(AKey),
     CtrlArrow(AKey),
+    ShiftArrow(AKey),
+    AltArrow(AKey),
+    CtrlAltArrow(AKey),
+    /// Alt + a control key, e.g. Ctrl-Alt-D, sent as <ESC> followed by the control key
+    AltChar(u8),
     Page(PageKey),
     Home,
     End,
     Delete,
+    CtrlDelete,
     Escape,
+    /// Shift + Tab
+    BackTab,
+    CtrlTab,
+    CtrlShiftTab,
+    Mouse(MouseEvent),
+    /// Text pasted in the terminal, when bracketed paste is enabled
+    Paste(Vec<u8>),
     Char(u8),
 }
 
+impl Key {
+    /// Return whether the key modifies the text or saves it, as opposed to keys that only navigate,
+    /// search, or quit. These keys are ignored in read-only mode.
+    fn is_edit(&self, keys: &KeyBindings) -> bool {
+        match self {
+            Self::Delete | Self::CtrlDelete | Self::BackTab | Self::AltArrow(_) | Self::Paste(_) =>
+                true,
+            Self::AltChar(c) => *c == keys.duplicate || *c == BACKSPACE,
+            Self::Char(c) => ![
+                keys.quit,
+                keys.refresh,
+                keys.find,
+                keys.goto,
+                keys.match_bracket,
+                keys.whitespace,
+                keys.cursorline,
+                keys.window,
+                keys.command,
+                keys.revert,
+                keys.fold,
+                keys.open,
+                keys.find_file,
+                keys.goto_symbol,
+            ]
+            .contains(c),
+            _ => false,
+        }
+    }
+}
+
 /// Enum of arrow keys
+#[derive(Debug)]
 enum AKey {
     Left,
     Right,
@@ -51,35 +210,451 @@ enum AKey {
     Down,
 }
 
+/// Enum of mouse events
+#[derive(Debug)]
+enum MouseEvent {
+    /// Left click, at the given (column, row) of the terminal, 0-indexed
+    Click(usize, usize),
+    WheelUp,
+    WheelDown,
+}
+
 /// Enum of page keys
+#[derive(Debug)]
 enum PageKey {
     Up,
     Down,
 }
 
-#[derive(Default, Clone)]
-struct CursorState {
-    /// x position (indexing the characters, not the columns)
-    x: usize,
-    /// y position (row number, 0-indexed)
-    y: usize,
-    /// Row offset
-    roff: usize,
-    /// Column offset
-    coff: usize,
+/// Options for the Find prompt, toggled while searching.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+struct FindOptions {
+    /// Whether ASCII letters should be compared case-insensitively.
+    ignore_case: bool,
+    /// Whether matches must be surrounded by non-alphanumeric characters.
+    whole_word: bool,
+    /// Whether the query is a regular expression. Can only be enabled with the `regex` feature.
+    regex: bool,
 }
 
-impl CursorState {
-    fn move_to_next_line(&mut self) {
-        self.y += 1;
-        self.x = 0;
+impl FindOptions {
+    /// Return a short description of the active options, to be shown in the prompt.
+    fn describe(self) -> String {
+        let flags = [
+            (self.ignore_case, "ignore case"),
+            (self.whole_word, "whole word"),
+            (self.regex, "regex"),
+        ];
+        let active: Vec<_> = flags.iter().filter(|(on, _)| *on).map(|(_, name)| *name).collect();
+        if active.is_empty() { String::new() } else { format!(" [{}]", active.join(", ")) }
     }
+}
 
-    /// Scroll the terminal window vertically and horizontally (i.e. adjusting the row offset and
-    /// the column offset) so that the cursor can be shown.
-    fn scroll(&mut self, rx: usize, screen_rows: usize, screen_cols: usize) {
-        self.roff = self.roff.clamp(self.y.saturating_sub(screen_rows.saturating_sub(1)), self.y);
-        self.coff = self.coff.clamp(rx.saturating_sub(screen_cols.saturating_sub(1)), rx);
+/// The queries entered in a prompt, which can be recalled with Up and Down.
+#[derive(Default)]
+struct History {
+    /// The queries, from the oldest to the most recent, without consecutive duplicates.
+    entries: Vec<String>,
+    /// The index of the query shown in the prompt, or `None` if the prompt shows the text typed by
+    /// the user.
+    position: Option<usize>,
+    /// The text typed by the user before recalling a query, shown again after the most recent
+    /// query.
+    draft: String,
+}
+
+impl History {
+    /// Append a query that has been submitted, unless it is empty or identical to the most recent
+    /// query.
+    fn push(&mut self, query: &str) {
+        if !query.is_empty() && self.entries.last().map(String::as_str) != Some(query) {
+            self.entries.push(String::from(query));
+            let n_dropped = self.entries.len().saturating_sub(MAX_HISTORY);
+            self.entries.drain(..n_dropped);
+        }
+    }
+
+    /// Process a keypress event in a prompt with this history, like `process_prompt_keypress`.
+    fn process_keypress(&mut self, buffer: String, key: &Key) -> PromptState {
+        process_prompt_keypress(self.recall(buffer, key), key)
+    }
+
+    /// If `key` is Up or Down, return the previous or next query to edit in the prompt instead of
+    /// `buffer`. Other keys return `buffer` unchanged.
+    fn recall(&mut self, buffer: String, key: &Key) -> String {
+        let n = self.entries.len();
+        let current = self.position.unwrap_or(n);
+        let new = match key {
+            Key::Arrow(AKey::Up) => current.saturating_sub(1),
+            Key::Arrow(AKey::Down) => (current + 1).min(n),
+            _ => return buffer,
+        };
+        if new == current {
+            return buffer;
+        } else if current == n {
+            self.draft = buffer;
+        }
+        self.position = (new < n).then_some(new);
+        self.entries.get(new).cloned().unwrap_or_else(|| mem::take(&mut self.draft))
+    }
+}
+
+/// The matches of the Find query in the whole document, counted when the query or its options
+/// change.
+struct MatchCount {
+    /// The query and the options for which the matches were counted.
+    search: (String, FindOptions),
+    /// The positions (y, x) of the matches, in the order of the document.
+    positions: Vec<(usize, usize)>,
+    /// Whether the count was stopped early, in which case only the first matches are known.
+    truncated: bool,
+}
+
+impl MatchCount {
+    /// Describe the position of the current match among all the matches, e.g. "Match 3/12".
+    fn describe(&self, current: Option<(usize, usize)>) -> String {
+        let more = if self.truncated { "+" } else { "" };
+        let index = current.and_then(|pos| self.positions.binary_search(&pos).ok());
+        match (index, self.positions.len()) {
+            (_, 0) if !self.truncated => String::from("No match"),
+            (Some(i), n) => format!("Match {}/{}{}", i + 1, n, more),
+            (None, n) => format!("Match ?/{}{}", n, more),
+        }
+    }
+}
+
+/// A vertical split of the screen, showing two buffers side by side.
+#[derive(Clone, Copy)]
+struct Split {
+    /// The index of the buffer shown in the pane that does not have the focus.
+    other: usize,
+    /// Whether the focused pane is the left one.
+    focus_left: bool,
+}
+
+/// The result of a successful save.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SaveOutcome {
+    /// The number of bytes written, including line endings.
+    pub bytes_written: usize,
+    /// The path of the file that was written.
+    pub path: PathBuf,
+}
+
+/// The state of a file opened in the editor, when it is not the active buffer. See `Editor` for the
+/// description of each field.
+#[derive(Default)]
+struct Buffer {
+    buf: TextBuffer,
+    selection_anchor: Option<(usize, usize)>,
+    bracket_pair: Option<[(usize, usize); 2]>,
+    last_save: Option<Instant>,
+    swap_written: Option<Instant>,
+    mtime: Option<SystemTime>,
+    /// The `.editorconfig` properties of the buffer, applied to the configuration when it becomes
+    /// the active buffer.
+    project_config: Properties,
+    paged: Option<PagedFile>,
+    first_row: usize,
+}
+
+impl Buffer {
+    /// Return the view of the buffer in a pane of the given width and first column, which does not
+    /// have the focus.
+    fn pane<'a>(&'a self, config: &'a Config, (width, offset): (usize, usize), screen_rows: usize)
+        -> Pane<'a> {
+        let n_lines = self.paged.as_ref().map_or(self.buf.rows.len(), PagedFile::n_lines);
+        let (ln_pad, screen_cols) = pane_columns(config, width, screen_rows, n_lines);
+        Pane {
+            buf: &self.buf,
+            selection_anchor: self.selection_anchor,
+            first_row: self.first_row,
+            n_lines,
+            config,
+            width,
+            offset,
+            screen_rows,
+            screen_cols,
+            ln_pad,
+        }
+    }
+}
+
+/// A buffer shown in a pane of the screen, with what is needed to scroll and draw it. The focused
+/// pane shows the active buffer; the other pane of a split screen shows a stored `Buffer`, which
+/// is drawn in place. See `Editor` for the description of the fields.
+struct Pane<'a> {
+    buf: &'a TextBuffer,
+    selection_anchor: Option<(usize, usize)>,
+    first_row: usize,
+    /// The number of lines of the file. This is the number of rows, unless the file is paged.
+    n_lines: usize,
+    config: &'a Config,
+    /// The width and the first column (0-indexed) of the pane.
+    width: usize,
+    offset: usize,
+    screen_rows: usize,
+    screen_cols: usize,
+    ln_pad: usize,
+}
+
+impl<'a> Pane<'a> {
+    /// Return the column and row offsets scrolling the pane so that the cursor can be shown, with
+    /// at least `scrolloff` lines above and below it and `sidescrolloff` columns on each side if
+    /// possible. The cursor must not be in a folded block.
+    fn scroll_offsets(&self) -> (usize, usize) {
+        let (y, rx, width) = (self.buf.cursor.y, self.buf.rx(), self.screen_cols);
+        // The column offset is not used when rows are wrapped
+        let coff = if self.config.wrap {
+            0
+        } else {
+            let side_margin = self.config.sidescrolloff.min(width.saturating_sub(1) / 2);
+            let min_coff = (rx + side_margin).saturating_sub(width.saturating_sub(1));
+            self.buf.cursor.coff.clamp(min_coff, rx.saturating_sub(side_margin))
+        };
+        let margin = self.config.scrolloff.min(self.screen_rows.saturating_sub(1) / 2);
+        let row_lines = |y: usize| match self.fold_at(y) {
+            // A folded block is shown as a single line
+            Some(fold) => (fold.clone(), 1),
+            None if self.config.wrap => (y..y + 1, self.buf.rows[y].wrap_columns(width).len()),
+            None => (y..y + 1, 1),
+        };
+        let mut n_lines = match self.buf.current_row() {
+            Some(row) if self.config.wrap =>
+                row.wrap_columns(width).iter().filter(|start| **start <= rx).count(),
+            _ => 1,
+        };
+        // Keep `margin` lines below the cursor, unless the end of the document is reached
+        let (mut below, mut n_below) = (y + 1, 0);
+        while n_below < margin && below < self.buf.rows.len() {
+            let (rows, n) = row_lines(below);
+            (below, n_below) = (rows.end, n_below + n.min(margin - n_below));
+        }
+        n_lines += n_below;
+        // Keep as many rows as possible above the cursor, without scrolling up unless there are
+        // less than `margin` lines above it
+        let (mut roff, mut n_above) = (y, 0);
+        while roff > 0 && (roff > self.buf.cursor.roff || n_above < margin) {
+            let (above, n) = match row_lines(roff - 1) {
+                // A folded block starting above the screen is not scrolled into view
+                (fold, _) if fold.start < self.buf.cursor.roff && n_above >= margin =>
+                    (self.buf.cursor.roff, 0),
+                (rows, n) => (rows.start, n),
+            };
+            if n_lines + n > self.screen_rows {
+                break;
+            }
+            (roff, n_lines, n_above) = (above, n_lines + n, n_above + n);
+        }
+        (coff, roff)
+    }
+
+    /// Return the rows shown on the lines of the screen, as (row index, range of rendered columns).
+    /// If `wrap` is enabled, a row can span several lines; if the row of the cursor does not fit on
+    /// the screen, its first lines are skipped so that the cursor is visible.
+    fn screen_lines(&self) -> Vec<(usize, Range<usize>)> {
+        let (mut y, coff, width) = (self.buf.cursor.roff, self.buf.cursor.coff, self.screen_cols);
+        let mut lines = Vec::new();
+        if self.screen_rows == 0 {
+            return lines;
+        }
+        while y < self.buf.rows.len()
+            && (lines.len() < self.screen_rows || (self.config.wrap && y <= self.buf.cursor.y))
+        {
+            if let Some(fold) = self.fold_at(y) {
+                // The summary line of a folded block is represented by its first row
+                if y == fold.start {
+                    lines.push((y, 0..0));
+                }
+                y = fold.end;
+                continue;
+            }
+            if self.config.wrap {
+                let row = &self.buf.rows[y];
+                let starts = row.wrap_columns(width);
+                let ends = starts.iter().skip(1).copied().chain(row.cx2rx.last().copied());
+                lines.extend(starts.iter().zip(ends).map(|(start, end)| (y, *start..end)));
+            } else {
+                lines.push((y, coff..coff + width));
+            }
+            y += 1;
+        }
+        if self.config.wrap && !lines.is_empty() {
+            let cursor_line = self.cursor_line(&lines).unwrap_or_default();
+            lines.drain(..(cursor_line + 1).saturating_sub(self.screen_rows));
+        }
+        lines.truncate(self.screen_rows);
+        lines
+    }
+
+    /// Return the index of the line containing the cursor among the given screen lines, if any.
+    fn cursor_line(&self, lines: &[(usize, Range<usize>)]) -> Option<usize> {
+        let rx = self.buf.rx();
+        lines.iter().rposition(|(y, cols)| *y == self.buf.cursor.y && cols.start <= rx)
+    }
+
+    /// Return the folded block containing row `y`, if any.
+    fn fold_at(&self, y: usize) -> Option<&'a Range<usize>> {
+        self.buf.folds.iter().find(|fold| fold.contains(&y))
+    }
+
+    /// Return the line number displayed on the left of row `y`, depending on `line_number_style`.
+    fn line_number(&self, y: usize) -> usize {
+        match self.config.line_number_style {
+            LineNumberStyle::Relative => y.abs_diff(self.buf.cursor.y),
+            LineNumberStyle::Hybrid if y != self.buf.cursor.y => y.abs_diff(self.buf.cursor.y),
+            LineNumberStyle::Absolute | LineNumberStyle::Hybrid => self.first_row + y + 1,
+        }
+    }
+
+    /// Return the range of selected rows, if there is a selection.
+    fn selected_rows(&self) -> Option<Range<usize>> {
+        let (anchor_y, _) = self.selection_anchor?;
+        let (start, end) = (anchor_y.min(self.buf.cursor.y), anchor_y.max(self.buf.cursor.y) + 1);
+        Some(start.min(self.buf.rows.len())..end.min(self.buf.rows.len()))
+    }
+
+    /// Return the end of the rows to highlight: the rows shown on the screen, which may extend
+    /// further than `screen_rows` rows below the first one if some rows are folded.
+    fn highlight_end(&self) -> usize {
+        let end = self.screen_lines().last().map_or(0, |(y, _)| y + 1);
+        end.max(self.buf.cursor.roff + self.screen_rows)
+    }
+
+    /// Draw the left part of the screen: line numbers and vertical bar.
+    fn draw_left_padding<T: Display>(&self, buffer: &mut String, val: T) {
+        if self.ln_pad >= 2 {
+            // \u{2502}: pipe "│"
+            let color = self.config.theme.line_number_escape();
+            buffer.push_str(&format!("{}{:>2$} \u{2502}", color, val, self.ln_pad - 2));
+            buffer.push_str(RESET_FMT);
+        }
+    }
+
+    /// Return the range of screen lines covered by the thumb of the scrollbar, which represents
+    /// the visible rows among all the lines of the file. The thumb is at least one line high.
+    fn scrollbar_thumb(&self) -> Range<usize> {
+        let (n_lines, height) = (self.n_lines.max(1), self.screen_rows);
+        let top = (self.first_row + self.buf.cursor.roff).min(n_lines - 1);
+        let len = (height * height).div_ceil(n_lines).clamp(1, height);
+        let start = (top * height / n_lines).min(height - len);
+        start..start + len
+    }
+
+    /// Draw the scrollbar at the end of the lines of the pane: a full block `█` for the thumb,
+    /// and a light shade `░` for the rest of the track.
+    fn draw_scrollbar(&self, lines: &mut [String]) {
+        let (thumb, col) = (self.scrollbar_thumb(), self.offset + self.width);
+        let color = self.config.theme.line_number_escape();
+        for (i, line) in lines.iter_mut().enumerate() {
+            let c = if thumb.contains(&i) { '\u{2588}' } else { '\u{2591}' };
+            // \x1b[{}G: move to the given column
+            line.push_str(&format!("\x1b[{}G{}{}{}", col, color, c, RESET_FMT));
+        }
+    }
+
+    /// Draw the additional cursors that are shown in the pane, in reverse video, at the end
+    /// of the screen lines containing them.
+    fn draw_extra_cursors(&self, screen_lines: &[(usize, Range<usize>)], lines: &mut [String]) {
+        let offset = self.offset + self.ln_pad;
+        for cursor in &self.buf.extra_cursors {
+            let Some(row) = self.buf.rows.get(cursor.y).filter(|_| self.fold_at(cursor.y).is_none())
+            else {
+                continue;
+            };
+            let rx = row.cx2rx[cursor.x];
+            let line = screen_lines.iter().rposition(|(y, c)| *y == cursor.y && c.start <= rx);
+            let Some((i, start)) = line.map(|i| (i, screen_lines[i].1.start))
+                .filter(|(_, start)| rx - start < self.screen_cols)
+            else {
+                continue;
+            };
+            let c = match &row.chars[cursor.x..row.next_boundary(cursor.x)] {
+                [] => Cow::Borrowed(" "),
+                [b] if b.is_ascii_control() => Cow::Borrowed(" "),
+                bytes => String::from_utf8_lossy(bytes),
+            };
+            // \x1b[{}G: move to the given column
+            let col = offset + rx - start + 1;
+            lines[i].push_str(&format!("\x1b[{}G{}{}{}", col, REVERSE_VIDEO, c, RESET_FMT));
+        }
+    }
+
+    /// Return the lines of the welcome message shown when the buffer is empty, or no line if
+    /// `show_welcome` is disabled.
+    fn welcome_lines(&self) -> Vec<&'a str> {
+        match (self.config.show_welcome, self.config.welcome_message.as_str()) {
+            (false, _) => Vec::new(),
+            (true, "") => vec![concat!("Rust Text Editor ", env!("RUST_TEXT_EDITOR_VERSION"))],
+            (true, message) => message.lines().collect(),
+        }
+    }
+
+    /// Draw the rows of the buffer that are shown in the pane, and return them.
+    fn draw(&self) -> Vec<String> {
+        let screen_lines = self.screen_lines();
+        let welcome_lines = if self.buf.is_empty() { self.welcome_lines() } else { Vec::new() };
+        let mut lines = Vec::with_capacity(self.screen_rows);
+        for i in 0..self.screen_rows {
+            let mut buffer = String::new();
+            let fold = screen_lines.get(i).and_then(|(y, _)| self.fold_at(*y));
+            if let Some(fold) = fold {
+                // Draw the summary of a folded block, indented like its first row
+                self.draw_left_padding(&mut buffer, "");
+                let row = &self.buf.rows[fold.start];
+                let indent = row.cx2rx[indent_len(&row.chars)].saturating_sub(self.buf.cursor.coff);
+                let summary = format!("{:1$}... {2} lines", "", indent, fold.len());
+                let summary = truncate_to_width(&summary, self.screen_cols);
+                let color = self.config.theme.line_number_escape();
+                buffer.push_str(&format!("{}{}{}", color, summary, RESET_FMT));
+            } else if let Some((y, cols)) = screen_lines.get(i) {
+                // Draw a row of text. Wrapped rows only show their number on their first line.
+                if self.config.wrap && cols.start > 0 {
+                    self.draw_left_padding(&mut buffer, "");
+                } else {
+                    self.draw_left_padding(&mut buffer, self.line_number(*y));
+                }
+                let is_selected = self.selected_rows().is_some_and(|r| r.contains(y));
+                if is_selected {
+                    buffer.push_str(REVERSE_VIDEO);
+                }
+                let row = &self.buf.rows[*y];
+                // The last line of a row may be drawn up to the edge of the screen, e.g. to show
+                // the ruler
+                let is_last_line = cols.end >= row.rx2cx.len() - 1;
+                let n_cols = if is_last_line { self.screen_cols } else { cols.len() };
+                // The selection takes precedence over the current line color
+                let is_cursorline = self.config.cursorline && *y == self.buf.cursor.y;
+                let background = if is_cursorline && !is_selected {
+                    self.config.theme.cursorline_escape()
+                } else {
+                    String::new()
+                };
+                row.draw(cols.start, n_cols, self.config, &background, &mut buffer);
+                if !background.is_empty() && n_cols < self.screen_cols {
+                    // Extend the background of a wrapped line to the edge of the screen
+                    let padding = " ".repeat(self.screen_cols - n_cols);
+                    buffer.push_str(&format!("{}{}{}", background, padding, RESET_FMT));
+                }
+            } else {
+                // Draw an empty row
+                self.draw_left_padding(&mut buffer, '~');
+                // The welcome message starts one third down the screen, each line being centered
+                let k = i.checked_sub(self.screen_rows / 3);
+                if let Some(line) = k.and_then(|k| welcome_lines.get(k)) {
+                    let line = truncate_to_width(line, self.screen_cols);
+                    let padding = self.screen_cols - line.width();
+                    let (left, right) = (padding / 2, padding - padding / 2);
+                    buffer.push_str(&format!("{:left$}{}{:right$}", "", line, ""));
+                }
+            }
+            lines.push(buffer);
+        }
+        self.draw_extra_cursors(&screen_lines, &mut lines);
+        if self.config.scrollbar && self.screen_rows > 0 {
+            self.draw_scrollbar(&mut lines);
+        }
+        lines
     }
 }
 
@@ -89,43 +664,140 @@ pub struct Editor {
     /// If not `None`, the current prompt mode (Save, Find, GoTo). If `None`, we are in regular
     /// edition mode.
     prompt_mode: Option<PromptMode>,
-    /// The current state of the cursor.
-    cursor: CursorState,
+    /// The text of the active buffer and its cursor.
+    buf: TextBuffer,
     /// The padding size used on the left for line numbering.
     ln_pad: usize,
     /// The width of the current window. Will be updated when the window is resized.
     window_width: usize,
+    /// The height of the current window, including the status bar and the message bar. Will be
+    /// updated when the window is resized.
+    window_height: usize,
     /// The number of rows that can be used for the editor, excluding the status bar and the message
     /// bar
     screen_rows: usize,
     /// The number of columns that can be used for the editor, excluding the part used for line numbers
     screen_cols: usize,
-    /// The collection of rows, including the content and the syntax highlighting information.
-    rows: Vec<Row>,
-    /// Whether the document has been modified since it was open.
-    dirty: bool,
+    /// The lines of the screen drawn by the last refresh, including the status bar and the message
+    /// bar. Only the lines that have changed are drawn again. Cleared to redraw the whole screen.
+    drawn_lines: Vec<String>,
     /// The configuration for the editor.
     config: Config,
     /// The number of warnings remaining before we can quit without saving. Defaults to
     /// `config.quit_times`, then decreases to 0.
     quit_times: usize,
-    /// The file name. If None, the user will be prompted for a file name the first time they try to
-    /// save.
-    // TODO: It may be better to store a PathBuf instead
-    file_name: Option<String>,
+    /// The number of warnings remaining before we can revert a modified file. Defaults to
+    /// `config.quit_times`, then decreases to 0.
+    revert_times: usize,
     /// The current status message being shown.
     status_msg: Option<StatusMessage>,
-    /// The syntax configuration corresponding to the current file's extension.
-    syntax: SyntaxConf,
-    /// The number of bytes contained in `rows`. This excludes new lines.
-    n_bytes: u64,
     /// The original terminal mode. It will be restored when the `Editor` instance is dropped.
     orig_term_mode: Option<sys::TermMode>,
+    /// Whether the editor switched to the alternate screen buffer, which will be left when the
+    /// `Editor` instance is dropped.
+    alternate_screen: bool,
+    /// If not `None`, the position (y, x) where the selection started. The selection is line-wise:
+    /// it spans all the rows between this position and the cursor.
+    selection_anchor: Option<(usize, usize)>,
+    /// The positions (y, x) of the bracket under the cursor and of its matching bracket, if any.
+    /// These brackets are highlighted when drawing the rows.
+    bracket_pair: Option<[(usize, usize); 2]>,
+    /// The word under the cursor whose occurrences are underlined, if `highlight_word` is enabled.
+    /// The occurrences are only searched again in the rows that have changed, unless the word
+    /// changes.
+    highlighted_word: Option<Vec<u8>>,
+    /// The query of the Find prompt and its options, while the prompt is open. The matches of the
+    /// query in the rows shown on the screen are highlighted.
+    search: Option<(String, FindOptions)>,
+    /// The rows in which the matches of `search` are currently highlighted.
+    search_rows: Vec<usize>,
+    /// The queries of the Find prompt and the search terms of the Replace prompt. It is loaded from
+    /// the state file at startup, and stored when quitting.
+    find_history: History,
+    /// The replacements entered in the Replace prompt.
+    replace_history: History,
+    /// The positions entered in the Go to prompt.
+    goto_history: History,
+    /// The matches of `search` in the whole document, shown as "Match 3/12" in the Find prompt.
+    match_count: Option<MatchCount>,
+    /// Whether the buffer is read-only. If so, editing keys and saving are disabled.
+    read_only: bool,
+    /// The time of the last attempt to save the file, or of the editor start. Used for auto-save.
+    last_save: Option<Instant>,
+    /// The time at which the swap file was last written or recovered from, if this happened since
+    /// the last save. The swap file is removed when the file is saved.
+    swap_written: Option<Instant>,
+    /// The modification time of the file on disk when it was loaded or last saved. Used to detect
+    /// modifications made by other programs.
+    mtime: Option<SystemTime>,
+    /// The buffers that are not active. The state of the active buffer is stored in the fields of
+    /// the editor (`rows`, `cursor`, etc.); it is exchanged with a `Buffer` when switching buffers.
+    buffers: Vec<Buffer>,
+    /// The index of the active buffer, among all the buffers.
+    active: usize,
+    /// Whether the editor should exit, once the current keypress has been processed. This is set
+    /// when quitting is confirmed, with the quit key or the `quit` command.
+    should_quit: bool,
+    /// If not `None`, the screen is split in two panes. The active buffer is shown in the focused
+    /// pane; it is the only one receiving cursor movements and edits.
+    split: Option<Split>,
+    /// The values of the user configuration overridden by the `.editorconfig` properties of the
+    /// active buffer. They are restored when switching to another buffer.
+    overridden_config: Properties,
+    /// The configuration file given on the command line, if any. It is read instead of the
+    /// `config.ini` files of the configuration directories when the configuration is reloaded.
+    config_file: Option<PathBuf>,
+    /// Whether the files are opened in read-only mode, and whether they are opened in paged mode
+    /// whatever their size, as requested on the command line. These override the configuration,
+    /// including when it is reloaded.
+    force_read_only: bool,
+    force_paged: bool,
+    /// Whether the keys are shown in the message bar, with the bytes they were decoded from,
+    /// instead of being acted on. Only the quit key is processed. This helps troubleshooting keys
+    /// that are not recognized.
+    debug_keys: bool,
+    /// Where the window size was obtained from, shown in debug mode.
+    window_size_source: &'static str,
+    /// The directory where the state of the editor (cursor positions, recent files, etc.) is
+    /// persisted across sessions. If `None`, as in tests, the state is neither read nor written.
+    state_dir: Option<PathBuf>,
+    /// If not `None`, the file is too large to be loaded in memory: `rows` only contains the rows
+    /// around the visible ones, which are read from the file on demand. Editing is disabled.
+    paged: Option<PagedFile>,
+    /// The index, in the file, of the first row in `rows`. This is always 0 unless the file is
+    /// paged; `cursor` is relative to this row.
+    first_row: usize,
+}
+
+/// The severity of a status message, which sets its color and how long it is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    /// Shown for `message_dur`.
+    Info,
+    /// Shown in yellow for `message_dur`.
+    Warning,
+    /// Shown in red until the next key press, regardless of `message_dur`.
+    Error,
+}
+
+/// A message shown in the message bar.
+struct StatusMessage {
+    /// The text of the message.
+    msg: String,
+    /// The time the message was set.
+    time: Instant,
+    /// The severity of the message.
+    severity: Severity,
 }
 
 impl StatusMessage {
     /// Create a new status message and set time to the current date/time.
-    fn new(msg: String) -> Self { Self { msg, time: Instant::now() } }
+    fn new(msg: String, severity: Severity) -> Self { Self { msg, time: Instant::now(), severity } }
+
+    /// Return whether the message should still be shown, `message_dur` after it was set.
+    fn is_visible(&self, message_dur: Duration) -> bool {
+        self.severity == Severity::Error || self.time.elapsed() < message_dur
+    }
 }
 
 /// Pretty-format a size in bytes.
@@ -135,17 +807,315 @@ fn format_size(n: u64) -> String {
     }
     // i is the largest value such that 1024 ^ i < n
     // To find i we compute the smallest b such that n <= 1024 ^ b and subtract 1 from it
-    let i = (64 - n.leading_zeros() + 9) / 10 - 1;
+    let i = (64 - n.leading_zeros()).div_ceil(10) - 1;
     // Compute the size with two decimal places (rounded down) as the last two digits of q
     // This avoid float formatting reducing the binary size
     let q = 100 * n / (1024 << ((i - 1) * 10));
     format!("{}.{:02}{}B", q / 100, q % 100, b" kMGTPEZ"[i as usize] as char)
 }
 
-/// `slice_find` returns the index of `needle` in slice `s` if `needle` is a subslice of `s`,
-/// otherwise returns `None`.
-fn slice_find<T: PartialEq>(s: &[T], needle: &[T]) -> Option<usize> {
-    (0..(s.len() + 1).saturating_sub(needle.len())).find(|&i| s[i..].starts_with(needle))
+/// Parse a range of lines such as `3,7`, whose bounds are 1-indexed and inclusive, into a range of
+/// row indices.
+fn parse_line_range(s: &str) -> Result<Range<usize>, ParseIntError> {
+    let (first, last) = s.split_once(',').unwrap_or((s, s));
+    Ok(first.trim().parse::<usize>()?.saturating_sub(1)..last.trim().parse()?)
+}
+
+/// Run a command with the shell of the user, with `input` written to its standard input, and
+/// return its standard output. If the command cannot be started, fails or times out, return the
+/// message to show in the status bar, e.g. the first line written to the standard error. While
+/// waiting for the command, `progress` is called regularly with the elapsed time.
+fn run_filter(
+    command: &str, input: Vec<u8>, timeout: Duration, progress: &mut dyn FnMut(Duration),
+) -> Result<Vec<u8>, String> {
+    /// Read a pipe to the end in a separate thread.
+    fn read_all<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            pipe.map(|mut pipe| pipe.read_to_end(&mut buffer));
+            buffer
+        })
+    }
+    let mut child = sys::shell_command(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    // The input is written in a separate thread, so that a command whose output is not read yet
+    // cannot block the editor
+    let mut stdin = child.stdin.take();
+    thread::spawn(move || stdin.as_mut().map(|stdin| stdin.write_all(&input)));
+    let (stdout, stderr) = (read_all(child.stdout.take()), read_all(child.stderr.take()));
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("{} timed out after {:?}", command, timeout));
+        }
+        progress(start.elapsed());
+        thread::sleep(Duration::from_millis(10));
+    };
+    if !status.success() {
+        let stderr = stderr.join().unwrap_or_default();
+        let first_line = String::from_utf8_lossy(&stderr).lines().next().map(String::from);
+        return Err(first_line.filter(|l| !l.trim().is_empty()).unwrap_or(status.to_string()));
+    }
+    Ok(stdout.join().unwrap_or_default())
+}
+
+/// Read the bytes of a key press, and return the corresponding `Key`. In particular, ANSI escape
+/// sequences are decoded to return `Key::Delete`, `Key::Home` etc. Return `None` if no byte is
+/// available, or if the bytes should be ignored, e.g. the release of a mouse button.
+fn read_key(bytes: &mut impl Iterator<Item = io::Result<u8>>) -> io::Result<Option<Key>> {
+    // Match on the next byte received or, if the first byte is <ESC> ('\x1b'), on the next few
+    // bytes.
+    match bytes.next().transpose()? {
+        Some(b'\x1b') => Ok(Some(match bytes.next().transpose()? {
+                Some(b @ (b'[' | b'O')) => match (b, bytes.next().transpose()?) {
+                    (b'[', Some(b'A')) => Key::Arrow(AKey::Up),
+                    (b'[', Some(b'B')) => Key::Arrow(AKey::Down),
+                    (b'[', Some(b'C')) => Key::Arrow(AKey::Right),
+                    (b'[', Some(b'D')) => Key::Arrow(AKey::Left),
+                    (b'[', Some(b'Z')) => Key::BackTab,
+                    (b'[' | b'O', Some(b'H')) => Key::Home,
+                    (b'[' | b'O', Some(b'F')) => Key::End,
+                    // Ctrl-Tab and Ctrl-Shift-Tab, as sent by terminals supporting the
+                    // "fixterms" or "modifyOtherKeys" protocols: <ESC>[9;5u, <ESC>[27;5;9~
+                    // Mouse events, in the SGR format: <ESC>[<button;column;row(M|m)
+                    (b'[', Some(b'<')) =>
+                        match parse_mouse_event(&read_csi_end(bytes)?) {
+                            Some(event) => Key::Mouse(event),
+                            // Ignore button releases and unsupported buttons
+                            None => return Ok(None),
+                        },
+                    (b'[', Some(b'9')) => match read_csi_end(bytes)?.as_slice() {
+                        b";5u" => Key::CtrlTab,
+                        b";6u" => Key::CtrlShiftTab,
+                        _ => Key::Escape,
+                    },
+                    (b'[', mut c @ Some(b'0'..=b'8')) => {
+                        let mut d = bytes.next().transpose()?;
+                        if let (Some(b'2'), Some(b'0')) = (c, d) {
+                            // Bracketed paste: <ESC>[200~, the text, then <ESC>[201~. An
+                            // unmatched <ESC>[201~ is ignored.
+                            match read_csi_end(bytes)?.as_slice() {
+                                b"0~" => return Ok(Some(Key::Paste(read_paste(bytes)?))),
+                                b"1~" => return Ok(None),
+                                _ => return Ok(Some(Key::Escape)),
+                            }
+                        }
+                        if let (Some(b'2'), Some(b'7')) = (c, d) {
+                            return Ok(Some(match read_csi_end(bytes)?.as_slice() {
+                                b";5;9~" => Key::CtrlTab,
+                                b";6;9~" => Key::CtrlShiftTab,
+                                _ => Key::Escape,
+                            }));
+                        }
+                        if let (Some(b'1'), Some(b';')) = (c, d) {
+                            // 1 is the default modifier value. Therefore, <ESC>[1;5C is
+                            // equivalent to <ESC>[5C, etc.
+                            c = bytes.next().transpose()?;
+                            d = bytes.next().transpose()?;
+                        }
+                        match (c, d) {
+                            (Some(c), Some(b'~')) if c == b'1' || c == b'7' => Key::Home,
+                            (Some(c), Some(b'~')) if c == b'4' || c == b'8' => Key::End,
+                            (Some(b'3'), Some(b'~')) => Key::Delete,
+                            (Some(b'3'), Some(b';')) => {
+                                let m = bytes.next().transpose()?;
+                                match (m, bytes.next().transpose()?) {
+                                    (Some(b'5'), Some(b'~')) => Key::CtrlDelete,
+                                    _ => Key::Escape,
+                                }
+                            }
+                            (Some(b'5'), Some(b'~')) => Key::Page(PageKey::Up),
+                            (Some(b'6'), Some(b'~')) => Key::Page(PageKey::Down),
+                            (Some(b'5'), Some(b'A')) => Key::CtrlArrow(AKey::Up),
+                            (Some(b'5'), Some(b'B')) => Key::CtrlArrow(AKey::Down),
+                            (Some(b'5'), Some(b'C')) => Key::CtrlArrow(AKey::Right),
+                            (Some(b'5'), Some(b'D')) => Key::CtrlArrow(AKey::Left),
+                            (Some(b'2'), Some(b'A')) => Key::ShiftArrow(AKey::Up),
+                            (Some(b'2'), Some(b'B')) => Key::ShiftArrow(AKey::Down),
+                            (Some(b'2'), Some(b'C')) => Key::ShiftArrow(AKey::Right),
+                            (Some(b'2'), Some(b'D')) => Key::ShiftArrow(AKey::Left),
+                            (Some(b'3'), Some(b'A')) => Key::AltArrow(AKey::Up),
+                            (Some(b'3'), Some(b'B')) => Key::AltArrow(AKey::Down),
+                            (Some(b'7'), Some(b'A')) => Key::CtrlAltArrow(AKey::Up),
+                            (Some(b'7'), Some(b'B')) => Key::CtrlAltArrow(AKey::Down),
+                            _ => Key::Escape,
+                        }
+                    }
+                    (b'O', Some(b'a')) => Key::CtrlArrow(AKey::Up),
+                    (b'O', Some(b'b')) => Key::CtrlArrow(AKey::Down),
+                    (b'O', Some(b'c')) => Key::CtrlArrow(AKey::Right),
+                    (b'O', Some(b'd')) => Key::CtrlArrow(AKey::Left),
+                    _ => Key::Escape,
+                },
+                Some(c) if c.is_ascii_control() && c != b'\x1b' => Key::AltChar(c),
+                _ => Key::Escape,
+            })),
+        Some(a) => Ok(Some(Key::Char(a))),
+        None => Ok(None),
+    }
+}
+
+/// Read the end of a CSI escape sequence, up to and including its final byte.
+fn read_csi_end(bytes: &mut impl Iterator<Item = io::Result<u8>>) -> io::Result<Vec<u8>> {
+    let mut end = Vec::new();
+    while let Some(b) = bytes.next().transpose()? {
+        end.push(b);
+        if (0x40..=0x7e).contains(&b) {
+            break;
+        }
+    }
+    Ok(end)
+}
+
+/// Read the text pasted in the terminal after `<ESC>[200~`, up to the `<ESC>[201~` sequence
+/// (excluded) or until no more bytes are available.
+fn read_paste(bytes: &mut impl Iterator<Item = io::Result<u8>>) -> io::Result<Vec<u8>> {
+    let mut text = Vec::new();
+    while let Some(b) = bytes.next().transpose()? {
+        text.push(b);
+        if text.ends_with(BRACKETED_PASTE_END.as_bytes()) {
+            text.truncate(text.len() - BRACKETED_PASTE_END.len());
+            break;
+        }
+    }
+    Ok(text)
+}
+
+/// Return the padding used on the left for line numbers and the number of columns left for the
+/// text, in a pane of the given width showing a file of `n_lines` lines.
+fn pane_columns(config: &Config, width: usize, screen_rows: usize, n_lines: usize)
+    -> (usize, usize) {
+    // The maximum number of digits to use for the line number is the number of digits of the
+    // largest displayed number: the last line number, or the number of screen rows if only
+    // relative numbers are shown. This is equal to the number of times we can divide this number
+    // by ten, computed below using `successors`. The first number is always counted, so that an
+    // empty document, whose largest number is 0, still uses one digit.
+    let max_number = match config.line_number_style {
+        LineNumberStyle::Relative => screen_rows,
+        LineNumberStyle::Absolute | LineNumberStyle::Hybrid => n_lines,
+    };
+    let n_digits = successors(Some(max_number), |u| Some(u / 10).filter(|&u| u > 0)).count();
+    let show_line_num = config.show_line_num && n_digits + 2 < width / 4;
+    let ln_pad = if show_line_num { n_digits + 2 } else { 0 };
+    // The scrollbar uses the rightmost column of the pane
+    let scrollbar_width = usize::from(config.scrollbar);
+    (ln_pad, width.saturating_sub(ln_pad + scrollbar_width))
+}
+
+/// Return the longest prefix of `s` that fits in `width` columns of the terminal.
+fn truncate_to_width(s: &str, width: usize) -> &str {
+    let mut w = 0;
+    let mut chars = s.char_indices().skip_while(|(_, c)| {
+        w += c.width().unwrap_or(1);
+        w <= width
+    });
+    chars.next().map_or(s, |(i, _)| &s[..i])
+}
+
+/// Parse the end of a SGR mouse escape sequence, for instance `0;12;5M` for a left click at column
+/// 12 and row 5. Return `None` for button releases and unsupported buttons.
+fn parse_mouse_event(seq: &[u8]) -> Option<MouseEvent> {
+    let (b'M', seq) = seq.split_last()? else { return None };
+    let mut values = std::str::from_utf8(seq).ok()?.split(';').map(str::parse::<usize>);
+    match (values.next()?.ok()?, values.next()?.ok()?, values.next()?.ok()?) {
+        (0, col, row) => Some(MouseEvent::Click(col.saturating_sub(1), row.saturating_sub(1))),
+        (64, ..) => Some(MouseEvent::WheelUp),
+        (65, ..) => Some(MouseEvent::WheelDown),
+        _ => None,
+    }
+}
+
+/// Return the path of the swap file used to recover the changes made to `file_name` after a crash.
+fn swap_path(file_name: &str) -> String { format!("{}.rte.swap", file_name) }
+
+/// Split a file name given on the command line into the path of the file and the position to move
+/// the cursor to, given as a `:line` or `:line:column` suffix. Only numeric suffixes are split, so
+/// that Windows paths such as `C:\src` are kept as is, and so is the name of an existing file.
+fn split_file_position(file_name: &str) -> (&str, Option<&str>) {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let mut path_len = file_name.len();
+    for _ in 0..2 {
+        match file_name[..path_len].rsplit_once(':') {
+            Some((path, n)) if is_number(n) && !path.is_empty() => path_len = path.len(),
+            _ => break,
+        }
+    }
+    if path_len == file_name.len() || Path::new(file_name).is_file() {
+        return (file_name, None);
+    }
+    (&file_name[..path_len], Some(&file_name[path_len + 1..]))
+}
+
+/// Return the length of the indentation (leading spaces and tabs) of a row.
+fn indent_len(chars: &[u8]) -> usize {
+    chars.iter().take_while(|c| matches!(c, b' ' | b'\t')).count()
+}
+
+/// Return whether a byte is part of a word: ASCII alphanumeric characters, underscores, and bytes
+/// of multi-byte UTF-8 characters.
+fn is_word_byte(b: u8) -> bool { b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii() }
+
+/// Compile a regular expression from the Find prompt buffer, taking the `FindOptions` into
+/// account.
+#[cfg(feature = "regex")]
+fn build_regex(query: &str, opts: FindOptions) -> Result<Regex, regex::Error> {
+    let pattern = if opts.whole_word { format!("\\b(?:{})\\b", query) } else { query.into() };
+    RegexBuilder::new(&pattern).case_insensitive(opts.ignore_case).build()
+}
+
+/// Same as `slice_find`, for UTF-8 bytes and with the given `FindOptions`: ASCII letters are
+/// compared case-insensitively if `ignore_case` is set, and the match must be surrounded by
+/// non-word characters if `whole_word` is set. Matches that start or end in the middle of
+/// a multi-byte character are rejected.
+fn slice_find_with(s: &[u8], needle: &[u8], opts: FindOptions) -> Option<usize> {
+    // UTF-8 continuation bytes are of the form 0b10xxxxxx
+    let is_char_boundary = |i: usize| s.get(i).is_none_or(|b| b & 0xc0 != 0x80);
+    (0..(s.len() + 1).saturating_sub(needle.len())).find(|&i| {
+        let (candidate, end) = (&s[i..i + needle.len()], i + needle.len());
+        let eq = candidate == needle || opts.ignore_case && candidate.eq_ignore_ascii_case(needle);
+        eq && is_char_boundary(i)
+            && is_char_boundary(end)
+            && (!opts.whole_word
+                || (i == 0 || !is_word_byte(s[i - 1]))
+                    && !s.get(end).copied().is_some_and(is_word_byte))
+    })
+}
+
+/// A function returning the byte ranges of the matches of a query in a row.
+type Matcher = Box<dyn Fn(&[u8]) -> Vec<Range<usize>>>;
+
+/// Return a `Matcher` for `query`, with the given `FindOptions`. The matches do not overlap; empty
+/// queries and invalid regular expressions don't match anything.
+fn search_matcher(query: &str, opts: FindOptions) -> Matcher {
+    #[cfg(feature = "regex")]
+    if opts.regex {
+        let re = build_regex(query, opts).ok();
+        return Box::new(move |s| {
+            let matches = re.iter().flat_map(|re| re.find_iter(s));
+            matches.map(|m| m.start()..m.end()).filter(|m| !m.is_empty()).collect()
+        });
+    }
+    let needle = query.as_bytes().to_vec();
+    Box::new(move |s| {
+        let (mut matches, mut start) = (Vec::new(), 0);
+        if needle.is_empty() {
+            return matches;
+        }
+        while let Some(i) = slice_find_with(&s[start..], &needle, opts) {
+            matches.push(start + i..start + i + needle.len());
+            start += i + needle.len();
+        }
+        matches
+    })
 }
 
 impl Editor {
@@ -158,713 +1128,5149 @@ impl Editor {
     #[allow(clippy::field_reassign_with_default)]
     pub fn new(config: Config) -> Result<Self, Error> {
         sys::register_winsize_change_signal_handler()?;
+        sys::register_termination_signal_handler()?;
         let mut editor = Self::default();
         editor.quit_times = config.quit_times;
+        editor.revert_times = config.quit_times;
+        editor.read_only = config.read_only;
+        editor.last_save = Some(Instant::now());
         editor.config = config;
+        editor.state_dir = state::state_dir();
+        let state_path = editor.state_file(state::FIND_HISTORY);
+        if let Some(Ok(queries)) = state_path.map(|p| state::read_history(&p)) {
+            editor.find_history.entries = queries;
+        }
 
         // Enable raw mode and store the original (non-raw) terminal mode.
-        editor.orig_term_mode = Some(sys::enable_raw_mode()?);
-        editor.update_window_size()?;
+        let orig_term_mode = sys::enable_raw_mode()?;
+        editor.orig_term_mode = Some(orig_term_mode);
+        editor.alternate_screen = editor.config.alternate_screen;
+        register_panic_term_mode(orig_term_mode, editor.alternate_screen);
+        if editor.alternate_screen {
+            print!("{}", ENTER_ALTERNATE_SCREEN);
+        }
+        print!("{}", ENABLE_BRACKETED_PASTE);
+        editor.update_window_size();
+        if editor.config.mouse {
+            print!("{}", ENABLE_MOUSE_REPORTING);
+        }
 
-        set_status!(editor, "{}", HELP_MESSAGE);
+        match editor.config.warnings() {
+            warnings if warnings.is_empty() =>
+                set_status!(editor, "{}", key_bindings_help(&editor.config.keys).join(" | ")),
+            warnings =>
+                set_status!(editor, Warning: "Configuration warning: {}", warnings.join(" | ")),
+        }
 
         Ok(editor)
     }
 
-    /// Return the current row if the cursor points to an existing row, `None` otherwise.
-    fn current_row(&self) -> Option<&Row> { self.rows.get(self.cursor.y) }
+    /// Set the configuration file read by the `reload-config` command, instead of the `config.ini`
+    /// files of the configuration directories.
+    pub fn set_config_file(&mut self, path: PathBuf) { self.config_file = Some(path); }
 
-    /// Return the position of the cursor, in terms of rendered characters (as opposed to
-    /// `self.cursor.x`, which is the position of the cursor in terms of bytes).
-    fn rx(&self) -> usize { self.current_row().map_or(0, |r| r.cx2rx[self.cursor.x]) }
+    /// Open the files in read-only mode if `read_only` is true, and in paged mode whatever their
+    /// size if `paged` is true, regardless of the configuration.
+    pub fn override_config(&mut self, read_only: bool, paged: bool) {
+        (self.force_read_only, self.force_paged) = (read_only, paged);
+        self.apply_config_overrides();
+        self.read_only = self.config.read_only;
+    }
 
-    /// Move the cursor following an arrow key (← → ↑ ↓).
-    fn move_cursor(&mut self, key: &AKey) {
-        match (key, self.current_row()) {
-            (AKey::Left, Some(row)) if self.cursor.x > 0 =>
-                self.cursor.x -= row.get_char_size(row.cx2rx[self.cursor.x] - 1),
-            (AKey::Left, _) if self.cursor.y > 0 => {
-                // ← at the beginning of the line: move to the end of the previous line. The x
-                // position will be adjusted after this `match` to accommodate the current row
-                // length, so we can just set here to the maximum possible value here.
-                self.cursor.y -= 1;
-                self.cursor.x = usize::MAX;
-            }
-            (AKey::Right, Some(row)) if self.cursor.x < row.chars.len() =>
-                self.cursor.x += row.get_char_size(row.cx2rx[self.cursor.x]),
-            (AKey::Right, Some(_)) => self.cursor.move_to_next_line(),
-            (AKey::Up, _) if self.cursor.y > 0 => self.cursor.y -= 1,
-            (AKey::Down, Some(_)) => self.cursor.y += 1,
-            _ => (),
+    /// Apply the overrides given to `override_config` to the configuration.
+    fn apply_config_overrides(&mut self) {
+        self.config.read_only |= self.force_read_only;
+        if self.force_paged {
+            self.config.large_file_size = 0;
         }
-        self.update_cursor_x_position();
     }
 
-    /// Update the cursor x position. If the cursor y position has changed, the current position
-    /// might be illegal (x is further right than the last character of the row). If that is the
-    /// case, clamp `self.cursor.x`.
-    fn update_cursor_x_position(&mut self) {
-        self.cursor.x = self.cursor.x.min(self.current_row().map_or(0, |row| row.chars.len()));
+    /// Enable the debug mode, where the bytes received and the keys they are decoded to are shown
+    /// in the message bar instead of being acted on. The quit key still quits.
+    pub fn enable_debug_keys(&mut self) {
+        self.debug_keys = true;
+        let quit = ctrl_key_name(self.config.keys.quit);
+        let size = (self.window_width, self.window_height, self.window_size_source);
+        set_status!(self, "Debug mode, window size {}x{} from {}: press keys to show their bytes, \
+                           {} to quit", size.0, size.1, size.2, quit);
     }
 
-    /// Run a loop to obtain the key that was pressed. At each iteration of the loop (until a key is
-    /// pressed), we listen to the `ws_changed` channel to check if a window size change signal has
-    /// been received. When bytes are received, we match to a corresponding `Key`. In particular,
-    /// we handle ANSI escape codes to return `Key::Delete`, `Key::Home` etc.
-    fn loop_until_keypress(&mut self) -> Result<Key, Error> {
-        loop {
-            // Handle window size if a signal has be received
-            if sys::has_window_size_changed() {
-                self.update_window_size()?;
-                self.refresh_screen()?;
-            }
-            let mut bytes = sys::stdin()?.bytes();
-            // Match on the next byte received or, if the first byte is <ESC> ('\x1b'), on the next
-            // few bytes.
-            match bytes.next().transpose()? {
-                Some(b'\x1b') => {
-                    return Ok(match bytes.next().transpose()? {
-                        Some(b @ (b'[' | b'O')) => match (b, bytes.next().transpose()?) {
-                            (b'[', Some(b'A')) => Key::Arrow(AKey::Up),
-                            (b'[', Some(b'B')) => Key::Arrow(AKey::Down),
-                            (b'[', Some(b'C')) => Key::Arrow(AKey::Right),
-                            (b'[', Some(b'D')) => Key::Arrow(AKey::Left),
-                            (b'[' | b'O', Some(b'H')) => Key::Home,
-                            (b'[' | b'O', Some(b'F')) => Key::End,
-                            (b'[', mut c @ Some(b'0'..=b'8')) => {
-                                let mut d = bytes.next().transpose()?;
-                                if let (Some(b'1'), Some(b';')) = (c, d) {
-                                    // 1 is the default modifier value. Therefore, <ESC>[1;5C is
-                                    // equivalent to <ESC>[5C, etc.
-                                    c = bytes.next().transpose()?;
-                                    d = bytes.next().transpose()?;
-                                }
-                                match (c, d) {
-                                    (Some(c), Some(b'~')) if c == b'1' || c == b'7' => Key::Home,
-                                    (Some(c), Some(b'~')) if c == b'4' || c == b'8' => Key::End,
-                                    (Some(b'3'), Some(b'~')) => Key::Delete,
-                                    (Some(b'5'), Some(b'~')) => Key::Page(PageKey::Up),
-                                    (Some(b'6'), Some(b'~')) => Key::Page(PageKey::Down),
-                                    (Some(b'5'), Some(b'A')) => Key::CtrlArrow(AKey::Up),
-                                    (Some(b'5'), Some(b'B')) => Key::CtrlArrow(AKey::Down),
-                                    (Some(b'5'), Some(b'C')) => Key::CtrlArrow(AKey::Right),
-                                    (Some(b'5'), Some(b'D')) => Key::CtrlArrow(AKey::Left),
-                                    _ => Key::Escape,
-                                }
-                            }
-                            (b'O', Some(b'a')) => Key::CtrlArrow(AKey::Up),
-                            (b'O', Some(b'b')) => Key::CtrlArrow(AKey::Down),
-                            (b'O', Some(b'c')) => Key::CtrlArrow(AKey::Right),
-                            (b'O', Some(b'd')) => Key::CtrlArrow(AKey::Left),
-                            _ => Key::Escape,
-                        },
-                        _ => Key::Escape,
-                    });
-                }
-                Some(a) => return Ok(Key::Char(a)),
-                None => continue,
-            }
+    /// Return the description of each key binding of `config`, for instance `Ctrl-S = save`, as
+    /// shown in the message bar when the editor starts.
+    pub fn key_bindings_help(config: &Config) -> Vec<String> { key_bindings_help(&config.keys) }
+
+    /// Move the cursor to the character shown at the given (column, row) of the terminal. If the
+    /// screen is split and the click is in the other pane, that pane gets the focus.
+    fn click(&mut self, col: usize, row: usize) {
+        if row >= self.screen_rows {
+            return;
+        }
+        if self.split.is_some() && col != self.pane_widths().0 {
+            self.focus_pane(col < self.pane_widths().0);
+        }
+        let col = col.saturating_sub(self.focused_pane().1);
+        // Clicking below the last row moves the cursor to the last row
+        let lines = self.screen_lines();
+        let Some((y, cols)) = lines.get(row).or(lines.last()).cloned() else { return };
+        self.buf.cursor.y = y;
+        if self.fold_at(y).is_some() {
+            // Clicking on the summary of a folded block unfolds it
+            self.buf.folds.retain(|fold| fold.start != y);
+            self.buf.cursor.x = 0;
+            return;
         }
+        let row = &self.buf.rows[y];
+        let mut rx = cols.start + col.saturating_sub(self.ln_pad);
+        if self.config.wrap && rx >= cols.end && cols.end < row.rx2cx.len() - 1 {
+            // After the end of a line that is not the last one of a wrapped row
+            rx = cols.end - 1;
+        }
+        // Clicking on the second column of a wide character selects that character
+        self.buf.cursor.x = row.rx2cx.get(rx).copied().unwrap_or(row.chars.len());
     }
 
-    /// Update the `screen_rows`, `window_width`, `screen_cols` and `ln_padding` attributes.
-    fn update_window_size(&mut self) -> Result<(), Error> {
-        let wsize = sys::get_window_size().or_else(|_| terminal::get_window_size_using_cursor())?;
-        self.screen_rows = wsize.0.saturating_sub(2); // Make room for the status bar and status message
-        self.window_width = size.1;
-        self.update_screen_cols();
-        Ok(())
+    /// Scroll the terminal window vertically and horizontally (i.e. adjusting the row offset and
+    /// the column offset) so that the cursor can be shown. If the cursor has been moved to a folded
+    /// row, e.g. by a search, the fold is opened.
+    fn scroll(&mut self) {
+        let y = self.buf.cursor.y;
+        self.buf.folds.retain(|fold| !fold.contains(&y));
+        (self.buf.cursor.coff, self.buf.cursor.roff) = self.pane().scroll_offsets();
     }
 
-    /// Update the `screen_cols` and `ln_padding` attributes based on the maximum number of digits
-    /// for line numbers (since the left padding depends on this number of digits).
-    fn update_screen_cols(&mut self) {
-        // The maximum number of digits to use for the line number is the number of digits of the
-        // last line number. This is equal to the number of times we can divide this number by ten,
-        // computed below using `successors`.
-        let n_digits =
-            successors(Some(self.rows.len()), |u| Some(u / 10).filter(|| u | *u > 0)).count();
-        let show_line_num = self.config.show_line_num && n_digits + 2 < self.window_width / 4;
-        self.ln_pad = if show_line_num { n_digits + 2 } else { 0 };
-        self.screen_cols = self.window_width.saturating_sub(self.ln_pad);
-    }
+    /// Return the rows shown on the lines of the focused pane. See `Pane::screen_lines`.
+    fn screen_lines(&self) -> Vec<(usize, Range<usize>)> { self.pane().screen_lines() }
 
-    /// Given a file path, try to find a syntax highlighting configuration that matches the path
-    /// extension in one of the config directories (`/etc/kibi/syntax.d`, etc.). If such a
-    /// configuration is found, set the `syntax` attribute of the editor.
-    fn select_syntax_highlight(&mut self, path: &Path) -> Result<(), Error> {
-        let extension = path.extension().and_then(std::ffi::OsStr::to_str);
-        if let Some(s) = extension.and_then(|e| SyntaxConf::get(e).transpose()) {
-            self.syntax = s?;
+    /// Return the position (column, line) of the cursor on the screen, relative to the first line
+    /// and to the first column after the line numbers.
+    fn cursor_screen_position(&self) -> (usize, usize) {
+        if !self.config.wrap && self.buf.folds.is_empty() {
+            return (self.buf.rx() - self.buf.cursor.coff, self.buf.cursor.y - self.buf.cursor.roff);
         }
-        Ok(())
+        let pane = self.pane();
+        let lines = pane.screen_lines();
+        match pane.cursor_line(&lines) {
+            Some(i) => (self.buf.rx() - lines[i].1.start, i),
+            // The cursor is after the last row
+            None => (0, lines.len()),
+        }
+    }
+
+    /// Scroll the screen by the given number of rows (upwards if negative). The cursor is moved if
+    /// it would otherwise be out of the screen.
+    fn scroll_by(&mut self, n_rows: isize) {
+        let max_roff = self.buf.rows.len().saturating_sub(1);
+        self.buf.cursor.roff = self.buf.cursor.roff.saturating_add_signed(n_rows).min(max_roff);
+        let last_row = (self.buf.cursor.roff + self.screen_rows).saturating_sub(1);
+        let cursor = &mut self.buf.cursor;
+        cursor.y = cursor.y.clamp(cursor.roff, last_row.max(cursor.roff));
+        self.update_cursor_x_position();
     }
 
-    /// Update a row, given its index. If `ignore_following_rows` is `false` and the highlight state
-    /// has changed during the update (for instance, it is now in "multi-line comment" state, keep
-    /// updating the next rows
-    fn update_row(&mut self, y: usize, ignore_following_rows: bool) {
-        let mut hl_state = if y > 0 { self.rows[y - 1].hl_state } else { HlState::Normal };
-        for row in self.rows.iter_mut().skip(y) {
-            let previous_hl_state = row.hl_state;
-            hl_state = row.update(&self.syntax, hl_state, self.config.tab_stop);
-            if ignore_following_rows || hl_state == previous_hl_state {
+    /// Move the cursor following an arrow key (← → ↑ ↓).
+    fn move_cursor(&mut self, key: &AKey) {
+        if self.config.wrap && matches!(key, AKey::Up | AKey::Down) {
+            let up = matches!(key, AKey::Up);
+            if self.move_cursor_wrapped(up) {
                 return;
             }
-            // If the state has changed (for instance, a multi-line comment started in this row),
-            // continue updating the following rows
         }
-    }
-
-    /// Update all the rows.
-    fn update_all_rows(&mut self) {
-        let mut hl_state = HlState::Normal;
-        for row in &mut self.rows {
-            hl_state = row.update(&self.syntax, hl_state, self.config.tab_stop);
+        match (key, self.buf.current_row()) {
+            (AKey::Left, Some(row)) if self.buf.cursor.x > 0 =>
+                self.buf.cursor.x = row.previous_boundary(self.buf.cursor.x),
+            (AKey::Left, _) if self.buf.cursor.y > 0 => {
+                // ← at the beginning of the line: move to the end of the previous line. The x
+                // position will be adjusted after this `match` to accommodate the current row
+                // length, so we can just set here to the maximum possible value here.
+                self.buf.cursor.y -= 1;
+                self.buf.cursor.x = usize::MAX;
+            }
+            (AKey::Right, Some(row)) if self.buf.cursor.x < row.chars.len() =>
+                self.buf.cursor.x = row.next_boundary(self.buf.cursor.x),
+            (AKey::Right, Some(_)) => self.buf.cursor.move_to_next_line(),
+            (AKey::Up, _) if self.buf.cursor.y > 0 => self.buf.cursor.y -= 1,
+            (AKey::Down, Some(_)) => self.buf.cursor.y += 1,
+            _ => (),
         }
-    }
-
-    /// Insert a byte at the current cursor position. If there is no row at the current cursor
-    /// position, add a new row and insert the byte.
-    fn insert_byte(&mut self, c: u8) {
-        if let Some(row) = self.rows.get_mut(self.cursor.y) {
-            row.chars.insert(self.cursor.x, c);
-        } else {
-            self.rows.push(Row::new(vec![c]));
-            // The number of rows has changed. The left padding may need to be updated.
-            self.update_screen_cols();
+        let up = matches!(key, AKey::Left | AKey::Up);
+        self.buf.cursor.y = self.skip_folded(self.buf.cursor.y, up);
+        match key {
+            AKey::Up | AKey::Down => self.move_to_desired_column(),
+            AKey::Left | AKey::Right => self.update_cursor_x_position(),
         }
-        self.update_row(self.cursor.y, false);
-        self.cursor.x += 1;
-        self.n_bytes += 1;
-        self.dirty = true;
     }
 
-    /// Insert a new line at the current cursor position and move the cursor to the start of the new
-    /// line. If the cursor is in the middle of a row, split off that row.
-    fn insert_new_line(&mut self) {
-        let (position, new_row_chars) = if self.cursor.x == 0 {
-            (self.cursor.y, Vec::new())
+    /// Add a cursor on the row below the last cursor, or above the first one, at the rendered
+    /// column of the primary cursor, or at the end of the row if it is shorter.
+    fn add_cursor(&mut self, up: bool) {
+        let ys = iter::once(&self.buf.cursor).chain(&self.buf.extra_cursors).map(|c| c.y);
+        let y = if up {
+            ys.min().and_then(|y| y.checked_sub(1))
         } else {
-            // self.rows[self.cursor.y] must exist, since cursor.x = 0 for any cursor.y ≥ row.len()
-            let new_chars = self.rows[self.cursor.y].chars.split_off(self.cursor.x);
-            self.update_row(self.cursor.y, false);
-            (self.cursor.y + 1, new_chars)
+            ys.max().map(|y| y + 1).filter(|y| *y < self.buf.rows.len())
         };
-        self.rows.insert(position, Row::new(new_row_chars));
-        self.update_row(position, false);
-        self.update_screen_cols();
-        self.cursor.move_to_next_line();
-        self.dirty = true;
+        let Some(y) = y else {
+            set_status!(self, "No row to add a cursor to");
+            return;
+        };
+        let row = &self.buf.rows[y];
+        let rx = self.buf.rx().min(row.rx2cx.len().saturating_sub(1));
+        let x = row.rx2cx.get(rx).copied().unwrap_or(row.chars.len());
+        self.buf.extra_cursors.push(CursorState { x, y, ..CursorState::default() });
+        set_status!(self, "{} cursors", self.buf.extra_cursors.len() + 1);
     }
 
-    /// Delete a character at the current cursor position. If the cursor is located at the beginning
-    /// of a row that is not the first or last row, merge the current row and the previous row. If
-    /// the cursor is located after the last row, move up to the last character of the previous row.
-    fn delete_char(&mut self) {
-        if self.cursor.x > 0 {
-            let row = &mut self.rows[self.cursor.y];
-            // Obtain the number of bytes to be removed: could be 1-4 (UTF-8 character size).
-            let n_bytes_to_remove = row.get_char_size(row.cx2rx[self.cursor.x] - 1);
-            row.chars.splice(self.curor.x - n_bytes_to_remove..self.cursor.x, iter::empty());
-            self.update_row(self.cursor.y, false);
-            self.cursor.x -= n_bytes_to_remove;
-            self.dirty = if self.is_empty() { self.fi.e_name.is_some() } else { true };
-            self.n_bytes -= n_bytes_to_remove as u64;
-        } else if self.cursor.y < self.rows.len() && self.cursor.y > 0 {
-            let row = self.rows.remove(self.cursor.y);
-            let previous_row = &mut self.rows[self.cursor.y - 1];
-            self.cursor.x = previous_row.chars.len();
-            previous_row.chars.extend(&row.chars);
-            self.update_row(self.cursor.y - 1, true);
-            self.update_row(self.cursor.y, false);
-            // The number of rows has changed. The left padding may need to be updated.
-            self.update_screen_cols();
-            self.dirty = true;
-            self.cursor.y -= 1;
-        } else if self.cursor.y == self.rows.len() {
-            // If the cursor is located after the last row, pressing backspace is equivalent to
-            // pressing the left arrow key.
-            self.move_cursor(&AKey::Left);
-        }
-    }
-
-    fn delete_current_row(mut self) {
-        if self.cursor.y < self.rows.len() {
-            self.rows[self.cursor.y].chars.clear();
-            self.update_row(self.cursor.y, false);
-            self.cursor.move_to_next_line();
-            self.delete_char();
-        }
-    }
-
-    fn duplicate_current_row(&mut self) {
-        if let Some(row) = self.current_row() {
-            let new_row = Row::new(row.chars.clone());
-            self.n_bytes += new_row.chars.len() as u64;
-            self.rows.insert(self.cursor.y + 1, new_row);
-            self.update_row(self.cursor.y + 1, false);
-            self.dirsty = true;
-            // The line number has changed
-            self.update_screen_cols();
+    /// Apply an edit at the primary cursor and at each additional cursor, starting from the end of
+    /// the document. Each edit may shift the rows and the columns of the cursors that follow it,
+    /// which are updated accordingly. Cursors ending at the same position are merged.
+    fn edit_at_cursors(&mut self, edit: &dyn Fn(&mut Self)) {
+        let extra_cursors = mem::take(&mut self.buf.extra_cursors);
+        let mut positions: Vec<_> =
+            iter::once(&self.buf.cursor).chain(&extra_cursors).map(|c| (c.y, c.x)).collect();
+        let mut order: Vec<_> = (0..positions.len()).collect();
+        order.sort_by_key(|i| Reverse(positions[*i]));
+        for (k, i) in order.iter().enumerate() {
+            let (old_y, old_x) = positions[*i];
+            (self.buf.cursor.y, self.buf.cursor.x) = (old_y, old_x);
+            edit(self);
+            let (new_y, new_x) = (self.buf.cursor.y, self.buf.cursor.x);
+            positions[*i] = (new_y, new_x);
+            for j in &order[..k] {
+                let (y, x) = &mut positions[*j];
+                if *y == old_y && *x >= old_x {
+                    // The cursor follows the text located after the edit
+                    (*y, *x) = (new_y, *x - old_x + new_x);
+                } else if *y > old_y {
+                    // Rows may have been merged
+                    *y -= old_y - new_y;
+                }
+            }
         }
+        (self.buf.cursor.y, self.buf.cursor.x) = positions[0];
+        let primary = positions.remove(0);
+        positions.sort_unstable();
+        positions.dedup();
+        self.buf.extra_cursors = positions.into_iter().filter(|p| *p != primary)
+            .map(|(y, x)| CursorState { x, y, ..CursorState::default() })
+            .collect();
     }
 
-    /// Try to load a file. If found, load the rows and update the render and syntax highlighting.
-    /// If not found, do not return an error.
-    fn load(&mut self, path: &Path) -> Result<(), Error> {
-        let ft = std::fs::metadata(path)?.file_type();
-        if !(ft.is_file() || ft.is_symlink()) {
-            return Err(io::Error::new(InvalidInput, "Invalid input file type").into());
+    /// Move the cursor and the row offset up or down by a screen, stopping at the first and last
+    /// rows. The cursor is moved to the rendered column `desired_rx` of the new row, or to the end
+    /// of the row if it is shorter.
+    fn move_page(&mut self, up: bool) {
+        let (n, n_rows) = (self.screen_rows.max(1), self.buf.rows.len());
+        let cursor = &mut self.buf.cursor;
+        if up {
+            cursor.y = cursor.y.saturating_sub(n);
+            cursor.roff = cursor.roff.saturating_sub(n);
+        } else {
+            cursor.y = (cursor.y + n).min(n_rows.saturating_sub(1));
+            cursor.roff = (cursor.roff + n).min(n_rows.saturating_sub(self.screen_rows));
+        }
+        self.buf.cursor.y = self.skip_folded(self.buf.cursor.y, up);
+        self.move_to_desired_column();
+    }
+
+    /// Move the cursor to the rendered column `desired_rx` of its row, or to the end of the row if
+    /// it is shorter. If `desired_rx` is in the middle of a wide character, move to that character.
+    fn move_to_desired_column(&mut self) {
+        if let Some(row) = self.buf.current_row() {
+            let rx = self.buf.cursor.desired_rx.min(row.rx2cx.len().saturating_sub(1));
+            self.buf.cursor.x = row.rx2cx.get(rx).copied().unwrap_or(row.chars.len());
         }
+    }
 
-        match File::open(path) {
-            Ok(file) => {
-                for line in BufReader::new(file).split(b'\n') {
-                    self.rows.push(Row::new(line?));
-                }
-                // If the file ends with an empty line or is empty, we need to append an empty row
-                // to `self.rows`. Unfortunately, BufReader::split doesn't yield an empty Vec in
-                // this case, so we need to check the last byte directly.
-                let mut file = File::open(path)?;
-                file.seek(io::SeekFrom::End(0))?;
-                if file.bytes().next().transpose()?.map_or(true, |b| b == b'\n') {
-                    self.rows.push(Row::new(Vec::new()));
+    /// When rows are wrapped, move the cursor to the previous or the next line of the screen,
+    /// keeping the same column if possible. Return `false` if the cursor is on the first line of
+    /// the first row or on the last line of the last row, in which case it is not moved.
+    fn move_cursor_wrapped(&mut self, up: bool) -> bool {
+        let (Some(row), width) = (self.buf.current_row(), self.screen_cols) else { return false };
+        let rx = row.cx2rx[self.buf.cursor.x];
+        let starts = row.wrap_columns(width);
+        let line = starts.iter().rposition(|start| *start <= rx).unwrap_or_default();
+        let col = rx - starts[line];
+        let next_y = self.skip_folded(self.buf.cursor.y + 1, false);
+        let (y, line) = match (up, line) {
+            (true, 0) if self.buf.cursor.y > 0 => {
+                (self.skip_folded(self.buf.cursor.y - 1, true), None)
+            }
+            (true, 0) => return false,
+            (true, line) => (self.buf.cursor.y, Some(line - 1)),
+            (false, line) if line + 1 < starts.len() => (self.buf.cursor.y, Some(line + 1)),
+            (false, _) if next_y < self.buf.rows.len() => (next_y, Some(0)),
+            (false, _) => return false,
+        };
+        let row = &self.buf.rows[y];
+        let starts = row.wrap_columns(width);
+        let line = line.unwrap_or(starts.len() - 1);
+        // Stay on the target line, unless it is the last line of the row
+        let end = starts.get(line + 1).map_or(row.rx2cx.len() - 1, |next| next - 1);
+        self.buf.cursor.y = y;
+        self.buf.cursor.x = row.rx2cx[(starts[line] + col).min(end)];
+        true
+    }
+
+    /// Move the cursor to the start of the word located before the cursor. At the beginning of a
+    /// row, move to the end of the last word of the previous row. Words are runs of word bytes (see
+    /// `is_word_byte`); since bytes of multi-byte characters are word bytes, the cursor never
+    /// lands in the middle of a character.
+    fn move_cursor_word_left(&mut self) {
+        if self.buf.cursor.x == 0 {
+            if self.buf.cursor.y > 0 {
+                self.buf.cursor.y = self.skip_folded(self.buf.cursor.y - 1, true);
+                let chars = &self.buf.rows[self.buf.cursor.y].chars;
+                self.buf.cursor.x =
+                    chars.iter().rposition(|b| is_word_byte(*b)).map_or(0, |i| i + 1);
+            }
+            return;
+        }
+        let (chars, mut x) = (&self.buf.rows[self.buf.cursor.y].chars, self.buf.cursor.x);
+        // Skip the non-word characters, then the word characters located before the cursor
+        while x > 0 && !is_word_byte(chars[x - 1]) {
+            x -= 1;
+        }
+        while x > 0 && is_word_byte(chars[x - 1]) {
+            x -= 1;
+        }
+        self.buf.cursor.x = x;
+    }
+
+    /// Move the cursor to the start of the word located after the cursor, or to the end of the
+    /// row. At the end of a row, move to the first word of the next row.
+    fn move_cursor_word_right(&mut self) {
+        let Some(row) = self.buf.current_row() else { return };
+        let (chars, mut x) = (&row.chars, self.buf.cursor.x);
+        if x >= chars.len() {
+            let next_y = self.skip_folded(self.buf.cursor.y + 1, false);
+            if let Some(next_row) = self.buf.rows.get(next_y) {
+                self.buf.cursor.x =
+                    next_row.chars.iter().position(|b| is_word_byte(*b)).unwrap_or(0);
+                self.buf.cursor.y = next_y;
+            }
+            return;
+        }
+        // Skip the word characters, then the non-word characters located after the cursor
+        while x < chars.len() && is_word_byte(chars[x]) {
+            x += 1;
+        }
+        while x < chars.len() && !is_word_byte(chars[x]) {
+            x += 1;
+        }
+        self.buf.cursor.x = x;
+    }
+
+    /// Update the cursor x position. If the cursor y position has changed, the current position
+    /// might be illegal (x is further right than the last character of the row). If that is the
+    /// case, clamp `self.buf.cursor.x`.
+    fn update_cursor_x_position(&mut self) {
+        let row_len = self.buf.current_row().map_or(0, |row| row.chars.len());
+        self.buf.cursor.x = self.buf.cursor.x.min(row_len);
+    }
+
+    /// Move the cursor to the start of the row, or to its first non-blank character if
+    /// `smart_home` is enabled and the cursor is not already there.
+    fn move_cursor_home(&mut self) {
+        let indent = self.buf.current_row().map_or(0, |row| indent_len(&row.chars));
+        let to_indent = self.config.smart_home && self.buf.cursor.x != indent;
+        self.buf.cursor.x = if to_indent { indent } else { 0 };
+        self.update_cursor_x_position();
+    }
+
+    /// Run a loop to obtain the key that was pressed. At each iteration of the loop (until a key is
+    /// pressed), we listen to the `ws_changed` channel to check if a window size change signal has
+    /// been received, and we check whether the file should be auto-saved. When bytes are received,
+    /// we match to a corresponding `Key` (see `read_key`); in debug mode, the bytes and the key are
+    /// shown in the message bar. If a termination signal has been received, return `None`.
+    fn loop_until_keypress(&mut self) -> Result<Option<Key>, Error> {
+        loop {
+            if sys::has_termination_signal() {
+                return Ok(None);
+            }
+            // Handle window size if a signal has be received
+            if sys::has_window_size_changed() {
+                self.update_window_size();
+                self.refresh_screen()?;
+            }
+            // Auto-save while waiting for a key, so that it also happens when the editor is idle
+            if self.autosave_if_due() {
+                self.refresh_screen()?;
+            }
+            self.update_swap_file();
+            // The bytes read are kept to be shown in debug mode
+            let mut raw = Vec::new();
+            let key = {
+                // Keys are read byte by byte as they arrive, so stdin must not be buffered
+                #[allow(clippy::unbuffered_bytes)]
+                let mut bytes = sys::stdin()?.bytes().inspect(|b| raw.extend(b.as_ref().ok()));
+                read_key(&mut bytes)?
+            };
+            if self.debug_keys && !raw.is_empty() {
+                let raw = raw.iter().flat_map(|b| ascii::escape_default(*b)).map(char::from);
+                set_status!(self, "{} => {:?}", raw.collect::<String>(), key);
+                self.refresh_screen()?;
+            }
+            if key.is_some() {
+                return Ok(key);
+            }
+        }
+    }
+
+    /// Query the size of the terminal, and update the attributes that depend on it. The size is
+    /// obtained from the terminal, then from the position of the cursor moved to the bottom-right
+    /// corner, then from the `LINES` and `COLUMNS` environment variables. If all of them fail, the
+    /// last known size is kept, or `DEFAULT_WINDOW_SIZE` is used at startup.
+    fn update_window_size(&mut self) { self.update_window_size_from(sys::get_window_size) }
+
+    /// Update the window size like `update_window_size`, with `terminal_size` returning the size
+    /// reported by the terminal as (rows, columns).
+    fn update_window_size_from<F>(&mut self, terminal_size: F)
+        where F: FnOnce() -> Result<(usize, usize), Error> {
+        let found = terminal_size().ok().filter(|(rows, cols)| *rows > 0 && *cols > 0)
+            .map(|wsize| (wsize, "the terminal"))
+            .or_else(|| {
+                let wsize = terminal::get_window_size_using_cursor().ok();
+                wsize.map(|wsize| (wsize, "the cursor position"))
+            })
+            .or_else(|| env_window_size().map(|wsize| (wsize, "$LINES and $COLUMNS")));
+        let last_wsize = (self.window_height, self.window_width);
+        let (wsize, source) = match found {
+            Some(found) => found,
+            None if last_wsize.0 > 0 => (last_wsize, "the last query"),
+            None => (DEFAULT_WINDOW_SIZE, "the default"),
+        };
+        self.window_size_source = source;
+        if self.debug_keys {
+            set_status!(self, "Window size {}x{} from {}", wsize.1, wsize.0, source);
+        }
+        self.set_window_size(wsize);
+    }
+
+    /// Update the `screen_rows`, `window_height`, `window_width`, `screen_cols` and `ln_padding`
+    /// attributes, given the window size as (rows, columns). If the screen is split, `screen_cols`
+    /// and `ln_padding` are those of the focused pane; the ones of the other pane are recomputed
+    /// when it is drawn. On windows with fewer than 3 rows, there is no room for the text.
+    fn set_window_size(&mut self, wsize: (usize, usize)) {
+        self.screen_rows = wsize.0.saturating_sub(2); // Make room for the status bar and status message
+        self.window_height = wsize.0;
+        self.window_width = wsize.1;
+        self.drawn_lines.clear();
+        self.update_screen_cols();
+    }
+
+    /// Update the `screen_cols` and `ln_padding` attributes based on the width of the focused pane
+    /// and the maximum number of digits for line numbers (since the left padding depends on this
+    /// number of digits).
+    fn update_screen_cols(&mut self) {
+        let (width, n_lines) = (self.focused_pane().0, self.n_lines());
+        (self.ln_pad, self.screen_cols) =
+            pane_columns(&self.config, width, self.screen_rows, n_lines);
+    }
+
+    /// Return the widths of the left and right panes. If the screen is not split, the left pane
+    /// spans the whole window. Otherwise, one column is used by the divider between the panes.
+    fn pane_widths(&self) -> (usize, usize) {
+        match self.split {
+            None => (self.window_width, 0),
+            Some(_) => {
+                let left = self.window_width.saturating_sub(1) / 2;
+                (left, self.window_width.saturating_sub(left + 1))
+            }
+        }
+    }
+
+    /// Return the width and the first column (0-indexed) of the left or right pane.
+    fn pane_position(&self, left: bool) -> (usize, usize) {
+        let (left_width, right_width) = self.pane_widths();
+        if left { (left_width, 0) } else { (right_width, left_width + 1) }
+    }
+
+    /// Return the width and the first column (0-indexed) of the focused pane.
+    fn focused_pane(&self) -> (usize, usize) {
+        self.pane_position(self.split.is_none_or(|split| split.focus_left))
+    }
+
+    /// Return the view of the active buffer in the focused pane.
+    fn pane(&self) -> Pane<'_> {
+        let (width, offset) = self.focused_pane();
+        Pane {
+            buf: &self.buf,
+            selection_anchor: self.selection_anchor,
+            first_row: self.first_row,
+            n_lines: self.n_lines(),
+            config: &self.config,
+            width,
+            offset,
+            screen_rows: self.screen_rows,
+            screen_cols: self.screen_cols,
+            ln_pad: self.ln_pad,
+        }
+    }
+
+    /// Split the screen and show the next buffer in the other pane, or remove the split.
+    fn toggle_split(&mut self) {
+        if self.split.take().is_none() {
+            if self.buffers.is_empty() {
+                set_status!(self, "Open at least two files to split the screen");
+                return;
+            }
+            let other = (self.active + 1) % (self.buffers.len() + 1);
+            self.split = Some(Split { other, focus_left: true });
+        }
+        self.update_screen_cols();
+    }
+
+    /// Move the focus to the left or right pane of the split screen.
+    fn focus_pane(&mut self, left: bool) {
+        if let Some(split) = self.split.filter(|s| s.focus_left != left) {
+            self.split = Some(Split { other: self.active, focus_left: left });
+            self.switch_buffer(split.other);
+        }
+    }
+
+    /// Show the next (or previous) buffer in the focused pane. If that buffer is shown in the
+    /// other pane of the split screen, the buffers of the two panes are exchanged.
+    fn cycle_buffers(&mut self, forward: bool) {
+        let n_buffers = self.buffers.len() + 1;
+        let index = (self.active + if forward { 1 } else { n_buffers - 1 }) % n_buffers;
+        if let Some(split) = self.split.as_mut().filter(|s| s.other == index) {
+            split.other = self.active;
+        }
+        self.switch_buffer(index);
+    }
+
+    /// Given a file path, try to find a syntax highlighting configuration that matches the path
+    /// extension in the `syntax.d` subdirectory of one of the config or data directories
+    /// (`~/.config/rust-text-editor/syntax.d`, `/usr/share/rust-text-editor/syntax.d`, etc.). If
+    /// such a configuration is found, set the `syntax` attribute of the editor. The rows are
+    /// rendered again if the language sets another tab stop.
+    fn select_syntax_highlight(&mut self, path: &Path) -> Result<(), Error> {
+        let extension = path.extension().and_then(std::ffi::OsStr::to_str);
+        if let Some(s) = extension.and_then(|e| SyntaxConf::get(e).transpose()) {
+            self.buf.syntax = s?;
+        }
+        if self.buf.tab_stop != self.tab_stop() {
+            self.update_all_rows();
+        }
+        Ok(())
+    }
+
+    /// Return the tab stop of the active buffer: the indentation size set by `.editorconfig`
+    /// files, or else the tab stop of the language, or else the configured tab stop.
+    fn tab_stop(&self) -> usize {
+        match self.buf.syntax.tab_stop {
+            Some(tab_stop) if self.overridden_config.indent_size.is_none() => tab_stop,
+            _ => self.config.tab_stop,
+        }
+    }
+
+    /// Render all the rows with the tab stop of the active buffer. Only the rows on the current
+    /// screen are highlighted; the following rows are highlighted when they are scrolled into
+    /// view.
+    fn update_all_rows(&mut self) {
+        self.buf.tab_stop = self.tab_stop();
+        self.buf.update_all_rows();
+        self.buf.highlight_rows(self.buf.cursor.roff + self.screen_rows);
+    }
+
+    /// Highlight the rows shown on the screen. If some rows are folded, these may extend further
+    /// than `screen_rows` rows below the first one.
+    fn highlight_screen_rows(&mut self) {
+        let end = self.pane().highlight_end();
+        self.buf.highlight_rows(end);
+    }
+
+    /// Insert a byte at the current cursor position. If there is no row at the current cursor
+    /// position, add a new row and insert the byte.
+    fn insert_byte(&mut self, c: u8) {
+        self.buf.insert_byte(c);
+        // The number of rows may have changed. The left padding may need to be updated.
+        self.update_screen_cols();
+    }
+
+    /// Insert text pasted in the terminal at the current cursor position, as is: the new lines are
+    /// not auto-indented. See `TextBuffer::insert_text`.
+    fn insert_text(&mut self, text: &[u8]) {
+        self.buf.insert_text(text);
+        // The number of rows may have changed. The left padding may need to be updated.
+        self.update_screen_cols();
+    }
+
+    /// Insert a new line at the current cursor position and move the cursor to the start of the new
+    /// line. If `auto_indent` is enabled, the new line starts with the leading whitespace located
+    /// before the cursor.
+    fn insert_new_line(&mut self) {
+        self.buf.insert_new_line(self.config.auto_indent);
+        // The number of rows has changed. The left padding may need to be updated.
+        self.update_screen_cols();
+    }
+
+    /// Insert generated text, such as an expanded snippet, at the current cursor position, as if it
+    /// was typed: tabs follow `soft_tabs`, and the new lines start with the indentation of the
+    /// current row, so that the text keeps its relative indentation.
+    fn insert_typed_text(&mut self, text: &str) {
+        let chars = self.buf.current_row().map_or(&[][..], |row| &row.chars[..]);
+        let indent = chars[..indent_len(chars)].to_vec();
+        for b in text.bytes() {
+            match b {
+                b'\n' => {
+                    self.buf.insert_new_line(false);
+                    indent.iter().for_each(|b| self.insert_byte(*b));
                 }
-                self.update_all_rows();
-                // The number of rows has changed. The left padding may need to be updated.
-                self.update_screen_cols();
-                self.n_bytes = self.rows.iter().map(|row| row.chars.len() as u64).sum();
+                b'\t' => self.insert_tab(),
+                b => self.insert_byte(b),
+            }
+        }
+    }
+
+    /// Replace the word located before the cursor with the template of the snippet it triggers.
+    fn expand_snippet(&mut self) {
+        let chars = self.buf.current_row().map_or(&[][..], |row| &row.chars[..self.buf.cursor.x]);
+        let n_bytes = chars.iter().rev().take_while(|b| snippets::is_trigger_byte(**b)).count();
+        let trigger = String::from_utf8_lossy(&chars[chars.len() - n_bytes..]).to_string();
+        match self.config.snippets.expand(&trigger, &self.config.date_format) {
+            Some(text) => {
+                (0..n_bytes).for_each(|_| self.delete_char());
+                self.insert_typed_text(&text);
+            }
+            None if trigger.is_empty() => set_status!(self, "No snippet trigger before the cursor"),
+            None => set_status!(self, "No snippet for {}", trigger),
+        }
+    }
+
+    /// Delete a character at the current cursor position, merging the current row and the previous
+    /// row at the beginning of a row. See `TextBuffer::delete_char`.
+    fn delete_char(&mut self) {
+        self.buf.delete_char();
+        // The number of rows may have changed. The left padding may need to be updated.
+        self.update_screen_cols();
+    }
+
+    /// Delete the character before the cursor, when Backspace is pressed. If `soft_tabs` is
+    /// enabled and the cursor is preceded by spaces up to the previous tab stop, they are all
+    /// deleted, as if they were a tab.
+    fn backspace(&mut self) {
+        let (rx, tab_stop) = (self.buf.rx(), self.tab_stop());
+        // The number of columns between the previous tab stop and the cursor
+        let n_cols = rx - rx.saturating_sub(1) / tab_stop * tab_stop;
+        let chars = self.buf.current_row().map_or(&[][..], |row| &row.chars[..self.buf.cursor.x]);
+        let n_spaces = chars.iter().rev().take_while(|c| **c == b' ').count();
+        let n_deleted = if self.config.soft_tabs && n_spaces >= n_cols { n_cols } else { 1 };
+        (0..n_deleted.max(1)).for_each(|_| self.delete_char());
+    }
+
+    /// Delete the word located before the cursor, as well as the non-word characters between this
+    /// word and the cursor. At the beginning of a row, merge the current row and the previous row,
+    /// like `delete_char`.
+    fn delete_word_left(&mut self) {
+        if self.buf.cursor.x == 0 {
+            return self.delete_char();
+        }
+        let end = self.buf.cursor.x;
+        self.move_cursor_word_left();
+        self.delete_in_current_row(self.buf.cursor.x..end);
+    }
+
+    /// Delete the non-word characters located after the cursor, as well as the following word. At
+    /// the end of a row, merge the next row into the current row, like the Delete key.
+    fn delete_word_right(&mut self) {
+        let Some(row) = self.buf.current_row() else { return };
+        if self.buf.cursor.x >= row.chars.len() {
+            self.move_cursor(&AKey::Right);
+            return self.delete_char();
+        }
+        let start = self.buf.cursor.x;
+        self.move_cursor_word_right();
+        let end = self.buf.cursor.x;
+        self.buf.cursor.x = start;
+        self.delete_in_current_row(start..end);
+    }
+
+    /// Delete a range of bytes of the current row, then update the row once.
+    fn delete_in_current_row(&mut self, range: Range<usize>) {
+        self.buf.rows[self.buf.cursor.y].chars.drain(range.clone());
+        self.buf.update_row(self.buf.cursor.y, false);
+        self.buf.n_bytes -= range.len() as u64;
+        self.buf.dirty = if self.buf.is_empty() { self.buf.file_name.is_some() } else { true };
+    }
+
+    /// Remove the row of the cursor. The cursor moves to the start of the next row, or of the
+    /// previous row if the last row is removed. The only row of a buffer is cleared instead.
+    fn delete_current_row(&mut self) {
+        let y = self.buf.cursor.y;
+        if y >= self.buf.rows.len() {
+            return;
+        }
+        self.buf.n_bytes -= self.buf.rows[y].chars.len() as u64;
+        if self.buf.rows.len() == 1 {
+            self.buf.rows[y].chars.clear();
+        } else {
+            self.buf.rows.remove(y);
+            // The following rows have moved up, and so has the watermark
+            self.buf.rows_removed(y, 1);
+        }
+        // The row that replaces the removed one may start in another highlight state
+        self.buf.update_row(y, false);
+        self.buf.cursor.y = y.min(self.buf.rows.len().saturating_sub(1));
+        self.buf.cursor.x = 0;
+        self.buf.dirty = true;
+        // The number of rows has changed. The left padding may need to be updated.
+        self.update_screen_cols();
+    }
+
+    /// Duplicate the row of the cursor, or the selected rows, below them (or above them if `up` is
+    /// true). The cursor stays on the original rows, except when a selection is duplicated below:
+    /// the cursor and the selection then move to the copy.
+    fn duplicate_rows(&mut self, up: bool) {
+        let selection = self.selected_rows();
+        let current_row = self.buf.current_row().map(|_| self.buf.cursor.y..self.buf.cursor.y + 1);
+        let Some(rows) = selection.clone().or(current_row).filter(|r| !r.is_empty()) else {
+            return;
+        };
+        let n_rows = rows.len();
+        self.insert_row_copies(rows.clone(), if up { rows.start } else { rows.end });
+        if up || selection.is_some() {
+            self.buf.cursor.y += n_rows;
+            self.selection_anchor = self.selection_anchor.map(|(y, x)| (y + n_rows, x));
+        }
+    }
+
+    /// Insert copies of the given rows before the row `y`, and update the rows from `y`.
+    fn insert_row_copies(&mut self, rows: Range<usize>, y: usize) {
+        let copies: Vec<_> =
+            self.buf.rows[rows].iter().map(|row| Row::new(row.chars.clone())).collect();
+        let n_rows = copies.len();
+        self.buf.n_bytes += copies.iter().map(|row| row.chars.len() as u64).sum::<u64>();
+        self.buf.rows.splice(y..y, copies);
+        // The following rows have moved down, and so has the watermark
+        self.buf.rows_inserted(y, n_rows);
+        for y in y..y + n_rows {
+            self.buf.update_row(y, false);
+        }
+        self.buf.dirty = true;
+        // The number of rows has changed. The left padding may need to be updated.
+        self.update_screen_cols();
+    }
+
+    /// Join the row of the cursor with the next one, or all the selected rows if there is a
+    /// selection, like `J` in vim. The whitespace around each line break is collapsed into a single
+    /// space, or removed if one of the joined rows is blank. The cursor is moved to the last join.
+    fn join_lines(&mut self) {
+        let rows = self.selected_rows().filter(|r| r.len() > 1);
+        let rows = rows.unwrap_or(self.buf.cursor.y..self.buf.cursor.y + 2);
+        let (y, end) = (rows.start, rows.end.min(self.buf.rows.len()));
+        if end <= y + 1 {
+            return;
+        }
+        let n_bytes_before: usize = self.buf.rows[y..end].iter().map(|row| row.chars.len()).sum();
+        let joined_rows: Vec<_> = self.buf.rows.drain(y + 1..end).collect();
+        // The following rows have moved up, and so has the watermark
+        self.buf.rows_removed(y + 1, end - y - 1);
+        let chars = &mut self.buf.rows[y].chars;
+        for row in joined_rows {
+            chars.truncate(chars.len() - trailing_whitespace_len(chars));
+            let indent = row.chars.iter().take_while(|c| matches!(c, b' ' | b'\t')).count();
+            self.buf.cursor.x = chars.len();
+            if !chars.is_empty() && indent < row.chars.len() {
+                chars.push(b' ');
+            }
+            chars.extend_from_slice(&row.chars[indent..]);
+        }
+        self.buf.n_bytes = self.buf.n_bytes + chars.len() as u64 - n_bytes_before as u64;
+        (self.buf.cursor.y, self.selection_anchor) = (y, None);
+        self.buf.update_row(y, false);
+        self.buf.dirty = true;
+        // The number of rows has changed. The left padding may need to be updated.
+        self.update_screen_cols();
+    }
+
+    /// Move the row of the cursor, or the selected rows, above the previous row (if `up` is true)
+    /// or below the next one. The cursor and the selection follow the moved rows.
+    fn move_lines(&mut self, up: bool) {
+        let rows = self.selected_rows().unwrap_or(self.buf.cursor.y..self.buf.cursor.y + 1);
+        if (up && rows.start == 0) || (!up && rows.end >= self.buf.rows.len()) {
+            return;
+        }
+        let affected = if up { rows.start - 1..rows.end } else { rows.start..rows.end + 1 };
+        if up {
+            self.buf.rows[affected.clone()].rotate_left(1);
+        } else {
+            self.buf.rows[affected.clone()].rotate_right(1);
+        }
+        let shift = |y: usize| if up { y - 1 } else { y + 1 };
+        self.buf.cursor.y = shift(self.buf.cursor.y);
+        self.selection_anchor = self.selection_anchor.map(|(y, x)| (shift(y), x));
+        // The highlight state of each affected row may have changed
+        for y in affected {
+            self.buf.update_row(y, false);
+        }
+        self.buf.dirty = true;
+    }
+
+    /// Return the range of selected rows, if there is a selection.
+    fn selected_rows(&self) -> Option<Range<usize>> { self.pane().selected_rows() }
+
+    /// Insert a tab at the cursor position. If `soft_tabs` is enabled, insert spaces up to the
+    /// next tab stop instead.
+    fn insert_tab(&mut self) {
+        if self.config.soft_tabs {
+            let n_spaces = self.tab_stop() - self.buf.rx() % self.tab_stop();
+            (0..n_spaces).for_each(|_| self.insert_byte(b' '));
+        } else {
+            self.insert_byte(b'\t');
+        }
+    }
+
+    /// Indent the given rows by `tab_stop` spaces.
+    fn indent_rows(&mut self, range: Range<usize>) {
+        let indent = vec![b' '; self.tab_stop()];
+        for y in range {
+            self.buf.rows[y].chars.splice(0..0, indent.iter().copied());
+            self.buf.update_row(y, false);
+            self.buf.n_bytes += indent.len() as u64;
+            if y == self.buf.cursor.y {
+                self.buf.cursor.x += indent.len();
+            }
+            self.buf.dirty = true;
+        }
+    }
+
+    /// Remove up to `tab_stop` leading spaces, or one leading tab, from each of the given rows.
+    fn dedent_rows(&mut self, range: Range<usize>) {
+        let tab_stop = self.tab_stop();
+        for y in range {
+            let chars = &mut self.buf.rows[y].chars;
+            let n_removed = if chars.first() == Some(&b'\t') {
+                1
+            } else {
+                chars.iter().take(tab_stop).take_while(|c| **c == b' ').count()
+            };
+            if n_removed == 0 {
+                continue;
+            }
+            chars.drain(..n_removed);
+            self.buf.update_row(y, false);
+            self.buf.n_bytes -= n_removed as u64;
+            if y == self.buf.cursor.y {
+                self.buf.cursor.x = self.buf.cursor.x.saturating_sub(n_removed);
+            }
+            self.buf.dirty = true;
+        }
+    }
+
+    /// Comment the given rows using the single-line comment token of the current syntax, or
+    /// uncomment them if they are all already commented. The token is inserted after the leading
+    /// indentation, and blank rows are left untouched.
+    fn toggle_comment(&mut self, range: Range<usize>) {
+        let Some(token) = self.buf.syntax.comment_token().map(|t| t.as_bytes().to_vec()) else {
+            set_status!(self, "No comment token defined for this file type");
+            return;
+        };
+        let is_blank = |chars: &[u8]| indent_len(chars) == chars.len();
+        let is_commented = |chars: &[u8]| chars[indent_len(chars)..].starts_with(&token);
+        let rows = &self.buf.rows[range.clone()];
+        let uncomment = rows.iter().any(|r| !is_blank(&r.chars))
+            && rows.iter().all(|r| is_blank(&r.chars) || is_commented(&r.chars));
+        for y in range {
+            let chars = &mut self.buf.rows[y].chars;
+            let start = indent_len(chars);
+            if start == chars.len() {
+                continue;
+            }
+            let (removed, inserted) = if uncomment {
+                // Also remove the space following the token, if any
+                let end = start + token.len();
+                let end = if chars.get(end) == Some(&b' ') { end + 1 } else { end };
+                chars.drain(start..end);
+                (end - start, 0)
+            } else {
+                chars.splice(start..start, token.iter().chain(b" ").copied());
+                (0, token.len() + 1)
+            };
+            self.buf.update_row(y, false);
+            self.buf.n_bytes = self.buf.n_bytes + inserted as u64 - removed as u64;
+            if y == self.buf.cursor.y && self.buf.cursor.x >= start {
+                let cursor = &mut self.buf.cursor;
+                cursor.x = (cursor.x + inserted).saturating_sub(removed).max(start);
+            }
+            self.buf.dirty = true;
+        }
+    }
+
+    /// Return the folded block containing row `y`, if any.
+    fn fold_at(&self, y: usize) -> Option<&Range<usize>> { self.pane().fold_at(y) }
+
+    /// Return the row to move the cursor to instead of row `y`, if it is folded: the row above the
+    /// folded block when moving up, or the row below it when moving down.
+    fn skip_folded(&self, y: usize, up: bool) -> usize {
+        match self.fold_at(y) {
+            Some(fold) if up => fold.start - 1,
+            Some(fold) => fold.end,
+            None => y,
+        }
+    }
+
+    /// Return the range of rows that can be folded below row `y`: the following rows that are more
+    /// indented than row `y`, and the blank rows between them. Return `None` if there are none.
+    fn foldable_rows(&self, y: usize) -> Option<Range<usize>> {
+        let indent = |row: &Row| Some(indent_len(&row.chars)).filter(|len| *len < row.chars.len());
+        let width = |row: &Row, len: usize| row.cx2rx[len];
+        let row = self.buf.rows.get(y)?;
+        let min_width = width(row, indent(row)?);
+        let mut end = y + 1;
+        for (i, row) in self.buf.rows.iter().enumerate().skip(y + 1) {
+            match indent(row) {
+                // Blank rows are only folded if they are followed by a folded row
+                None => continue,
+                Some(len) if width(row, len) <= min_width => break,
+                Some(_) => end = i + 1,
+            }
+        }
+        Some(y + 1..end).filter(|rows| !rows.is_empty())
+    }
+
+    /// Fold the block of rows below the row of the cursor that are more indented, or unfold it if
+    /// it is already folded.
+    fn toggle_fold(&mut self) {
+        let y = self.buf.cursor.y;
+        if let Some(i) = self.buf.folds.iter().position(|fold| fold.start == y + 1) {
+            self.buf.folds.remove(i);
+        } else if let Some(rows) = self.foldable_rows(y) {
+            // Nested folds are replaced
+            self.buf.folds.retain(|fold| !(rows.start <= fold.start && fold.end <= rows.end));
+            self.buf.folds.push(rows);
+        } else {
+            set_status!(self, "Nothing to fold below line {}", self.first_row + y + 1);
+        }
+    }
+
+    /// Move the cursor to the position entered in the GoTo prompt: `line[:column]`, where `line`
+    /// and `column` are 1-indexed, or `+N`/`-N` to move relative to row `from_y`. Out-of-range
+    /// values are clamped to the document bounds. An empty input does not move the cursor.
+    fn go_to(&mut self, input: &str, from_y: usize) -> Result<(), ParseIntError> {
+        let from_y = self.first_row + from_y;
+        let mut split = input.splitn(2, ':').map(str::trim);
+        let y = match split.next().unwrap_or_default() {
+            "" => return Ok(()),
+            line => match (line.strip_prefix('+'), line.strip_prefix('-')) {
+                (Some(n), _) => from_y.saturating_add(n.parse()?),
+                (_, Some(n)) => from_y.saturating_sub(n.parse()?),
+                // saturating_sub: Lines are 1-indexed
+                _ => line.parse::<usize>()?.saturating_sub(1),
+            },
+        };
+        let col = split.next().map(str::parse::<usize>).transpose()?;
+        let y = y.min(self.n_lines().saturating_sub(1));
+        // In paged mode, the target row may not be in memory
+        if !(self.first_row..self.first_row + self.buf.rows.len()).contains(&y) {
+            if let Err(err) = self.load_page(y) {
+                set_status!(self, Error: "Can't read the file! {:?}", err);
+                return Ok(());
             }
-            Err(e) if e.kind() == NotFound => self.rows.push(Row::new(Vec::new())),
-            Err(e) => return Err(e.into()),
         }
+        self.buf.cursor.y = y - self.first_row;
+        match (col, self.buf.current_row()) {
+            (Some(col), Some(row)) => {
+                let rx = col.saturating_sub(1).min(row.rx2cx.len() - 1);
+                self.buf.cursor.x = row.rx2cx[rx];
+            }
+            _ => self.update_cursor_x_position(),
+        }
+        self.buf.cursor.desired_rx = self.buf.rx();
         Ok(())
     }
 
-    /// Save the text to a file, given its name.
-    fn save(&self, file_name: &str) -> Result<usize, io::Error> {
-        let mut file = File::create(file_name)?;
-        let mut written = 0;
-        for (i, row) in self.rows.iter().enumerate() {
-            file.write_all(&row.chars)?;
-            written += row.chars.len();
-            if i != (self.rows.len() - 1) {
-                file.write_all(&[b'\n'])?;
-                written += 1;
+    /// Return the rows defining symbols according to the syntax configuration, as (line index in
+    /// the file, trimmed text of the row).
+    fn symbols(&self) -> Vec<(usize, String)> {
+        let rows = self.buf.rows.iter().enumerate();
+        rows.filter_map(|(y, row)| {
+            let text = String::from_utf8_lossy(&row.chars);
+            let is_symbol = self.buf.syntax.is_symbol(&text);
+            is_symbol.then(|| (self.first_row + y, text.trim().to_string()))
+        })
+        .collect()
+    }
+
+    /// Move the cursor to the start of the given line of the file, and scroll so that it is shown
+    /// in the middle of the screen.
+    fn center_on_line(&mut self, y: usize) {
+        if self.go_to(&(y + 1).to_string(), 0).is_ok() && self.buf.current_row().is_some() {
+            (self.buf.cursor.x, self.buf.cursor.desired_rx) = (0, 0);
+            self.buf.cursor.roff = self.buf.cursor.y.saturating_sub(self.screen_rows / 2);
+        }
+    }
+
+    /// Given the position of a bracket (one of `()[]{}`), return the position of the matching
+    /// bracket, taking nesting into account. Brackets that are part of a string or a comment are
+    /// ignored, unless they are located after the highlighting watermark. Return `None` if there is
+    /// no bracket at this position or if it has no match.
+    fn find_matching_bracket(&self, y: usize, x: usize) -> Option<(usize, usize)> {
+        const BRACKETS: &[u8; 6] = b"()[]{}";
+        let row = self.buf.rows.get(y)?;
+        let i = BRACKETS.iter().position(|b| Some(b) == row.chars.get(x))?;
+        if !row.is_code(x) {
+            return None;
+        }
+        // Opening brackets have an even index in BRACKETS, and we must search forward
+        let forward = i % 2 == 0;
+        let (nest, unnest) = (BRACKETS[i], BRACKETS[if forward { i + 1 } else { i - 1 }]);
+        let (mut cy, mut cx, mut depth) = (y, x, 0_usize);
+        loop {
+            let row = &self.buf.rows[cy];
+            match row.chars.get(cx) {
+                Some(c) if *c == nest && row.is_code(cx) => depth += 1,
+                Some(c) if *c == unnest && row.is_code(cx) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((cy, cx));
+                    }
+                }
+                _ => (),
+            }
+            if forward {
+                cx += 1;
+                while cx >= self.buf.rows[cy].chars.len() {
+                    (cy, cx) = (cy + 1, 0);
+                    if cy >= self.buf.rows.len() {
+                        return None;
+                    }
+                }
+            } else {
+                while cx == 0 {
+                    cy = cy.checked_sub(1)?;
+                    cx = self.buf.rows[cy].chars.len();
+                }
+                cx -= 1;
+            }
+        }
+    }
+
+    /// Update `bracket_pair` and the brackets highlighted in the rows, according to the cursor
+    /// position.
+    fn update_bracket_highlight(&mut self) {
+        for (y, _) in self.bracket_pair.take().into_iter().flatten() {
+            if let Some(row) = self.buf.rows.get_mut(y) {
+                row.bracket_rx.clear();
+            }
+        }
+        let (y, x) = (self.buf.cursor.y, self.buf.cursor.x);
+        if let Some(matching) = self.find_matching_bracket(y, x) {
+            let pair = [(y, x), matching];
+            for (y, x) in pair {
+                let row = &mut self.buf.rows[y];
+                let rx = row.cx2rx[x];
+                row.bracket_rx.push(rx);
+            }
+            self.bracket_pair = Some(pair);
+        }
+    }
+
+    /// Return the word containing the cursor, or ending at the cursor, if any.
+    fn word_at_cursor(&self) -> Option<Vec<u8>> {
+        let (chars, x) = (&self.buf.current_row()?.chars, self.buf.cursor.x);
+        let start = chars[..x].iter().rposition(|b| !is_word_byte(*b)).map_or(0, |i| i + 1);
+        let end = chars[x..].iter().position(|b| !is_word_byte(*b)).map_or(chars.len(), |i| x + i);
+        (start < end).then(|| chars[start..end].to_vec())
+    }
+
+    /// Update the occurrences of the word under the cursor underlined in the rows shown on the
+    /// screen. They are cleared in all the rows when the word changes, and only searched in the
+    /// rows that were not searched since they were last rendered.
+    fn update_word_highlight(&mut self) {
+        let word = if self.config.highlight_word { self.word_at_cursor() } else { None };
+        if word != self.highlighted_word {
+            self.buf.rows.iter_mut().for_each(|row| row.word_matches = None);
+            self.highlighted_word = word;
+        }
+        let Some(word) = &self.highlighted_word else { return };
+        let opts = FindOptions { whole_word: true, ..FindOptions::default() };
+        for (y, _) in self.screen_lines() {
+            let row = &mut self.buf.rows[y];
+            if row.word_matches.is_some() {
+                continue;
+            }
+            let (mut matches, mut start) = (Vec::new(), 0);
+            while let Some(i) = slice_find_with(&row.chars[start..], word, opts) {
+                let (cx, end) = (start + i, start + i + word.len());
+                matches.push(row.cx2rx[cx]..row.cx2rx[end]);
+                start = end;
+            }
+            row.word_matches = Some(matches);
+        }
+    }
+
+    /// Count the matches of `query` in the whole document, unless they were already counted for
+    /// the same query and options. The count stops after `MAX_COUNTED_MATCHES` matches or
+    /// `MAX_COUNT_DURATION`.
+    fn update_match_count(&mut self, query: &str, opts: FindOptions) {
+        let search = (String::from(query), opts);
+        if self.match_count.as_ref().is_some_and(|count| count.search == search) {
+            return;
+        }
+        let (find_matches, start) = (search_matcher(query, opts), Instant::now());
+        let (mut positions, mut truncated) = (Vec::new(), false);
+        for (y, row) in self.buf.rows.iter().enumerate() {
+            if positions.len() > MAX_COUNTED_MATCHES || start.elapsed() > MAX_COUNT_DURATION {
+                truncated = true;
+                break;
+            }
+            positions.extend(find_matches(&row.chars).into_iter().map(|m| (y, m.start)));
+        }
+        truncated |= positions.len() > MAX_COUNTED_MATCHES;
+        positions.truncate(MAX_COUNTED_MATCHES);
+        self.match_count = Some(MatchCount { search, positions, truncated });
+    }
+
+    /// Update the matches of the Find query highlighted in the rows shown on the screen. The
+    /// highlights of the previous update are cleared, in particular once the prompt is closed.
+    fn update_search_highlight(&mut self) {
+        for y in self.search_rows.drain(..) {
+            if let Some(row) = self.buf.rows.get_mut(y) {
+                row.search_matches.clear();
+            }
+        }
+        let Some((query, opts)) = &self.search else { return };
+        let find_matches = search_matcher(query, *opts);
+        let mut rows: Vec<usize> = self.screen_lines().into_iter().map(|(y, _)| y).collect();
+        rows.dedup();
+        for y in rows {
+            let row = &mut self.buf.rows[y];
+            let matches = find_matches(&row.chars).into_iter();
+            row.search_matches = matches.map(|m| row.cx2rx[m.start]..row.cx2rx[m.end]).collect();
+            self.search_rows.push(y);
+        }
+    }
+
+    /// Try to load a file. If found, load the rows and update the render and syntax highlighting.
+    /// Return `Error::FileNotFound` if the file does not exist, and `Error::NotARegularFile` if it
+    /// is e.g. a directory.
+    fn load(&mut self, path: &Path) -> Result<(), Error> {
+        let metadata = std::fs::metadata(path).map_err(|err| match err.kind() {
+            NotFound => Error::FileNotFound(path.to_path_buf()),
+            _ => err.into(),
+        })?;
+        if !metadata.is_file() {
+            return Err(Error::NotARegularFile(path.to_path_buf()));
+        }
+        (self.paged, self.first_row) = (None, 0);
+        if metadata.len() >= self.config.large_file_size.saturating_mul(1 << 20) {
+            return self.load_paged(path);
+        }
+
+        let file = File::open(path)?;
+        let (mut last_update, mut n_read) = (Instant::now(), 0);
+        for (i, line) in BufReader::new(file).split(b'\n').enumerate() {
+            let line = line?;
+            n_read += line.len() as u64 + 1;
+            // Checking the time of every line would slow the loading down
+            if i % 1024 == 0 {
+                let percent = n_read * 100 / metadata.len().max(1);
+                self.show_progress(&mut last_update, || format!("Loading... {}%", percent));
             }
+            self.buf.rows.push(Row::new(line));
+        }
+        // If the file ends with an empty line or is empty, we need to append an empty row
+        // to `self.buf.rows`. Unfortunately, BufReader::split doesn't yield an empty Vec in
+        // this case, so we need to check the last byte directly.
+        let mut file = File::open(path)?;
+        let last_byte = if file.metadata()?.len() == 0 {
+            None
+        } else {
+            let mut last_byte = [0_u8];
+            file.seek(io::SeekFrom::End(-1))?;
+            file.read_exact(&mut last_byte)?;
+            Some(last_byte[0])
+        };
+        if last_byte.is_none_or(|b| b == b'\n') {
+            self.buf.rows.push(Row::new(Vec::new()));
+        }
+        self.mtime = std::fs::metadata(path)?.modified().ok();
+        self.finish_loading();
+        self.restore_cursor_position(path);
+        Ok(())
+    }
+
+    /// Open a file in paged mode: only the rows around the visible ones are kept in memory, and
+    /// editing is disabled.
+    fn load_paged(&mut self, path: &Path) -> Result<(), Error> {
+        let (mut last_update, len) = (Instant::now(), std::fs::metadata(path)?.len().max(1));
+        let paged = PagedFile::open(path, &mut |n_read| {
+            self.show_progress(&mut last_update, || format!("Indexing... {}%", n_read * 100 / len));
+        })?;
+        self.paged = Some(paged);
+        self.load_page(0)?;
+        self.mtime = std::fs::metadata(path)?.modified().ok();
+        set_status!(self, Warning: "Large file opened in paged mode: editing is disabled");
+        Ok(())
+    }
+
+    /// In paged mode, replace the rows in memory with the rows around the row `top` of the file.
+    /// The cursor and the row offset keep the same positions in the file, if possible.
+    fn load_page(&mut self, top: usize) -> Result<(), Error> {
+        let Some(paged) = &self.paged else { return Ok(()) };
+        let start = top.saturating_sub(PAGE_MARGIN);
+        let lines = paged.read_lines(start..top + self.screen_rows + PAGE_MARGIN)?;
+        let (y, roff) = (self.first_row + self.buf.cursor.y, self.first_row + self.buf.cursor.roff);
+        self.buf.rows = lines.into_iter().map(Row::new).collect();
+        self.first_row = start;
+        self.buf.cursor.y = y.saturating_sub(start).min(self.buf.rows.len().saturating_sub(1));
+        self.buf.cursor.roff = roff.saturating_sub(start);
+        self.update_cursor_x_position();
+        self.finish_loading();
+        Ok(())
+    }
+
+    /// In paged mode, load the rows around the visible ones when the screen gets close to the
+    /// first or the last row in memory.
+    fn update_page(&mut self) {
+        let Some(n_lines) = self.paged.as_ref().map(PagedFile::n_lines) else { return };
+        let (roff, n_rows) = (self.buf.cursor.roff, self.buf.rows.len());
+        let near_start = self.first_row > 0 && roff < PAGE_MARGIN / 2;
+        let near_end =
+            self.first_row + n_rows < n_lines && roff + self.screen_rows + PAGE_MARGIN / 2 > n_rows;
+        if near_start || near_end {
+            if let Err(err) = self.load_page(self.first_row + roff) {
+                set_status!(self, Error: "Can't read the file! {:?}", err);
+            }
+        }
+    }
+
+    /// Open a file in the active buffer: select the syntax highlighting based on its extension,
+    /// load its rows and its modification time, and move the cursor to its position when the file
+    /// was last closed. If a swap file more recent than the file is found, the user is asked
+    /// whether to recover it. The properties of the `.editorconfig` files applying to the file
+    /// override the configuration while the buffer is active.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Error::FileNotFound` if the file does not exist, `Error::NotARegularFile` if it
+    /// is e.g. a directory, and `Err` if the file or the configuration files cannot be read.
+    pub fn open(&mut self, path: &Path) -> Result<(), Error> {
+        self.select_syntax_highlight(path)?;
+        // Project settings take precedence over the user configuration
+        let properties = Properties::find(path)?;
+        mem::take(&mut self.overridden_config).apply(&mut self.config);
+        self.overridden_config = properties.apply(&mut self.config);
+        self.load(path)?;
+        if let Some(line_ending) = properties.line_ending {
+            self.buf.line_ending = line_ending;
+        }
+        self.buf.file_name = Some(path.to_string_lossy().to_string());
+        self.store_recent_file(path);
+        if let Some(swap) = self.newer_swap_file().filter(|_| !self.read_only) {
+            self.prompt_mode = Some(PromptMode::RecoverSwap(swap));
+        }
+        Ok(())
+    }
+
+    /// Open a file given on the command line. If its name ends with a `:line` or `:line:column`
+    /// suffix, the suffix is removed and the cursor is moved to this position.
+    fn open_at_position(&mut self, file_name: &str) -> Result<(), Error> {
+        let (file_name, position) = split_file_position(file_name);
+        self.open(&sys::path(file_name))?;
+        if let Some(Err(e)) = position.map(|position| self.go_to(position, 0)) {
+            set_status!(self, Error: "Parsing error: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Exchange the state of the active buffer, stored in the editor fields, with `buffer`.
+    fn swap_buffer_state(&mut self, buffer: &mut Buffer) {
+        mem::swap(&mut self.buf, &mut buffer.buf);
+        mem::swap(&mut self.selection_anchor, &mut buffer.selection_anchor);
+        mem::swap(&mut self.bracket_pair, &mut buffer.bracket_pair);
+        mem::swap(&mut self.last_save, &mut buffer.last_save);
+        mem::swap(&mut self.swap_written, &mut buffer.swap_written);
+        mem::swap(&mut self.mtime, &mut buffer.mtime);
+        mem::swap(&mut self.paged, &mut buffer.paged);
+        mem::swap(&mut self.first_row, &mut buffer.first_row);
+    }
+
+    /// Make the buffer with the given index the active buffer.
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.active || index > self.buffers.len() {
+            return;
+        }
+        // The occurrences of the word under the cursor are searched again after switching back
+        self.buf.rows.iter_mut().for_each(|row| row.word_matches = None);
+        self.highlighted_word = None;
+        let mut current = Buffer::default();
+        self.swap_buffer_state(&mut current);
+        // Restore the user configuration; the overriding values are kept with the buffer
+        current.project_config = mem::take(&mut self.overridden_config).apply(&mut self.config);
+        self.buffers.insert(self.active, current);
+        let mut buffer = self.buffers.remove(index);
+        self.swap_buffer_state(&mut buffer);
+        self.overridden_config = buffer.project_config.apply(&mut self.config);
+        self.active = index;
+        // The rows may have been rendered with another tab stop
+        if self.buf.tab_stop != self.tab_stop() {
+            self.update_all_rows();
+        }
+        // The number of rows may be different. The left padding may need to be updated.
+        self.update_screen_cols();
+    }
+
+    /// Load text that has been piped to the standard input. The buffer is not associated with any
+    /// file, so that the first save prompts for a file name.
+    pub fn load_text(&mut self, text: &[u8]) {
+        self.buf.file_name = None;
+        self.load_rows(text);
+    }
+
+    /// Return the content of the document, with rows separated by the detected line ending. A
+    /// document loaded from a file ending with a new line ends with an empty row, so the text also
+    /// ends with a new line. Bytes that are not valid UTF-8 are replaced with `U+FFFD`.
+    pub fn text(&self) -> String { self.buf.text() }
+
+    /// Replace the content of the document with `text`, and move the cursor to the beginning. Line
+    /// endings are detected as when loading a file: if most lines end with `\r\n`, all of them
+    /// will be saved with `\r\n`. The file name is kept, and the document is considered modified.
+    pub fn set_text(&mut self, text: &str) {
+        (self.paged, self.first_row) = (None, 0);
+        self.load_rows(text.as_bytes());
+        (self.buf.cursor, self.selection_anchor) = (CursorState::default(), None);
+        self.buf.dirty = true;
+    }
+
+    /// Replace the rows with the lines of `text`.
+    fn load_rows(&mut self, text: &[u8]) {
+        self.buf.load_rows(text);
+        self.finish_loading();
+    }
+
+    /// Read all the text piped to the standard input, then reopen the controlling terminal as the
+    /// standard input so that key presses can be read. This must be called before `Editor::new`,
+    /// since raw mode can only be enabled on a terminal.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the standard input cannot be read or the terminal cannot be reopened.
+    pub fn read_stdin() -> Result<Vec<u8>, Error> {
+        let mut text = Vec::new();
+        io::stdin().read_to_end(&mut text)?;
+        sys::reopen_stdin_from_tty()?;
+        Ok(text)
+    }
+
+    /// Update the editor state after rows have been loaded: line endings, rendering, left padding
+    /// and number of bytes. If the rows are not valid UTF-8, a status message is shown.
+    fn finish_loading(&mut self) {
+        self.buf.finish_loading();
+        self.update_all_rows();
+        // The number of rows has changed. The left padding may need to be updated.
+        self.update_screen_cols();
+        let invalid_utf8 = |row: &Row| std::str::from_utf8(&row.chars).is_err();
+        if let Some(y) = self.buf.rows.iter().position(invalid_utf8) {
+            let msg = "invalid bytes are shown as \\xNN";
+            set_status!(self, Warning: "Invalid UTF-8 on line {}: {}", y + 1, msg);
+        }
+    }
+
+    /// Convert the line endings of the file between LF and CRLF.
+    fn toggle_line_ending(&mut self) {
+        self.buf.line_ending = match self.buf.line_ending {
+            LineEnding::Lf => LineEnding::CrLf,
+            LineEnding::CrLf => LineEnding::Lf,
+        };
+        self.buf.dirty = true;
+        set_status!(self, "Line endings converted to {}", self.buf.line_ending.name());
+    }
+
+    /// Return the path of the state file with the given name, if the state is persisted.
+    fn state_file(&self, name: &str) -> Option<PathBuf> {
+        self.state_dir.as_ref().map(|dir| dir.join(name))
+    }
+
+    /// If `remember_cursor` is enabled, move the cursor to the position stored for this file in
+    /// the state file, if any. The position is clamped in case the file has been modified.
+    fn restore_cursor_position(&mut self, path: &Path) {
+        let state_path = self.state_file(state::CURSOR_POSITIONS);
+        let (Some(state_path), Ok(path), true) =
+            (state_path, std::fs::canonicalize(path), self.config.remember_cursor)
+        else {
+            return;
+        };
+        let path = path.to_string_lossy();
+        if let Ok(Some((y, x))) = state::load_cursor_position(&state_path, &path) {
+            self.buf.cursor.y = y.min(self.buf.rows.len());
+            self.buf.cursor.x = x;
+            self.update_cursor_x_position();
+        }
+    }
+
+    /// If `remember_cursor` is enabled, store the cursor position for the current file in the
+    /// state file. Errors are ignored, since they should not prevent saving or quitting.
+    fn store_cursor_position(&self) {
+        let state_path = self.state_file(state::CURSOR_POSITIONS);
+        let path = self.buf.file_name.as_ref().map(std::fs::canonicalize);
+        if let (Some(state_path), Some(Ok(path)), true) =
+            (state_path, path, self.config.remember_cursor)
+        {
+            let pos = (self.first_row + self.buf.cursor.y, self.buf.cursor.x);
+            let _ = state::store_cursor_position(&state_path, &path.to_string_lossy(), pos);
+        }
+    }
+
+    /// Store the queries of the Find prompt in the state file. Errors are ignored, since they
+    /// should not prevent quitting.
+    fn store_find_history(&self) {
+        if let Some(state_path) = self.state_file(state::FIND_HISTORY) {
+            let _ = state::store_history(&state_path, &self.find_history.entries);
+        }
+    }
+
+    /// If `recent_files` is not zero, store the file as the most recently opened one in the state
+    /// file. Errors are ignored, since they should not prevent opening the file.
+    fn store_recent_file(&self, path: &Path) {
+        let state_path = self.state_file(state::RECENT_FILES);
+        let (path, max_files) = (std::fs::canonicalize(path), self.config.recent_files);
+        if let (Some(state_path), Ok(path), 1..) = (state_path, path, max_files) {
+            let _ = state::store_recent_file(&state_path, &path.to_string_lossy(), max_files);
+        }
+    }
+
+    /// Return the recently opened files that still exist, from the most to the least recent,
+    /// except the file of the active buffer.
+    fn recent_files(&self) -> Vec<String> {
+        let state_path = self.state_file(state::RECENT_FILES);
+        let files = state_path.and_then(|p| state::read_recent_files(&p).ok()).unwrap_or_default();
+        let current = self.buf.file_name.as_ref().and_then(|f| std::fs::canonicalize(f).ok());
+        let is_current = |f: &String| current.as_ref().is_some_and(|c| c == Path::new(f));
+        files.into_iter().rev().filter(|f| Path::new(f).is_file() && !is_current(f)).collect()
+    }
+
+    /// Replace the active buffer with the given file. If the buffer has unsaved changes, or if the
+    /// file cannot be opened, the buffer is kept and a message is shown.
+    fn open_in_active_buffer(&mut self, file_name: &str) {
+        if self.buf.dirty {
+            set_status!(self, Warning: "Unsaved changes! Save or revert the buffer first");
+            return;
+        }
+        self.store_cursor_position();
+        let mut previous = Buffer::default();
+        self.swap_buffer_state(&mut previous);
+        previous.project_config = mem::take(&mut self.overridden_config).apply(&mut self.config);
+        if let Err(err) = self.open(&sys::path(file_name)) {
+            mem::take(&mut self.overridden_config).apply(&mut self.config);
+            self.swap_buffer_state(&mut previous);
+            self.overridden_config = previous.project_config.apply(&mut self.config);
+            self.update_all_rows();
+            set_status!(self, Error: "Can't open {}: {:?}", file_name, err);
+        }
+        self.update_screen_cols();
+    }
+
+    /// Save the text to a file, given its name. Rows are separated by line endings. When a file
+    /// ending with a line ending is loaded, an empty last row is appended; it is written back as
+    /// nothing, so that round-tripping a file does not add or remove lines. If
+    /// `insert_final_newline` is enabled and the last row is not empty, a line ending is written
+    /// after it.
+    ///
+    /// I/O errors are returned as `Error::Save`, together with the path of the file.
+    fn save(&self, file_name: &str) -> Result<SaveOutcome, Error> {
+        self.save_rows(file_name, 0..self.buf.rows.len())
+    }
+
+    /// Same as `save`, for the given range of rows only. Each row is followed by a line ending,
+    /// except the last row of the document.
+    fn save_rows(&self, file_name: &str, rows: Range<usize>) -> Result<SaveOutcome, Error> {
+        let path = PathBuf::from(file_name);
+        let write = || -> Result<usize, io::Error> {
+            let mut file = File::create(file_name)?;
+            let mut written = 0;
+            for (i, row) in self.buf.rows.iter().enumerate().take(rows.end).skip(rows.start) {
+                let chars = self.saved_chars(row);
+                file.write_all(&chars)?;
+                written += chars.len();
+                let is_last_row = i == self.buf.rows.len() - 1;
+                if !is_last_row || (self.config.insert_final_newline && !chars.is_empty()) {
+                    file.write_all(self.buf.line_ending.as_bytes())?;
+                    written += self.buf.line_ending.as_bytes().len();
+                }
+            }
+            file.sync_all()?;
+            Ok(written)
+        };
+        match write() {
+            Ok(bytes_written) => Ok(SaveOutcome { bytes_written, path }),
+            Err(err) => Err(Error::Save(path, err)),
+        }
+    }
+
+    /// Return the characters of a row as they are written to the file, i.e. with tabs expanded
+    /// into spaces if `expand_tabs_on_save` is enabled, and without trailing whitespace if
+    /// `trim_trailing_whitespace_on_save` is enabled.
+    fn saved_chars<'a>(&self, row: &'a Row) -> Cow<'a, [u8]> {
+        let mut chars = Cow::Borrowed(&row.chars[..]);
+        if self.config.expand_tabs_on_save && row.chars.contains(&b'\t') {
+            chars = Cow::Owned(row.expanded_tabs());
+        }
+        if self.config.trim_trailing_whitespace_on_save {
+            let len = chars.len() - trailing_whitespace_len(&chars);
+            match &mut chars {
+                Cow::Borrowed(c) => *c = &c[..len],
+                Cow::Owned(c) => c.truncate(len),
+            }
+        }
+        chars
+    }
+
+    /// Remove the trailing spaces and tabs of every row of the buffer.
+    fn trim_trailing_whitespace(&mut self) {
+        for y in 0..self.buf.rows.len() {
+            let row = &mut self.buf.rows[y];
+            let n_trailing = trailing_whitespace_len(&row.chars);
+            if n_trailing > 0 {
+                row.chars.truncate(row.chars.len() - n_trailing);
+                self.buf.n_bytes -= n_trailing as u64;
+                self.buf.update_row(y, false);
+            }
+        }
+        self.update_cursor_x_position();
+    }
+
+    /// Replace the tabs of the buffer with spaces, up to the next tab stop. The cursor is kept on
+    /// the same rendered column.
+    fn retab(&mut self) {
+        let rx = self.buf.rx();
+        let mut n_replaced = 0;
+        for row in self.buf.rows.iter_mut().filter(|row| row.chars.contains(&b'\t')) {
+            row.chars = row.expanded_tabs();
+            n_replaced += 1;
+        }
+        if n_replaced > 0 {
+            self.update_all_rows();
+            self.buf.n_bytes = self.buf.rows.iter().map(|row| row.chars.len() as u64).sum();
+            self.buf.cursor.x = self.buf.current_row().map_or(0, |row| row.rx2cx[rx]);
+            self.buf.dirty = true;
+        }
+        set_status!(self, "Tabs replaced in {} row(s)", n_replaced);
+    }
+
+    /// Return whether the file has been modified on disk by another program since it was loaded or
+    /// last saved. Always return `false` for files other than the current one.
+    fn modified_on_disk(&self, file_name: &str) -> bool {
+        let is_current = self.buf.file_name.as_deref() == Some(file_name);
+        let Some(mtime) = self.mtime.filter(|_| is_current) else {
+            return false;
+        };
+        std::fs::metadata(file_name).and_then(|m| m.modified()).is_ok_and(|t| t > mtime)
+    }
+
+    /// Save the text of the active buffer to a file, without checking whether the file has been
+    /// modified on disk. If successful, the buffer is no longer considered modified and its swap
+    /// file is removed. The file name of the buffer is left unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Error::Save` if the file cannot be written; the kind of the wrapped
+    /// `io::Error` tells, for instance, a permission error from a full disk.
+    pub fn save_file(&mut self, file_name: &str) -> Result<SaveOutcome, Error> {
+        let conf = &self.config;
+        if conf.trim_trailing_whitespace_on_save && conf.trim_trailing_whitespace_in_buffer {
+            self.trim_trailing_whitespace();
+        }
+        let outcome = self.save(file_name)?;
+        // If save was successful, set dirty to false.
+        self.buf.dirty = false;
+        self.mtime = std::fs::metadata(file_name).and_then(|m| m.modified()).ok();
+        self.store_cursor_position();
+        self.remove_swap_file();
+        Ok(outcome)
+    }
+
+    /// Save the text to a file and handle all errors. Errors and success messages will be printed
+    /// to the status bar. Return whether the file was successfully saved. If the file has been
+    /// modified on disk since it was loaded, it is not saved: instead, the user is asked to confirm
+    /// that it should be overwritten.
+    fn save_and_handle_io_errors(&mut self, file_name: &str) -> bool {
+        self.last_save = Some(Instant::now());
+        if self.modified_on_disk(file_name) {
+            if self.prompt_mode.is_none() {
+                let question = format!("{} has been modified on disk. Overwrite it?", file_name);
+                let action = ConfirmAction::Overwrite(String::from(file_name));
+                self.prompt_mode = Some(PromptMode::Confirm(question, action));
+            }
+            set_status!(self, "{} has been modified on disk since it was loaded", file_name);
+            return false;
+        }
+        let saved = self.save_file(file_name);
+        self.set_save_status(&saved, file_name);
+        saved.is_ok()
+    }
+
+    /// Filter the selected rows, or all the rows if there is no selection, through a command:
+    /// the rows are written to its standard input, and replaced with its standard output. If the
+    /// command fails or does not finish within `PIPE_TIMEOUT`, the text is unchanged.
+    fn pipe_rows(&mut self, command: &str) {
+        let n_rows = self.buf.rows.len();
+        let mut rows = self.selected_rows().unwrap_or(0..n_rows);
+        // An empty last row stands for the line ending at the end of the file
+        if rows.end == n_rows && self.buf.rows.last().is_some_and(|row| row.chars.is_empty()) {
+            rows.end -= 1;
+        }
+        let mut input = Vec::new();
+        for row in &self.buf.rows[rows.clone()] {
+            input.extend_from_slice(&row.chars);
+            input.push(b'\n');
+        }
+        let mut last_update = Instant::now();
+        let mut progress = |elapsed: Duration| {
+            let frame = SPINNER[(elapsed.as_millis() / PROGRESS_INTERVAL.as_millis()) as usize % 4];
+            self.show_progress(&mut last_update, || format!("Running {}... {}", command, frame));
+        };
+        let mut output = match run_filter(command, input, PIPE_TIMEOUT, &mut progress) {
+            Ok(output) => output,
+            Err(err) => {
+                set_status!(self, Error: "{}", err);
+                return;
+            }
+        };
+        if output.last() == Some(&b'\n') {
+            output.pop();
+        }
+        let new_rows: Vec<_> = if output.is_empty() && rows.end < n_rows {
+            Vec::new()
+        } else {
+            output.split(|b| *b == b'\n').map(|line| Row::new(line.to_vec())).collect()
+        };
+        let n_lines = new_rows.len();
+        self.buf.rows.splice(rows.clone(), new_rows);
+        self.buf.folds.retain(|fold| fold.end <= rows.start);
+        self.update_all_rows();
+        self.buf.n_bytes = self.buf.rows.iter().map(|row| row.chars.len() as u64).sum();
+        (self.buf.cursor.y, self.buf.cursor.x) = (rows.start.min(self.buf.rows.len() - 1), 0);
+        (self.selection_anchor, self.buf.dirty) = (None, true);
+        self.update_screen_cols();
+        set_status!(self, "{} lines replaced with {} lines", rows.len(), n_lines);
+    }
+
+    /// Run a command with the shell of the user, and return its output. The terminal is switched
+    /// back to its original mode and to the main screen while the command runs, so that it can read
+    /// from the terminal, e.g. a password. Its standard output and standard error are captured,
+    /// though: a command that draws on the terminal, such as a pager or an editor, shows nothing
+    /// and may seem to hang while waiting for input.
+    fn run_shell_command(&mut self, command: &str) -> Result<std::process::Output, Error> {
+        if let Some(orig_term_mode) = &self.orig_term_mode {
+            if self.config.mouse {
+                print!("{}", DISABLE_MOUSE_REPORTING);
+            }
+            if self.alternate_screen {
+                print!("{}", LEAVE_ALTERNATE_SCREEN);
+            }
+            print!("{}", DISABLE_BRACKETED_PASTE);
+            io::stdout().flush()?;
+            sys::set_term_mode(orig_term_mode)?;
+        }
+        let output = sys::shell_command(command).stdin(Stdio::inherit()).output();
+        // Ctrl-C interrupts the command, not the editor
+        sys::clear_interrupt_signal();
+        if self.orig_term_mode.is_some() {
+            self.orig_term_mode = Some(sys::enable_raw_mode()?);
+            if self.alternate_screen {
+                print!("{}", ENTER_ALTERNATE_SCREEN);
+            }
+            print!("{}", ENABLE_BRACKETED_PASTE);
+            self.drawn_lines.clear();
+            if self.config.mouse {
+                print!("{}", ENABLE_MOUSE_REPORTING);
+            }
+        }
+        Ok(output?)
+    }
+
+    /// Execute a command with the shell of the user. Its standard output and standard error are
+    /// combined, and shown according to `execute_output`: inserted at the cursor position, or in
+    /// the status bar. If the command fails, the text is unchanged and the output is shown in the
+    /// status bar. Interactive commands are not supported; see `run_shell_command`.
+    fn execute(&mut self, command: &str) {
+        let output = match self.run_shell_command(command) {
+            Ok(output) => output,
+            Err(Error::Io(err)) => {
+                set_status!(self, Error: "Can't execute {}: {}", command, err);
+                return;
+            }
+            Err(err) => {
+                set_status!(self, Error: "Can't execute {}: {:?}", command, err);
+                return;
+            }
+        };
+        let mut text = output.stdout;
+        text.extend_from_slice(&output.stderr);
+        let text_lines = || String::from_utf8_lossy(&text).lines().collect::<Vec<_>>().join(" | ");
+        match self.config.execute_output {
+            _ if !output.status.success() =>
+                set_status!(self, Error: "{} ({}): {}", command, output.status, text_lines()),
+            ExecuteOutput::Status => set_status!(self, "{}", text_lines()),
+            ExecuteOutput::Insert => text.iter().for_each(|c| match c {
+                b'\n' => self.insert_new_line(),
+                b'\r' => (),
+                c => self.insert_byte(*c),
+            }),
+        }
+    }
+
+    /// Print the error or success message of a save to the status bar.
+    fn set_save_status(&mut self, saved: &Result<SaveOutcome, Error>, file_name: &str) {
+        match saved {
+            Ok(o) => {
+                let size = format_size(o.bytes_written as u64);
+                set_status!(self, "{} written to {}", size, file_name);
+            }
+            Err(Error::Save(_, err)) => set_status!(self, Error: "Can't save! I/O error: {}", err),
+            Err(err) => set_status!(self, Error: "Can't save! {:?}", err),
+        }
+    }
+
+    /// Write all the rows to another file, like `save` does. Unlike `save_as`, the file name, the
+    /// modified state and the syntax of the buffer are unchanged.
+    fn write_copy(&mut self, file_name: &str) {
+        let saved = self.save(file_name);
+        self.set_save_status(&saved, file_name);
+    }
+
+    /// Write some rows to another file, e.g. to extract a snippet. The file name and the modified
+    /// state of the buffer are unchanged. The argument is the path of the file, optionally preceded
+    /// by a range of lines such as `3,7`; without a range, the selected rows are written.
+    fn write_range(&mut self, arg: &str) {
+        let (range, file_name) = match arg.split_once(' ') {
+            Some((range, file_name)) if range.contains(',') => (Some(range), file_name.trim()),
+            _ => (None, arg),
+        };
+        if file_name.is_empty() {
+            set_status!(self, "Usage: write-range [<first line>,<last line>] <path>");
+            return;
+        }
+        let rows = match (range.map(parse_line_range), self.selected_rows()) {
+            (Some(Ok(lines)), _) => {
+                // In paged mode, the lines that are not in memory are ignored
+                let (first_row, n_rows) = (self.first_row, self.buf.rows.len());
+                let row = |line: usize| line.saturating_sub(first_row).min(n_rows);
+                row(lines.start)..row(lines.end)
+            }
+            (Some(Err(e)), _) => {
+                set_status!(self, Error: "Parsing error: {}", e);
+                return;
+            }
+            (None, Some(rows)) => rows,
+            (None, None) => {
+                set_status!(self, "No selection: select rows, or give a range of lines");
+                return;
+            }
+        };
+        if rows.is_empty() {
+            set_status!(self, "No lines in this range");
+            return;
+        }
+        let saved = self.save_rows(file_name, rows);
+        self.set_save_status(&saved, file_name);
+    }
+
+    /// Save the file if auto-save is enabled, the buffer has been modified and has a file name, and
+    /// the auto-save interval has elapsed since the last save. Return whether the file was saved.
+    fn autosave_if_due(&mut self) -> bool {
+        let interval = self.config.autosave_interval;
+        if interval.is_zero() || !self.buf.dirty || self.read_only {
+            return false;
+        }
+        if self.last_save.is_some_and(|t| t.elapsed() < interval) {
+            return false;
+        }
+        match self.buf.file_name.clone() {
+            Some(file_name) => self.save_and_handle_io_errors(&file_name),
+            None => false,
+        }
+    }
+
+    /// Write the rows to the swap file, if the buffer has been modified since the last save and the
+    /// swap file has not been written during the last `SWAP_INTERVAL`. Nothing is written if the
+    /// buffer has no file name yet.
+    fn update_swap_file(&mut self) {
+        let recently_written = self.swap_written.is_some_and(|t| t.elapsed() < SWAP_INTERVAL);
+        if !self.buf.dirty || self.read_only || recently_written {
+            return;
+        }
+        let Some(file_name) = self.buf.file_name.as_deref() else { return };
+        self.swap_written = Some(Instant::now());
+        if let Err(Error::Save(_, err)) = self.save(&swap_path(file_name)) {
+            set_status!(self, Error: "Can't write swap file! I/O error: {}", err);
+        }
+    }
+
+    /// Delete the swap file, if it has been written since the last save.
+    fn remove_swap_file(&mut self) {
+        let swap_written = self.swap_written.take();
+        if let (Some(_), Some(file_name)) = (swap_written, self.buf.file_name.as_deref()) {
+            // The swap file may have been removed by another process
+            let _ = std::fs::remove_file(swap_path(file_name));
+        }
+    }
+
+    /// Return the path of the swap file of the current file, if it is more recent than the file
+    /// itself. This means that the editor did not exit cleanly after the file was modified.
+    fn newer_swap_file(&self) -> Option<String> {
+        let file_name = self.buf.file_name.as_deref()?;
+        let swap = swap_path(file_name);
+        let swap_modified = std::fs::metadata(&swap).and_then(|m| m.modified()).ok()?;
+        let file_modified = std::fs::metadata(file_name).and_then(|m| m.modified()).ok();
+        file_modified.is_none_or(|t| swap_modified > t).then_some(swap)
+    }
+
+    /// Replace the rows with the content of a swap file. The buffer is then considered modified,
+    /// and the swap file is up to date with it.
+    fn recover_swap_file(&mut self, swap: &str) -> Result<(), Error> {
+        self.load_rows(&std::fs::read(swap)?);
+        self.buf.cursor.y = self.buf.cursor.y.min(self.buf.rows.len() - 1);
+        self.update_cursor_x_position();
+        self.buf.dirty = true;
+        self.swap_written = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Save to a file after obtaining the file path from the prompt. If successful, the `file_name`
+    /// attribute of the editor will be set and syntax highlighting will be updated.
+    fn save_as(&mut self, file_name: String) -> Result<(), Error> {
+        if self.save_and_handle_io_errors(&file_name) {
+            // If save was successful
+            self.select_syntax_highlight(Path::new(&file_name))?;
+            self.buf.file_name = Some(file_name);
+            self.update_all_rows();
+        }
+        Ok(())
+    }
+
+    /// Run a command entered in the command prompt, for instance `goto 42` or `set tab_stop=2`.
+    /// Return the prompt mode to switch to, if any.
+    fn run_command(&mut self, command: &str) -> Result<Option<PromptMode>, Error> {
+        let command = command.trim();
+        let (name, arg) = command.split_once(' ').map_or((command, ""), |(n, a)| (n, a.trim()));
+        match name {
+            "" => (),
+            // Writing to another file leaves the buffer unchanged, even in read-only mode
+            "w" | "write" if !arg.is_empty() && self.buf.file_name.as_deref() != Some(arg) =>
+                self.write_copy(arg),
+            "w" | "write" | "saveas" if self.is_read_only() =>
+                set_status!(self, Warning: "Read-only mode: editing and saving are disabled"),
+            "w" | "write" => match self.buf.file_name.clone() {
+                None => return Ok(Some(PromptMode::Save(String::new()))),
+                Some(file_name) => {
+                    self.save_and_handle_io_errors(&file_name);
+                }
+            },
+            "saveas" if arg.is_empty() => return Ok(Some(PromptMode::Save(String::new()))),
+            "saveas" => self.save_as(String::from(arg))?,
+            // Follow the same flow as the quit key, including the confirmation for unsaved changes
+            "q" | "quit" => {
+                let quit_key = Key::Char(self.config.keys.quit);
+                let (should_quit, prompt_mode) = self.process_keypress(&quit_key);
+                self.should_quit = should_quit;
+                return Ok(prompt_mode);
+            }
+            // Quit immediately, discarding the unsaved changes of all the buffers
+            "q!" | "force-quit" => self.should_quit = true,
+            "g" | "goto" =>
+                if let Err(e) = self.go_to(arg, self.buf.cursor.y) {
+                    set_status!(self, Error: "Parsing error: {}", e);
+                },
+            "retab" if self.is_read_only() =>
+                set_status!(self, Warning: "Read-only mode: editing and saving are disabled"),
+            "retab" => self.retab(),
+            "date" if self.is_read_only() =>
+                set_status!(self, Warning: "Read-only mode: editing and saving are disabled"),
+            "date" => {
+                let format = if arg.is_empty() { &self.config.date_format } else { arg };
+                let date = snippets::format_date(format, SystemTime::now());
+                self.insert_typed_text(&date);
+            }
+            "set" => match arg.split_once('=').map(|(k, v)| (k.trim(), v)) {
+                Some((key, value)) => match self.config.set(key, value) {
+                    Ok(()) => self.apply_config(key),
+                    Err(e) => set_status!(self, Error: "{}", e),
+                },
+                None => set_status!(self, "Usage: set <option>=<value>"),
+            },
+            "reload-config" => self.reload_config(),
+            "wr" | "write-range" => self.write_range(arg),
+            // Follow the same flow as the revert key, including the confirmation if modified
+            "reload" | "revert" => {
+                let revert_key = Key::Char(self.config.keys.revert);
+                return Ok(self.process_keypress(&revert_key).1);
+            }
+            _ => set_status!(self, Error: "Unknown command: {}", name),
+        }
+        Ok(None)
+    }
+
+    /// Apply a configuration option that has just been changed with the `set` command, updating
+    /// the editor state that depends on it.
+    fn apply_config(&mut self, key: &str) {
+        match key {
+            "tab_stop" => self.update_all_rows(),
+            "show_line_numbers" | "line_number_style" | "scrollbar" => self.update_screen_cols(),
+            "read_only" => self.read_only = self.config.read_only,
+            "quit_times" => self.quit_times = self.config.quit_times,
+            "mouse" if self.orig_term_mode.is_some() => {
+                let mouse = self.config.mouse;
+                print!("{}", if mouse { ENABLE_MOUSE_REPORTING } else { DISABLE_MOUSE_REPORTING });
+            }
+            _ => (),
+        }
+    }
+
+    /// Load the configuration again and apply it, keeping the `.editorconfig` properties of the
+    /// active buffer. If the new configuration cannot be loaded, the current one is kept.
+    fn reload_config(&mut self) {
+        let loaded = match &self.config_file {
+            Some(path) => Config::load_from(std::slice::from_ref(path)),
+            None => Config::load(),
+        };
+        let config = match loaded {
+            Ok(config) => config,
+            Err(err) => {
+                set_status!(self, Error: "Can't reload the configuration! {:?}", err);
+                return;
+            }
+        };
+        let old_options = applied_options(&self.config);
+        let project_config = mem::take(&mut self.overridden_config).apply(&mut self.config);
+        self.config = config;
+        self.apply_config_overrides();
+        self.overridden_config = project_config.apply(&mut self.config);
+        let changed: Vec<_> = applied_options(&self.config).into_iter().zip(old_options)
+            .filter(|(new, old)| new != old)
+            .map(|((key, _), _)| key)
+            .collect();
+        changed.iter().for_each(|key| self.apply_config(key));
+        if changed.is_empty() {
+            set_status!(self, "Configuration reloaded");
+        } else {
+            set_status!(self, "Configuration reloaded, changed: {}", changed.join(", "));
+        }
+    }
+
+    /// Reload the file from disk, discarding unsaved changes. The cursor is kept at the same
+    /// position, clamped to the new content. If the file cannot be read (for instance, if it has
+    /// been deleted), report the error and keep the buffer intact.
+    fn revert(&mut self) {
+        let Some(file_name) = self.buf.file_name.clone() else {
+            set_status!(self, "No file to revert to");
+            return;
+        };
+        let path = Path::new(&file_name);
+        if let Err(err) = File::open(path) {
+            set_status!(self, Error: "Can't revert! I/O error: {}", err);
+            return;
+        }
+        let (rows, cursor) = (mem::take(&mut self.buf.rows), self.buf.cursor.clone());
+        if let Err(err) = self.load(path) {
+            self.buf.rows = rows;
+            set_status!(self, Error: "Can't revert! {:?}", err);
+            return;
+        }
+        if self.buf.rows.is_empty() {
+            self.buf.rows.push(Row::new(Vec::new()));
+        }
+        (self.buf.cursor, self.selection_anchor, self.bracket_pair) = (cursor, None, None);
+        self.buf.cursor.y = self.buf.cursor.y.min(self.buf.rows.len() - 1);
+        self.update_cursor_x_position();
+        self.buf.dirty = false;
+        self.remove_swap_file();
+        self.update_screen_cols();
+        set_status!(self, "Reverted to {}", file_name);
+    }
+
+    /// Return whether editing and saving are disabled, either because of read-only mode or because
+    /// the file is paged.
+    fn is_read_only(&self) -> bool { self.read_only || self.paged.is_some() }
+
+    /// Return the number of lines of the file. This is the number of rows, unless the file is
+    /// paged.
+    fn n_lines(&self) -> usize {
+        self.paged.as_ref().map_or(self.buf.rows.len(), PagedFile::n_lines)
+    }
+
+    /// Draw rows of text and empty rows, and return the lines of the screen they occupy. If the
+    /// screen is split, the rows of both panes are drawn, separated by a divider.
+    fn draw_rows(&mut self) -> Vec<String> {
+        let focused = self.pane().draw();
+        let Some(split) = self.split else {
+            return focused.iter().map(|l| format!("{}{}", CLEAR_LINE_RIGHT_OF_CURSOR, l)).collect();
+        };
+        let other = self.draw_other_pane(split);
+        let (left, right) = if split.focus_left { (focused, other) } else { (other, focused) };
+        let divider_col = self.pane_widths().0 + 1;
+        let color = self.config.theme.line_number_escape();
+        // \x1b[{}G: move to the given column; \u{2502}: pipe "│"
+        let divider = format!("\x1b[{}G{}\u{2502}{}", divider_col, color, RESET_FMT);
+        left.iter().zip(&right)
+            .map(|(l, r)| format!("{}{}{}{}", CLEAR_LINE_RIGHT_OF_CURSOR, l, divider, r))
+            .collect()
+    }
+
+    /// Scroll, highlight and draw the rows of the buffer shown in the pane that does not have the
+    /// focus, and return them. The buffer is drawn where it is stored, without making it active.
+    fn draw_other_pane(&mut self, split: Split) -> Vec<String> {
+        // The active buffer is not stored in `buffers`, so the following ones are shifted by one
+        let index = if split.other < self.active { split.other } else { split.other - 1 };
+        let position = self.pane_position(!split.focus_left);
+        let (config, screen_rows) = (&self.config, self.screen_rows);
+        let buffer = &mut self.buffers[index];
+        let y = buffer.buf.cursor.y;
+        buffer.buf.folds.retain(|fold| !fold.contains(&y));
+        (buffer.buf.cursor.coff, buffer.buf.cursor.roff) =
+            buffer.pane(config, position, screen_rows).scroll_offsets();
+        let end = buffer.pane(config, position, screen_rows).highlight_end();
+        buffer.buf.highlight_rows(end);
+        buffer.pane(config, position, screen_rows).draw()
+    }
+
+    /// Draw the status bar on terminal, by adding characters to the buffer. The file name and its
+    /// state are shown on the left; the file type, line ending, size, number of lines and cursor
+    /// position are right-aligned. On narrow terminals, the right part is truncated first, keeping
+    /// the cursor position as long as possible.
+    fn draw_status_bar(&self, buffer: &mut String) {
+        // Left part of the status bar.
+        let modified = if self.buf.dirty { " (modified)" } else { "" };
+        let read_only = if self.is_read_only() { " [RO]" } else { "" };
+        let file_name = self.buf.file_name.as_deref().unwrap_or("[No Name]");
+        let n_buffers = self.buffers.len() + 1;
+        let position = if n_buffers > 1 {
+            format!("[{}/{}] ", self.active + 1, n_buffers)
+        } else {
+            String::new()
+        };
+        let file_name = truncate_to_width(file_name, 30);
+        let left = format!("{}{}{}{}", position, file_name, read_only, modified);
+        let left = truncate_to_width(&left, self.window_width);
+
+        // Right part of the status bar.
+        let newlines =
+            self.buf.rows.len().saturating_sub(1) * self.buf.line_ending.as_bytes().len();
+        let size = self.paged.as_ref().map_or(self.buf.n_bytes + newlines as u64, PagedFile::len);
+        let (y, rx) = (self.first_row + self.buf.cursor.y + 1, self.buf.rx() + 1);
+        let (n_lines, size) = (self.n_lines(), format_size(size));
+        let mut fields = vec![
+            String::from(self.buf.syntax.file_type()),
+            String::from(self.buf.line_ending.name()),
+            size,
+            format!("{} lines", n_lines),
+            format!("{}:{}", y, rx),
+        ];
+        let rw = self.window_width - left.width();
+        while fields.len() > 1 && fields.join(" | ").width() > rw {
+            fields.remove(0);
+        }
+
+        // Draw
+        let right = fields.join(" | ");
+        let right = truncate_to_width(&right, rw);
+        let padding = " ".repeat(rw - right.width());
+        buffer.push_str(&format!("{}{}{}{}{}", REVERSE_VIDEO, left, padding, right, RESET_FMT));
+    }
+
+    /// Draw the message bar on the terminal, by adding characters to the buffer.
+    fn draw_message_bar(&self, buffer: &mut String) {
+        buffer.push_str(CLEAR_LINE_RIGHT_OF_CURSOR);
+        let msg_duration = self.config.message_dur;
+        if let Some(sm) = self.status_msg.as_ref().filter(|sm| sm.is_visible(msg_duration)) {
+            let msg = truncate_to_width(&sm.msg, self.window_width);
+            let theme = &self.config.theme;
+            match sm.severity {
+                Severity::Info => buffer.push_str(msg),
+                Severity::Warning =>
+                    buffer.push_str(&format!("{}{}{}", theme.warning_escape(), msg, RESET_FMT)),
+                Severity::Error =>
+                    buffer.push_str(&format!("{}{}{}", theme.error_escape(), msg, RESET_FMT)),
+            }
+        }
+    }
+
+    /// Show the progress of a long operation in the message bar, if `PROGRESS_INTERVAL` has elapsed
+    /// since `last_update`, which is then updated. Only the message bar is drawn, so that the
+    /// operation is not slowed down. Nothing is drawn if the terminal is not in raw mode.
+    fn show_progress(&mut self, last_update: &mut Instant, msg: impl FnOnce() -> String) {
+        if last_update.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        *last_update = Instant::now();
+        if self.orig_term_mode.is_none() || self.window_height == 0 {
+            return;
+        }
+        let msg = msg();
+        let msg = truncate_to_width(&msg, self.window_width);
+        let line = format!("{}{}", CLEAR_LINE_RIGHT_OF_CURSOR, msg);
+        print!("{}\x1b[{};1H{}", HIDE_CURSOR, self.window_height, line);
+        let _ = io::stdout().flush();
+        if let Some(drawn_line) = self.drawn_lines.get_mut(self.window_height - 1) {
+            *drawn_line = line;
+        }
+    }
+
+    /// Refresh the screen: update the offsets, draw the rows, the status bar, the message bar, and
+    /// move the cursor to the correct position. The escape sequences are written at once, and
+    /// contain no new line, so that the line-buffered standard output does not split them.
+    fn refresh_screen(&mut self) -> Result<(), Error> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(self.draw_screen().as_bytes())?;
+        stdout.flush().map_err(Error::from)
+    }
+
+    /// Update the offsets, and return the escape sequences drawing the lines of the screen that
+    /// have changed since the last refresh, and moving the cursor. The status bar and the message
+    /// bar are only drawn if the window is high enough; the message bar, used by the prompts, is
+    /// kept first.
+    fn draw_screen(&mut self) -> String {
+        self.scroll();
+        self.update_page();
+        self.highlight_screen_rows();
+        self.update_bracket_highlight();
+        self.update_search_highlight();
+        self.update_word_highlight();
+        let mut lines = self.draw_rows();
+        match &self.prompt_mode {
+            Some(PromptMode::FindFile(query, selected, files)) =>
+                self.draw_candidates(&rank_files(files, query), *selected, &mut lines),
+            Some(PromptMode::GoToSymbol(query, selected, symbols)) => {
+                let symbols = filter_symbols(symbols, query);
+                let texts: Vec<_> = symbols.iter().map(|(_, text)| text.as_str()).collect();
+                self.draw_candidates(&texts, *selected, &mut lines);
+            }
+            _ => (),
+        }
+        if self.window_height >= 2 {
+            let mut status_bar = String::new();
+            self.draw_status_bar(&mut status_bar);
+            lines.push(status_bar);
+        }
+        if self.window_height >= 1 {
+            let mut message_bar = String::new();
+            self.draw_message_bar(&mut message_bar);
+            lines.push(message_bar);
+        }
+        let mut buffer = String::from(HIDE_CURSOR);
+        self.scroll_drawn_lines(&lines[..self.screen_rows], &mut buffer);
+        for (i, line) in lines.iter().enumerate() {
+            if self.drawn_lines.get(i) != Some(line) {
+                buffer.push_str(&format!("\x1b[{};1H{}", i + 1, line));
+            }
+        }
+        self.drawn_lines = lines;
+        let (cursor_x, cursor_y) = if self.prompt_mode.is_none() {
+            // If not in prompt mode, position the cursor according to the `cursor` attributes.
+            let (col, line) = self.cursor_screen_position();
+            (col + 1 + self.ln_pad + self.focused_pane().1, line + 1)
+        } else {
+            // If in prompt mode, position the cursor on the prompt line at the end of the line.
+            (self.status_msg.as_ref().map_or(0, |sm| sm.msg.len() + 1), self.window_height)
+        };
+        // Finally, move the cursor
+        buffer.push_str(&format!("\x1b[{};{}H{}", cursor_y, cursor_x, SHOW_CURSOR));
+        buffer
+    }
+
+    /// Draw the candidates of the file finder or of the go-to-symbol prompt over the last rows of
+    /// text, right above the prompt.
+    /// The selected candidate is drawn in reverse video.
+    fn draw_candidates(&self, candidates: &[&str], selected: usize, lines: &mut [String]) {
+        let n = candidates.len().min(MAX_SHOWN_CANDIDATES).min(self.screen_rows);
+        let first = (selected + 1).saturating_sub(n);
+        for (i, candidate) in candidates[first..first + n].iter().enumerate() {
+            let candidate = truncate_to_width(candidate, self.window_width);
+            lines[self.screen_rows - n + i] = if first + i == selected {
+                format!("{}{}{}{}", CLEAR_LINE_RIGHT_OF_CURSOR, REVERSE_VIDEO, candidate, RESET_FMT)
+            } else {
+                format!("{}{}", CLEAR_LINE_RIGHT_OF_CURSOR, candidate)
+            };
+        }
+    }
+
+    /// If the rows of text drawn by the last refresh have only moved up or down by a few lines,
+    /// scroll them on the terminal and in `drawn_lines`, so that only the rows that appeared have
+    /// to be drawn.
+    fn scroll_drawn_lines(&mut self, rows: &[String], buffer: &mut String) {
+        let n = rows.len();
+        if self.drawn_lines.len() < n || self.drawn_lines[..n] == *rows {
+            return;
+        }
+        let drawn = &self.drawn_lines[..n];
+        let Some((k, up)) = (1..=n / 2).find_map(|k| {
+            (rows[..n - k] == drawn[k..]).then_some((k, true))
+                .or_else(|| (rows[k..] == drawn[..n - k]).then_some((k, false)))
+        }) else { return };
+        // \x1b[{};{}r: set the scrolling region; \x1b[{}S, \x1b[{}T: scroll up, down
+        let direction = if up { 'S' } else { 'T' };
+        buffer.push_str(&format!("\x1b[1;{}r\x1b[{}{}\x1b[r", n, k, direction));
+        let (removed, inserted) = if up { (0..k, n - k) } else { (n - k..n, 0) };
+        self.drawn_lines.drain(removed);
+        self.drawn_lines.splice(inserted..inserted, repeat_n(String::new(), k));
+    }
+
+    /// Process a key that has been pressed, when not in prompt mode. Returns whether the program
+    /// should exit, and optionally the prompt mode to switch to.
+    fn process_keypress(&mut self, key: &Key) -> (bool, Option<PromptMode>) {
+        // These won't be mutated, unless key is the quit key or the revert key
+        let (mut quit_times, mut revert_times) = (self.config.quit_times, self.config.quit_times);
+        let mut prompt_mode = None;
+
+        // Moving the cursor without Shift, or typing text, cancels the selection.
+        let keep_selection = match key {
+            Key::ShiftArrow(_) | Key::AltArrow(_) | Key::BackTab | Key::Char(b'\t') => true,
+            Key::AltChar(c) | Key::Char(c) =>
+                c.is_ascii_control() && !matches!(*c, b'\r' | BACKSPACE | DELETE_BIS),
+            _ => false,
+        };
+        if !keep_selection {
+            self.selection_anchor = None;
+        }
+
+        let keys = self.config.keys;
+        if self.is_read_only() && key.is_edit(&keys) {
+            set_status!(self, Warning: "Read-only mode: editing and saving are disabled");
+            return (false, None);
+        }
+
+        // Only typed characters and Backspace apply to the additional cursors; other keys remove
+        // them.
+        let multi_edit = match key {
+            Key::Char(c) => matches!(*c, BACKSPACE | DELETE_BIS) || !c.is_ascii_control(),
+            _ => false,
+        };
+        if !multi_edit && !matches!(key, Key::CtrlAltArrow(_)) {
+            self.buf.extra_cursors.clear();
+        }
+
+        match key {
+            Key::CtrlAltArrow(AKey::Up) => self.add_cursor(true),
+            Key::CtrlAltArrow(AKey::Down) => self.add_cursor(false),
+            Key::CtrlAltArrow(_) => (),
+            Key::Char(BACKSPACE | DELETE_BIS) if !self.buf.extra_cursors.is_empty() =>
+                self.edit_at_cursors(&Self::backspace),
+            Key::Char(c) if !self.buf.extra_cursors.is_empty() && !c.is_ascii_control() =>
+                self.edit_at_cursors(&|editor: &mut Self| editor.insert_byte(*c)),
+            Key::Arrow(arrow) | Key::CtrlArrow(arrow @ (AKey::Up | AKey::Down)) =>
+                self.move_cursor(arrow),
+            Key::CtrlArrow(AKey::Left) => self.move_cursor_word_left(),
+            Key::AltArrow(AKey::Up | AKey::Left) => self.move_lines(true),
+            Key::AltArrow(AKey::Down | AKey::Right) => self.move_lines(false),
+            Key::CtrlArrow(AKey::Right) => self.move_cursor_word_right(),
+            Key::ShiftArrow(arrow) => {
+                if self.selection_anchor.is_none() {
+                    self.selection_anchor = Some((self.buf.cursor.y, self.buf.cursor.x));
+                }
+                self.move_cursor(arrow);
+            }
+            Key::Char(b'\t') => match self.selected_rows() {
+                Some(range) => self.indent_rows(range),
+                None => self.insert_tab(),
+            },
+            Key::Char(c) if *c == keys.comment => {
+                let y = self.buf.cursor.y;
+                let current_row = y..(y + 1).min(self.buf.rows.len());
+                self.toggle_comment(self.selected_rows().unwrap_or(current_row));
+            }
+            Key::Char(c) if *c == keys.match_bracket => {
+                match self.find_matching_bracket(self.buf.cursor.y, self.buf.cursor.x) {
+                    Some((y, x)) => (self.buf.cursor.y, self.buf.cursor.x) = (y, x),
+                    None => set_status!(self, "No matching bracket"),
+                }
+            }
+            Key::Char(c) if *c == keys.line_ending => self.toggle_line_ending(),
+            Key::Char(c) if *c == keys.whitespace => {
+                self.config.show_whitespace ^= true;
+                let state = if self.config.show_whitespace { "shown" } else { "hidden" };
+                set_status!(self, "Whitespace {}", state);
+            }
+            Key::Char(c) if *c == keys.cursorline => {
+                self.config.cursorline ^= true;
+                let state = if self.config.cursorline { "shown" } else { "hidden" };
+                set_status!(self, "Cursor line {}", state);
+            }
+            Key::Mouse(MouseEvent::Click(col, row)) => self.click(*col, *row),
+            Key::Mouse(MouseEvent::WheelUp) => self.scroll_by(-MOUSE_SCROLL_ROWS),
+            Key::Mouse(MouseEvent::WheelDown) => self.scroll_by(MOUSE_SCROLL_ROWS),
+            Key::CtrlTab => self.cycle_buffers(true),
+            Key::CtrlShiftTab => self.cycle_buffers(false),
+            Key::BackTab => {
+                let range = self.selected_rows();
+                let y = self.buf.cursor.y;
+                let current_row = y..(y + 1).min(self.buf.rows.len());
+                self.dedent_rows(range.unwrap_or(current_row));
+            }
+            Key::Page(PageKey::Up) => self.move_page(true),
+            Key::Page(PageKey::Down) => self.move_page(false),
+            Key::Home => self.move_cursor_home(),
+            Key::End => self.buf.cursor.x = self.buf.current_row().map_or(0, |row| row.chars.len()),
+            Key::Char(b'\r') => self.insert_new_line(), // Enter
+            Key::Paste(text) => self.insert_text(text),
+            Key::Char(c) if *c == keys.join_lines => self.join_lines(),
+            Key::Char(BACKSPACE | DELETE_BIS) => self.backspace(), // Backspace or Ctrl + H
+            // Terminals send Alt + Backspace as <ESC> followed by Backspace
+            Key::AltChar(BACKSPACE) => self.delete_word_left(),
+            Key::Char(c) if *c == keys.remove_line => self.delete_current_row(),
+            Key::Char(c) if *c == keys.fold => self.toggle_fold(),
+            Key::Delete => {
+                self.move_cursor(&AKey::Right);
+                self.delete_char();
+            }
+            Key::CtrlDelete => self.delete_word_right(),
+            Key::Escape => (),
+            Key::Char(c) if *c == keys.refresh => self.drawn_lines.clear(),
+            Key::Char(c) if *c == keys.quit => {
+                quit_times = self.quit_times.saturating_sub(1);
+                let n_dirty = usize::from(self.buf.dirty)
+                    + self.buffers.iter().filter(|b| b.buf.dirty).count();
+                if n_dirty == 0 || quit_times == 0 {
+                    return (true, None);
+                }
+                let times = if quit_times > 1 { "times" } else { "time" };
+                let warning = match n_dirty {
+                    1 if self.buf.dirty => String::from("File has unsaved changes."),
+                    1 => String::from("Another buffer has unsaved changes."),
+                    n => format!("{} buffers have unsaved changes.", n),
+                };
+                let (n, quit) = (quit_times, ctrl_key_name(keys.quit));
+                let question =
+                    format!("{} Quit (or press {} {} more {})?", warning, quit, n, times);
+                prompt_mode = Some(PromptMode::Confirm(question, ConfirmAction::Quit));
+            }
+            Key::Char(c) if *c == keys.revert => {
+                revert_times = self.revert_times.saturating_sub(1);
+                if !self.buf.dirty || revert_times == 0 {
+                    self.revert();
+                } else {
+                    let times = if revert_times > 1 { "times" } else { "time" };
+                    let (n, revert) = (revert_times, ctrl_key_name(keys.revert));
+                    let warning = "Unsaved changes will be lost.";
+                    let question =
+                        format!("{} Revert (or press {} {} more {})?", warning, revert, n, times);
+                    prompt_mode = Some(PromptMode::Confirm(question, ConfirmAction::Revert));
+                }
+            }
+            Key::Char(c) if *c == keys.save => match self.buf.file_name.take() {
+                // TODO: Can we avoid using take() then reassigning the value to file_name?
+                Some(file_name) => {
+                    self.save_and_handle_io_errors(&file_name);
+                    self.buf.file_name = Some(file_name);
+                }
+                None => prompt_mode = Some(PromptMode::Save(String::new())),
+            },
+            Key::Char(c) if *c == keys.find => {
+                self.find_history.position = None;
+                prompt_mode = Some(PromptMode::Find(
+                    String::new(),
+                    self.buf.cursor.clone(),
+                    None,
+                    FindOptions::default(),
+                ));
+            }
+            Key::Char(c) if *c == keys.replace => {
+                (self.find_history.position, self.replace_history.position) = (None, None);
+                prompt_mode = Some(PromptMode::Replace(String::new(), None, None, 0));
+            }
+            Key::Char(c) if *c == keys.goto => {
+                self.goto_history.position = None;
+                prompt_mode = Some(PromptMode::GoTo(String::new(), self.buf.cursor.clone()));
+            }
+            Key::Char(c) if *c == keys.duplicate => self.duplicate_rows(false),
+            Key::AltChar(c) if *c == keys.duplicate => self.duplicate_rows(true),
+            Key::AltChar(_) => (),
+            Key::Char(c) if *c == keys.execute =>
+                prompt_mode = Some(PromptMode::Execute(String::new())),
+            Key::Char(c) if *c == keys.pipe => prompt_mode = Some(PromptMode::Pipe(String::new())),
+            Key::Char(c) if *c == keys.window => prompt_mode = Some(PromptMode::Window),
+            Key::Char(c) if *c == keys.command =>
+                prompt_mode = Some(PromptMode::Command(String::new())),
+            Key::Char(c) if *c == keys.open => match self.recent_files() {
+                files if files.is_empty() => set_status!(self, "No recently opened files"),
+                files => prompt_mode = Some(PromptMode::QuickOpen(String::new(), 0, files)),
+            },
+            Key::Char(c) if *c == keys.find_file => {
+                match env::current_dir().map(|dir| scan_files(&dir)).unwrap_or_default() {
+                    files if files.is_empty() => set_status!(self, "No files found"),
+                    files => prompt_mode = Some(PromptMode::FindFile(String::new(), 0, files)),
+                }
+            }
+            Key::Char(c) if *c == keys.goto_symbol => match self.symbols() {
+                symbols if symbols.is_empty() => {
+                    let file_type = self.buf.syntax.file_type();
+                    set_status!(self, "No symbols found ({})", file_type);
+                }
+                symbols => prompt_mode = Some(PromptMode::GoToSymbol(String::new(), 0, symbols)),
+            },
+            Key::Char(c) if *c == keys.snippet => self.expand_snippet(),
+            Key::Char(c) => self.insert_byte(*c),
+        }
+        // When rows are wrapped, moving up or down keeps the column within the wrapped lines
+        let vertical = match key {
+            Key::Arrow(AKey::Up | AKey::Down) | Key::CtrlArrow(AKey::Up | AKey::Down) =>
+                !self.config.wrap,
+            Key::Page(_) => true,
+            _ => false,
+        };
+        let cursor = &self.buf.cursor;
+        let rx = self.buf.current_row().and_then(|row| row.cx2rx.get(cursor.x).copied());
+        if let (Some(rx), false) = (rx, vertical) {
+            self.buf.cursor.desired_rx = rx;
+        }
+        (self.quit_times, self.revert_times) = (quit_times, revert_times);
+        (false, prompt_mode)
+    }
+
+    /// Try to find a query, this is called after pressing Ctrl-F and for each key that is pressed.
+    /// `last_match` is the last row that was matched, `forward` indicates whether to search forward
+    /// or backward, `opts` are the options toggled in the prompt. Returns the row of a new match,
+    /// or `None` if the search was unsuccessful.
+    #[allow(clippy::trivially_copy_pass_by_ref)] // This Clippy recommendation is only relevant on 32 bit platforms.
+    fn find(
+        &mut self, query: &str, last_match: &Option<usize>, forward: bool, opts: FindOptions,
+    ) -> Option<usize> {
+        #[cfg(feature = "regex")]
+        if opts.regex {
+            return self.find_with_regex(query, last_match, forward, opts);
+        }
+        let num_rows = self.buf.rows.len();
+        let mut current = last_match.unwrap_or_else(|| num_rows.saturating_sub(1));
+        for _ in 0..num_rows {
+            current = (current + if forward { 1 } else { num_rows - 1 }) % num_rows;
+            let chars = &self.buf.rows[current].chars;
+            if let Some(cx) = slice_find_with(chars, query.as_bytes(), opts) {
+                self.move_to_match(current, cx, query.len());
+                return Some(current);
+            }
+        }
+        None
+    }
+
+    /// Same as `find`, when the query is a regular expression. The search starts after the last
+    /// match, or before it when searching backward. If there is no last match, it starts at the
+    /// beginning of the document, or at its end when searching backward. Invalid regular
+    /// expressions don't match anything; the error is shown by the prompt.
+    #[cfg(feature = "regex")]
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn find_with_regex(
+        &mut self, query: &str, last_match: &Option<usize>, forward: bool, opts: FindOptions,
+    ) -> Option<usize> {
+        let re = build_regex(query, opts).ok()?;
+        let cursor = self.buf.cursor.clone();
+        let found = match (last_match, forward) {
+            (Some(_), true) => self.find_regex(&re, CursorState { x: cursor.x + 1, ..cursor }),
+            (None, true) => self.find_regex(&re, CursorState::default()),
+            (Some(_), false) => self.rfind_regex(&re, cursor),
+            (None, false) => {
+                let y = self.buf.rows.len().saturating_sub(1);
+                self.rfind_regex(&re, CursorState { y, x: usize::MAX, ..cursor })
+            }
+        };
+        let CursorState { y, x, .. } = found?;
+        let len = re.find_at(&self.buf.rows[y].chars, x).map_or(0, |m| m.end() - m.start());
+        self.move_to_match(y, x, len);
+        Some(y)
+    }
+
+    /// Return the position of the next match of `re`, starting at position `start` (included) and
+    /// wrapping around the end of the document. Matching is done on the bytes of each row, so that
+    /// the match positions are byte positions, like `cursor.x`.
+    #[cfg(feature = "regex")]
+    fn find_regex(&self, re: &Regex, start: CursorState) -> Option<CursorState> {
+        let rows = self.buf.rows.iter().enumerate();
+        let x0 = |y| if y == start.y { start.x } else { 0 };
+        let after = rows.clone().skip(start.y).map(|(y, r)| (y, r, x0(y)));
+        let before = rows.take(start.y + 1).map(|(y, r)| (y, r, 0));
+        after.chain(before).find_map(|(y, row, x)| {
+            let m = re.find_at(&row.chars, x.min(row.chars.len()))?;
+            Some(CursorState { y, x: m.start(), ..start.clone() })
+        })
+    }
+
+    /// Return the position of the previous match of `re`, starting before position `end`
+    /// (excluded) and wrapping around the beginning of the document.
+    #[cfg(feature = "regex")]
+    fn rfind_regex(&self, re: &Regex, end: CursorState) -> Option<CursorState> {
+        let rows = self.buf.rows.iter().enumerate();
+        let x1 = |y| if y == end.y { end.x } else { usize::MAX };
+        let before = rows.clone().take(end.y + 1).rev().map(|(y, r)| (y, r, x1(y)));
+        let after = rows.skip(end.y).rev().map(|(y, r)| (y, r, usize::MAX));
+        before.chain(after).find_map(|(y, row, x)| {
+            let m = re.find_iter(&row.chars).take_while(|m| m.start() < x).last()?;
+            Some(CursorState { y, x: m.start(), ..end.clone() })
+        })
+    }
+
+    /// Move the cursor to a match of `len` bytes located at row `y`, byte `x`, and highlight it.
+    fn move_to_match(&mut self, y: usize, x: usize, len: usize) {
+        self.buf.cursor.y = y;
+        self.buf.cursor.x = x;
+        // Try to reset the column offset; if the match is after the offset, this
+        // will be updated in self.scroll() so that the result is visible
+        self.buf.cursor.coff = 0;
+        let row = &mut self.buf.rows[y];
+        row.match_segment = Some(row.cx2rx[x]..row.cx2rx[x + len]);
+        self.buf.cursor.desired_rx = row.cx2rx[x];
+    }
+
+    /// Load each file of `file_names` in its own buffer, then run the text editor. If text has
+    /// already been loaded with `load_text` and `file_names` is empty, that text is edited instead.
+    ///
+    /// # Errors
+    ///
+    /// Will Return `Err` if any error occur.
+    pub fn run(&mut self, file_names: &[String]) -> Result<(), Error> {
+        for (i, file_name) in file_names.iter().enumerate() {
+            if i > 0 {
+                self.buffers.push(Buffer::default());
+                self.switch_buffer(i);
+            }
+            self.open_at_position(file_name)?;
+        }
+        self.switch_buffer(0);
+        if self.buf.rows.is_empty() {
+            self.buf.rows.push(Row::new(Vec::new()));
+            self.buf.file_name = None;
+        }
+        loop {
+            if let Some(mode) = self.prompt_mode.as_ref() {
+                set_status!(self, "{}", mode.status_msg(self));
+            }
+            self.refresh_screen()?;
+            let Some(key) = self.loop_until_keypress()? else {
+                // The editor has been killed: quit without saving, keeping the swap files so that
+                // the changes can be recovered. The terminal is restored when the editor is
+                // dropped.
+                for i in 0..=self.buffers.len() {
+                    self.switch_buffer(i);
+                    self.store_cursor_position();
+                }
+                self.store_find_history();
+                return Ok(());
+            };
+            if self.debug_keys && !matches!(key, Key::Char(c) if c == self.config.keys.quit) {
+                continue;
+            }
+            // Errors are shown until a key is pressed
+            if self.status_msg.as_ref().is_some_and(|sm| sm.severity == Severity::Error) {
+                self.status_msg = None;
+            }
+            self.prompt_mode = match self.prompt_mode.take() {
+                // process_keypress returns (should_quit, prompt_mode)
+                None => {
+                    let (should_quit, prompt_mode) = self.process_keypress(&key);
+                    self.should_quit |= should_quit;
+                    prompt_mode
+                }
+                Some(prompt_mode) => prompt_mode.process_keypress(self, &key)?
+            }
+            // A prompt may also be opened while processing the key, e.g. to confirm overwriting
+            // a file that has been modified on disk
+            .or(self.prompt_mode.take());
+            if self.should_quit {
+                for i in 0..=self.buffers.len() {
+                    self.switch_buffer(i);
+                    self.store_cursor_position();
+                    self.remove_swap_file();
+                }
+                self.store_find_history();
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Register the original terminal mode of an editor that has just enabled raw mode, so that it is
+/// restored if the program panics. The panic hook is installed the first time; it restores the
+/// mode, disables mouse reporting and bracketed paste, clears the screen and leaves the alternate
+/// screen if `alternate_screen` is set, before the panic message is printed.
+fn register_panic_term_mode(orig_term_mode: sys::TermMode, alternate_screen: bool) {
+    static INSTALL_HOOK: Once = Once::new();
+    if let Ok(mut guard) = PANIC_TERM_MODE.lock() {
+        let (n_editors, term_mode) = &mut *guard;
+        *n_editors += 1;
+        term_mode.get_or_insert((orig_term_mode, alternate_screen));
+    }
+    INSTALL_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // The mode is restored only once, even if several threads panic
+            let term_mode = PANIC_TERM_MODE.lock().ok().and_then(|mut guard| guard.1.take());
+            if let Some((term_mode, alternate_screen)) = term_mode {
+                // Errors are ignored: the panic message is more useful than a new panic
+                let _ = sys::set_term_mode(&term_mode);
+                print!("{}{}", DISABLE_MOUSE_REPORTING, DISABLE_BRACKETED_PASTE);
+                print!("{}{}", CLEAR_SCREEN, MOVE_CURSOR_TO_START);
+                if alternate_screen {
+                    print!("{}", LEAVE_ALTERNATE_SCREEN);
+                }
+                let _ = io::stdout().flush();
+            }
+            default_hook(info);
+        }));
+    });
+}
+
+/// Unregister an editor that restored its original terminal mode. When no editor is in raw mode
+/// anymore, the panic hook has no terminal mode to restore.
+fn unregister_panic_term_mode() {
+    if let Ok(mut guard) = PANIC_TERM_MODE.lock() {
+        guard.0 = guard.0.saturating_sub(1);
+        if guard.0 == 0 {
+            guard.1 = None;
+        }
+    }
+}
+
+impl Drop for Editor {
+    /// When the editor is dropped, restore the original terminal mode.
+    fn drop(&mut self) {
+        if let Some(orig_term_mode) = self.orig_term_mode.take() {
+            unregister_panic_term_mode();
+            sys::set_term_mode(&orig_term_mode).expect("Could not restore original terminal mode.");
+            if self.config.mouse {
+                print!("{}", DISABLE_MOUSE_REPORTING);
+            }
+            print!("{}", DISABLE_BRACKETED_PASTE);
+        }
+        // When panicking, the hook has already left the alternate screen
+        if !thread::panicking() {
+            print!("{}{}{}{}", RESET_FMT, SHOW_CURSOR, CLEAR_SCREEN, MOVE_CURSOR_TO_START);
+            if self.alternate_screen {
+                print!("{}", LEAVE_ALTERNATE_SCREEN);
+            }
+            io::stdout().flush().expect("Could not flush stdout");
+        }
+    }
+}
+
+/// An action that requires a confirmation from the user.
+#[derive(Debug, PartialEq, Eq)]
+enum ConfirmAction {
+    /// Quit the editor, discarding the unsaved changes
+    Quit,
+    /// Reload the file from disk, discarding the unsaved changes
+    Revert,
+    /// Overwrite(name of the file that has been modified on disk since it was loaded)
+    Overwrite(String),
+}
+
+impl ConfirmAction {
+    /// Return the key that triggers the action, which may be pressed several times instead of
+    /// answering the confirmation prompt.
+    const fn key(&self, keys: &KeyBindings) -> Option<u8> {
+        match self {
+            Self::Quit => Some(keys.quit),
+            Self::Revert => Some(keys.revert),
+            Self::Overwrite(_) => None,
+        }
+    }
+
+    /// Perform the action, after the user answered yes.
+    fn perform(self, ed: &mut Editor) {
+        match self {
+            Self::Quit => ed.should_quit = true,
+            Self::Revert => ed.revert(),
+            Self::Overwrite(file_name) => {
+                // The modification time is reset, so that the file is saved unconditionally
+                ed.mtime = None;
+                ed.save_and_handle_io_errors(&file_name);
+            }
+        }
+    }
+
+    /// Cancel the action, after the user answered no or pressed another key. The key countdowns
+    /// start over.
+    fn cancel(self, ed: &mut Editor) {
+        (ed.quit_times, ed.revert_times) = (ed.config.quit_times, ed.config.quit_times);
+        match self {
+            Self::Quit => set_status!(ed, "Quit aborted"),
+            Self::Revert => set_status!(ed, "Revert aborted"),
+            Self::Overwrite(_) => set_status!(ed, "Save aborted"),
+        }
+    }
+}
+
+/// The prompt mode.
+enum PromptMode {
+    /// Save(prompt buffer)
+    Save(String),
+    /// Find(prompt buffer, saved cursor state, last match, find options)
+    Find(String, CursorState, Option<usize>, FindOptions),
+    /// Replace(search prompt buffer, replacement prompt buffer, position of the match being
+    /// considered, number of replacements so far). The replacement buffer is `None` until the
+    /// search term is entered, and the match position is `None` until the replacement is entered.
+    Replace(String, Option<String>, Option<(usize, usize)>, usize),
+    /// GoTo(prompt buffer, saved cursor state)
+    GoTo(String, CursorState),
+    /// Execute(prompt buffer)
+    Execute(String),
+    /// Pipe(prompt buffer): the command the selected rows, or all the rows, are filtered through
+    Pipe(String),
+    /// RecoverSwap(path of the swap file)
+    RecoverSwap(String),
+    /// Window: waiting for the key following the window key (split the screen or move the focus)
+    Window,
+    /// Command(prompt buffer)
+    Command(String),
+    /// Confirm(question, action to perform if the answer is yes)
+    Confirm(String, ConfirmAction),
+    /// QuickOpen(prompt buffer, index of the selected match, recently opened files)
+    QuickOpen(String, usize, Vec<String>),
+    /// FindFile(prompt buffer, index of the selected match, files of the working directory)
+    FindFile(String, usize, Vec<String>),
+    /// GoToSymbol(prompt buffer, index of the selected match, line index and text of the symbols)
+    GoToSymbol(String, usize, Vec<(usize, String)>),
+}
+
+impl PromptMode {
+    /// Return the status message to print for the selected `PromptMode`.
+    fn status_msg(&self, ed: &Editor) -> String {
+        match self {
+            Self::Save(buffer) => format!("Save as: {}", buffer),
+            #[cfg(feature = "regex")]
+            Self::Find(buffer, .., opts) if opts.regex && build_regex(buffer, *opts).is_err() => {
+                let err = build_regex(buffer, *opts).err().map(|e| e.to_string());
+                let err = err.as_deref().and_then(|e| e.lines().last()).unwrap_or_default();
+                let err = err.trim_start_matches("error: ");
+                format!("Search (invalid regex: {}): {}", err, buffer)
+            }
+            Self::Find(buffer, _, last_match, opts) => {
+                let toggles = format!("{}{}", find_toggles(&ed.config.keys), opts.describe());
+                let current = last_match.map(|_| (ed.buf.cursor.y, ed.buf.cursor.x));
+                let count = ed.match_count.as_ref().filter(|_| !buffer.is_empty());
+                let count = count.map(|c| format!(" [{}]", c.describe(current)));
+                let count = count.unwrap_or_default();
+                let keys = "Use ESC/Left/Right/Enter, Up/Down = history";
+                format!("Search{} ({}, {}): {}", count, keys, toggles, buffer)
+            }
+            Self::Replace(buffer, None, ..) => format!("Replace: {}", buffer),
+            Self::Replace(query, Some(buffer), None, _) =>
+                format!("Replace {} with: {}", query, buffer),
+            Self::Replace(..) => String::from("Replace this occurrence? (y)es/(n)o/(a)ll/ESC"),
+            Self::GoTo(buffer, _) =>
+                format!("Enter line number[:column number] or +/-offset: {}", buffer),
+            Self::Execute(buffer) => format!("Shell command to execute: {}", buffer),
+            Self::Pipe(buffer) => {
+                let rows = if ed.selection_anchor.is_some() { "selected rows" } else { "buffer" };
+                format!("Pipe the {} through: {}", rows, buffer)
+            }
+            Self::RecoverSwap(swap) =>
+                format!("Found newer swap file {}. Recover it? (y)es/(n)o, discard it/ESC", swap),
+            Self::Window => String::from("(v) split/unsplit, Left/Right = move focus, ESC"),
+            Self::Command(buffer) => format!("Command: {}", buffer),
+            Self::Confirm(question, _) => format!("{} (y)es/(n)o/ESC", question),
+            Self::QuickOpen(buffer, selected, files) => match filter_files(files, buffer)[..] {
+                [] => format!("Open recent (no match): {}", buffer),
+                ref matches => {
+                    let (file, i, n) = (matches[*selected], selected + 1, matches.len());
+                    format!("Open {} [{}/{}] (Use ESC/Up/Down/Enter): {}", file, i, n, buffer)
+                }
+            },
+            Self::FindFile(buffer, selected, files) => {
+                let n_matches = rank_files(files, buffer).len();
+                let capped = if files.len() == MAX_SCANNED_FILES { "+" } else { "" };
+                let count = format!("{} of {}{} files", n_matches, files.len(), capped);
+                let count =
+                    if n_matches == 0 { count } else { format!("{}/{}", selected + 1, count) };
+                format!("Find file [{}] (Use ESC/Up/Down/Enter): {}", count, buffer)
+            }
+            Self::GoToSymbol(buffer, selected, symbols) => {
+                let n_matches = filter_symbols(symbols, buffer).len();
+                let count = if n_matches == 0 { String::from("no match") } else {
+                    format!("{}/{}", selected + 1, n_matches)
+                };
+                format!("Go to symbol [{}] (Use ESC/Up/Down/Enter): {}", count, buffer)
+            }
+        }
+    }
+
+    /// Process a keypress event for the selected `PromptMode`.
+    fn process_keypress(self, ed: &mut Editor, key: &Key) -> Result<Option<Self>, Error> {
+        ed.status_msg = None;
+        // Pressing the key that opened a confirmation again counts down like outside the prompt
+        if let Self::Confirm(_, action) = &self {
+            if matches!(key, Key::Char(c) if Some(*c) == action.key(&ed.config.keys)) {
+                let (should_quit, prompt_mode) = ed.process_keypress(key);
+                ed.should_quit |= should_quit;
+                return Ok(prompt_mode);
+            }
+        }
+        // The quit key cancels the prompt, like Escape
+        let is_quit = matches!(key, Key::Char(c) if *c == ed.config.keys.quit);
+        let key = if is_quit { &Key::Escape } else { key };
+        match self {
+            Self::Save(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Ok(Some(Self::Save(b))),
+                PromptState::Cancelled => set_status!(ed, "Save aborted"),
+                PromptState::Completed(file_name) => ed.save_as(file_name)?,
+            },
+            Self::Find(b, saved_cursor, last_match, mut opts) => {
+                if let Some(row_idx) = last_match {
+                    ed.buf.rows[row_idx].match_segment = None;
+                }
+                match ed.find_history.process_keypress(b, key) {
+                    PromptState::Active(query) => {
+                        match key {
+                            Key::Char(TOGGLE_CASE) => opts.ignore_case ^= true,
+                            Key::Char(c) if *c == ed.config.keys.whole_word =>
+                                opts.whole_word ^= true,
+                            #[cfg(feature = "regex")]
+                            Key::Char(c) if *c == ed.config.keys.regex => opts.regex ^= true,
+                            _ => (),
+                        }
+                        let (last_match, forward) = match key {
+                            Key::Arrow(AKey::Right) => (last_match, true),
+                            Key::Char(c) if *c == ed.config.keys.find =>
+                                (last_match, true),
+                            Key::Arrow(AKey::Left) => (last_match, false),
+                            _ => (None, true),
+                        };
+                        let curr_match = ed.find(&query, &last_match, forward, opts);
+                        ed.update_match_count(&query, opts);
+                        ed.search = Some((query.clone(), opts));
+                        return Ok(Some(Self::Find(query, saved_cursor, curr_match, opts)));
+                    }
+                    // The prompt was cancelled. Restore the previous position.
+                    PromptState::Cancelled => ed.buf.cursor = saved_cursor,
+                    // Cursor has already been moved, only remember the query
+                    PromptState::Completed(query) => ed.find_history.push(&query),
+                }
+                (ed.search, ed.match_count) = (None, None);
+            }
+            Self::Replace(b, None, ..) => match ed.find_history.process_keypress(b, key) {
+                PromptState::Active(b) => return Ok(Some(Self::Replace(b, None, None, 0))),
+                PromptState::Completed(query) if !query.is_empty() => {
+                    ed.find_history.push(&query);
+                    return Ok(Some(Self::Replace(query, Some(String::new()), None, 0)));
+                }
+                PromptState::Cancelled | PromptState::Completed(_) =>
+                    set_status!(ed, "Replace aborted"),
+            },
+            Self::Replace(query, Some(b), None, _) =>
+                match ed.replace_history.process_keypress(b, key) {
+                    PromptState::Active(b) =>
+                        return Ok(Some(Self::Replace(query, Some(b), None, 0))),
+                    PromptState::Cancelled => set_status!(ed, "Replace aborted"),
+                    PromptState::Completed(replacement) => {
+                        ed.replace_history.push(&replacement);
+                        let (y, x) = (ed.buf.cursor.y, ed.buf.cursor.x);
+                        match ed.buf.find_from(query.as_bytes(), y, x) {
+                            Some((y, x)) => {
+                                ed.move_to_match(y, x, query.len());
+                                let pos = Some((y, x));
+                                return Ok(Some(Self::Replace(query, Some(replacement), pos, 0)));
+                            }
+                            None => set_status!(ed, "No match found for {}", query),
+                        }
+                    }
+                },
+            Self::Replace(query, Some(replacement), Some((y, x)), mut n_replaced) => {
+                ed.buf.rows[y].match_segment = None;
+                let (q, r) = (query.as_bytes(), replacement.as_bytes());
+                let next_match = match key {
+                    Key::Char(b'y') => {
+                        ed.buf.replace_at(y, x, q.len(), r);
+                        n_replaced += 1;
+                        ed.buf.find_from(q, y, x + r.len())
+                    }
+                    Key::Char(b'n') => ed.buf.find_from(q, y, x + q.len()),
+                    Key::Char(b'a') => {
+                        let mut next_match = Some((y, x));
+                        while let Some((y, x)) = next_match {
+                            ed.buf.replace_at(y, x, q.len(), r);
+                            n_replaced += 1;
+                            next_match = ed.buf.find_from(q, y, x + r.len());
+                        }
+                        None
+                    }
+                    Key::Escape => None,
+                    _ => Some((y, x)),
+                };
+                if let Some((y, x)) = next_match {
+                    ed.move_to_match(y, x, q.len());
+                    let replacement = Some(replacement);
+                    return Ok(Some(Self::Replace(query, replacement, next_match, n_replaced)));
+                }
+                set_status!(ed, "{} occurrence(s) replaced", n_replaced);
+            }
+            Self::GoTo(b, saved_cursor) => match ed.goto_history.process_keypress(b, key) {
+                PromptState::Active(b) => {
+                    // Preview the position as the user types; errors are only shown when the
+                    // prompt is completed.
+                    ed.buf.cursor = saved_cursor.clone();
+                    let _ = ed.go_to(&b, saved_cursor.y);
+                    return Ok(Some(Self::GoTo(b, saved_cursor)));
+                }
+                // The prompt was cancelled. Restore the previous position.
+                PromptState::Cancelled => ed.buf.cursor = saved_cursor,
+                PromptState::Completed(b) => {
+                    ed.goto_history.push(&b);
+                    ed.buf.cursor = saved_cursor.clone();
+                    if let Err(e) = ed.go_to(&b, saved_cursor.y) {
+                        set_status!(ed, Error: "Parsing error: {}", e);
+                    }
+                }
+            },
+            Self::RecoverSwap(swap) => match key {
+                Key::Char(b'y') => match ed.recover_swap_file(&swap) {
+                    Ok(()) => set_status!(ed, "Recovered from {}", swap),
+                    Err(err) => set_status!(ed, Error: "Can't recover from {}: {:?}", swap, err),
+                },
+                Key::Char(b'n') => match std::fs::remove_file(&swap) {
+                    Ok(()) => set_status!(ed, "Discarded {}", swap),
+                    Err(err) => set_status!(ed, Error: "Can't discard {}: {}", swap, err),
+                },
+                Key::Escape => (),
+                _ => return Ok(Some(Self::RecoverSwap(swap))),
+            },
+            Self::Confirm(_, action) => match key {
+                Key::Char(b'y') => action.perform(ed),
+                // Like outside the prompt, any other key resets the countdown
+                _ => action.cancel(ed),
+            },
+            Self::Command(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Ok(Some(Self::Command(b))),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => return ed.run_command(&b),
+            },
+            Self::Window => match key {
+                Key::Char(b'v') => ed.toggle_split(),
+                Key::Arrow(AKey::Left) => ed.focus_pane(true),
+                Key::Arrow(AKey::Right) => ed.focus_pane(false),
+                _ => (),
+            },
+            Self::Execute(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Ok(Some(Self::Execute(b))),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => ed.execute(&b),
+            },
+            Self::Pipe(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Ok(Some(Self::Pipe(b))),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => ed.pipe_rows(&b),
+            },
+            Self::QuickOpen(b, selected, files) => {
+                let state =
+                    process_file_prompt_keypress(ed, b, selected, &files, key, filter_files);
+                return Ok(state.map(|(b, selected)| Self::QuickOpen(b, selected, files)));
+            }
+            Self::FindFile(b, selected, files) => {
+                let state = process_file_prompt_keypress(ed, b, selected, &files, key, rank_files);
+                return Ok(state.map(|(b, selected)| Self::FindFile(b, selected, files)));
+            }
+            Self::GoToSymbol(b, selected, symbols) => {
+                let n_matches = filter_symbols(&symbols, &b).len();
+                match process_select_keypress(b, selected, n_matches, key) {
+                    (PromptState::Active(b), selected) =>
+                        return Ok(Some(Self::GoToSymbol(b, selected, symbols))),
+                    (PromptState::Cancelled, _) => (),
+                    (PromptState::Completed(b), selected) =>
+                        match filter_symbols(&symbols, &b).get(selected) {
+                            Some((y, _)) => ed.center_on_line(*y),
+                            None => set_status!(ed, "No symbol matching {}", b),
+                        },
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Process a keypress event in a prompt selecting a file among `files`. `matches` returns the
+/// files matching the query, the best match first. Up and Down move the selection, and Enter opens
+/// the selected file in the active buffer. Return the new prompt buffer and selection, or `None` if
+/// the prompt is closed.
+fn process_file_prompt_keypress(
+    ed: &mut Editor, buffer: String, selected: usize, files: &[String], key: &Key,
+    matches: for<'a> fn(&'a [String], &str) -> Vec<&'a str>,
+) -> Option<(String, usize)> {
+    let n_matches = matches(files, &buffer).len();
+    match process_select_keypress(buffer, selected, n_matches, key) {
+        (PromptState::Active(query), selected) => return Some((query, selected)),
+        (PromptState::Cancelled, _) => (),
+        (PromptState::Completed(query), selected) => match matches(files, &query).get(selected) {
+            Some(file) => ed.open_in_active_buffer(file),
+            None => set_status!(ed, "No file matching {}", query),
+        },
+    }
+    None
+}
+
+/// Process a keypress event in a prompt selecting an item among `n_matches` items matching the
+/// prompt buffer. Up and Down move the selection. Return the new state of the prompt and the new
+/// selection, which moves back to the first match when the query changes.
+fn process_select_keypress(
+    buffer: String, selected: usize, n_matches: usize, key: &Key,
+) -> (PromptState, usize) {
+    let selected = match key {
+        Key::Arrow(AKey::Up) => selected.saturating_sub(1),
+        Key::Arrow(AKey::Down) => (selected + 1).min(n_matches.saturating_sub(1)),
+        _ => selected,
+    };
+    match process_prompt_keypress(buffer.clone(), key) {
+        PromptState::Active(query) if query != buffer => (PromptState::Active(query), 0),
+        state => (state, selected),
+    }
+}
+
+/// Return the symbols whose text contains `query`, ignoring case.
+fn filter_symbols<'a>(symbols: &'a [(usize, String)], query: &str) -> Vec<&'a (usize, String)> {
+    let query = query.to_lowercase();
+    symbols.iter().filter(|(_, text)| text.to_lowercase().contains(&query)).collect()
+}
+
+/// Return the paths of the files in `dir` and its subdirectories, relative to `dir`, sorted by
+/// depth then by name. Hidden files and directories (e.g. `.git`) are skipped, as well as the
+/// symbolic links to directories. At most `MAX_SCANNED_FILES` files are returned, and the
+/// directories deeper than `FIND_FILE_MAX_DEPTH` are not scanned.
+fn scan_files(dir: &Path) -> Vec<String> {
+    let (mut files, mut dirs) = (Vec::new(), std::collections::VecDeque::from([(dir.into(), 0)]));
+    while let Some((current, depth)) = dirs.pop_front() {
+        let Ok(entries) = std::fs::read_dir::<&PathBuf>(&current) else { continue };
+        let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
+        entries.sort_by_key(std::fs::DirEntry::file_name);
+        for entry in entries.iter().filter(|e| !e.file_name().to_string_lossy().starts_with('.')) {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(ft) if ft.is_dir() && depth < FIND_FILE_MAX_DEPTH =>
+                    dirs.push_back((path, depth + 1)),
+                Ok(_) if path.is_file() => {
+                    let relative = path.strip_prefix(dir).unwrap_or(&path);
+                    files.push(relative.to_string_lossy().to_string());
+                    if files.len() == MAX_SCANNED_FILES {
+                        return files;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    files
+}
+
+/// Return the fuzzy score of `candidate` for `query`, ignoring case, or `None` if the characters
+/// of the query do not appear in order in the candidate. Characters following the previous
+/// matching character, and characters starting a path component or a word, score higher.
+fn fuzzy_score(candidate: &str, query: &str) -> Option<usize> {
+    let candidate: Vec<_> = candidate.chars().flat_map(char::to_lowercase).collect();
+    let mut query = query.chars().flat_map(char::to_lowercase).peekable();
+    let (mut score, mut last_match) = (0, None);
+    for (i, c) in candidate.iter().enumerate() {
+        if query.next_if_eq(c).is_none() {
+            continue;
+        }
+        score += 1;
+        if i > 0 && last_match == Some(i - 1) {
+            score += 2;
+        }
+        if i == 0 || matches!(candidate[i - 1], '/' | '\\' | '_' | '-' | '.' | ' ') {
+            score += 2;
+        }
+        last_match = Some(i);
+    }
+    query.peek().is_none().then_some(score)
+}
+
+/// Return the files matching the fuzzy `query`, from the best to the worst score. Files with the
+/// same score are sorted by length, then by name.
+fn rank_files<'a>(files: &'a [String], query: &str) -> Vec<&'a str> {
+    let mut ranked: Vec<_> =
+        files.iter().filter_map(|f| Some((fuzzy_score(f, query)?, f.as_str()))).collect();
+    ranked.sort_by_key(|(score, f)| (std::cmp::Reverse(*score), f.len(), *f));
+    ranked.into_iter().map(|(_, f)| f).collect()
+}
+
+/// Return the files whose path contains `query`, ignoring case.
+fn filter_files<'a>(files: &'a [String], query: &str) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    files.iter().filter(|f| f.to_lowercase().contains(&query)).map(String::as_str).collect()
+}
+
+/// The state of the prompt after processing a keypress event.
+enum PromptState {
+    // Active contains the current buffer
+    Active(String),
+    // Completed contains the final string
+    Completed(String),
+    Cancelled,
+}
+
+/// Process a prompt keypress event and return the new state for the prompt.
+fn process_prompt_keypress(mut buffer: String, key: &Key) -> PromptState {
+    match key {
+        Key::Char(b'\r') => return PromptState::Completed(buffer),
+        Key::Escape => return PromptState::Cancelled,
+        Key::Char(BACKSPACE | DELETE_BIS) => {
+            buffer.pop();
+        }
+        Key::Char(c @ 0..=126) if !c.is_ascii_control() => buffer.push(*c as char),
+        Key::Paste(text) => buffer.extend(
+            text.iter().filter(|c| **c <= 126 && !c.is_ascii_control()).map(|c| *c as char),
+        ),
+        // No-op
+        _ => (),
+    }
+    PromptState::Active(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use super::*;
+    use crate::config::tests::TempEnvVar;
+    use crate::row::HlState;
+    use crate::syntax::HlType;
+
+    #[test]
+    fn format_size_output() {
+        assert_eq!(format_size(0), "0B");
+        assert_eq!(format_size(1), "1B");
+        assert_eq!(format_size(1023), "1023B");
+        assert_eq!(format_size(1024), "1.00kB");
+        assert_eq!(format_size(1536), "1.50kB");
+        // round down!
+        assert_eq!(format_size(21 * 1024 - 11), "20.98kB");
+        assert_eq!(format_size(21 * 1024 - 10), "20.99kB");
+        assert_eq!(format_size(21 * 1024 - 3), "20.99kB");
+        assert_eq!(format_size(21 * 1024), "21.00kB");
+        assert_eq!(format_size(21 * 1024 + 3), "21.00kB");
+        assert_eq!(format_size(21 * 1024 + 10), "21.00kB");
+        assert_eq!(format_size(21 * 1024 + 11), "21.01kB");
+        assert_eq!(format_size(1024 * 1024 - 1), "1023.99kB");
+        assert_eq!(format_size(1024 * 1024), "1.00MB");
+        assert_eq!(format_size(1024 * 1024 + 1), "1.00MB");
+        assert_eq!(format_size(100 * 1024 * 1024 * 1024), "100.00GB");
+        assert_eq!(format_size(313 * 1024 * 1024 * 1024 * 1024), "313.00TB");
+    }
+
+    #[test]
+    fn slice_find_with_options() {
+        let opts = |ignore_case, whole_word| FindOptions { ignore_case, whole_word, regex: false };
+        assert_eq!(slice_find_with(b"Hello hello", b"hello", opts(false, false)), Some(6));
+        assert_eq!(slice_find_with(b"Hello hello", b"hello", opts(true, false)), Some(0));
+        assert_eq!(slice_find_with(b"the other", b"the", opts(false, true)), Some(0));
+        assert_eq!(slice_find_with(b"other the", b"the", opts(false, true)), Some(6));
+        assert_eq!(slice_find_with(b"others", b"the", opts(false, true)), None);
+        assert_eq!(slice_find_with(b"THE_END the", b"the", opts(true, true)), Some(8));
+        // "é" is encoded as [0xc3, 0xa9]: the needle must not match the second byte only
+        assert_eq!(slice_find_with("é".as_bytes(), &[0xa9], opts(false, false)), None);
+    }
+
+    #[test]
+    fn editor_insert_byte() {
+        let mut editor = Editor::default();
+        let editor_cursor_x_before = editor.buf.cursor.x;
+
+        editor.insert_byte(b'X');
+        editor.insert_byte(b'Y');
+        editor.insert_byte(b'Z');
+
+        assert_eq!(editor.buf.cursor.x, editor_cursor_x_before + 3);
+        assert_eq!(editor.buf.rows.len(), 1);
+        assert_eq!(editor.buf.n_bytes, 3);
+        assert_eq!(editor.buf.rows[0].chars, [b'X', b'Y', b'Z']);
+    }
+
+    #[test]
+    fn editor_insert_new_line() {
+        let mut editor = Editor::default();
+        let editor_cursor_y_before = editor.buf.cursor.y;
+
+        for _ in 0..3 {
+            editor.insert_new_line();
+        }
+
+        assert_eq!(editor.buf.cursor.y, editor_cursor_y_before + 3);
+        assert_eq!(editor.buf.rows.len(), 3);
+        assert_eq!(editor.buf.n_bytes, 0);
+
+        for row in &editor.buf.rows {
+            assert_eq!(row.chars, []);
+        }
+    }
+    #[test]
+    fn editor_insert_new_line_auto_indent() {
+        let mut editor = Editor::default();
+        for b in "\t  fn x".as_bytes() {
+            editor.insert_byte(*b);
+        }
+        editor.insert_new_line();
+        assert_eq!(editor.buf.rows[1].chars, "\t  ".as_bytes());
+        assert_eq!(editor.buf.cursor.x, 3);
+        assert_eq!(editor.buf.n_bytes, 10);
+
+        // Splitting in the middle of the indentation only copies the whitespace before the cursor
+        editor.buf.cursor.y = 0;
+        editor.buf.cursor.x = 2;
+        editor.insert_new_line();
+        assert_eq!(editor.buf.rows[0].chars, "\t ".as_bytes());
+        assert_eq!(editor.buf.rows[1].chars, "\t  fn x".as_bytes());
+
+        editor.config.auto_indent = false;
+        editor.buf.cursor.x = 3;
+        editor.insert_new_line();
+        assert_eq!(editor.buf.rows[2].chars, "fn x".as_bytes());
+        assert_eq!(editor.buf.cursor.x, 0);
+    }
+
+    #[test]
+    fn editor_bracketed_paste() {
+        let mut input = b"fn f() {\r    x();\r}\x1b[201~rest".iter().map(|b| Ok(*b));
+        let text = read_paste(&mut input).unwrap();
+        assert_eq!(text, b"fn f() {\r    x();\r}");
+        assert_eq!(input.next().transpose().unwrap(), Some(b'r'));
+
+        // The pasted text is not auto-indented
+        let mut editor = Editor::default();
+        editor.load_text(b"    ab");
+        editor.buf.cursor.x = 5;
+        editor.process_keypress(&Key::Paste(text));
+        assert_eq!(editor.buf.text(), "    afn f() {\n    x();\n}b");
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x, editor.buf.n_bytes), (2, 1, 23));
+
+        editor.read_only = true;
+        editor.process_keypress(&Key::Paste(b"x".to_vec()));
+        assert_eq!(editor.buf.n_bytes, 23);
+        let state = process_prompt_keypress(String::from("a"), &Key::Paste(b"b\rc\x1b".to_vec()));
+        assert!(matches!(state, PromptState::Active(buffer) if buffer == "abc"));
+    }
+
+    #[test]
+    fn editor_soft_tabs() {
+        let mut editor = Editor::default();
+        editor.config.set("indent_style", "space").unwrap();
+        editor.load_text(b"ab");
+        editor.buf.cursor.x = 1;
+        editor.insert_tab();
+        editor.insert_tab();
+        assert_eq!(editor.buf.rows[0].chars, b"a       b");
+        // Auto-indentation copies the spaces, which are then deleted one tab stop at a time
+        editor.buf.cursor.x = 0;
+        editor.insert_tab();
+        editor.buf.cursor.x = 13;
+        editor.insert_new_line();
+        assert_eq!((editor.buf.rows[1].chars.as_slice(), editor.buf.cursor.x), (&b"    "[..], 4));
+        editor.insert_byte(b' ');
+        editor.backspace();
+        assert_eq!(editor.buf.cursor.x, 4);
+        editor.backspace();
+        assert_eq!((editor.buf.rows[1].chars.as_slice(), editor.buf.cursor.x), (&b""[..], 0));
+        editor.backspace();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (0, 13));
+        // Spaces that do not reach the previous tab stop are deleted one by one
+        editor.buf.cursor.x = 6;
+        editor.backspace();
+        assert_eq!(editor.buf.rows[0].chars, b"    a      b");
+
+        editor.config.set("indent_style", "tab").unwrap();
+        editor.buf.cursor.x = 4;
+        editor.backspace();
+        assert_eq!(editor.buf.rows[0].chars, b"   a      b");
+        assert!(editor.config.set("indent_style", "tabs").is_err());
+    }
+
+    #[test]
+    fn editor_indent_dedent_rows() {
+        let mut editor = Editor::default();
+        editor.config.auto_indent = false;
+        for b in "a\n\tb\n  c".as_bytes() {
+            match b {
+                b'\n' => editor.insert_new_line(),
+                b => editor.insert_byte(*b),
+            }
+        }
+        editor.indent_rows(0..3);
+        let rows: Vec<_> = editor.buf.rows.iter().map(|r| r.chars.clone()).collect();
+        assert_eq!(rows, ["    a".as_bytes(), "    \tb".as_bytes(), "      c".as_bytes()]);
+        assert_eq!(editor.buf.n_bytes, 18);
+        assert_eq!(editor.buf.cursor.x, 7);
+        editor.dedent_rows(0..3);
+        editor.dedent_rows(0..3);
+        let rows: Vec<_> = editor.buf.rows.iter().map(|r| r.chars.clone()).collect();
+        assert_eq!(rows, ["a".as_bytes(), "b".as_bytes(), "c".as_bytes()]);
+        assert_eq!(editor.buf.n_bytes, 3);
+        assert_eq!(editor.buf.cursor.x, 1);
+    }
+
+    #[test]
+    fn editor_toggle_comment() {
+        let mut editor = Editor::default();
+        editor.config.auto_indent = false;
+        for b in "  a\n\n// b".as_bytes() {
+            match b {
+                b'\n' => editor.insert_new_line(),
+                b => editor.insert_byte(*b),
+            }
+        }
+        editor.toggle_comment(0..3);
+        assert_eq!(editor.buf.rows[0].chars, "  a".as_bytes());
+        assert!(editor.status_msg.is_some());
+
+        editor.buf.syntax.sl_comment_start = vec![String::from("//")];
+        editor.toggle_comment(0..3);
+        let rows: Vec<_> = editor.buf.rows.iter().map(|r| r.chars.clone()).collect();
+        assert_eq!(rows, ["  // a".as_bytes(), b"", "// // b".as_bytes()]);
+        assert_eq!(editor.buf.n_bytes, 13);
+        editor.toggle_comment(0..3);
+        let rows: Vec<_> = editor.buf.rows.iter().map(|r| r.chars.clone()).collect();
+        assert_eq!(rows, ["  a".as_bytes(), b"", "// b".as_bytes()]);
+        assert_eq!(editor.buf.n_bytes, 7);
+    }
+
+    #[test]
+    fn editor_find_matching_bracket() {
+        let mut editor = Editor::default();
+        editor.config.auto_indent = false;
+        editor.buf.syntax.sl_string_quotes = vec!['"'];
+        for b in "f(a[0], \")\") {\n  {}\n}".as_bytes() {
+            match b {
+                b'\n' => editor.insert_new_line(),
+                b => editor.insert_byte(*b),
+            }
+        }
+        editor.buf.highlight_rows(editor.buf.rows.len());
+        assert_eq!(editor.find_matching_bracket(0, 1), Some((0, 11)));
+        assert_eq!(editor.find_matching_bracket(0, 11), Some((0, 1)));
+        assert_eq!(editor.find_matching_bracket(0, 3), Some((0, 5)));
+        assert_eq!(editor.find_matching_bracket(0, 13), Some((2, 0)));
+        assert_eq!(editor.find_matching_bracket(2, 0), Some((0, 13)));
+        assert_eq!(editor.find_matching_bracket(1, 2), Some((1, 3)));
+        // Not a bracket, or a bracket in a string
+        assert_eq!(editor.find_matching_bracket(0, 0), None);
+        assert_eq!(editor.find_matching_bracket(0, 9), None);
+    }
+
+    #[test]
+    fn editor_lazy_highlighting() {
+        let mut editor = Editor::default();
+        editor.buf.syntax.ml_comment_delims = Some((String::from("/*"), String::from("*/")));
+        editor.screen_rows = 10;
+        editor.load_text("a\n".repeat(100).as_bytes());
+        assert_eq!(editor.buf.highlighted_up_to, 10);
+
+        // Opening a comment on the first row propagates up to the watermark only
+        editor.insert_byte(b'/');
+        editor.insert_byte(b'*');
+        assert!(editor.buf.rows[9].hl_state == HlState::MultiLineComment);
+        assert!(editor.buf.rows[50].hl_state == HlState::Normal);
+
+        editor.buf.highlight_rows(60);
+        assert_eq!(editor.buf.highlighted_up_to, 60);
+        assert!(editor.buf.rows[50].hl_state == HlState::MultiLineComment);
+
+        // Merging two rows moves the watermark up
+        (editor.buf.cursor.y, editor.buf.cursor.x) = (5, 0);
+        editor.delete_char();
+        assert_eq!(editor.buf.highlighted_up_to, 59);
+
+        // So does removing a row, while inserting rows moves it down
+        editor.delete_current_row();
+        assert_eq!(editor.buf.highlighted_up_to, 58);
+        editor.insert_new_line();
+        assert_eq!(editor.buf.highlighted_up_to, 59);
+        editor.duplicate_rows(false);
+        assert_eq!(editor.buf.highlighted_up_to, 60);
+        // Rows inserted below the watermark are highlighted when they are scrolled into view
+        editor.buf.cursor.y = 70;
+        editor.duplicate_rows(false);
+        assert_eq!(editor.buf.highlighted_up_to, 60);
+    }
+
+    #[test]
+    fn editor_word_movement() {
+        let mut editor = Editor::default();
+        editor.load_text("fn été(x: u8) {\n    x + 1 }".as_bytes());
+        let mut positions = Vec::new();
+        for _ in 0..8 {
+            editor.move_cursor_word_right();
+            positions.push((editor.buf.cursor.y, editor.buf.cursor.x));
+        }
+        let expected = [(0, 3), (0, 9), (0, 12), (0, 17), (1, 4), (1, 8), (1, 11), (1, 11)];
+        assert_eq!(positions, expected);
+
+        positions.clear();
+        for _ in 0..7 {
+            editor.move_cursor_word_left();
+            positions.push((editor.buf.cursor.y, editor.buf.cursor.x));
+        }
+        let expected = [(1, 8), (1, 4), (1, 0), (0, 14), (0, 12), (0, 9), (0, 3)];
+        assert_eq!(positions, expected);
+    }
+
+    #[test]
+    fn editor_delete_words() {
+        let mut editor = Editor::default();
+        editor.load_text(b"let value = 42;\nfoo");
+        (editor.buf.cursor.y, editor.buf.cursor.x) = (0, 9);
+        editor.delete_word_left();
+        assert_eq!(editor.buf.rows[0].chars, b"let  = 42;");
+        assert_eq!((editor.buf.cursor.x, editor.buf.n_bytes), (4, 13));
+        assert!(editor.buf.dirty);
+
+        editor.delete_word_right();
+        assert_eq!(editor.buf.rows[0].chars, b"let 42;");
+        editor.delete_word_right();
+        assert_eq!(editor.buf.rows[0].chars, b"let ");
+
+        // At the end of a row, the next row is merged
+        editor.delete_word_right();
+        assert_eq!(editor.buf.rows.len(), 1);
+        assert_eq!(editor.buf.rows[0].chars, b"let foo");
+
+        // At the beginning of a row, the row is merged with the previous one
+        editor.insert_new_line();
+        editor.delete_word_left();
+        assert_eq!(editor.buf.rows[0].chars, b"let foo");
+        assert_eq!(editor.buf.n_bytes, 7);
+
+        // Ctrl-H is Backspace on some terminals, and Alt-Backspace deletes a word
+        editor.buf.cursor.x = 7;
+        editor.process_keypress(&Key::Char(DELETE_BIS));
+        assert_eq!(editor.buf.rows[0].chars, b"let fo");
+        editor.process_keypress(&Key::AltChar(BACKSPACE));
+        assert_eq!(editor.buf.rows[0].chars, b"let ");
+    }
+
+    #[test]
+    fn editor_autosave() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("autosave.txt");
+        let mut editor = Editor::default();
+        editor.load_text(b"abc");
+        editor.buf.file_name = Some(path.to_string_lossy().to_string());
+        editor.insert_byte(b'x');
+        assert!(!editor.autosave_if_due(), "Auto-save is disabled by default");
+
+        editor.config.autosave_interval = Duration::from_secs(60);
+        editor.last_save = Some(Instant::now());
+        assert!(!editor.autosave_if_due(), "The interval has not elapsed");
+
+        editor.last_save = Instant::now().checked_sub(Duration::from_secs(61));
+        assert!(editor.autosave_if_due());
+        assert!(!editor.buf.dirty);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xabc\n");
+        assert!(!editor.autosave_if_due(), "The buffer is not dirty");
+    }
+
+    #[test]
+    fn editor_save_outcome() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("saved.txt");
+        let mut editor = Editor::default();
+        editor.load_text(b"abc\n");
+        editor.insert_byte(b'x');
+        let outcome = editor.save_file(&path.to_string_lossy()).unwrap();
+        assert_eq!(outcome, SaveOutcome { bytes_written: 5, path: path.clone() });
+        assert!(!editor.buf.dirty);
+
+        editor.insert_byte(b'y');
+        let missing = tmp_dir.path().join("missing").join("saved.txt");
+        match editor.save_file(&missing.to_string_lossy()) {
+            Err(Error::Save(p, err)) => {
+                assert_eq!((p, err.kind()), (missing, io::ErrorKind::NotFound));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(editor.buf.dirty);
+        assert!(!editor.save_and_handle_io_errors(&tmp_dir.path().join("a/b").to_string_lossy()));
+        let status = editor.status_msg.as_ref().map(|m| m.msg.as_str()).unwrap_or_default();
+        assert!(status.starts_with("Can't save! I/O error: "), "{}", status);
+
+        // A failed save keeps the buffer modified, until a save succeeds
+        assert!(editor.buf.dirty);
+        assert!(editor.save_and_handle_io_errors(&path.to_string_lossy()));
+        assert!(!editor.buf.dirty);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xyabc\n");
+    }
+
+    #[test]
+    fn editor_swap_file() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("swap.txt");
+        let file_name = path.to_string_lossy().to_string();
+        let swap = swap_path(&file_name);
+        std::fs::write(&path, "abc\n").unwrap();
+        let mut editor = Editor::default();
+        editor.load(&path).unwrap();
+        editor.buf.file_name = Some(file_name.clone());
+        assert_eq!(editor.newer_swap_file(), None);
+
+        editor.insert_byte(b'x');
+        editor.update_swap_file();
+        assert_eq!(std::fs::read_to_string(&swap).unwrap(), "xabc\n");
+        // Saving removes the swap file
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert!(!Path::new(&swap).exists());
+
+        // A swap file left by a crash is more recent than the file
+        std::fs::write(&swap, "recovered\n").unwrap();
+        let modified = std::fs::metadata(&swap).unwrap().modified().unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified - Duration::from_secs(10)).unwrap();
+        assert_eq!(editor.newer_swap_file().as_deref(), Some(swap.as_str()));
+        editor.recover_swap_file(&swap).unwrap();
+        assert_eq!(editor.buf.rows.len(), 2);
+        assert_eq!(editor.buf.rows[0].chars, b"recovered");
+        assert!(editor.buf.dirty);
+        // Saving right after recovering removes the swap file
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert!(!Path::new(&swap).exists());
+    }
+
+    #[test]
+    fn editor_switch_buffers() {
+        let mut editor = Editor::default();
+        editor.load_text(b"first");
+        editor.buffers.push(Buffer::default());
+        editor.switch_buffer(1);
+        assert!(editor.buf.rows.is_empty());
+        editor.load_text(b"second\nbuffer");
+        editor.insert_byte(b'x');
+
+        editor.switch_buffer(0);
+        assert_eq!(editor.active, 0);
+        assert_eq!(editor.buf.rows[0].chars, b"first");
+        assert!(!editor.buf.dirty);
+        assert_eq!(editor.buf.n_bytes, 5);
+
+        editor.process_keypress(&Key::CtrlTab);
+        assert_eq!(editor.active, 1);
+        assert_eq!(editor.buf.rows[0].chars, b"xsecond");
+        assert!(editor.buf.dirty);
+        assert_eq!(editor.buffers.len(), 1);
+        editor.process_keypress(&Key::CtrlShiftTab);
+        assert_eq!(editor.active, 0);
+    }
+
+    #[test]
+    fn editor_editorconfig() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let ini = "root = true\n[*.txt]\nindent_size = 2\nend_of_line = crlf\n";
+        std::fs::write(tmp_dir.path().join(".editorconfig"), ini).unwrap();
+        std::fs::write(tmp_dir.path().join("a.txt"), "a\n").unwrap();
+        std::fs::write(tmp_dir.path().join("b.md"), "b\n").unwrap();
+        let mut editor = Editor::default();
+        editor.open(&tmp_dir.path().join("a.txt")).unwrap();
+        assert_eq!((editor.config.tab_stop, editor.buf.line_ending), (2, LineEnding::CrLf));
+
+        editor.buffers.push(Buffer::default());
+        editor.switch_buffer(1);
+        editor.open(&tmp_dir.path().join("b.md")).unwrap();
+        assert_eq!((editor.config.tab_stop, editor.buf.line_ending), (4, LineEnding::Lf));
+        editor.switch_buffer(0);
+        assert_eq!(editor.config.tab_stop, 2);
+        editor.switch_buffer(1);
+        assert_eq!(editor.config, Config::default());
+    }
+
+    #[test]
+    fn editor_open() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("a.txt");
+        let mut editor = Editor::default();
+        match editor.open(&path) {
+            Err(Error::FileNotFound(p)) => assert_eq!(p, path),
+            r => panic!("Unexpected result {:?}", r),
+        }
+        match editor.open(tmp_dir.path()) {
+            Err(Error::NotARegularFile(p)) => assert_eq!(p, tmp_dir.path()),
+            r => panic!("Unexpected result {:?}", r),
+        }
+        std::fs::write(&path, "a\nb\n").unwrap();
+        editor.open(&path).unwrap();
+        assert_eq!(editor.text(), "a\nb\n");
+        assert_eq!(editor.buf.file_name, Some(path.to_string_lossy().to_string()));
+        assert!(editor.mtime.is_some() && !editor.buf.dirty);
+    }
+
+    #[test]
+    fn editor_state_files() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let (path, state_dir) = (tmp_dir.path().join("a.txt"), tmp_dir.path().join("state"));
+        std::fs::write(&path, "a\nbb\nccc\n").unwrap();
+        // Without a state directory, nothing is persisted
+        let mut editor = Editor::default();
+        editor.open(&path).unwrap();
+        editor.buf.cursor.y = 2;
+        editor.store_cursor_position();
+        assert!(!state_dir.exists());
+
+        editor.state_dir = Some(state_dir.clone());
+        editor.open(&path).unwrap();
+        (editor.buf.cursor.y, editor.buf.cursor.x) = (2, 1);
+        editor.store_cursor_position();
+        let mut editor = Editor::default();
+        editor.state_dir = Some(state_dir);
+        editor.open(&path).unwrap();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (2, 1));
+        editor.buf.file_name = None;
+        let path = std::fs::canonicalize(&path).unwrap();
+        assert_eq!(editor.recent_files(), [path.to_string_lossy()]);
+    }
+
+    #[test]
+    fn file_positions() {
+        assert_eq!(split_file_position("a.rs"), ("a.rs", None));
+        assert_eq!(split_file_position("a.rs:12"), ("a.rs", Some("12")));
+        assert_eq!(split_file_position("a.rs:12:5"), ("a.rs", Some("12:5")));
+        assert_eq!(split_file_position("a:b.rs:12:5:7"), ("a:b.rs:12", Some("5:7")));
+        assert_eq!(split_file_position(r"C:\src\a.rs:3"), (r"C:\src\a.rs", Some("3")));
+        assert_eq!(split_file_position(r"C:\src\a.rs"), (r"C:\src\a.rs", None));
+        for file_name in ["a.rs:", "a.rs:x", "a.rs:-1", ":12", "12"] {
+            assert_eq!(split_file_position(file_name), (file_name, None));
+        }
+    }
+
+    #[test]
+    fn editor_open_at_position() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("a.txt");
+        let text: String = (1..=50).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &text).unwrap();
+        let mut editor = Editor::default();
+        editor.set_window_size((12, 80));
+        let file_name = path.to_string_lossy().to_string();
+        editor.open_at_position(&format!("{}:40:6", file_name)).unwrap();
+        assert_eq!(editor.buf.file_name.as_ref(), Some(&file_name));
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (39, 5));
+        // The row is scrolled into view
+        editor.draw_screen();
+        let roff = editor.buf.cursor.roff;
+        assert!((roff..roff + editor.screen_rows).contains(&39));
+
+        // A file whose name ends with a number is opened as is
+        let path = tmp_dir.path().join("b.txt:7");
+        std::fs::write(&path, "b\n").unwrap();
+        let file_name = path.to_string_lossy().to_string();
+        let mut editor = Editor::default();
+        editor.open_at_position(&file_name).unwrap();
+        assert_eq!(editor.buf.file_name.as_ref(), Some(&file_name));
+        assert_eq!(editor.buf.cursor.y, 0);
+    }
+
+    #[test]
+    fn editor_quick_open() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let files: Vec<_> = ["notes.txt", "main.RS", "lib.rs"].iter().map(|name| {
+            let path = tmp_dir.path().join(name);
+            std::fs::write(&path, name).unwrap();
+            path.to_string_lossy().to_string()
+        }).collect();
+        assert_eq!(filter_files(&files, ".rs"), [&files[1], &files[2]]);
+
+        let mut editor = Editor::default();
+        editor.load_text(b"unsaved");
+        editor.buf.dirty = true;
+        let mut prompt = PromptMode::QuickOpen(String::new(), 0, files.clone());
+        let down = || Key::Arrow(AKey::Down);
+        for key in [Key::Char(b'r'), Key::Char(b's'), down(), down()] {
+            prompt = prompt.process_keypress(&mut editor, &key).unwrap().unwrap();
+        }
+        let msg = format!("Open {} [2/2] (Use ESC/Up/Down/Enter): rs", files[2]);
+        assert_eq!(prompt.status_msg(&editor), msg);
+        assert!(prompt.process_keypress(&mut editor, &Key::Char(b'\r')).unwrap().is_none());
+        assert_eq!(editor.text(), "unsaved");
+
+        editor.buf.dirty = false;
+        let prompt = PromptMode::QuickOpen(String::from("rs"), 1, files.clone());
+        assert!(prompt.process_keypress(&mut editor, &Key::Char(b'\r')).unwrap().is_none());
+        assert_eq!(editor.text(), "lib.rs");
+        assert_eq!(editor.buf.file_name.as_ref(), Some(&files[2]));
+
+        std::fs::remove_file(&files[0]).unwrap();
+        let prompt = PromptMode::QuickOpen(String::new(), 0, files.clone());
+        assert!(prompt.process_keypress(&mut editor, &Key::Char(b'\r')).unwrap().is_none());
+        assert_eq!(editor.text(), "lib.rs");
+        assert!(editor.status_msg.as_ref().is_some_and(|m| m.severity == Severity::Error));
+    }
+
+    #[test]
+    fn editor_find_file() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        for name in ["src/main.rs", "src/editor.rs", ".git/config", "README.md", ".hidden"] {
+            let path = tmp_dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, name).unwrap();
+        }
+        let files = scan_files(tmp_dir.path());
+        assert_eq!(files, ["README.md", "src/editor.rs", "src/main.rs"]);
+        assert_eq!(fuzzy_score("src/main.rs", "SM"), Some(6));
+        assert_eq!(fuzzy_score("src/main.rs", "ms"), Some(4));
+        assert_eq!(fuzzy_score("src/main.rs", "sm.x"), None);
+        assert_eq!(rank_files(&files, "srcmain"), ["src/main.rs"]);
+        assert_eq!(rank_files(&files, "e"), ["src/editor.rs", "README.md"]);
+
+        let mut editor = Editor::default();
+        editor.set_window_size((6, 20));
+        editor.prompt_mode = Some(PromptMode::FindFile(String::new(), 1, files));
+        let status_msg = editor.prompt_mode.as_ref().map(|p| p.status_msg(&editor));
+        assert_eq!(status_msg.unwrap(), "Find file [2/3 of 3 files] (Use ESC/Up/Down/Enter): ");
+        editor.draw_screen();
+        assert_eq!(editor.drawn_lines[1..4], [
+            "\x1b[KREADME.md",
+            "\x1b[K\x1b[7msrc/main.rs\x1b[m",
+            "\x1b[Ksrc/editor.rs"
+        ]);
+    }
+
+    #[test]
+    fn editor_goto_symbol() {
+        let mut editor = Editor::default();
+        editor.set_window_size((6, 20));
+        editor.buf.syntax.symbol_prefixes = vec![String::from("fn"), String::from("struct")];
+        let text: String = (0..30).map(|i| format!("fn f{}() {{}}\n// {}\n", i, i)).collect();
+        editor.load_text(format!("pub struct S;\n{}", text).as_bytes());
+        let symbols = editor.symbols();
+        assert_eq!((symbols.len(), &symbols[0]), (31, &(0, String::from("pub struct S;"))));
+        assert_eq!(symbols[30], (59, String::from("fn f29() {}")));
+
+        let mut prompt = PromptMode::GoToSymbol(String::new(), 0, symbols);
+        for key in [Key::Char(b'2'), Key::Char(b'5'), Key::Arrow(AKey::Down)] {
+            prompt = prompt.process_keypress(&mut editor, &key).unwrap().unwrap();
+        }
+        assert_eq!(prompt.status_msg(&editor), "Go to symbol [1/1] (Use ESC/Up/Down/Enter): 25");
+        assert!(prompt.process_keypress(&mut editor, &Key::Char(b'\r')).unwrap().is_none());
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x, editor.buf.cursor.roff), (51, 0, 49));
+    }
+
+    #[test]
+    fn editor_split() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.screen_rows) = (41, 2);
+        editor.load_text(b"left");
+        editor.buffers.push(Buffer::default());
+        editor.switch_buffer(1);
+        editor.load_text(b"right\npane");
+        editor.switch_buffer(0);
+        assert_eq!(editor.screen_cols, 41 - editor.ln_pad);
+
+        PromptMode::Window.process_keypress(&mut editor, &Key::Char(b'v')).unwrap();
+        assert_eq!(editor.pane_widths(), (20, 20));
+        assert_eq!(editor.screen_cols, 20 - editor.ln_pad);
+        let buffer = editor.draw_rows().concat();
+        assert!(buffer.contains("left") && buffer.contains("right") && buffer.contains('\u{2502}'));
+        assert_eq!(editor.active, 0);
+
+        // The other pane is drawn without switching buffers, which would clear the word matches
+        editor.config.highlight_word = true;
+        editor.update_word_highlight();
+        editor.draw_rows();
+        assert_eq!(editor.highlighted_word.as_deref(), Some(&b"left"[..]));
+        assert!(editor.buf.rows[0].word_matches.is_some());
+
+        // Edits only go to the focused pane
+        editor.focus_pane(false);
+        assert_eq!((editor.active, editor.focused_pane()), (1, (20, 21)));
+        editor.insert_byte(b'x');
+        assert_eq!(editor.buf.rows[0].chars, b"xright");
+        editor.focus_pane(true);
+        assert_eq!(editor.buf.rows[0].chars, b"left");
+
+        // Cycling to the buffer shown in the other pane exchanges the panes
+        editor.process_keypress(&Key::CtrlTab);
+        assert_eq!(editor.active, 1);
+        assert_eq!(editor.split.map(|s| s.other), Some(0));
+
+        editor.toggle_split();
+        assert!(editor.split.is_none());
+        assert_eq!(editor.screen_cols, 41 - editor.ln_pad);
+    }
+
+    #[test]
+    fn editor_window_size() {
+        let mut editor = Editor::default();
+        editor.load_text(b"a\nb\nc");
+        editor.update_window_size_from(|| Ok((24, 80)));
+        assert_eq!((editor.screen_rows, editor.window_height, editor.window_width), (22, 24, 80));
+        assert_eq!((editor.ln_pad, editor.screen_cols), (3, 77));
+        assert_eq!(editor.window_size_source, "the terminal");
+
+        // The line numbers are hidden when the window is too narrow, instead of overflowing it
+        editor.update_window_size_from(|| Ok((3, 2)));
+        assert_eq!((editor.screen_rows, editor.window_width), (1, 2));
+        assert_eq!((editor.ln_pad, editor.screen_cols), (0, 2));
+        editor.config.scrollbar = true;
+        editor.update_window_size_from(|| Ok((2, 1)));
+        assert_eq!((editor.screen_rows, editor.ln_pad, editor.screen_cols), (0, 0, 0));
+    }
+
+    #[test]
+    fn editor_welcome_message() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.screen_rows, editor.config.show_line_num) = (12, 6, false);
+        editor.load_text(b"");
+        editor.update_screen_cols();
+        let welcome = concat!("Rust Text Editor ", env!("RUST_TEXT_EDITOR_VERSION"));
+        assert_eq!(editor.pane().draw()[2], welcome[..12]);
+
+        // Each line of a custom message is truncated, then centered
+        let message = " Hello\\n\u{e9}t\u{e9}\\nlonger than the screen";
+        editor.config.set("welcome_message", message).unwrap();
+        let lines = editor.pane().draw();
+        assert_eq!(lines[1..6], ["", "   Hello    ", "    \u{e9}t\u{e9}     ", "longer than ", ""]);
+
+        editor.config.show_welcome = false;
+        assert!(editor.pane().draw()[1..].iter().all(String::is_empty));
+        editor.config.show_welcome = true;
+        editor.insert_byte(b'a');
+        assert!(editor.pane().draw()[2..].iter().all(String::is_empty));
+    }
+
+    #[test]
+    fn editor_line_number_padding() {
+        for (n_rows, ln_pad) in [(0, 3), (9, 3), (10, 4), (99, 4), (100, 5), (1000, 6)] {
+            let mut editor = Editor::default();
+            editor.buf.rows = (0..n_rows).map(|_| Row::new(Vec::new())).collect();
+            editor.set_window_size((24, 80));
+            assert_eq!((editor.ln_pad, editor.screen_cols), (ln_pad, 80 - ln_pad), "{n_rows} rows");
+        }
+    }
+
+    #[test]
+    fn read_keys() {
+        let read = |bytes: &[u8]| {
+            let mut bytes = bytes.iter().map(|b| Ok(*b));
+            let key = read_key(&mut bytes).unwrap();
+            (format!("{:?}", key), bytes.count())
+        };
+        assert_eq!(read(b"ab"), (String::from("Some(Char(97))"), 1));
+        assert_eq!(read(b"\x1b[1;5C"), (String::from("Some(CtrlArrow(Right))"), 0));
+        assert_eq!(read(b"\x1b[1;7B"), (String::from("Some(CtrlAltArrow(Down))"), 0));
+        assert_eq!(read(b"\x1b\x04a"), (String::from("Some(AltChar(4))"), 1));
+        assert_eq!(read(b"\x1b\x7f"), (String::from("Some(AltChar(127))"), 0));
+        assert_eq!(read(b"\x1b[27;6;9~"), (String::from("Some(CtrlShiftTab)"), 0));
+        assert_eq!(read(b"\x1b[200~a\rb\x1b[201~"), (String::from("Some(Paste([97, 13, 98]))"), 0));
+        assert_eq!(read(b"\x1b[<0;1;1m"), (String::from("None"), 0));
+        assert_eq!(read(b"\x1b[Q"), (String::from("Some(Escape)"), 0));
+        assert_eq!(read(b""), (String::from("None"), 0));
+    }
+
+    #[test]
+    #[serial]
+    fn env_window_sizes() {
+        let size = |lines: &str, columns: &str| {
+            let _lines = TempEnvVar::new("LINES".as_ref(), Some(lines.as_ref()));
+            let _columns = TempEnvVar::new("COLUMNS".as_ref(), Some(columns.as_ref()));
+            env_window_size()
+        };
+        assert_eq!(size("30", " 100\n"), Some((30, 100)));
+        assert_eq!(size("0", "100"), None);
+        assert_eq!(size("30", "wide"), None);
+    }
+
+    #[test]
+    fn mouse_events() {
+        assert!(matches!(parse_mouse_event(b"0;12;5M"), Some(MouseEvent::Click(11, 4))));
+        assert!(matches!(parse_mouse_event(b"64;1;1M"), Some(MouseEvent::WheelUp)));
+        assert!(matches!(parse_mouse_event(b"65;1;1M"), Some(MouseEvent::WheelDown)));
+        assert!(parse_mouse_event(b"0;12;5m").is_none());
+        assert!(parse_mouse_event(b"2;12;5M").is_none());
+        assert!(parse_mouse_event(b"0;x;5M").is_none());
+    }
+
+    #[test]
+    fn editor_mouse() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.screen_rows) = (80, 4);
+        editor.load_text(b"a\tb\nl2\nl3\nl4\nl5\nl6\nl7\nl8");
+        assert_eq!(editor.ln_pad, 3);
+
+        // Click on "b", after the tab
+        editor.process_keypress(&Key::Mouse(MouseEvent::Click(7, 0)));
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (0, 2));
+        // Click in the middle of the tab, then after the end of a row
+        editor.process_keypress(&Key::Mouse(MouseEvent::Click(5, 0)));
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (0, 1));
+        editor.process_keypress(&Key::Mouse(MouseEvent::Click(40, 2)));
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (2, 2));
+
+        editor.process_keypress(&Key::Mouse(MouseEvent::WheelDown));
+        assert_eq!((editor.buf.cursor.roff, editor.buf.cursor.y), (3, 3));
+        editor.process_keypress(&Key::Mouse(MouseEvent::WheelDown));
+        editor.process_keypress(&Key::Mouse(MouseEvent::WheelDown));
+        assert_eq!((editor.buf.cursor.roff, editor.buf.cursor.y), (7, 7));
+        editor.process_keypress(&Key::Mouse(MouseEvent::Click(3, 0)));
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (7, 0));
+        editor.process_keypress(&Key::Mouse(MouseEvent::WheelUp));
+        assert_eq!((editor.buf.cursor.roff, editor.buf.cursor.y), (4, 7));
+    }
+
+    #[test]
+    fn editor_line_number_style() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.screen_rows) = (80, 5);
+        editor.load_text(&b"\n".repeat(11));
+        editor.buf.cursor.y = 10;
+        let numbers = |editor: &Editor, y| {
+            let pane = editor.pane();
+            (pane.line_number(10), pane.line_number(y))
+        };
+        assert_eq!((numbers(&editor, 7), editor.ln_pad), ((11, 8), 4));
+
+        editor.run_command("set line_number_style=relative").unwrap();
+        assert_eq!((numbers(&editor, 7), editor.ln_pad), ((0, 3), 3));
+        editor.run_command("set line_number_style=hybrid").unwrap();
+        assert_eq!((numbers(&editor, 11), editor.ln_pad), ((11, 1), 4));
+        editor.run_command("set line_number_style=none").unwrap();
+        let status = editor.status_msg.as_ref().map(|m| m.msg.as_str()).unwrap_or_default();
+        assert_eq!(status, "Parser error: none (expected absolute, relative or hybrid)");
+    }
+
+    #[test]
+    fn editor_wrap() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.screen_rows) = (12, 3);
+        (editor.config.show_line_num, editor.config.wrap) = (false, true);
+        editor.load_text(b"aaaa bbbb cccc dddd eeee\nx");
+        assert_eq!(editor.screen_lines(), [(0, 0..10), (0, 10..20), (0, 20..24)]);
+        let lines = editor.pane().draw();
+        assert!(lines[1].contains("cccc dddd ") && lines[2].contains("eeee"));
+
+        editor.buf.cursor.x = 2;
+        editor.move_cursor(&AKey::Down);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (0, 12));
+        editor.move_cursor(&AKey::Down);
+        editor.move_cursor(&AKey::Down);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (1, 1));
+        editor.scroll();
+        assert_eq!((editor.buf.cursor.roff, editor.cursor_screen_position()), (1, (1, 0)));
+        editor.move_cursor(&AKey::Up);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (0, 21));
+        editor.scroll();
+        assert_eq!((editor.buf.cursor.roff, editor.cursor_screen_position()), (0, (1, 2)));
+
+        editor.click(3, 1);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (0, 13));
+        editor.click(11, 0);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (0, 9));
+    }
+
+    #[test]
+    fn editor_text() {
+        let mut editor = Editor::default();
+        assert_eq!(editor.text(), "");
+        for text in ["", "a", "a\nb", "a\n\nb\n", "a\r\nb\r\n", "\u{e9}\t\n"] {
+            editor.set_text(text);
+            assert_eq!(editor.text(), text);
+        }
+        // The dominant line ending is used for all the rows
+        editor.set_text("a\r\nb\nc\r\n");
+        assert_eq!(editor.text(), "a\r\nb\r\nc\r\n");
+        assert_eq!(editor.buf.line_ending, LineEnding::CrLf);
+
+        editor.buf.file_name = Some(String::from("x.txt"));
+        (editor.buf.cursor.y, editor.buf.cursor.x, editor.buf.dirty) = (1, 1, false);
+        editor.selection_anchor = Some((0, 0));
+        editor.set_text("fn main() {\n}\n");
+        assert_eq!((editor.buf.rows.len(), editor.buf.n_bytes, editor.buf.cursor.y), (3, 12, 0));
+        assert_eq!((editor.buf.cursor.x, editor.selection_anchor), (0, None));
+        assert!(editor.buf.dirty && editor.buf.file_name.as_deref() == Some("x.txt"));
+        for c in b"// " {
+            editor.insert_byte(*c);
+        }
+        assert_eq!(editor.text(), "// fn main() {\n}\n");
+    }
+
+    #[test]
+    fn editor_cursorline() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.screen_rows) = (12, 4);
+        (editor.config.show_line_num, editor.config.wrap) = (false, true);
+        editor.load_text(b"ab\naaaa bbbb cccc");
+        let tint = editor.config.theme.cursorline_escape();
+        assert!(!editor.pane().draw().iter().any(|line| line.contains(&tint)));
+
+        editor.process_keypress(&Key::Char(editor.config.keys.cursorline));
+        assert!(editor.config.cursorline);
+        let lines = editor.pane().draw();
+        assert_eq!(lines[0], format!("{tint}ab{RESET_FMT}{tint}{}{RESET_FMT}", " ".repeat(10)));
+        assert!(!lines[1].contains(&tint));
+
+        // Each line of a wrapped row is tinted up to the edge of the screen
+        editor.buf.cursor.y = 1;
+        let lines = editor.pane().draw();
+        assert!(lines[1].ends_with(&format!("{tint}  {RESET_FMT}")));
+        assert_eq!(lines[2], format!("{tint}cccc{RESET_FMT}{tint}{}{RESET_FMT}", " ".repeat(8)));
+        assert!(!lines[0].contains(&tint));
+
+        // No row is tinted when the cursor is on the virtual row after the last one
+        editor.buf.cursor.y = 2;
+        assert!(!editor.pane().draw().iter().any(|line| line.contains(&tint)));
+    }
+
+    #[test]
+    fn editor_scrollbar() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.screen_rows, editor.config.show_line_num) = (10, 4, false);
+        let text: String = (0..16).map(|i| format!("{i}\n")).collect();
+        editor.load_text(text.as_bytes());
+        assert_eq!(editor.screen_cols, 10);
+        assert!(!editor.pane().draw()[0].contains('\u{2588}'));
+
+        editor.config.scrollbar = true;
+        editor.apply_config("scrollbar");
+        assert_eq!(editor.screen_cols, 9);
+        // 4 of the 17 lines are visible, so the thumb is one line high
+        assert_eq!(editor.pane().scrollbar_thumb(), 0..1);
+        let color = editor.config.theme.line_number_escape();
+        let lines = editor.pane().draw();
+        assert!(lines[0].ends_with(&format!("\x1b[10G{color}\u{2588}{RESET_FMT}")));
+        assert!(lines[1].ends_with(&format!("\x1b[10G{color}\u{2591}{RESET_FMT}")));
+
+        // The thumb follows the row offset, and reaches the bottom at the end of the file
+        editor.buf.cursor.roff = 8;
+        assert_eq!(editor.pane().scrollbar_thumb(), 1..2);
+        editor.buf.cursor.roff = 16;
+        assert_eq!(editor.pane().scrollbar_thumb(), 3..4);
+
+        // The thumb fills the track when the whole file is visible
+        editor.load_text(b"a\nb");
+        assert_eq!(editor.pane().scrollbar_thumb(), 0..4);
+    }
+
+    #[test]
+    fn editor_fold() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.screen_rows) = (80, 4);
+        editor.load_text(b"fn a() {\n    x\n\n\ty\n\n}\n\nfn b() {\n    z\n}");
+        let fold = Key::Char(editor.config.keys.fold);
+        editor.process_keypress(&fold);
+        assert_eq!((editor.buf.folds.len(), editor.buf.folds[0].clone()), (1, 1..4));
+        let screen_rows =
+            |editor: &Editor| editor.screen_lines().iter().map(|(y, _)| *y).collect::<Vec<_>>();
+        assert_eq!(screen_rows(&editor), [0, 1, 4, 5]);
+        assert!(editor.pane().draw()[1].contains("    ... 3 lines"));
+
+        // The cursor steps over the folded rows
+        editor.move_cursor(&AKey::Down);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (4, 0));
+        editor.move_cursor(&AKey::Left);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (0, 8));
+        editor.move_cursor(&AKey::Right);
+        editor.move_cursor(&AKey::Down);
+        editor.move_cursor(&AKey::Down);
+        editor.move_cursor(&AKey::Down);
+        editor.scroll();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.roff), (7, 4));
+        assert_eq!(editor.cursor_screen_position(), (0, 3));
+
+        editor.process_keypress(&fold);
+        assert_eq!(editor.buf.folds, [1..4, 8..9]);
+        // Editing a row only drops the folds that are below it
+        editor.process_keypress(&Key::Char(b'x'));
+        assert_eq!((editor.buf.folds.len(), editor.buf.folds[0].clone()), (1, 1..4));
+        (editor.buf.cursor.y, editor.buf.cursor.x) = (2, 0);
+        editor.scroll();
+        assert!(editor.buf.folds.is_empty());
+
+        editor.buf.cursor.y = 1;
+        editor.process_keypress(&fold);
+        let status = editor.status_msg.as_ref().map(|m| m.msg.as_str()).unwrap_or_default();
+        assert_eq!(status, "Nothing to fold below line 2");
+        editor.buf.cursor.y = 0;
+        editor.process_keypress(&fold);
+        editor.process_keypress(&fold);
+        assert!(editor.buf.folds.is_empty());
+    }
+
+    #[test]
+    fn editor_join_lines() {
+        let mut editor = Editor::default();
+        editor.load_text(b"fn f(  \n    a,\n\n\tb)\nlast");
+        let join_lines = Key::Char(editor.config.keys.join_lines);
+        editor.process_keypress(&join_lines);
+        assert_eq!(editor.buf.rows[0].chars, b"fn f( a,");
+        assert_eq!((editor.buf.rows.len(), editor.buf.cursor.x, editor.buf.n_bytes), (4, 5, 15));
+        assert!(editor.buf.dirty);
+
+        editor.selection_anchor = Some((0, 0));
+        editor.buf.cursor.y = 2;
+        editor.process_keypress(&join_lines);
+        assert_eq!(editor.buf.rows[0].chars, b"fn f( a, b)");
+        assert_eq!((editor.buf.rows.len(), editor.buf.cursor.x, editor.buf.n_bytes), (2, 8, 15));
+        assert_eq!(editor.selection_anchor, None);
+
+        editor.buf.cursor.y = 1;
+        editor.process_keypress(&join_lines);
+        assert_eq!((editor.buf.rows.len(), editor.buf.rows[1].chars.as_slice()), (2, &b"last"[..]));
+
+        // The rows moved up into the screen are highlighted
+        (editor.window_width, editor.screen_rows, editor.config.show_line_num) = (20, 10, false);
+        editor.load_text("x\n".repeat(100).as_bytes());
+        editor.update_screen_cols();
+        (editor.selection_anchor, editor.buf.cursor.y) = (Some((0, 0)), 5);
+        editor.process_keypress(&join_lines);
+        assert_eq!(editor.buf.highlighted_up_to, 5);
+        editor.highlight_screen_rows();
+        assert_ne!(editor.pane().draw()[9], RESET_FMT);
+    }
+
+    #[test]
+    fn editor_move_lines() {
+        let mut editor = Editor::default();
+        editor.load_text(b"a\nb\nc\nd");
+        editor.process_keypress(&Key::AltArrow(AKey::Up));
+        assert_eq!(editor.buf.rows[0].chars, b"a");
+        assert!(!editor.buf.dirty);
+
+        editor.buf.cursor.y = 2;
+        editor.process_keypress(&Key::AltArrow(AKey::Up));
+        let chars = |editor: &Editor| 
+            editor.buf.rows.iter().map(|r| r.chars.clone()).collect::<Vec<_>>();
+        assert_eq!(chars(&editor), [b"a", b"c", b"b", b"d"]);
+        assert_eq!(editor.buf.cursor.y, 1);
+        assert!(editor.buf.dirty);
+
+        editor.selection_anchor = Some((0, 0));
+        editor.process_keypress(&Key::AltArrow(AKey::Down));
+        assert_eq!(chars(&editor), [b"b", b"a", b"c", b"d"]);
+        assert_eq!((editor.buf.cursor.y, editor.selection_anchor), (2, Some((1, 0))));
+        editor.process_keypress(&Key::AltArrow(AKey::Down));
+        editor.process_keypress(&Key::AltArrow(AKey::Down));
+        assert_eq!(chars(&editor), [b"b", b"d", b"a", b"c"]);
+        assert_eq!((editor.buf.cursor.y, editor.selection_anchor), (3, Some((2, 0))));
+    }
+
+    #[test]
+    fn editor_delete_current_row() {
+        let mut editor = Editor::default();
+        editor.load_text(b"a\n/* bb\nc */\ndddd");
+        let chars = |editor: &Editor|
+            editor.buf.rows.iter().map(|r| r.chars.clone()).collect::<Vec<_>>();
+        let remove_line = Key::Char(editor.config.keys.remove_line);
+        editor.buf.cursor.x = 1;
+        editor.process_keypress(&remove_line);
+        assert_eq!(chars(&editor), [&b"/* bb"[..], b"c */", b"dddd"]);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x, editor.buf.n_bytes), (0, 0, 13));
+        assert!(editor.buf.dirty);
+
+        editor.buf.cursor.y = 1;
+        editor.process_keypress(&remove_line);
+        assert_eq!(chars(&editor), [&b"/* bb"[..], b"dddd"]);
+        assert_eq!((editor.buf.cursor.y, editor.buf.n_bytes), (1, 9));
+
+        // Removing the last row moves the cursor to the new last row
+        editor.process_keypress(&remove_line);
+        assert_eq!(chars(&editor), [b"/* bb"]);
+        assert_eq!((editor.buf.cursor.y, editor.buf.n_bytes), (0, 5));
+        assert_eq!(editor.text(), "/* bb");
+
+        // The only row is cleared instead of being removed
+        editor.buf.cursor.x = 3;
+        editor.process_keypress(&remove_line);
+        assert_eq!(chars(&editor), [b""]);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x, editor.buf.n_bytes), (0, 0, 0));
+        assert!(editor.buf.is_empty());
+    }
+
+    #[test]
+    fn editor_duplicate_rows() {
+        let mut editor = Editor::default();
+        editor.load_text(b"a\nbb\nc");
+        let chars = |editor: &Editor|
+            editor.buf.rows.iter().map(|r| r.chars.clone()).collect::<Vec<_>>();
+        editor.process_keypress(&Key::Char(editor.config.keys.duplicate));
+        assert_eq!(chars(&editor), [&b"a"[..], b"a", b"bb", b"c"]);
+        assert_eq!((editor.buf.cursor.y, editor.buf.n_bytes), (0, 5));
+
+        // Duplicating above keeps the cursor on the original row
+        editor.buf.cursor.y = 2;
+        editor.process_keypress(&Key::AltChar(editor.config.keys.duplicate));
+        assert_eq!(chars(&editor), [&b"a"[..], b"a", b"bb", b"bb", b"c"]);
+        assert_eq!((editor.buf.cursor.y, editor.buf.n_bytes), (3, 7));
+
+        // The selection moves to the copy of the selected rows
+        editor.selection_anchor = Some((3, 1));
+        editor.buf.cursor.y = 4;
+        editor.process_keypress(&Key::Char(editor.config.keys.duplicate));
+        assert_eq!(chars(&editor), [&b"a"[..], b"a", b"bb", b"bb", b"c", b"bb", b"c"]);
+        assert_eq!((editor.buf.cursor.y, editor.selection_anchor), (6, Some((5, 1))));
+        assert_eq!((editor.buf.n_bytes, editor.buf.dirty), (10, true));
+
+        // Nothing is duplicated on the empty row after the last one
+        editor.selection_anchor = None;
+        editor.buf.cursor.y = 7;
+        editor.process_keypress(&Key::Char(editor.config.keys.duplicate));
+        assert_eq!(editor.buf.rows.len(), 7);
+    }
+
+    #[test]
+    fn editor_status_bar() {
+        let mut editor = Editor::default();
+        editor.load_text(b"ab\ncd\n");
+        editor.buf.file_name = Some(String::from("\u{6587}\u{4ef6}.txt"));
+        let status_bar = |editor: &Editor| {
+            let mut buffer = String::new();
+            editor.draw_status_bar(&mut buffer);
+            let content = buffer.strip_prefix(REVERSE_VIDEO).unwrap();
+            String::from(content.strip_suffix(RESET_FMT).unwrap())
+        };
+        editor.window_width = 60;
+        let bar = status_bar(&editor);
+        assert!(bar.starts_with("\u{6587}\u{4ef6}.txt "));
+        assert!(bar.ends_with("no ft | LF | 6B | 3 lines | 1:1"));
+        assert_eq!(bar.width(), 60);
+
+        editor.window_width = 20;
+        assert_eq!(status_bar(&editor), "\u{6587}\u{4ef6}.txt         1:1");
+        editor.window_width = 5;
+        assert_eq!(status_bar(&editor), "\u{6587}\u{4ef6}.");
+        assert_eq!(truncate_to_width("\u{6587}\u{4ef6}", 3), "\u{6587}");
+    }
+
+    #[test]
+    fn editor_message_bar() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.config.message_dur) = (80, Duration::ZERO);
+        let message_bar = |editor: &Editor| {
+            let mut buffer = String::new();
+            editor.draw_message_bar(&mut buffer);
+            String::from(buffer.strip_prefix(CLEAR_LINE_RIGHT_OF_CURSOR).unwrap())
+        };
+        // Information and warnings expire after `message_dur`, errors are kept
+        set_status!(editor, "Saved");
+        assert_eq!(message_bar(&editor), "");
+        set_status!(editor, Warning: "Read-only");
+        assert_eq!(message_bar(&editor), "");
+        set_status!(editor, Error: "Can't save! {}", 42);
+        let error = editor.config.theme.error_escape();
+        assert_eq!(message_bar(&editor), format!("{}Can't save! 42{}", error, RESET_FMT));
+
+        editor.config.message_dur = Duration::from_secs(60);
+        set_status!(editor, Warning: "Read-only");
+        let warning = editor.config.theme.warning_escape();
+        assert_eq!(message_bar(&editor), format!("{}Read-only{}", warning, RESET_FMT));
+        editor.run_command("unknown").unwrap();
+        assert_eq!(editor.status_msg.as_ref().map(|sm| sm.severity), Some(Severity::Error));
+    }
+
+    #[test]
+    fn editor_tiny_window() {
+        let mut editor = Editor::default();
+        editor.load_text(b"fn main() {\n    println!();\n}\n");
+        editor.buf.cursor = CursorState { y: 1, x: 4, ..CursorState::default() };
+        let keys = [Key::Page(PageKey::Down), Key::Page(PageKey::Up), Key::Arrow(AKey::Down)];
+        for wrap in [false, true] {
+            editor.config.wrap = wrap;
+            for (height, width) in [(0, 0), (1, 1), (2, 3), (3, 2), (4, 1)] {
+                editor.set_window_size((height, width));
+                assert_eq!(editor.screen_rows, height.saturating_sub(2));
+                assert_eq!((editor.ln_pad, editor.screen_cols), (0, width));
+                for key in &keys {
+                    editor.process_keypress(key);
+                }
+                let screen = editor.draw_screen();
+                // Each line of the window is drawn once, and no new line is printed
+                assert_eq!(editor.drawn_lines.len(), height);
+                assert!(!screen.contains('\n'));
+                assert_eq!(screen.contains(REVERSE_VIDEO), height >= 2);
+            }
+        }
+        editor.set_window_size((12, 80));
+        assert_eq!((editor.screen_rows, editor.ln_pad, editor.screen_cols), (10, 3, 77));
+
+        // An empty buffer has no rows
+        let mut editor = Editor::default();
+        editor.config.wrap = true;
+        for (height, width) in [(0, 0), (2, 1), (3, 1)] {
+            editor.set_window_size((height, width));
+            assert_eq!(editor.draw_screen().contains(REVERSE_VIDEO), height >= 2);
+        }
+    }
+
+    #[test]
+    fn editor_redraw_changed_lines() {
+        let mut editor = Editor::default();
+        let line = |i: usize| format!("let x{} = \"{}\";\n", i, "x".repeat(i % 60));
+        let text: String = (0..1000).map(line).collect();
+        editor.load_text(text.as_bytes());
+        editor.set_window_size((50, 100));
+        let full = editor.draw_screen();
+        assert_eq!(editor.drawn_lines.len(), 50);
+
+        // Moving the cursor only redraws the status bar
+        editor.process_keypress(&Key::Arrow(AKey::Right));
+        let screen = editor.draw_screen();
+        let status_bar = &editor.drawn_lines[48];
+        let cursor = format!("\x1b[1;{}H{}", editor.ln_pad + 2, SHOW_CURSOR);
+        assert_eq!(screen, format!("{}\x1b[49;1H{}{}", HIDE_CURSOR, status_bar, cursor));
+
+        // Typing only redraws the edited row and the status bar
+        editor.process_keypress(&Key::Char(b'x'));
+        let screen = editor.draw_screen();
+        assert!(screen.starts_with(&format!("{}\x1b[1;1H", HIDE_CURSOR)));
+        assert!(screen.contains("lxet x0 ") && screen.contains("\x1b[49;1H"));
+        assert_eq!(screen.matches(";1H").count(), 2);
+        editor.process_keypress(&Key::Char(BACKSPACE));
+        editor.draw_screen();
+
+        // Scrolling by a line scrolls the terminal, then draws the new row and the status bar
+        editor.buf.cursor.y = 47;
+        editor.draw_screen();
+        editor.process_keypress(&Key::Arrow(AKey::Down));
+        let scrolled = editor.draw_screen();
+        assert!(scrolled.starts_with(&format!("{}\x1b[1;48r\x1b[1S\x1b[r\x1b[48;1H", HIDE_CURSOR)));
+        assert!(scrolled.contains("let x48 ") && !scrolled.contains("let x47 "));
+        assert!(scrolled.len() * 10 < full.len());
+        editor.process_keypress(&Key::Page(PageKey::Up));
+        assert!(editor.draw_screen().contains("\x1b[1;48r\x1b[1T\x1b[r\x1b[1;1H"));
+        let lines = editor.drawn_lines.clone();
+        editor.process_keypress(&Key::Char(editor.config.keys.refresh));
+        assert_eq!(editor.draw_screen().len(), full.len());
+        assert_eq!(editor.drawn_lines, lines);
+    }
+
+    #[test]
+    fn editor_prompt_history() {
+        let mut editor = Editor::default();
+        editor.load_text(b"ab\ncd\nab cd\n");
+        let (find, goto) = (Key::Char(editor.config.keys.find), Key::Char(editor.config.keys.goto));
+        let (up, down) = (|| Key::Arrow(AKey::Up), || Key::Arrow(AKey::Down));
+        let enter = Key::Char(b'\r');
+        let type_keys = |editor: &mut Editor, prompt: PromptMode, keys: &[Key]| {
+            keys.iter().fold(prompt, |p, key| p.process_keypress(editor, key).unwrap().unwrap())
+        };
+        let query = |prompt: &PromptMode| match prompt {
+            PromptMode::Find(b, ..) | PromptMode::GoTo(b, _) => b.clone(),
+            _ => String::new(),
+        };
+        for text in ["cd", "ab", "ab", ""] {
+            let keys: Vec<_> = text.bytes().map(Key::Char).collect();
+            let prompt = editor.process_keypress(&find).1.unwrap();
+            let prompt = type_keys(&mut editor, prompt, &keys);
+            assert!(prompt.process_keypress(&mut editor, &enter).unwrap().is_none());
+        }
+        // Empty and consecutive identical queries are not added to the history
+        assert_eq!(editor.find_history.entries, ["cd", "ab"]);
+
+        // Up and Down recall the queries, then the text that was being typed
+        let prompt = editor.process_keypress(&find).1.unwrap();
+        let hint = "Search (Use ESC/Left/Right/Enter, Up/Down = history, ";
+        assert!(prompt.status_msg(&editor).starts_with(hint));
+        let prompt = type_keys(&mut editor, prompt, &[Key::Char(b'x'), up()]);
+        assert_eq!(query(&prompt), "ab");
+        let prompt = type_keys(&mut editor, prompt, &[up(), up()]);
+        assert_eq!(query(&prompt), "cd");
+        assert_eq!(editor.search.as_ref().map(|(q, _)| q.as_str()), Some("cd"));
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (1, 0));
+        let prompt = type_keys(&mut editor, prompt, &[Key::Char(b'e'), down()]);
+        assert_eq!(query(&prompt), "ab");
+        let prompt = type_keys(&mut editor, prompt, &[down(), down()]);
+        assert_eq!(query(&prompt), "x");
+        assert!(prompt.process_keypress(&mut editor, &Key::Escape).unwrap().is_none());
+
+        // Each prompt has its own history
+        let prompt = editor.process_keypress(&goto).1.unwrap();
+        let prompt = type_keys(&mut editor, prompt, &[Key::Char(b'3')]);
+        assert!(prompt.process_keypress(&mut editor, &enter).unwrap().is_none());
+        let prompt = editor.process_keypress(&goto).1.unwrap();
+        assert_eq!(query(&type_keys(&mut editor, prompt, &[up(), up()])), "3");
+        assert_eq!(editor.find_history.entries, ["cd", "ab"]);
+
+        // The oldest queries are dropped
+        (0..MAX_HISTORY + 5).for_each(|i| editor.goto_history.push(&i.to_string()));
+        assert_eq!(editor.goto_history.entries.len(), MAX_HISTORY);
+        assert_eq!(editor.goto_history.entries[0], "5");
+    }
+
+    #[test]
+    fn editor_word_highlight() {
+        let mut editor = Editor::default();
+        editor.load_text(b"let x_1 = x;\nx_1 + x_10\n\nx_1");
+        editor.set_window_size((5, 80));
+        // The ranges of the matches, as (start, end) rendered positions
+        let matches = |editor: &Editor| -> Vec<Option<Vec<_>>> {
+            let ranges = |m: &Vec<Range<usize>>| m.iter().map(|r| (r.start, r.end)).collect();
+            editor.buf.rows.iter().map(|row| row.word_matches.as_ref().map(ranges)).collect()
+        };
+        editor.buf.cursor.x = 5;
+        editor.draw_screen();
+        assert_eq!(matches(&editor), [None, None, None, None]);
+
+        // Only whole words are underlined, in the rows shown on the screen
+        editor.config.highlight_word = true;
+        editor.draw_screen();
+        assert_eq!(editor.highlighted_word.as_deref(), Some(&b"x_1"[..]));
+        assert_eq!(matches(&editor), [Some(vec![(4, 7)]), Some(vec![(0, 3)]), Some(vec![]), None]);
+        editor.buf.cursor.x = 7;
+        editor.draw_screen();
+        assert_eq!(matches(&editor)[0], Some(vec![(4, 7)]));
+
+        // Edited rows are searched again, and other rows are cleared when the word changes
+        editor.buf.cursor = CursorState { y: 1, x: 0, ..CursorState::default() };
+        editor.insert_byte(b' ');
+        assert_eq!(matches(&editor)[1], None);
+        editor.draw_screen();
+        assert_eq!(matches(&editor)[1], Some(vec![(1, 4)]));
+        editor.buf.cursor.x = 8;
+        editor.draw_screen();
+        assert_eq!(editor.highlighted_word.as_deref(), Some(&b"x_10"[..]));
+        assert_eq!(matches(&editor)[..2], [Some(vec![]), Some(vec![(7, 11)])]);
+        editor.buf.cursor.x = 0;
+        editor.draw_screen();
+        assert_eq!(matches(&editor), [None, None, None, None]);
+    }
+
+    #[test]
+    fn editor_search_highlight() {
+        let mut editor = Editor::default();
+        editor.load_text("ab \u{e9}b ab\nb\nnone".as_bytes());
+        editor.set_window_size((12, 80));
+        let cursor = editor.buf.cursor.clone();
+        let prompt = PromptMode::Find(String::new(), cursor, None, FindOptions::default());
+        let prompt = prompt.process_keypress(&mut editor, &Key::Char(b'b')).unwrap().unwrap();
+        let screen = editor.draw_screen();
+        let matches = |editor: &Editor, y: usize| editor.buf.rows[y].search_matches.clone();
+        assert_eq!(editor.buf.rows[0].match_segment, Some(1..2));
+        assert_eq!(matches(&editor, 0), [1..2, 4..5, 7..8]);
+        assert_eq!((matches(&editor, 1).len(), matches(&editor, 2).len()), (1, 0));
+        // The active match has its own color, and the background is reset after each match
+        let (other, active) = (HlType::OtherMatch, HlType::Match);
+        let expected = format!("a{1}b{2} \u{e9}{0}b{2} a{0}b{2}", other, active, RESET_FMT);
+        assert!(screen.contains(&expected));
+
+        let key = Key::Arrow(AKey::Right);
+        let prompt = prompt.process_keypress(&mut editor, &key).unwrap().unwrap();
+        editor.draw_screen();
+        assert_eq!(editor.buf.rows[0].match_segment, None);
+        assert_eq!(editor.buf.rows[1].match_segment, Some(0..1));
+        assert_eq!(matches(&editor, 0), [1..2, 4..5, 7..8]);
+
+        assert!(prompt.process_keypress(&mut editor, &Key::Escape).unwrap().is_none());
+        let screen = editor.draw_screen();
+        assert!(editor.search_rows.is_empty() && matches(&editor, 0).is_empty());
+        assert!(!screen.contains(&other.to_string()) && !screen.contains(&active.to_string()));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn editor_find_regex() {
+        let mut editor = Editor::default();
+        editor.load_text(b"ab ab\nab");
+        let opts = FindOptions { regex: true, ..FindOptions::default() };
+        let mut find = |last_match, forward| {
+            editor.find("ab", &last_match, forward, opts)?;
+            Some((editor.buf.cursor.y, editor.buf.cursor.x))
+        };
+        assert_eq!(find(None, true), Some((0, 0)));
+        assert_eq!(find(Some(0), true), Some((0, 3)));
+        // Searching backward goes to the previous match, wrapping around the beginning
+        assert_eq!(find(Some(0), false), Some((0, 0)));
+        assert_eq!(find(Some(0), false), Some((1, 0)));
+        assert_eq!(find(Some(1), false), Some((0, 3)));
+        assert_eq!(find(None, false), Some((1, 0)));
+    }
+
+    #[test]
+    fn editor_match_count() {
+        let mut editor = Editor::default();
+        editor.load_text("ab \u{e9}b ab\nb\nnone".as_bytes());
+        let mut prompt =
+            PromptMode::Find(String::new(), CursorState::default(), None, FindOptions::default());
+        assert!(!prompt.status_msg(&editor).contains("Match"));
+        let keys = [Key::Char(b'b'), Key::Arrow(AKey::Right), Key::Arrow(AKey::Left)];
+        for (key, count) in keys.iter().zip(["[Match 1/4]", "[Match 4/4]", "[Match 1/4]"]) {
+            prompt = prompt.process_keypress(&mut editor, key).unwrap().unwrap();
+            assert!(prompt.status_msg(&editor).starts_with(&format!("Search {} (", count)));
+        }
+        prompt = prompt.process_keypress(&mut editor, &Key::Char(b'z')).unwrap().unwrap();
+        assert!(prompt.status_msg(&editor).starts_with("Search [No match] ("));
+        assert!(prompt.process_keypress(&mut editor, &Key::Escape).unwrap().is_none());
+        assert!(editor.match_count.is_none());
+
+        // The count is capped in large documents
+        editor.load_text("a\n".repeat(MAX_COUNTED_MATCHES + 5).as_bytes());
+        editor.update_match_count("a", FindOptions::default());
+        let count = editor.match_count.as_ref().unwrap();
+        assert_eq!((count.positions.len(), count.truncated), (MAX_COUNTED_MATCHES, true));
+        assert_eq!(count.describe(Some((2, 0))), "Match 3/10000+");
+        assert_eq!(count.describe(Some((MAX_COUNTED_MATCHES + 1, 0))), "Match ?/10000+");
+    }
+
+    #[test]
+    fn editor_multiple_cursors() {
+        let mut editor = Editor::default();
+        (editor.window_width, editor.screen_rows, editor.config.show_line_num) = (10, 4, false);
+        editor.load_text(b"a1\nb22\nc");
+        editor.buf.cursor.x = 1;
+        editor.process_keypress(&Key::CtrlAltArrow(AKey::Down));
+        editor.process_keypress(&Key::CtrlAltArrow(AKey::Down));
+        editor.process_keypress(&Key::CtrlAltArrow(AKey::Down));
+        let status = editor.status_msg.as_ref().map(|m| m.msg.as_str());
+        assert_eq!(status, Some("No row to add a cursor to"));
+        let positions = |editor: &Editor| {
+            let cursors = iter::once(&editor.buf.cursor).chain(&editor.buf.extra_cursors);
+            cursors.map(|c| (c.y, c.x)).collect::<Vec<_>>()
+        };
+        assert_eq!(positions(&editor), [(0, 1), (1, 1), (2, 1)]);
+
+        // The additional cursors are drawn in reverse video
+        let lines = editor.pane().draw();
+        assert!(lines[1].ends_with(&format!("\x1b[2G{REVERSE_VIDEO}2{RESET_FMT}")));
+        assert!(lines[2].ends_with(&format!("\x1b[2G{REVERSE_VIDEO} {RESET_FMT}")));
+
+        editor.process_keypress(&Key::Char(b'x'));
+        assert_eq!(editor.text(), "ax1\nbx22\ncx");
+        (0..2).for_each(|_| _ = editor.process_keypress(&Key::Char(BACKSPACE)));
+        assert_eq!(editor.text(), "1\n22\n");
+        assert_eq!(positions(&editor), [(0, 0), (1, 0), (2, 0)]);
+
+        // Backspace at the start of the rows merges them, shifting the cursors that follow
+        editor.process_keypress(&Key::Char(BACKSPACE));
+        editor.process_keypress(&Key::Char(b'z'));
+        assert_eq!(editor.text(), "z1z22z");
+        assert_eq!(positions(&editor), [(0, 1), (0, 3), (0, 6)]);
+        assert_eq!(editor.buf.n_bytes, 6);
+
+        // Other keys remove the additional cursors
+        editor.process_keypress(&Key::Escape);
+        assert!(editor.buf.extra_cursors.is_empty());
+    }
+
+    #[test]
+    fn editor_snippets() {
+        let mut editor = Editor::default();
+        editor.config.snippets.set("main", "fn main() {\\n\\tx\\n}").unwrap();
+        editor.config.snippets.set("todo", "// TODO({date}): ").unwrap();
+        editor.load_text(b"    main\nx=todo");
+        editor.buf.cursor.x = 8;
+        editor.process_keypress(&Key::Char(editor.config.keys.snippet));
+        assert_eq!(editor.text(), "    fn main() {\n    \tx\n    }\nx=todo");
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x, editor.buf.n_bytes), (2, 5, 32));
+
+        (editor.buf.cursor.y, editor.buf.cursor.x) = (3, 6);
+        editor.config.date_format = String::from("%Y");
+        editor.process_keypress(&Key::Char(editor.config.keys.snippet));
+        let year = snippets::format_date("%Y", SystemTime::now());
+        assert_eq!(editor.buf.rows[3].chars, format!("x=// TODO({year}): ").as_bytes());
+
+        editor.process_keypress(&Key::Char(editor.config.keys.snippet));
+        let status = editor.status_msg.as_ref().map(|m| m.msg.as_str());
+        assert_eq!(status, Some("No snippet trigger before the cursor"));
+        editor.insert_byte(b'y');
+        editor.process_keypress(&Key::Char(editor.config.keys.snippet));
+        let status = editor.status_msg.as_ref().map(|m| m.msg.as_str());
+        assert_eq!(status, Some("No snippet for y"));
+
+        editor.run_command("date %Y!").unwrap();
+        assert!(editor.buf.rows[3].chars.ends_with(format!("y{year}!").as_bytes()));
+    }
+
+    #[test]
+    fn editor_syntax_tab_stop() {
+        let mut editor = Editor::default();
+        editor.config.soft_tabs = true;
+        editor.load_text(b"\ta");
+        assert_eq!((editor.tab_stop(), editor.buf.rows[0].cx2rx[1]), (4, 4));
+
+        // The tab stop of the language overrides the configured one
+        editor.buf.syntax = SyntaxConf { tab_stop: Some(2), ..SyntaxConf::default() };
+        editor.update_all_rows();
+        assert_eq!((editor.tab_stop(), editor.buf.rows[0].cx2rx[1]), (2, 2));
+        editor.buf.cursor.x = 1;
+        editor.insert_tab();
+        assert_eq!(editor.buf.rows[0].chars, b"\t  a");
+
+        // The indentation size set by .editorconfig files takes precedence
+        editor.overridden_config.indent_size = Some(4);
+        editor.config.tab_stop = 8;
+        editor.apply_config("tab_stop");
+        assert_eq!((editor.tab_stop(), editor.buf.rows[0].cx2rx[1]), (8, 8));
+    }
+
+    #[test]
+    fn editor_commands() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("commands.txt");
+        let mut editor = Editor::default();
+        editor.load_text(b"\ta\nb\nc");
+        let status = |editor: &Editor| editor.status_msg.as_ref().map(|m| m.msg.clone());
+
+        editor.run_command("set tab_stop=2").unwrap();
+        assert_eq!((editor.config.tab_stop, editor.buf.rows[0].cx2rx[1]), (2, 2));
+        editor.run_command("set tab_stop=0").unwrap();
+        assert_eq!(status(&editor).as_deref(), Some("tab_stop must be > 0"));
+        editor.run_command("set tab_stop").unwrap();
+        assert_eq!(status(&editor).as_deref(), Some("Usage: set <option>=<value>"));
+        editor.run_command(" goto 3 ").unwrap();
+        assert_eq!(editor.buf.cursor.y, 2);
+        editor.run_command("frobnicate 1").unwrap();
+        assert_eq!(status(&editor).as_deref(), Some("Unknown command: frobnicate"));
+
+        assert!(matches!(editor.run_command("write"), Ok(Some(PromptMode::Save(_)))));
+        editor.insert_byte(b'x');
+        editor.run_command(&format!("saveas {}", path.to_string_lossy())).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "\ta\nb\nxc\n");
+        assert!(!editor.buf.dirty);
+        assert_eq!(editor.buf.file_name.as_deref(), Some(&*path.to_string_lossy()));
+
+        // Writing a copy leaves the file name and the modified state unchanged
+        let copy = tmp_dir.path().join("copy.txt");
+        editor.insert_byte(b'y');
+        editor.run_command(&format!("write {}", copy.to_string_lossy())).unwrap();
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "\ta\nb\nxyc\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "\ta\nb\nxc\n");
+        assert!(editor.buf.dirty);
+        assert_eq!(editor.buf.file_name.as_deref(), Some(&*path.to_string_lossy()));
+        editor.run_command("w").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "\ta\nb\nxyc\n");
+        assert!(!editor.buf.dirty);
+
+        std::fs::write(&path, "reloaded\n").unwrap();
+        editor.run_command("reload").unwrap();
+        assert_eq!(editor.buf.rows[0].chars, b"reloaded");
+
+        editor.insert_byte(b'x');
+        editor.quit_times = 2;
+        editor.run_command("quit").unwrap();
+        assert!(!editor.should_quit);
+        editor.run_command("q").unwrap();
+        assert!(editor.should_quit);
+    }
+
+    #[test]
+    fn editor_quit_confirmation() {
+        let mut editor = Editor::default();
+        editor.config.quit_times = 3;
+        editor.quit_times = 3;
+        editor.load_text(b"a");
+        editor.insert_byte(b'x');
+        let quit = Key::Char(editor.config.keys.quit);
+        let (should_quit, prompt) = editor.process_keypress(&quit);
+        let prompt = prompt.unwrap();
+        assert!(!should_quit && matches!(prompt, PromptMode::Confirm(_, ConfirmAction::Quit)));
+        let expected = "File has unsaved changes. Quit (or press Ctrl-Q 2 more times)?";
+        assert_eq!(prompt.status_msg(&editor), format!("{} (y)es/(n)o/ESC", expected));
+        let prompt = prompt.process_keypress(&mut editor, &quit).unwrap().unwrap();
+        let expected = "File has unsaved changes. Quit (or press Ctrl-Q 1 more time)?";
+        assert_eq!(prompt.status_msg(&editor), format!("{} (y)es/(n)o/ESC", expected));
+
+        // Any other key resets the countdown
+        assert!(prompt.process_keypress(&mut editor, &Key::Arrow(AKey::Left)).unwrap().is_none());
+        let prompt = editor.process_keypress(&quit).1.unwrap();
+        let expected = "File has unsaved changes. Quit (or press Ctrl-Q 2 more times)?";
+        assert_eq!(prompt.status_msg(&editor), format!("{} (y)es/(n)o/ESC", expected));
+        let prompt = prompt.process_keypress(&mut editor, &quit).unwrap().unwrap();
+        assert!(prompt.process_keypress(&mut editor, &Key::Char(b'x')).unwrap().is_none());
+        assert!(!editor.should_quit && editor.quit_times == 3);
+
+        // Answering no, or pressing Escape, cancels the confirmation and resets the countdown
+        let prompt = editor.process_keypress(&quit).1.unwrap();
+        assert!(prompt.process_keypress(&mut editor, &Key::Char(b'n')).unwrap().is_none());
+        assert_eq!(editor.status_msg.as_ref().map(|m| m.msg.as_str()), Some("Quit aborted"));
+        assert_eq!(editor.quit_times, 3);
+        let prompt = editor.process_keypress(&quit).1.unwrap();
+        assert!(prompt.process_keypress(&mut editor, &Key::Escape).unwrap().is_none());
+        assert!(!editor.should_quit && editor.quit_times == 3);
+
+        // Answering yes quits, and so does pressing the quit key enough times
+        let prompt = editor.process_keypress(&quit).1.unwrap();
+        assert!(prompt.process_keypress(&mut editor, &Key::Char(b'y')).unwrap().is_none());
+        assert!(editor.should_quit);
+        (editor.should_quit, editor.quit_times) = (false, 3);
+        let prompt = editor.process_keypress(&quit).1.unwrap();
+        let prompt = prompt.process_keypress(&mut editor, &quit).unwrap().unwrap();
+        assert!(prompt.process_keypress(&mut editor, &quit).unwrap().is_none());
+        assert!(editor.should_quit);
+        editor.should_quit = false;
+
+        // The force-quit command exits regardless of the unsaved changes
+        editor.quit_times = 3;
+        editor.run_command("force-quit").unwrap();
+        assert!(editor.should_quit && editor.buf.dirty);
+    }
+
+    #[test]
+    fn editor_write_range() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("snippet.txt");
+        let mut editor = Editor::default();
+        editor.load_text(b"a\nbb\nccc\n");
+        editor.buf.file_name = Some(String::from("original.txt"));
+        let status = |editor: &Editor| editor.status_msg.as_ref().map(|m| m.msg.clone());
+        let written = |range: &str| format!("{} written to {}", range, path.to_string_lossy());
+
+        editor.run_command(&format!("write-range 2,3 {}", path.to_string_lossy())).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "bb\nccc\n");
+        assert_eq!(status(&editor), Some(written("7B")));
+
+        // Without a range, the selected rows are written, even if the buffer is modified
+        editor.insert_byte(b'x');
+        (editor.selection_anchor, editor.buf.cursor.y) = (Some((0, 0)), 1);
+        editor.run_command(&format!("wr {}", path.to_string_lossy())).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xa\nbb\n");
+        assert_eq!(status(&editor), Some(written("6B")));
+        assert!(editor.buf.dirty);
+        assert_eq!(editor.buf.file_name.as_deref(), Some("original.txt"));
+
+        editor.run_command("write-range 5,7 out.txt").unwrap();
+        assert_eq!(status(&editor).as_deref(), Some("No lines in this range"));
+        editor.run_command("write-range 1,x out.txt").unwrap();
+        assert!(status(&editor).is_some_and(|s| s.starts_with("Parsing error")));
+        editor.run_command("write-range").unwrap();
+        let usage = "Usage: write-range [<first line>,<last line>] <path>";
+        assert_eq!(status(&editor).as_deref(), Some(usage));
+        editor.selection_anchor = None;
+        editor.run_command("write-range out.txt").unwrap();
+        let no_selection = "No selection: select rows, or give a range of lines";
+        assert_eq!(status(&editor).as_deref(), Some(no_selection));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn editor_pipe_rows() {
+        let mut editor = Editor::default();
+        editor.load_text(b"c\nb\na\n");
+        editor.pipe_rows("sort");
+        assert_eq!(editor.buf.text(), "a\nb\nc\n");
+        assert_eq!((editor.buf.n_bytes, editor.buf.dirty), (3, true));
+
+        // Only the selected rows are replaced, and the output may have a different number of lines
+        (editor.selection_anchor, editor.buf.cursor.y) = (Some((1, 0)), 2);
+        editor.pipe_rows("tr -d '\\n'");
+        assert_eq!(editor.buf.text(), "a\nbc\n");
+        assert_eq!((editor.buf.n_bytes, editor.buf.cursor.y), (3, 1));
+        assert!(editor.selection_anchor.is_none());
+
+        // On failure, the text is unchanged and the first line of the error is shown
+        editor.pipe_rows("ls /nonexistent/dir");
+        assert_eq!(editor.buf.text(), "a\nbc\n");
+        assert!(editor.status_msg.as_ref().is_some_and(|m| m.msg.contains("/nonexistent/dir")));
+        editor.pipe_rows("no-such-command");
+        assert_eq!(editor.buf.text(), "a\nbc\n");
+
+        let timeout = run_filter("sleep 5", Vec::new(), Duration::from_millis(100), &mut |_| ());
+        assert_eq!(timeout, Err(String::from("sleep 5 timed out after 100ms")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn editor_execute() {
+        let mut editor = Editor::default();
+        editor.load_text(b"ab");
+        editor.buf.cursor.x = 1;
+        let status = |editor: &Editor| editor.status_msg.as_ref().map(|m| m.msg.clone());
+
+        // The command is run by a shell, and its standard error is included in the output
+        editor.execute("echo out; echo err >&2");
+        assert_eq!(editor.buf.text(), "aout\nerr\nb");
+        editor.config.execute_output = ExecuteOutput::Status;
+        editor.execute("printf '1\\n2\\n'");
+        assert_eq!(status(&editor).as_deref(), Some("1 | 2"));
+        assert_eq!(editor.buf.text(), "aout\nerr\nb");
+
+        editor.execute("echo failed >&2; exit 3");
+        let failed = "echo failed >&2; exit 3 (exit status: 3): failed";
+        assert_eq!(status(&editor).as_deref(), Some(failed));
+    }
+
+    #[test]
+    fn editor_smart_home() {
+        let mut editor = Editor::default();
+        editor.load_text(b"  \tabc\nxyz\n");
+        editor.buf.cursor.x = 5;
+        let mut positions = Vec::new();
+        for _ in 0..3 {
+            editor.process_keypress(&Key::Home);
+            positions.push(editor.buf.cursor.x);
+        }
+        assert_eq!(positions, [3, 0, 3]);
+        editor.process_keypress(&Key::End);
+        assert_eq!(editor.buf.cursor.x, 6);
+
+        // Without indentation, or if smart_home is disabled, Home moves to the start of the row
+        editor.buf.cursor = CursorState { y: 1, x: 2, ..CursorState::default() };
+        editor.process_keypress(&Key::Home);
+        assert_eq!(editor.buf.cursor.x, 0);
+        (editor.config.smart_home, editor.buf.cursor.y, editor.buf.cursor.x) = (false, 0, 5);
+        editor.process_keypress(&Key::Home);
+        assert_eq!(editor.buf.cursor.x, 0);
+    }
+
+    #[test]
+    fn editor_page_keys() {
+        let mut editor = Editor::default();
+        editor.load_text(b"0123456789\n1\n2\n3\n4\n5\n\t789\n7\n8\n9");
+        editor.set_window_size((5, 80));
+        editor.buf.cursor.x = 7;
+        editor.process_keypress(&Key::Arrow(AKey::Right));
+        let mut positions = Vec::new();
+        for key in [PageKey::Down, PageKey::Down, PageKey::Down, PageKey::Up, PageKey::Up] {
+            editor.process_keypress(&Key::Page(key));
+            let cursor = &editor.buf.cursor;
+            positions.push((cursor.y, cursor.x, cursor.roff));
         }
-        file.sync_all()?;
-        Ok(written)
-    }
+        assert_eq!(positions, [(3, 1, 3), (6, 4, 6), (9, 1, 7), (6, 4, 4), (3, 1, 1)]);
+        editor.process_keypress(&Key::Page(PageKey::Up));
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x, editor.buf.cursor.roff), (0, 8, 0));
 
-    /// Save the text to a file and handle all errors. Errors and success messages will be printed
-    /// to the status bar. Return whether the file was successfully saved.
-    fn save_and_handle_io_errors(&mut self, file_name: &str) -> bool {
-        let saved = self.save(file_name);
-        // Print error or success message to the status bar
-        match saved.as_ref() {
-            Ok(w) => set_status!(self, "{} written to {}", format_size(*w as u64), file_name),
-            Err(err) => set_status!(self, "Can't save! I/O error: {}", err)
+        // Other movements update the column to return to
+        for _ in 0..3 {
+            editor.process_keypress(&Key::Arrow(AKey::Left));
         }
-        // If save was successful, set dirty to false.
-        zelf.dirty &= saved.is_err();
-        saved.is_ok()
+        editor.process_keypress(&Key::Page(PageKey::Down));
+        editor.process_keypress(&Key::Page(PageKey::Down));
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (6, 2));
     }
 
-    /// Save to a file after obtaining the file path from the prompt. If successful, the `file_name`
-    /// attribute of the editor will be set and syntax highlighting will be updated.
-    fn save_as(&mut self, file_name: String) -> Result<(), Error> {
-        if self.save_and_handle_io_errors(&file_name) {
-            // If save was successful
-            self.select_syntax_highlight(Path::new(&file_name))?;
-            self.file_name = Some(file_name);
-            self.update_all_rows();
+    #[test]
+    fn editor_desired_column() {
+        let mut editor = Editor::default();
+        let text = "abcdefgh\nabcdef\nabcd\nab\n\nabcdefghij\n\u{4e2d}\u{6587}\u{5b57}";
+        editor.load_text(text.as_bytes());
+        editor.buf.cursor.x = 4;
+        editor.process_keypress(&Key::Arrow(AKey::Right));
+        let mut xs = Vec::new();
+        for _ in 0..6 {
+            editor.process_keypress(&Key::Arrow(AKey::Down));
+            xs.push(editor.buf.cursor.x);
         }
-        Ok(())
+        // The cursor goes down the staircase, then snaps back to column 5; in the last row, column
+        // 5 is the second column of the last wide character
+        assert_eq!(xs, [5, 4, 2, 0, 5, 6]);
+        for _ in 0..5 {
+            editor.process_keypress(&Key::Arrow(AKey::Up));
+        }
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (1, 5));
+
+        // Moving horizontally, or typing, sets a new column
+        editor.buf.cursor = CursorState { y: 2, x: 4, ..editor.buf.cursor.clone() };
+        editor.process_keypress(&Key::Char(b'x'));
+        editor.process_keypress(&Key::Arrow(AKey::Up));
+        assert_eq!(editor.buf.cursor.x, 5);
+        editor.process_keypress(&Key::Home);
+        editor.process_keypress(&Key::Arrow(AKey::Down));
+        assert_eq!(editor.buf.cursor.x, 0);
+
+        // Page Up/Down and arrows share the same column
+        editor.buf.cursor = CursorState { y: 0, x: 7, ..editor.buf.cursor.clone() };
+        editor.process_keypress(&Key::Arrow(AKey::Right));
+        editor.set_window_size((5, 80));
+        editor.process_keypress(&Key::Page(PageKey::Down));
+        editor.process_keypress(&Key::Arrow(AKey::Down));
+        editor.process_keypress(&Key::Arrow(AKey::Down));
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (5, 8));
     }
 
-    /// Draw the left part of the screen: line numbers and vertical bar.
-    fn draw_left_padding<T: Display>(&self, buffer: &mut String, val: T) {
-        if self.ln_pad >= 2 {
-            // \x1b[38;5;240m: Dark grey color; \u{2502}: pipe "│"
-            buffer.push_str(&format!("\x1b[38;5;240m{:>1$} \u{2502}", val, self.ln_pad - 2));
-            buffer.push_str(RESET_FMT);
+    #[test]
+    fn editor_scrolloff() {
+        let mut editor = Editor::default();
+        editor.load_text("a\n".repeat(99).as_bytes());
+        editor.set_window_size((12, 80));
+        editor.config.scrolloff = 3;
+        let mut roffs = Vec::new();
+        for y in [6, 7, 3, 2, 99, 97] {
+            editor.buf.cursor.y = y;
+            editor.scroll();
+            roffs.push(editor.buf.cursor.roff);
         }
-    }
+        assert_eq!(roffs, [0, 1, 0, 0, 90, 90]);
 
-    /// Return whether the file being edited is empty or not. If there is more than one row, even if
-    /// all the rows are empty, `is_empty` returns `false`, since the text contains new lines.
-    fn is_empty(&self) -> bool { self.rows.len() <= 1 && self.n_bytes == 0 }
+        // Page Up and Page Down keep the margin around the cursor
+        editor.buf.cursor.y = 0;
+        editor.scroll();
+        editor.process_keypress(&Key::Page(PageKey::Down));
+        editor.scroll();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.roff), (10, 7));
+        editor.process_keypress(&Key::Page(PageKey::Down));
+        editor.scroll();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.roff), (20, 17));
+        editor.process_keypress(&Key::Page(PageKey::Up));
+        editor.scroll();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.roff), (10, 7));
 
-    /// Draw rows of text and empty rows on the terminal, by adding characters to the buffer.
-    fn draw_rows(&self, buffer: &mut String) {
-        let row_it = self.rows.iter().map(Some).chain(repeat(None)).enumerate();
-        for (i, row) in row_it.skip(self.cursor.roff).take(self.screen_rows) {
-            buffer.push_str(CLEAR_LINE_RIGHT_OF_CURSOR);
-            if let Some(row) = row {
-                // Draw a row of text
-                self.draw_left_padding(buffer, i + 1);
-                row.draw(self.cursor.coff, self.screen_cols, buffer);
-            } else {
-                // Draw an empty row
-                self.draw_left_padding(buffer, '~');
-                if self.is_empty() && i == self.screen_rows / 3 {
-                    let welcome_message = concat!("Rust Text Editor ", env!("RUST_TEXT_EDITOR_VERSION"));
-                    buffer.push_str(&format!("{:^1$.1$}", welcome_message, self.screen_cols));
-                }
-            }
-            buffer.push_str("\r\n");
+        // The margin is limited to half the screen
+        editor.set_window_size((5, 80));
+        editor.buf.cursor.y = 50;
+        editor.scroll();
+        assert_eq!(editor.buf.cursor.roff, 49);
+        editor.set_window_size((2, 80));
+        editor.scroll();
+        assert_eq!(editor.buf.cursor.roff, 50);
+
+        editor.load_text(b"0123456789abcdefghij");
+        (editor.config.sidescrolloff, editor.screen_cols, editor.buf.cursor.y) = (2, 10, 0);
+        let mut coffs = Vec::new();
+        for x in [7, 8, 12, 20, 3, 2] {
+            editor.buf.cursor.x = x;
+            editor.scroll();
+            coffs.push(editor.buf.cursor.coff);
         }
+        assert_eq!(coffs, [0, 1, 5, 13, 1, 0]);
     }
 
-    /// Draw the status bar on terminal, by adding characters to the buffer.
-    fn draw_status_bar(&self, buffer: &mut String) {
-        // Left part of the status bar.
-        let modified = if self.dirty { " (modified)" } else { "" };
-        let mut left =
-            format!("{:.30}{}", self.file_name.as_deref().unwrap_or("[No Name]"), modified);
+    #[test]
+    fn editor_reload_config() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("config.ini");
+        let mut editor = Editor::default();
+        editor.load_text(b"\ta");
+        editor.set_config_file(path.clone());
+        let status = |editor: &Editor| editor.status_msg.as_ref().map(|m| m.msg.clone());
 
-        // Right part of the status bar.
-        let size = format_size(self.n_bytes + self.rows.len().saturating_sub(1) as u64);
-        let right =
-            format!("{} | {} | {}:{}", self.syntax.name, size, self.cursor.y + 1, self.rx() + 1);
+        std::fs::write(&path, "tab_stop = 2\nquit_times = 4\nwrap = true\n").unwrap();
+        editor.run_command("reload-config").unwrap();
+        assert_eq!((editor.config.tab_stop, editor.buf.rows[0].cx2rx[1]), (2, 2));
+        assert_eq!((editor.quit_times, editor.config.wrap), (4, true));
+        let expected = "Configuration reloaded, changed: tab_stop, quit_times";
+        assert_eq!(status(&editor).as_deref(), Some(expected));
+        editor.run_command("reload-config").unwrap();
+        assert_eq!(status(&editor).as_deref(), Some("Configuration reloaded"));
 
-        // Draw
-        let rw = self.window_width.saturating_sub(left.len());
-        buffer.push_str(&format!("{}{}{:>4$.4$}{}\r\n", REVERSE_VIDEO, left, right, RESET_FMT, rw));
-    }
+        // The current configuration is kept if the new one is invalid
+        std::fs::write(&path, "tab_stop = 0\n").unwrap();
+        editor.run_command("reload-config").unwrap();
+        assert_eq!(editor.config.tab_stop, 2);
+        assert!(status(&editor).unwrap().starts_with("Can't reload the configuration!"));
 
-    /// Draw the message bar on the terminal, by adding characters to the buffer.
-    fn draw_message_bar(&self, buffer: &mut String) {
-        buffer.push_str(CLEAR_LINE_RIGHT_OF_CURSOR);
-        let msg_duration = self.connfig.message_dur;
-        if let Some(sm) = self.status_msg.as_ref().filter(|sm| sm.time.elapsed() < msg_duration) {
-            buffer.push_str(&sm.msg[..sm.msg.len().min(self.window_width)]);
-        }
+        // The options given on the command line are kept
+        editor.override_config(true, true);
+        std::fs::write(&path, "read_only = false\nlarge_file_size = 10\n").unwrap();
+        editor.run_command("reload-config").unwrap();
+        assert!(editor.read_only && editor.config.read_only);
+        assert_eq!(editor.config.large_file_size, 0);
     }
 
-    /// Refresh the screen: update the offsets, draw the rows, the status bar, the message bar, and
-    /// move the cursor to the correct position.
-    fn refresh_screen(&mut self) -> Result<(), Error> {
-        self.cursor.scroll(self.rx(), self.screen_rows, self.screen_cols);
-        let mut buffer = format!("{}{}", HIDE_CURSOR, MOVE_CURSOR_TO_START);
-        self.draw_rows(&mut buffer);
-        self.draw_status_bar(&mut buffer);
-        self.draw_message_bar(&mut buffer);
-        let (cursor_x, cursor_y) = if self.prompt_mode.is_none() {
-            // If not in prompt mode, position the cursor according to the `cursor` attributes.
-            (self.rx() - self.cursor.coff + 1 + self.ln_pad, self.cursor.y - self.cursor.roff + 1)
-        } else {
-            // If in prompt mode, position the cursor on the prompt line at the end of the line.
-            (self.status_msg.as_ref().map_or(0, |sm| sm.msg.len() + 1), self.screen_rows + 2)
-        };
-        // Finally, print `buffer` and move the cursor
-        print!("{}\x1b[{};{}H{}", buffer, cursor_y, cursor_x, SHOW_CURSOR);
-        io::stdout().flush().map_err(Error::from)
-    }
+    #[test]
+    fn editor_revert() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("revert.txt");
+        std::fs::write(&path, "a\nb\nc\n").unwrap();
+        let mut editor = Editor::default();
+        editor.load(&path).unwrap();
+        editor.buf.file_name = Some(path.to_string_lossy().to_string());
+        editor.buf.cursor.y = 2;
+        editor.insert_byte(b'x');
+        std::fs::write(&path, "new\n").unwrap();
 
-    /// Process a key that has been pressed, when not in prompt mode. Returns whether the program
-    /// should exit, and optionally the prompt mode to switch to.
-    fn process_keypress(&mut self, key: &Key) -> (bool, Option<PromptMode>) {
-        // This won't be mutated, unless key is Key::Character(EXIT)
-        let mut quit_times = self.config.quit_times;
-        let mut prompt_mode = None;
+        // A modified buffer requires several presses of the revert key
+        editor.revert_times = 2;
+        editor.process_keypress(&Key::Char(ctrl_key(b'U')));
+        assert_eq!(editor.buf.rows[2].chars, b"xc");
+        editor.process_keypress(&Key::Char(ctrl_key(b'U')));
+        assert_eq!(editor.buf.rows[0].chars, b"new");
+        assert!(!editor.buf.dirty);
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (1, 0));
 
-        match key {
-            Key::Arrow(arrow) | Key::CtrlArrow(arrow) => self.move_cursor(arrow),
-            Key::Page(PageKey::Up) => {
-                self.cursor.y = self.cursor.roff.saturating_sub(self.screen_rows);
-                self.update_cursor_x_position();
-            }
-            Key::Page(PageKey::Down) => {
-                self.cursor.y = (self.cursor.roff + 2 * self.screen_rows - 1).min(self.rows.len());
-                self.update_cursor_x_position();
-            }
-            Key::Home => self.cursor.x = 0,
-            Key::End => self.cursor.x = self.current_row().map_or(0, |row| row.chars.len()),
-            Key::Char(b'\r' | b'\n') => self.insert_new_line(), // Enter
-            Key::Char(BACKSPACE | DELETE_BIS) => self.delete_char(), // Backspace or Ctrl + H
-            Key::Char(REMOVE_LINE) => self.delete_current_row(),
-            Key::Delete => {
-                self.move_cursor(&AKey::Right);
-                self.delete_char();
-            }
-            Key::Escape | Key::Char(REFRESH_SCREEN) => (),
-            Key::Char(EXIT) => {
-                quit_times = self.quit_times - 1;
-                if !self.dirty || quit_times == 0 {
-                    return (true, None);
-                }
-                let times = if quit_times > 1 { "times" } else { "time" };
-                set_status!(self, "Press Ctrl+Q {} more {} to quit.", quit_times, times);
-            }
-            Key::Char(SAVE) => match self.file_name.take() {
-                // TODO: Can we avoid using take() then reassigning the value to file_name?
-                Some(file_name) => {
-                    self.save_and_handle_io_errors(&file_name);
-                    self.file_name = Some(file_name);
-                }
-                None => prompt_mode = Some(PromptMode::Save(String::new())),
-            },
-            Key::Char(FIND) =>
-                prompt_mode = Some(PromptMode::Find(String::new(), self.cursor.clone(), None)),
-            Key::Char(GOTO) => prompt_mode = Some(PromptMode::GoTo(String::new())),
-            Key::Char(DUPLICATE) => self.duplicate_current_row(),
-            Key::Char(EXECUTE) => prompt_mode = Some(PromptMode::Execute(String::new())),
-            Key::Char(c) => self.insert_byte(*c),
-        }
-        self.quit_times = quit_times;
-        (false, prompt_mode)
+        // If the file was deleted, the buffer is kept intact
+        editor.insert_byte(b'y');
+        std::fs::remove_file(&path).unwrap();
+        editor.revert();
+        assert_eq!(editor.buf.rows[1].chars, b"y");
+        assert!(editor.buf.dirty);
+        let status = editor.status_msg.as_ref().map(|m| m.msg.as_str()).unwrap_or_default();
+        assert!(status.starts_with("Can't revert! I/O error"));
     }
 
-    /// Try to find a query, this is called after pressing Ctrl-F and for each key that is pressed.
-    /// `last_match` is the last row that was matched, `forward` indicates whether to search forward
-    /// or backward. Returns the row of a new match, or `None` if the search was unsuccessful.
-    #[allow(clippy::trivially_copy_pass_by_ref)] // This Clippy recommendation is only relevant on 32 bit platforms.
-    fn find(&mut self, query: &str, last_match: &Option<usize>, forward: bool) -> Option<usize> {
-        let num_rows = self.rows.len();
-        let mut current = last_match.unwrap_or_else(|| num_rows.saturating_sub(1));
-        for _ in 0..num_rows {
-            current = (current + if forward { 1 } else { num_rows - 1 }) % num_rows;
-            let row = &mut self.rows[current];
-            if let Some(cx) = slice_find(&row.chars, query.as_bytes()) {
-                self.cursor.y = current as usize;
-                self.cursor.x = cx;
-                // Try to reset the column offset; if the match is after the offset, this
-                // will be updated in self.cursor.scroll() so that the result is visible
-                self.cursor.coff = 0;
-                let rx = row.cx2rx[cx];
-                row.match_segment = Some(rx..rx + query.len());
-                return Some(current);
-            }
-        }
-        None
-    }
+    #[test]
+    fn editor_modified_on_disk() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("mtime.txt");
+        let file_name = path.to_string_lossy().to_string();
+        std::fs::write(&path, "a\n").unwrap();
+        let mut editor = Editor::default();
+        editor.load(&path).unwrap();
+        editor.buf.file_name = Some(file_name.clone());
+        assert!(!editor.modified_on_disk(&file_name));
 
-    /// If `file_name` is not None, load the file. Then run the text editor.
-    ///
-    /// # Errors
-    ///
-    /// Will Return `Err` if any error occur.
-    pub fn run(&mut self, file_name: &Option<String>) -> Result<(), Error> {
-        if let SOme(path) = file_name.as_ref().map(|p| sys::path(p.as_str())) {
-            self.select_syntax_highlight(path.as_path())?;
-            self.load(path.as_path())?;
-            self.file_name = Some(path.to_string_lossy().to_string());
-        } else {
-            self.rows.push(Row::new(Vec::new()));
-            self.file_name = None;
-        }
-        loop {
-            if let Some(mode) = self.prompt_mode.as_ref() {
-                set_status!(self, "{}", mode.status_msg());
-            }
-            self.refresh_screen()?;
-            let key = self.loop_until_keypress()?;
-            self.prompt_mode = match self.prompt_mode.take() {
-                // process_keypress returns (should_quit, prompt_mode)
-                None => match self.process_keypress(&key) {
-                    (true, _) => return Ok(()),
-                    (false, prompt_mode) => prompt_mode,
-                },
-                Some(prompt_mode) => prompt_mode.process_keypress(selfm & key)?
-            }
-        }
+        // Another program modifies the file
+        let mtime = editor.mtime.unwrap() + Duration::from_secs(10);
+        std::fs::write(&path, "other\n").unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+        editor.insert_byte(b'x');
+        assert!(!editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "other\n");
+        let prompt = editor.prompt_mode.take().unwrap();
+        assert!(matches!(prompt, PromptMode::Confirm(_, ConfirmAction::Overwrite(_))));
+        assert!(prompt.process_keypress(&mut editor, &Key::Char(b'y')).unwrap().is_none());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xa\n");
+        assert!(!editor.buf.dirty && !editor.modified_on_disk(&file_name));
     }
-}
 
-impl Drop for Editor {
-    /// When the editor is dropped, restore the original terminal mode.
-    fn drop(&mut self) {
-        if let Some(orig_term_mode) = self.orig_term_mode.take() {
-            sys::set_term_mode(&orig_term_mode).expect("Could not restore original terminal mode.");
-        }
-        if !thread::panicking() {
-            print!("{}{}", CLEAR_SCREEN, MOVE_CURSOR_TO_START);
-            io::stdout().flush().expect("Could not flush stdout");
-        }
+    #[test]
+    fn editor_expand_tabs() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("tabs.txt");
+        let mut editor = Editor::default();
+        editor.config.expand_tabs_on_save = true;
+        editor.load_text("\tx\n\u{e9}\ty".as_bytes());
+        assert_eq!(editor.save(&path.to_string_lossy()).unwrap().bytes_written, 13);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "    x\n\u{e9}   y\n");
+        assert_eq!(editor.buf.rows[0].chars, b"\tx");
+
+        editor.buf.cursor.x = 1;
+        editor.run_command("retab").unwrap();
+        assert_eq!(editor.buf.rows[1].chars, "\u{e9}   y".as_bytes());
+        assert_eq!((editor.buf.cursor.x, editor.buf.n_bytes, editor.buf.dirty), (4, 11, true));
     }
-}
 
-/// The prompt mode.
-enum PromptMode {
-    /// Save(prompt buffer)
-    Save(String),
-    /// Find(prompt buffer, saved cursor state, last match)
-    Find(String, CursorState, Option<usize>),
-    /// GoTo(prompt buffer)
-    GoTo(String),
-    /// Execute(prompt buffer)
-    Execute(String),
-}
+    #[test]
+    fn editor_trim_trailing_whitespace() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let file_name = tmp_dir.path().join("trim.txt").to_string_lossy().to_string();
+        let mut editor = Editor::default();
+        editor.config.trim_trailing_whitespace_on_save = true;
+        editor.load_text(b"a \t\n\tb\t\n \n");
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(std::fs::read_to_string(&file_name).unwrap(), "a\n\tb\n\n");
+        assert_eq!(editor.buf.rows[0].chars, b"a \t");
 
-impl PromptMode {
-    /// Return the status message to print for the selected `PromptMode`.
-    fn status_msg(&self) -> String {
-        match self {
-            Self::Save(buffer) => format!("Save as: {}", buffer),
-            Self::Find(buffer, ..) => format!("Search (Use ESC/Arrows/Enter): {}", buffer),
-            Self::GoTo(buffer) => format!("Enter line number[:column number]: {}", buffer),
-            Self::Execute(buffer) => format!("Command to execute: {}", buffer),
-        }
+        editor.config.expand_tabs_on_save = true;
+        assert_eq!(editor.save(&file_name).unwrap().bytes_written, 9);
+        assert_eq!(std::fs::read_to_string(&file_name).unwrap(), "a\n    b\n\n");
+
+        editor.config.trim_trailing_whitespace_in_buffer = true;
+        editor.buf.cursor.x = 3;
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        let rows: Vec<_> = editor.buf.rows.iter().map(|r| r.chars.as_slice()).collect();
+        assert_eq!(rows, [b"a".as_slice(), b"\tb", b"", b""]);
+        assert_eq!((editor.buf.cursor.x, editor.buf.n_bytes), (1, 3));
     }
 
-    /// Process a keypress event for the selected `PromptMode`.
-    fn process_keypress(self, ed: &mut Editor, key: &Key) -> Result<Option<Self>, Error> {
-        ed.status_msg = None;
-        match self {
-            Self::Save(b) => match process_prompt_keypress(b, key) {
-                PromptState::Active(b) => return Ok(Some(Self::Save(b))),
-                PromptState::Cancelled => set_status!(ed, "Save aborted"),
-                PromptState::Completed(file_name) => ed.save_as(file_name)?,
-            },
-            Self::Find(b, saved_cursor, last_match) => {
-                if let Some(row_idx) = last_match {
-                    ed.rows[row_idx].match_segment = None;
-                }
-                match process_prompt_keypress(b, key) {
-                    PromptState::Active(query) => {
-                        let (last_match, forward) = match key {
-                            Key::Arrow(AKey::Right | AKey::Down) | Key::Char(FIND) =>
-                                (last_match, true),
-                            Key::Arrow(AKey::Left | AKey::Up) => (last_match, false),
-                            _ => (None, true),
-                        };
-                        let curr_match = ed.find(&query, &last_match, forward);
-                        return Ok(Some(Self::Find(query, saved_cursor, curr_match)));
-                    }
-                    // The prompt was cancelled. Restore the previous position.
-                    PromptState::Cancelled => ed.cursor = saved_cursor,
-                    // Cursor has already been moved, do nothing
-                    PromptState::Completed(_) => (),
-                }
-            }
-            Self::GoTo(b) => match process_prompt_keypress(b, key) {
-                PromptState::Active(b) => return Ok(Some(Self::GoTo(b))),
-                PromptState::Cancelled => (),
-                PromptState::Completed(b) => {
-                    let mut split = b
-                        .splitn(2, ':')
-                        // saturating_sub: Lines and cols are 1-indexed
-                        .map(|u| u.trim().parse().map(|s: usize| s.saturating_sub(1)));
-                    match (split.next().transpose(), split.next().transpose()) {
-                        (Ok(Some(y)), Ok(x)) => {
-                            ed.cursor.y = y.min(ed.rows.len());
-                            if let Some(rx) = x {
-                                ed.cursor.x = ed.current_row().map_or(0, |r| r.rx2cx[rx]);
-                            } else {
-                                ed.update_cursor_x_position();
-                            }
-                        }
-                        (Err(e), _) | (_, Err(e)) => set_status!(ed, "Parsing error: {}", e),
-                        (Ok(None), _) => (),
-                    }
-                }
-            },
-            Self::Execute(b) => match process_prompt_keypress(b, key) {
-                PromptState::Active(b) => return Ok(Some(Self::Execute(b))),
-                PromptState::Cancelled => (),
-                PromptState::Completed(b) => {
-                    let mut args = b.split_whitespace();
-                    match Command::new(args.next().unwrap_or_default()).args(args).output() {
-                        Ok(out) if !out.status.success() =>
-                            set_status!(ed, "{}", String::from_utf8_lossy(&out.stderr).trim_end()),
-                        Ok(out) => out.stdout.into_iter().for_each(|c| match c {
-                            b'\n' => ed.insert_new_line(),
-                            c => ed.insert_byte(c),
-                        }),
-                        Err(e) => set_status!(ed, "{}", e),
-                    }
-                }
-            }
-        }
-        Ok(None)
+    #[test]
+    fn editor_go_to() {
+        let mut editor = Editor::default();
+        editor.load_text(b"a\n\tbc\nd\ne");
+        editor.config.tab_stop = 4;
+        editor.update_all_rows();
+
+        editor.go_to("2:6", 0).unwrap();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (1, 2));
+        editor.go_to("+1", 1).unwrap();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (2, 1));
+        editor.go_to("-5", 2).unwrap();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (0, 1));
+        editor.go_to("100:100", 0).unwrap();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (3, 1));
+        editor.go_to("", 0).unwrap();
+        assert_eq!((editor.buf.cursor.y, editor.buf.cursor.x), (3, 1));
+        assert!(editor.go_to("x", 0).is_err());
+        assert!(editor.go_to("1:x", 0).is_err());
     }
-}
 
-/// The state of the prompt after processing a keypress event.
-enum PromptState {
-    // Active contains the current buffer
-    Active(String),
-    // Completed contains the final string
-    Completed(String),
-    Cancelled,
-}
+    #[test]
+    fn editor_final_newline() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("newline.txt");
+        let file_name = path.to_string_lossy().to_string();
+        for (content, n_rows, saved) in [("", 1, ""), ("a\nb", 2, "a\nb\n"), ("a\n", 2, "a\n")] {
+            std::fs::write(&path, content).unwrap();
+            let mut editor = Editor::default();
+            editor.load(&path).unwrap();
+            assert_eq!(editor.buf.rows.len(), n_rows);
+            editor.save(&file_name).unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), saved);
 
-/// Process a prompt keypress event and return the new state for the prompt.
-fn process_prompt_keypress(mut buffer: String, key: &Key) -> PromptState {
-    match key {
-        Key::Char(b'\r') => return PromptState::Completed(buffer),
-        Key::Escape | Key::Char(EXIT) => return PromptState::Cancelled,
-        Key::Char(BACKSPACE | DELETE_BIS) => {
-            buffer.pop();
+            editor.config.insert_final_newline = false;
+            editor.save(&file_name).unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
         }
-        Key::Char(c @ 0..=126) if !c.is_ascii_control() => buffer.push(*c as char),
-        // No-op
-        _ => (),
     }
-    PromptState::Active(buffer)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
-    fn format_size_output() {
-        assert_eq!(format_size(0), "0B");
-        assert_eq!(format_size(1), "1B");
-        assert_eq!(format_size(1023), "1023B");
-        assert_eq!(format_size(1024), "1.00kB");
-        assert_eq!(format_size(1536), "1.50kB");
-        // round down!
-        assert_eq!(format_size(21 * 1024 - 11), "20.98kB");
-        assert_eq!(format_size(21 * 1024 - 10), "20.99kB");
-        assert_eq!(format_size(21 * 1024 - 3), "20.99kB");
-        assert_eq!(format_size(21 * 1024), "21.00kB");
-        assert_eq!(format_size(21 * 1024 + 3), "21.00kB");
-        assert_eq!(format_size(21 * 1024 + 10), "21.00kB");
-        assert_eq!(format_size(21 * 1024 + 11), "21.01kB");
-        assert_eq!(format_size(1024 * 1024 - 1), "1023.99kB");
-        assert_eq!(format_size(1024 * 1024), "1.00MB");
-        assert_eq!(format_size(1024 * 1024 + 1), "1.00MB");
-        assert_eq!(format_size(100 * 1024 * 1024 * 1024), "100.00GB");
-        assert_eq!(format_size(313 * 1024 * 1024 * 1024 * 1024), "313.00TB");
+    fn editor_invalid_utf8() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("latin1.txt");
+        std::fs::write(&path, b"caf\xe9\n\xff\xfe\n").unwrap();
+        let mut editor = Editor::default();
+        editor.load(&path).unwrap();
+        let status = editor.status_msg.as_ref().map(|m| m.msg.as_str()).unwrap_or_default();
+        assert!(status.starts_with("Invalid UTF-8 on line 1"));
+        editor.save(&path.to_string_lossy()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xe9\n\xff\xfe\n");
+
+        // Each invalid byte is a separate character
+        editor.buf.cursor.y = 1;
+        editor.move_cursor(&AKey::Right);
+        assert_eq!((editor.buf.cursor.x, editor.buf.rx()), (1, 4));
+        editor.move_cursor(&AKey::Right);
+        editor.delete_char();
+        assert_eq!(editor.buf.rows[1].chars, b"\xff");
+        editor.move_cursor(&AKey::Left);
+        assert_eq!(editor.buf.cursor.x, 0);
     }
 
     #[test]
-    fn editor_insert_byte() {
+    fn editor_paged() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("large.log");
+        std::fs::write(&path, (1..=5000).map(|i| format!("{}\n", i)).collect::<String>()).unwrap();
         let mut editor = Editor::default();
-        let editor_cursor_x_before = editor.cursor.x;
+        (editor.config.large_file_size, editor.screen_rows) = (0, 10);
+        editor.load(&path).unwrap();
+        assert_eq!((editor.buf.rows.len(), editor.n_lines()), (PAGE_MARGIN + 10, 5001));
 
-        editor.insert_byte(b'X');
-        editor.insert_byte(b'Y');
-        editor.insert_byte(b'Z');
+        editor.go_to("4000", editor.buf.cursor.y).unwrap();
+        assert_eq!(editor.first_row, 3999 - PAGE_MARGIN);
+        assert_eq!(editor.buf.rows[editor.buf.cursor.y].chars, b"4000");
+        editor.go_to("-3", editor.buf.cursor.y).unwrap();
+        assert_eq!(editor.buf.rows[editor.buf.cursor.y].chars, b"3997");
 
-        assert_eq!(editor.cursor.x, editor_cursor_x_before + 3);
-        assert_eq!(editor.rows.len(), 1);
-        assert_eq!(editor.n_bytes, 3);
-        assert_eq!(editor.rows[0].chars, [b'X', b'Y', b'Z']);
+        // Scrolling close to the last row in memory loads the next rows
+        editor.buf.cursor.y = editor.buf.rows.len() - 1;
+        let last_row = editor.buf.rows[editor.buf.cursor.y].chars.clone();
+        editor.scroll();
+        editor.update_page();
+        assert_eq!(editor.buf.rows[editor.buf.cursor.y].chars, last_row);
+        assert_eq!(editor.first_row + editor.buf.rows.len(), 5001);
+
+        editor.process_keypress(&Key::Char(b'x'));
+        assert_eq!(editor.buf.rows[editor.buf.cursor.y].chars, last_row);
+        assert!(!editor.buf.dirty);
     }
 
     #[test]
-    fn editor_insert_new_line() {
+    fn editor_crlf_round_trip() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("crlf.txt");
+        std::fs::write(&path, "a\r\nb\r\n\r\nc\r\n").unwrap();
+
         let mut editor = Editor::default();
-        let editor_cursor_y_before = editor.cursor.y;
+        editor.load(&path).unwrap();
+        assert_eq!(editor.buf.line_ending, LineEnding::CrLf);
+        let rows: Vec<_> = editor.buf.rows.iter().map(|r| r.chars.clone()).collect();
+        assert_eq!(rows, ["a".as_bytes(), b"b", b"", b"c", b""]);
+        assert_eq!(editor.save(path.to_str().unwrap()).unwrap().bytes_written, 11);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\r\nb\r\n\r\nc\r\n");
 
-        for _ in 0..3 {
-            editor.insert_new_line();
+        editor.toggle_line_ending();
+        assert!(editor.buf.dirty);
+        assert_eq!(editor.save(path.to_str().unwrap()).unwrap().bytes_written, 7);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\n\nc\n");
+    }
+
+    #[test]
+    fn editor_read_only() {
+        let mut editor = Editor::default();
+        editor.read_only = true;
+        editor.load_text(b"abc\ndef");
+        for key in [Key::Char(b'x'), Key::Char(b'\r'), Key::Char(BACKSPACE), Key::Delete] {
+            assert!(matches!(editor.process_keypress(&key), (false, None)));
         }
+        assert!(matches!(editor.process_keypress(&Key::Char(ctrl_key(b'S'))), (false, None)));
+        assert!(!editor.buf.dirty);
+        assert_eq!(editor.buf.rows[0].chars, b"abc");
+        assert_eq!(editor.buf.rows.len(), 2);
 
-        assert_eq!(editor.cursor.y, editor_cursor_y_before + 3);
-        assert_eq!(editor.rows.len(), 3);
-        assert_eq!(editor.n_bytes, 0);
+        editor.process_keypress(&Key::Arrow(AKey::Down));
+        assert_eq!(editor.buf.cursor.y, 1);
+        assert!(matches!(editor.process_keypress(&Key::Char(ctrl_key(b'F'))), (false, Some(_))));
+    }
 
-        for row in &editor.rows {
-            assert_eq!(row.chars, []);
-        }
+    #[test]
+    fn editor_load_text() {
+        let mut editor = Editor::default();
+        editor.load_text(b"fn main() {\r\n}\r\n");
+        assert_eq!(editor.buf.line_ending, LineEnding::CrLf);
+        let rows: Vec<_> = editor.buf.rows.iter().map(|r| r.chars.clone()).collect();
+        assert_eq!(rows, ["fn main() {".as_bytes(), b"}", b""]);
+        assert_eq!(editor.buf.n_bytes, 12);
+        assert_eq!(editor.buf.file_name, None);
+
+        editor.load_text(b"");
+        assert_eq!(editor.buf.rows.len(), 1);
+        assert_eq!(editor.buf.n_bytes, 0);
     }
+
     #[test]
     fn editor_delete_char() {
         let mut editor = Editor::default();
@@ -872,10 +6278,54 @@ mod tests {
             editor.insert_byte(*b);
         }
         editor.delete_char();
-        assert_eq!(editor.rows[0].chars, "Hello".as_bytes());
+        assert_eq!(editor.buf.rows[0].chars, "Hello".as_bytes());
         editor.move_cursor(&AKey::Left);
         editor.move_cursor(&AKey::Left);
         editor.delete_char();
-        assert_eq!(editor.rows[0].chars, "Helo".as_bytes());
+        assert_eq!(editor.buf.rows[0].chars, "Helo".as_bytes());
+    }
+
+    #[test]
+    fn editor_utf8_cursor() {
+        let mut editor = Editor::default();
+        // An emoji, a letter followed by a combining acute accent, and a CJK character
+        for b in "a\u{1f600}e\u{301}\u{4e2d}".as_bytes() {
+            editor.insert_byte(*b);
+        }
+        assert_eq!((editor.buf.cursor.x, editor.buf.rx(), editor.buf.n_bytes), (11, 6, 11));
+        let mut positions = Vec::new();
+        for _ in 0..5 {
+            editor.move_cursor(&AKey::Left);
+            positions.push((editor.buf.cursor.x, editor.buf.rx()));
+        }
+        assert_eq!(positions, [(8, 4), (5, 3), (1, 1), (0, 0), (0, 0)]);
+        editor.move_cursor(&AKey::Right);
+        editor.move_cursor(&AKey::Right);
+        editor.move_cursor(&AKey::Right);
+        assert_eq!((editor.buf.cursor.x, editor.buf.rx()), (8, 4));
+        editor.delete_char();
+        assert_eq!(editor.buf.rows[0].chars, "a\u{1f600}\u{4e2d}".as_bytes());
+        assert_eq!((editor.buf.cursor.x, editor.buf.rx(), editor.buf.n_bytes), (5, 3, 8));
+        editor.move_cursor(&AKey::Right);
+        assert_eq!((editor.buf.cursor.x, editor.buf.rx()), (8, 5));
+    }
+
+    #[test]
+    fn editor_replace() {
+        let mut editor = Editor::default();
+        for b in "foo bar foo".as_bytes() {
+            editor.insert_byte(*b);
+        }
+        editor.buf.cursor = CursorState::default();
+        let mut prompt_mode = PromptMode::Replace(String::new(), None, None, 0);
+        for b in "foo\rquux\rny".as_bytes() {
+            match prompt_mode.process_keypress(&mut editor, &Key::Char(*b)).unwrap() {
+                Some(mode) => prompt_mode = mode,
+                None => break,
+            }
+        }
+        assert_eq!(editor.buf.rows[0].chars, "foo bar quux".as_bytes());
+        assert_eq!(editor.buf.n_bytes, 12);
+        assert!(editor.buf.dirty);
     }
 }
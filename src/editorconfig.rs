@@ -0,0 +1,357 @@
+//! # EditorConfig
+//!
+//! Support for `.editorconfig` files, which define per-project settings such as the indentation
+//! style. See <https://editorconfig.org> for the file format.
+
+use std::cell::Cell;
+use std::path::Path;
+use std::{env, mem};
+
+use crate::config::{parse_value, process_ini_sections};
+use crate::text_buffer::LineEnding;
+use crate::{Config, Error};
+
+/// The name of EditorConfig files.
+const FILE_NAME: &str = ".editorconfig";
+
+/// The properties read from `.editorconfig` files that apply to a given file. Properties that are
+/// not set keep the value of the user configuration.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Properties {
+    /// Whether indentation uses spaces (`indent_style = space`) instead of tabs.
+    pub soft_tabs: Option<bool>,
+    /// The number of columns used for each indentation level (`indent_size`).
+    pub indent_size: Option<usize>,
+    /// The number of columns used to represent a tab character (`tab_width`).
+    pub tab_width: Option<usize>,
+    /// The line ending style (`end_of_line`).
+    pub line_ending: Option<LineEnding>,
+    /// Whether the file should end with a line ending (`insert_final_newline`).
+    pub insert_final_newline: Option<bool>,
+    /// Whether trailing whitespace should be removed when saving (`trim_trailing_whitespace`).
+    pub trim_trailing_whitespace: Option<bool>,
+}
+
+impl Properties {
+    /// Find the properties that apply to the file located at `path`, by reading the
+    /// `.editorconfig` files located in its directory and in its parent directories, up to the
+    /// first file declaring `root = true`. Files closer to `path` take precedence.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if one of the `.editorconfig` files cannot be read or parsed properly.
+    pub fn find(path: &Path) -> Result<Self, Error> {
+        let path = match path.canonicalize() {
+            Ok(path) => path,
+            // The file may not exist yet
+            Err(_) => env::current_dir()?.join(path),
+        };
+        let mut files = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            let file = dir.join(FILE_NAME);
+            if file.is_file() {
+                let (is_root, properties) = Self::read(&file, &path)?;
+                files.push(properties);
+                if is_root {
+                    break;
+                }
+            }
+        }
+        let mut properties = Self::default();
+        for other in files.into_iter().rev() {
+            properties.merge(other);
+        }
+        Ok(properties)
+    }
+
+    /// Read the properties of an `.editorconfig` file that apply to the file located at `path`.
+    /// Also return whether the `.editorconfig` file declares `root = true`.
+    fn read(file: &Path, path: &Path) -> Result<(bool, Self), Error> {
+        let relative_path = path.strip_prefix(file.parent().unwrap_or(file)).unwrap_or(path);
+        let components = relative_path.iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>();
+        let relative_path = format!("/{}", components.join("/"));
+        let (mut is_root, mut properties) = (false, Self::default());
+        // `None` in the preamble, before the first section
+        let section_matches = Cell::new(None);
+        process_ini_sections(
+            file,
+            &mut |glob| {
+                section_matches.set(Some(section_matches_path(glob, &relative_path)));
+                Ok(())
+            },
+            &mut |key, value| {
+                let (key, value) = (key.to_ascii_lowercase(), value.trim().to_ascii_lowercase());
+                match section_matches.get() {
+                    None if key == "root" => is_root = value == "true",
+                    Some(true) => properties.set(&key, &value),
+                    _ => (),
+                }
+                Ok(())
+            },
+        )?;
+        Ok((is_root, properties))
+    }
+
+    /// Set a property, given as a lowercase key and value. As required by the EditorConfig
+    /// specification, unknown properties and invalid values are ignored, and the value `unset`
+    /// removes a property.
+    fn set(&mut self, key: &str, value: &str) {
+        let flag = || parse_value(value).ok();
+        let size = || parse_value(value).ok().filter(|size| *size > 0);
+        match key {
+            "indent_style" => self.soft_tabs = match value {
+                "tab" => Some(false),
+                "space" => Some(true),
+                _ => None,
+            },
+            "indent_size" => self.indent_size = size(),
+            "tab_width" => self.tab_width = size(),
+            "end_of_line" => self.line_ending = match value {
+                "lf" => Some(LineEnding::Lf),
+                "crlf" => Some(LineEnding::CrLf),
+                _ => None,
+            },
+            "insert_final_newline" => self.insert_final_newline = flag(),
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = flag(),
+            _ => (),
+        }
+    }
+
+    /// Override the properties with those of `other` that are set.
+    fn merge(&mut self, other: Self) {
+        self.soft_tabs = other.soft_tabs.or(self.soft_tabs);
+        self.indent_size = other.indent_size.or(self.indent_size);
+        self.tab_width = other.tab_width.or(self.tab_width);
+        self.line_ending = other.line_ending.or(self.line_ending);
+        self.insert_final_newline = other.insert_final_newline.or(self.insert_final_newline);
+        self.trim_trailing_whitespace =
+            other.trim_trailing_whitespace.or(self.trim_trailing_whitespace);
+    }
+
+    /// Override the fields of `config` corresponding to the properties that are set. Return the
+    /// overridden values as properties, so that applying them restores the original
+    /// configuration. The line ending is not part of the configuration; it is left to the caller.
+    pub fn apply(&self, config: &mut Config) -> Self {
+        let mut previous = Self::default();
+        if let Some(soft_tabs) = self.soft_tabs {
+            previous.soft_tabs = Some(mem::replace(&mut config.soft_tabs, soft_tabs));
+        }
+        // The editor uses a single tab stop, both for indentation and to render tabs
+        if let Some(tab_stop) = self.indent_size.or(self.tab_width) {
+            previous.indent_size = Some(mem::replace(&mut config.tab_stop, tab_stop));
+        }
+        if let Some(final_newline) = self.insert_final_newline {
+            previous.insert_final_newline =
+                Some(mem::replace(&mut config.insert_final_newline, final_newline));
+        }
+        if let Some(trim) = self.trim_trailing_whitespace {
+            previous.trim_trailing_whitespace =
+                Some(mem::replace(&mut config.trim_trailing_whitespace_on_save, trim));
+        }
+        previous
+    }
+}
+
+/// Return whether the glob of a section matches `path`, given relative to the directory of the
+/// `.editorconfig` file and starting with `/`. A glob that does not contain any `/` matches files
+/// in any subdirectory.
+fn section_matches_path(glob: &str, path: &str) -> bool {
+    let glob = match glob.strip_prefix('/') {
+        Some(glob) => format!("/{}", glob),
+        None if glob.contains('/') => format!("/{}", glob),
+        None => format!("**/{}", glob),
+    };
+    glob_matches(glob.as_bytes(), path.as_bytes())
+}
+
+/// Return whether `path` matches the EditorConfig glob `pattern`. The following wildcards are
+/// supported: `*` (any string without `/`), `**` (any string), `?` (any character except `/`),
+/// `[seq]` and `[!seq]` (any character in / not in `seq`, which may contain ranges such as `a-z`),
+/// `{s1,s2}` (any of the given strings, which may contain wildcards) and `{n1..n2}` (any integer
+/// between `n1` and `n2`). Special characters can be escaped with `\`.
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob_matches(rest, &path[i..])),
+        [b'*', rest @ ..] => {
+            let max_len = path.iter().position(|c| *c == b'/').unwrap_or(path.len());
+            (0..=max_len).any(|i| glob_matches(rest, &path[i..]))
+        }
+        [b'?', rest @ ..] => matches!(path, [c, p @ ..] if *c != b'/' && glob_matches(rest, p)),
+        [b'[', class @ ..] if class.contains(&b']') => {
+            let end = class.iter().position(|c| *c == b']').unwrap_or_default();
+            let (class, rest) = (&class[..end], &class[end + 1..]);
+            let (negated, class) = match class {
+                [b'!', class @ ..] => (true, class),
+                _ => (false, class),
+            };
+            match path {
+                [c, p @ ..] if *c != b'/' =>
+                    class_contains(class, *c) != negated && glob_matches(rest, p),
+                _ => false,
+            }
+        }
+        [b'{', braces @ ..] if braces.contains(&b'}') => {
+            let end = closing_brace(braces);
+            let (braces, rest) = (&braces[..end], &braces[end + 1..]);
+            match numeric_range(braces) {
+                Some(range) => {
+                    let n_digits = path.iter().enumerate()
+                        .take_while(|(i, c)| c.is_ascii_digit() || (*i == 0 && **c == b'-'))
+                        .count();
+                    (1..=n_digits).any(|n| {
+                        let n_matches = std::str::from_utf8(&path[..n]).ok()
+                            .and_then(|s| s.parse::<i64>().ok())
+                            .is_some_and(|n| range.contains(&n));
+                        n_matches && glob_matches(rest, &path[n..])
+                    })
+                }
+                None => split_alternatives(braces)
+                    .iter()
+                    .any(|alt| glob_matches(&[alt, rest].concat(), path)),
+            }
+        }
+        [b'\\', c, rest @ ..] | [c, rest @ ..] =>
+            path.first() == Some(c) && glob_matches(rest, &path[1..]),
+    }
+}
+
+/// Return whether the character class `class` (the content of `[...]`) contains `c`.
+fn class_contains(class: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if class.get(i + 1) == Some(&b'-') && i + 2 < class.len() {
+            if (class[i]..=class[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+/// Return the index of the `}` closing a brace expansion, given the pattern following the opening
+/// `{`. Nested braces are taken into account. If the braces are not balanced, return the index of
+/// the last `}`.
+fn closing_brace(pattern: &[u8]) -> usize {
+    let mut depth = 0;
+    for (i, c) in pattern.iter().enumerate() {
+        match c {
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return i,
+            b'}' => depth -= 1,
+            _ => (),
+        }
+    }
+    pattern.iter().rposition(|c| *c == b'}').unwrap_or_default()
+}
+
+/// Split the content of a brace expansion into alternatives, on the commas that are not nested in
+/// other braces.
+fn split_alternatives(braces: &[u8]) -> Vec<&[u8]> {
+    let (mut alternatives, mut depth, mut start) = (Vec::new(), 0, 0);
+    for (i, c) in braces.iter().enumerate() {
+        match c {
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            b',' if depth == 0 => {
+                alternatives.push(&braces[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    alternatives.push(&braces[start..]);
+    alternatives
+}
+
+/// Parse the content of a numeric brace expansion, for instance `1..10`.
+fn numeric_range(braces: &[u8]) -> Option<std::ops::RangeInclusive<i64>> {
+    let (start, end) = std::str::from_utf8(braces).ok()?.split_once("..")?;
+    Some(start.parse().ok()?..=end.parse().ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Return the path of the `.editorconfig` file located in `dir`.
+    fn file_path(dir: &Path) -> PathBuf { dir.join(FILE_NAME) }
+
+    #[test]
+    fn globs() {
+        let matches = |glob: &str, path: &str| section_matches_path(glob, path);
+        assert!(matches("*", "/a.rs"));
+        assert!(matches("*.rs", "/src/a.rs"));
+        assert!(!matches("*.rs", "/src/a.rst"));
+        assert!(matches("src/*.rs", "/src/a.rs"));
+        assert!(!matches("/src/*.rs", "/lib/src/a.rs"));
+        assert!(!matches("/*.rs", "/src/a.rs"));
+        assert!(matches("src/**.rs", "/src/x/y/a.rs"));
+        assert!(matches("a?c.txt", "/abc.txt"));
+        assert!(matches("*.{js,py}", "/x/a.py"));
+        assert!(!matches("*.{js,py}", "/x/a.rs"));
+        assert!(matches("{Makefile,*.mk}", "/b/c.mk"));
+        assert!(matches("[Mm]akefile", "/makefile"));
+        assert!(matches("[!a-c]x", "/dx"));
+        assert!(!matches("[!a-c]x", "/bx"));
+        assert!(matches("file{1..12}.txt", "/file10.txt"));
+        assert!(!matches("file{1..12}.txt", "/file13.txt"));
+        assert!(matches("a\\*b", "/a*b"));
+        assert!(!matches("a\\*b", "/axb"));
+    }
+
+    #[test]
+    fn properties() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let (root, project) = (tmp_dir.path(), tmp_dir.path().join("project"));
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::write(file_path(root), "[*]\nindent_style = tab\nend_of_line = crlf\n").unwrap();
+        let ini = "root = true\n\n[*]\nindent_style = space\nindent_size = 2\n\
+                   insert_final_newline = false\n\n[*.rs]\nindent_size = 4\n\
+                   trim_trailing_whitespace = TRUE\nunknown = 1\n\n\
+                   [Makefile]\nindent_style = tab\n";
+        fs::write(file_path(&project), ini).unwrap();
+        fs::write(file_path(&project.join("src")), "[lib.rs]\ntab_width = 8\n").unwrap();
+
+        let properties = Properties::find(&project.join("src").join("lib.rs")).unwrap();
+        let expected = Properties {
+            soft_tabs: Some(true),
+            indent_size: Some(4),
+            tab_width: Some(8),
+            line_ending: None,
+            insert_final_newline: Some(false),
+            trim_trailing_whitespace: Some(true),
+        };
+        assert_eq!(properties, expected);
+
+        let properties = Properties::find(&project.join("Makefile")).unwrap();
+        assert_eq!((properties.soft_tabs, properties.indent_size), (Some(false), Some(2)));
+
+        let properties = Properties::find(&root.join("a.txt")).unwrap();
+        assert_eq!(properties.line_ending, Some(LineEnding::CrLf));
+
+        let mut config = Config::default();
+        let user_config = Properties::find(&project.join("a.rs")).unwrap().apply(&mut config);
+        assert!(config.soft_tabs && !config.insert_final_newline);
+        assert_eq!((config.tab_stop, config.trim_trailing_whitespace_on_save), (4, true));
+        user_config.apply(&mut config);
+        assert_eq!(config, Config::default());
+
+        fs::write(file_path(&project), "[*\nindent_size = 2\n").unwrap();
+        match Properties::find(&project.join("a.rs")) {
+            Err(Error::Config(p, 1, _)) if p == file_path(&project) => (),
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+}
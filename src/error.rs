@@ -12,11 +12,24 @@ pub enum Error {
     /// Configuration error. The tree attributes correspond the file path, the line number and the
     /// error message.
     Config(std::path::PathBuf, usize, String),
+    /// Several configuration errors, reported together so that they can all be fixed at once.
+    ConfigErrors(Vec<Error>),
     /// Too many arguments given to Rust Text Editor. The attribute corresponds to the total number of command
     /// line arguments.
     TooManyArguments(usize),
     /// Unrecognized option given as a command line argument.
     UnrecognizedOption(String),
+    /// Command line option given without the value it requires. The attribute corresponds to the
+    /// option.
+    MissingOptionValue(String),
+    /// The file to open does not exist. The attribute corresponds to the path of the file.
+    FileNotFound(std::path::PathBuf),
+    /// The file to open is not a regular file, e.g. a directory. The attribute corresponds to the
+    /// path of the file.
+    NotARegularFile(std::path::PathBuf),
+    /// I/O error while saving a file. The attributes correspond to the path of the file and the
+    /// underlying error, whose kind tells e.g. permission errors from a full disk.
+    Save(std::path::PathBuf, std::io::Error),
 }
 
 impl From<std::io::Error> for Error {
@@ -1,11 +1,28 @@
-pub use crate::{config::Config, editor::Editor, error::Error};
+pub use crate::{config::Config, editor::Editor, editor::SaveOutcome, error::Error};
+pub use crate::text_buffer::TextBuffer;
+pub use crate::syntax::{highlight, Conf as SyntaxConf, HlSpan, HlType};
 
+mod ansi_escape;
 mod error;
 mod config;
 mod editor;
+mod editorconfig;
+mod paged;
+mod row;
+mod snippets;
+mod state;
 mod syntax;
 mod terminal;
-mod unix;
-mod wasi;
-mod xdg;
-mod windows;
+mod text_buffer;
+mod theme;
+
+#[cfg(windows)] mod windows;
+#[cfg(windows)] use windows as sys;
+
+#[cfg(unix)] mod unix;
+#[cfg(unix)] use unix as sys;
+
+#[cfg(target_os = "wasi")] mod wasi;
+#[cfg(target_os = "wasi")] use wasi as sys;
+
+#[cfg(any(unix, target_os = "wasi"))] mod xdg;
@@ -1,3 +1,137 @@
-fn main() {
-    println!("Hello, world!");
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use rust_text_editor::{Config, Editor, Error};
+
+/// The usage message printed by `--help`, followed by the key bindings.
+const USAGE: &str = "\
+Usage: rust-text-editor [OPTIONS] [FILE]...
+
+Edit the given files, or the text piped to the standard input if FILE is - or omitted.
+A FILE may be followed by :LINE or :LINE:COLUMN to open it at the given position.
+
+Options:
+  -R, --view           Open the files in read-only mode
+      --huge           Open the files in paged mode, whatever their size
+  -c, --config <PATH>  Read the configuration from PATH only, instead of the config.ini files
+                       of the configuration directories
+  -h, --help           Print this help and exit
+  -V, --version        Print the version and exit";
+
+/// What to do, according to the command line arguments.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Print the usage and the key bindings, then exit.
+    Help,
+    /// Print the version, then exit.
+    Version,
+    /// Run the editor with the given options.
+    Edit(Options),
+}
+
+/// The options given on the command line to run the editor.
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    /// Whether to open the files in read-only mode (`--view`).
+    view: bool,
+    /// Whether to open the files in paged mode, whatever their size (`--huge`).
+    huge: bool,
+    /// The configuration file to read instead of the default ones (`--config`).
+    config: Option<PathBuf>,
+    /// Whether to show the keys pressed instead of acting on them (`--debug-keys`, or
+    /// `RTE_DEBUG_KEYS=1`). This option is not listed in the usage message.
+    debug_keys: bool,
+    /// The files to open. `-` stands for the standard input.
+    file_names: Vec<String>,
+}
+
+/// Parse the command line arguments, excluding the program name. `--help` and `--version` are
+/// handled as soon as they are found; the arguments following `--` are all file names.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, Error> {
+    let (mut options, mut args) = (Options::default(), args.into_iter());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-R" | "--view" => options.view = true,
+            "--huge" => options.huge = true,
+            "--debug-keys" => options.debug_keys = true,
+            "-c" | "--config" => match args.next() {
+                Some(path) => options.config = Some(PathBuf::from(path)),
+                None => return Err(Error::MissingOptionValue(arg)),
+            },
+            "--" => options.file_names.extend(args.by_ref()),
+            _ if arg.starts_with('-') && arg != "-" => return Err(Error::UnrecognizedOption(arg)),
+            _ => options.file_names.push(arg),
+        }
+    }
+    Ok(Command::Edit(options))
+}
+
+fn main() -> Result<(), Error> {
+    // The arguments are parsed before raw mode is enabled by `Editor::new`, so that the output of
+    // `--help` and `--version` is printed as is
+    let options = match parse_args(std::env::args().skip(1))? {
+        Command::Help => {
+            let config = Config::load().unwrap_or_default();
+            println!("{}\n\nKey bindings:", USAGE);
+            Editor::key_bindings_help(&config).iter().for_each(|help| println!("  {}", help));
+            return Ok(());
+        }
+        Command::Version => {
+            println!("rust-text-editor {}", env!("RUST_TEXT_EDITOR_VERSION"));
+            return Ok(());
+        }
+        Command::Edit(options) => options,
+    };
+    let config = match &options.config {
+        Some(path) => Config::load_from(std::slice::from_ref(path))?,
+        None => Config::load()?,
+    };
+    let file_names = options.file_names;
+    // Piped text must be read before raw mode is enabled by `Editor::new`
+    let from_stdin = match file_names.as_slice() {
+        [] => !std::io::stdin().is_terminal(),
+        [file_name] => file_name == "-",
+        names if names.iter().any(|f| f == "-") => return Err(Error::TooManyArguments(names.len())),
+        _ => false,
+    };
+    let text = if from_stdin { Some(Editor::read_stdin()?) } else { None };
+    let mut editor = Editor::new(config)?;
+    editor.override_config(options.view, options.huge);
+    if let Some(path) = options.config {
+        editor.set_config_file(path);
+    }
+    if options.debug_keys || std::env::var("RTE_DEBUG_KEYS").is_ok_and(|v| v == "1") {
+        editor.enable_debug_keys();
+    }
+    if let Some(text) = text {
+        editor.load_text(&text);
+    }
+    editor.run(if from_stdin { &[] } else { &file_names })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, Error> {
+        parse_args(args.iter().map(|arg| String::from(*arg)))
+    }
+
+    #[test]
+    fn command_line_arguments() {
+        let file_names = vec![String::from("a.rs"), String::from("-"), String::from("--huge")];
+        let options = Options { view: true, file_names, ..Options::default() };
+        assert_eq!(parse(&["-R", "a.rs", "-", "--", "--huge"]).unwrap(), Command::Edit(options));
+        let options = Options { debug_keys: true, ..Options::default() };
+        assert_eq!(parse(&["--debug-keys"]).unwrap(), Command::Edit(options));
+        let options = Options { config: Some(PathBuf::from("a.ini")), ..Options::default() };
+        assert_eq!(parse(&["--config", "a.ini"]).unwrap(), Command::Edit(options));
+        assert!(matches!(parse(&["-c"]), Err(Error::MissingOptionValue(o)) if o == "-c"));
+        assert_eq!(parse(&[]).unwrap(), Command::Edit(Options::default()));
+        assert_eq!(parse(&["a.rs", "--help", "--bogus"]).unwrap(), Command::Help);
+        assert_eq!(parse(&["-V"]).unwrap(), Command::Version);
+        assert!(matches!(parse(&["--bogus"]), Err(Error::UnrecognizedOption(o)) if o == "--bogus"));
+    }
 }
@@ -0,0 +1,105 @@
+//! # Paged files
+//!
+//! Support for files that are too large to be loaded in memory. Only the offsets of some lines are
+//! kept in memory, and the rows are read from the file on demand.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+/// The number of lines between two consecutive lines whose byte offset is recorded.
+const CHECKPOINT_INTERVAL: usize = 1024;
+
+/// A file whose lines are read on demand.
+pub struct PagedFile {
+    /// The underlying file, kept open so that it can be read even if it is replaced on disk.
+    file: File,
+    /// The byte offset of every `CHECKPOINT_INTERVAL`-th line, starting with the first one.
+    checkpoints: Vec<u64>,
+    /// The number of lines. As when loading a file in memory, a file ending with a new line ends
+    /// with an empty line.
+    n_lines: usize,
+    /// The size of the file, in bytes.
+    len: u64,
+}
+
+impl PagedFile {
+    /// Open a file and index its lines. The whole file is read once, but only the offsets of some
+    /// lines are kept in memory. `progress` is called with the number of bytes read so far after
+    /// each chunk of the file.
+    pub fn open(path: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mut reader = BufReader::new(&file);
+        let (mut checkpoints, mut n_lines, mut len) = (vec![0], 1, 0);
+        loop {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            for (i, _) in buf.iter().enumerate().filter(|(_, b)| **b == b'\n') {
+                // Line `n_lines` starts after this new line
+                if n_lines % CHECKPOINT_INTERVAL == 0 {
+                    checkpoints.push(len + i as u64 + 1);
+                }
+                n_lines += 1;
+            }
+            let n_bytes = buf.len();
+            len += n_bytes as u64;
+            reader.consume(n_bytes);
+            progress(len);
+        }
+        Ok(Self { file, checkpoints, n_lines, len })
+    }
+
+    /// Return the number of lines of the file.
+    pub fn n_lines(&self) -> usize { self.n_lines }
+
+    /// Return the size of the file, in bytes.
+    pub fn len(&self) -> u64 { self.len }
+
+    /// Read the given range of lines, without the new line characters. Lines past the end of the
+    /// file are ignored.
+    pub fn read_lines(&self, lines: Range<usize>) -> io::Result<Vec<Vec<u8>>> {
+        let first_line = lines.start - lines.start % CHECKPOINT_INTERVAL;
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.checkpoints[first_line / CHECKPOINT_INTERVAL]))?;
+        let mut reader = BufReader::new(file);
+        for _ in first_line..lines.start {
+            reader.skip_until(b'\n')?;
+        }
+        let mut rows = Vec::with_capacity(lines.len());
+        for _ in lines.start..lines.end.min(self.n_lines) {
+            let mut line = Vec::new();
+            if reader.read_until(b'\n', &mut line)? > 0 && line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            rows.push(line);
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn paged_file() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("large.log");
+        let content: String = (0..3000).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let mut n_read = Vec::new();
+        let paged = PagedFile::open(&path, &mut |n| n_read.push(n)).unwrap();
+        assert_eq!((paged.n_lines(), paged.len()), (3001, content.len() as u64));
+        assert!(n_read.len() > 1 && n_read.is_sorted() && n_read.last() == Some(&paged.len()));
+        assert_eq!(paged.checkpoints.len(), 3);
+        let lines = paged.read_lines(2047..2050).unwrap();
+        assert_eq!(lines, [b"line 2047".to_vec(), b"line 2048".to_vec(), b"line 2049".to_vec()]);
+        assert_eq!(paged.read_lines(2999..3005).unwrap(), [b"line 2999".to_vec(), Vec::new()]);
+    }
+}
@@ -0,0 +1,889 @@
+//! # Row
+//!
+//! Utilities for rows. A `Row` owns the underlying characters, the rendered string and the syntax
+//! highlighting information.
+
+use std::iter::repeat_n;
+use std::ops::Range;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::ansi_escape::{NO_UNDERLINE, RESET_FMT, REVERSE_VIDEO, UNDERLINE};
+use crate::syntax::{Conf as SyntaxConf, HlSpan, HlType};
+use crate::Config;
+
+/// The "Highlight State" of the row
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum HlState {
+    /// Normal state.
+    #[default]
+    Normal,
+    /// A multi-line comment has been open, but not yet closed.
+    MultiLineComment,
+    /// A string has been open with the given quote character (for instance b'\'' or b'"'), but not
+    /// yet closed.
+    String(u8),
+    /// A multi-line string has been open with the delimiter of the given index in the syntax
+    /// configuration (for instance `"""` or `'''`), but not yet closed.
+    MultiLineString(usize),
+    /// A raw string has been open with the given number of `#` (for instance 1 for `r#"`), but not
+    /// yet closed.
+    RawString(usize),
+    /// A fenced code block has been open, but not yet closed.
+    CodeBlock,
+}
+
+/// Represents a row of characters and how it is rendered.
+#[derive(Default)]
+pub struct Row {
+    /// The characters of the row.
+    pub chars: Vec<u8>,
+    /// How the characters are rendered. In particular, tabs are converted into several spaces, and
+    /// bytes may be combined into single UTF-8 characters.
+    render: String,
+    /// Mapping from indices in `self.chars` to the corresponding indices in `self.render`.
+    pub cx2rx: Vec<usize>,
+    /// Mapping from indices in `self.render` to the corresponding indices in `self.chars`.
+    pub rx2cx: Vec<usize>,
+    /// The tab stop used to render the row, which is also the spacing of the indent guides.
+    tab: usize,
+    /// The vector of `HLType` for each rendered character.
+    hl: Vec<HlType>,
+    /// The final state of the row.
+    pub hl_state: HlState,
+    /// If not `None`, the range that is currently matched during a FIND operation.
+    pub match_segment: Option<std::ops::Range<usize>>,
+    /// The rendered ranges of the other matches of the FIND query, highlighted with a dimmer
+    /// background than `match_segment`.
+    pub search_matches: Vec<std::ops::Range<usize>>,
+    /// The rendered positions of the brackets to highlight, i.e. the bracket under the cursor and
+    /// its matching bracket.
+    pub bracket_rx: Vec<usize>,
+    /// The rendered ranges of the occurrences of the word under the cursor, which are underlined.
+    /// `None` if they have not been searched since the row was last rendered.
+    pub word_matches: Option<Vec<std::ops::Range<usize>>>,
+}
+
+impl Row {
+    /// Create a new row, containing characters `chars`.
+    pub fn new(chars: Vec<u8>) -> Self { Self { chars, cx2rx: vec![0], ..Self::default() } }
+
+    /// Update the row: convert tabs into spaces and compute highlight symbols
+    /// The `hl_state` argument is the `HLState` for the previous row.
+    pub fn update(&mut self, syntax: &SyntaxConf, hl_state: HlState, tab: usize) -> HlState {
+        self.update_render(tab);
+        self.update_syntax(syntax, hl_state)
+    }
+
+    /// Convert tabs into spaces and update the mappings between characters and rendered
+    /// characters. Bytes that are not valid UTF-8 are rendered as `\xNN` escapes, so that each of
+    /// them can be edited separately. The syntax highlighting is cleared, and must be computed
+    /// with `update_syntax` before the row is drawn.
+    pub fn update_render(&mut self, tab: usize) {
+        self.hl.clear();
+        self.render.clear();
+        self.cx2rx.clear();
+        self.rx2cx.clear();
+        self.word_matches = None;
+        self.tab = tab;
+        let (mut cx, mut rx) = (0, 0);
+        for chunk in self.chars.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                // The number of rendered characters
+                let n_rend_chars =
+                    if c == '\t' { tab - (rx % tab) } else { c.width().unwrap_or(1) };
+                if c == '\t' {
+                    self.render.push_str(&" ".repeat(n_rend_chars));
+                } else {
+                    self.render.push(c);
+                }
+                self.cx2rx.extend(repeat_n(rx, c.len_utf8()));
+                self.rx2cx.extend(repeat_n(cx, n_rend_chars));
+                (rx, cx) = (rx + n_rend_chars, cx + c.len_utf8());
+            }
+            for byte in chunk.invalid() {
+                let escape = format!("\\x{:02X}", byte);
+                self.render.push_str(&escape);
+                self.cx2rx.push(rx);
+                self.rx2cx.resize(self.rx2cx.len() + escape.len(), cx);
+                (rx, cx) = (rx + escape.len(), cx + 1);
+            }
+        }
+        self.cx2rx.push(rx);
+        self.rx2cx.push(cx);
+    }
+
+    /// Return the characters of the row, with each tab replaced by the spaces it is rendered as.
+    /// The number of spaces is based on the rendered columns, so that wide and multi-byte
+    /// characters before a tab are taken into account.
+    pub fn expanded_tabs(&self) -> Vec<u8> {
+        let mut chars = Vec::with_capacity(self.chars.len());
+        for (cx, c) in self.chars.iter().enumerate() {
+            match c {
+                b'\t' => chars.resize(chars.len() + self.cx2rx[cx + 1] - self.cx2rx[cx], b' '),
+                c => chars.push(*c),
+            }
+        }
+        chars
+    }
+
+    /// Return the rendered columns where the segments of the row start, when the row is wrapped to
+    /// lines of at most `width` columns. The first segment starts at column 0. Rows are split
+    /// after the last space that fits on the line if any, and before a wide character that would
+    /// not fit entirely.
+    pub fn wrap_columns(&self, width: usize) -> Vec<usize> {
+        let (mut starts, mut start, mut last_space_end) = (vec![0], 0, None);
+        let mut rx = 0;
+        for c in self.render.chars() {
+            let w = c.width().unwrap_or(1);
+            while rx + w > start + width.max(1) && rx > start {
+                start = last_space_end.filter(|end| *end > start).unwrap_or(rx);
+                starts.push(start);
+            }
+            if c == ' ' {
+                last_space_end = Some(rx + w);
+            }
+            rx += w;
+        }
+        starts
+    }
+
+    /// Return the byte indices where the cursor can be placed, in increasing order: the start of
+    /// the row, the start of each rendered character and the end of the row. Zero-width characters,
+    /// such as combining marks, are not boundaries: they belong to the character before them, so
+    /// that the cursor moves over, and deletes, both of them at once.
+    pub fn char_boundaries(&self) -> Vec<usize> {
+        let mut boundaries = vec![0];
+        boundaries.extend(&self.rx2cx);
+        boundaries.dedup();
+        boundaries
+    }
+
+    /// Return the last character boundary before byte `cx`, or 0 if there is none.
+    pub fn previous_boundary(&self, cx: usize) -> usize {
+        self.char_boundaries().into_iter().rfind(|b| *b < cx).unwrap_or(0)
+    }
+
+    /// Return the first character boundary after byte `cx`, or the length of the row if there is
+    /// none.
+    pub fn next_boundary(&self, cx: usize) -> usize {
+        self.char_boundaries().into_iter().find(|b| *b > cx).unwrap_or(self.chars.len())
+    }
+
+    /// Return whether the character at byte `cx` is code, i.e. it is not part of a string or of a
+    /// comment.
+    pub fn is_code(&self, cx: usize) -> bool {
+        let hl_type = self.cx2rx.get(cx).and_then(|rx| self.hl.get(*rx));
+        !matches!(
+            hl_type,
+            Some(HlType::String | HlType::MlString | HlType::Comment | HlType::MlComment)
+        )
+    }
+
+    /// Return the rendered characters, and the spans of rendered bytes that share the same
+    /// highlight type, in order.
+    pub fn hl_spans(&self) -> (&str, Vec<HlSpan>) {
+        let mut spans: Vec<(HlType, Range<usize>)> = Vec::new();
+        for (i, hl_type) in self.hl.iter().enumerate() {
+            match spans.last_mut() {
+                Some((t, range)) if t == hl_type => range.end = i + 1,
+                _ => spans.push((*hl_type, i..i + 1)),
+            }
+        }
+        (&self.render, spans)
+    }
+
+    /// Update the syntax highlighting types of the row.
+    /// The `hl_state` argument is the `HLState` for the previous row.
+    pub fn update_syntax(&mut self, syntax: &SyntaxConf, mut hl_state: HlState) -> HlState {
+        self.hl.clear();
+        let line = self.render.as_bytes();
+
+        // Delimiters for multi-line comments and multi-line strings, as (start, end, state, type)
+        let ml_comment_delims = syntax.ml_comment_delims.iter().map(|(start, end)| {
+            (start, end, HlState::MultiLineComment, HlType::MlComment)
+        });
+        let ml_string_delims = syntax.ml_string_delims.iter().enumerate();
+        let ml_delims: Vec<_> = ml_comment_delims
+            .chain(ml_string_delims.map(|(k, d)| {
+                (d, d, HlState::MultiLineString(k), HlType::MlString)
+            }))
+            .collect();
+
+        // Fences are only recognized at the start of the row, after indentation, and headings at
+        // the very start of the row
+        let indent = line.iter().take_while(|c| **c == b' ').count();
+        let starts_with = |s: &Option<String>, i: usize| {
+            s.as_ref().is_some_and(|s| line[i..].starts_with(s.as_bytes()))
+        };
+        if matches!(hl_state, HlState::Normal | HlState::CodeBlock) {
+            let is_fence = starts_with(&syntax.code_fence, indent);
+            if is_fence || hl_state == HlState::CodeBlock {
+                // The content of a fenced code block, including the fences, is not highlighted
+                self.hl.extend(repeat_n(HlType::MlString, line.len()));
+                self.hl_state = match (is_fence, hl_state) {
+                    (true, HlState::Normal) => HlState::CodeBlock,
+                    (true, _) => HlState::Normal,
+                    (false, state) => state,
+                };
+                return self.hl_state;
+            }
+            let has_keys = syntax.key_separator.is_some();
+            if syntax.heading_start.as_ref().is_some_and(|s| is_heading(line, s, has_keys)) {
+                self.hl.extend(repeat_n(HlType::Heading, line.len()));
+                self.hl_state = HlState::Normal;
+                return self.hl_state;
+            }
+        }
+
+        if hl_state == HlState::Normal {
+            if let Some(len) = syntax.key_separator.as_ref().and_then(|sep| key_len(line, sep)) {
+                self.hl.extend(repeat_n(HlType::Normal, key_start(line)));
+                self.hl.extend(repeat_n(HlType::Keyword1, len));
+            }
+        }
+
+        'syntax_loop: while self.hl.len() < line.len() {
+            let i = self.hl.len();
+            let find_str = |s: &str| line.get(i..(i + s.len())).is_some_and(|r| r.eq(s.as_bytes()));
+
+            if hl_state == HlState::Normal && syntax.sl_comment_start.iter().any(|s| find_str(s)) {
+                self.hl.extend(repeat_n(HlType::Comment, line.len() - i));
+                continue;
+            }
+
+            // Multi-line strings and multi-line comments have the same behavior; the only
+            // differences are: the start/end delimiters, the `HLState`, the `HLType`.
+            for (start, end, mstate, mtype) in &ml_delims {
+                if hl_state == *mstate {
+                    if find_str(end) {
+                        // Highlight the remaining symbols of the multi line comment end
+                        self.hl.extend(repeat_n(mtype, end.len()));
+                        hl_state = HlState::Normal;
+                    } else {
+                        self.hl.push(*mtype);
+                    }
+                    continue 'syntax_loop;
+                } else if hl_state == HlState::Normal && find_str(start) {
+                    // Highlight the remaining symbols of the multi line comment start
+                    self.hl.extend(repeat_n(mtype, start.len()));
+                    hl_state = *mstate;
+                    continue 'syntax_loop;
+                }
+            }
+
+            let c = line[i];
+            let prev_sep = (i == 0) || is_sep(line[i - 1]);
+
+            // Raw strings end with a quote followed by as many `#` as after their prefix
+            let hashes = |j: usize| line[j..].iter().take_while(|c| **c == b'#').count();
+            if let HlState::RawString(n_hashes) = hl_state {
+                if c == b'"' && hashes(i + 1) >= n_hashes {
+                    self.hl.extend(repeat_n(HlType::MlString, 1 + n_hashes));
+                    hl_state = HlState::Normal;
+                } else {
+                    self.hl.push(HlType::MlString);
+                }
+                continue;
+            } else if hl_state == HlState::Normal && prev_sep {
+                for prefix in syntax.raw_string_prefixes.iter().filter(|p| find_str(p)) {
+                    let n_hashes = hashes(i + prefix.len());
+                    if line.get(i + prefix.len() + n_hashes) == Some(&b'"') {
+                        self.hl.extend(repeat_n(HlType::MlString, prefix.len() + n_hashes + 1));
+                        hl_state = HlState::RawString(n_hashes);
+                        continue 'syntax_loop;
+                    }
+                }
+            }
+
+            // At this point, hl_state is Normal or String
+
+            if let HlState::String(quote) = hl_state {
+                self.hl.push(HlType::String);
+                if c == quote {
+                    hl_state = HlState::Normal;
+                } else if c == b'\\' && i != line.len() - 1 {
+                    self.hl.push(HlType::String);
+                }
+                continue;
+            } else if syntax.sl_string_quotes.contains(&(c as char)) {
+                hl_state = HlState::String(c);
+                self.hl.push(HlType::String);
+                continue;
+            }
+
+            if let Some((hl_type, len)) = markup_span(syntax, line, i) {
+                self.hl.extend(repeat_n(hl_type, len));
+                continue;
+            }
+
+            let number = (syntax.highlight_numbers && prev_sep).then(|| number_len(line, i));
+            if let Some(len) = number.flatten() {
+                self.hl.extend(repeat_n(HlType::Number, len));
+                continue;
+            }
+
+            if prev_sep {
+                // This filter makes sure that names such as "in_comment" are not partially
+                // highlighted (even though "in" is a keyword in rust)
+                // The argument is the keyword that is matched at `i`.
+                let s_filter = |kw: &str| line.get(i + kw.len()).is_none_or(|c| is_sep(*c));
+                for (keyword_highlight_type, kws) in &syntax.keywords {
+                    if let Some(keyword) = kws.iter().find(|kw| find_str(kw) && s_filter(kw)) {
+                        self.hl.extend(repeat_n(*keyword_highlight_type, keyword.len()));
+                        continue 'syntax_loop;
+                    }
+                }
+            }
+
+            self.hl.push(HlType::Normal);
+        }
+
+        // String state doesn't propagate to the next row
+        self.hl_state =
+            if matches!(hl_state, HlState::String(_)) { HlState::Normal } else { hl_state };
+        self.hl_state
+    }
+
+    /// Draw the row and write the result to a buffer. An `offset` can be given, as well as a limit
+    /// on the length of the row (`max_len`). The colors are taken from the theme of `config`. If
+    /// `highlight_trailing_whitespace` is set, trailing spaces and tabs are drawn with a red
+    /// background. If `show_whitespace` is set, spaces are drawn as `·` and tabs start with `→`.
+    /// If `ruler_column` is set, the characters beyond it are highlighted, and the ruler is drawn
+    /// after the end of the row if it is within the drawn columns. If `background` is not empty,
+    /// the row is drawn on the background set by this escape sequence (e.g. the current line
+    /// color), which is restored after each reset of the colors and extends to `max_len` columns.
+    /// If `scroll_markers` is set and the row continues beyond the drawn columns, its first or
+    /// last drawn column is replaced with `<` or `>`; a wide character is replaced entirely. If
+    /// `indent_guides` is set, `│` is drawn at each tab stop of the leading whitespace. The
+    /// `word_matches` are underlined, keeping their colors.
+    pub fn draw(
+        &self, offset: usize, max_len: usize, config: &Config, background: &str,
+        buffer: &mut String,
+    ) {
+        let (theme, show_whitespace) = (&config.theme, config.show_whitespace);
+        let highlight_trailing = config.highlight_trailing_whitespace;
+        let (mut current_hl_type, mut underlined) = (HlType::Normal, false);
+        let n_trailing = trailing_whitespace_len(&self.chars);
+        // The rendered position where the highlighted trailing whitespace starts, if any
+        let trailing_rx =
+            if highlight_trailing { self.cx2rx[self.chars.len() - n_trailing] } else { usize::MAX };
+        let ruler = Some(config.ruler_column).filter(|col| *col > 0);
+        // The rendered position where the content starts, after the leading whitespace
+        let n_leading = self.chars.iter().take_while(|c| matches!(c, b' ' | b'\t')).count();
+        let indent_rx =
+            if config.indent_guides && self.tab > 0 { self.cx2rx[n_leading] } else { 0 };
+        let chars = self.render.chars().skip(offset).take(max_len);
+        let mut rx = self.render.chars().take(offset).map(|c| c.width().unwrap_or(1)).sum();
+        // The rendered positions of the drawn columns. If the row is shorter than the offset, they
+        // start after its end.
+        let start = rx + offset.saturating_sub(self.render.chars().count());
+        let end = start + max_len;
+        let markers = config.scroll_markers && !config.wrap;
+        let continues_right = markers && self.cx2rx.last().is_some_and(|width| *width > end);
+        buffer.push_str(background);
+        for (i, (c, mut hl_type)) in chars.zip(self.hl.iter().skip(offset)).enumerate() {
+            let width = c.width().unwrap_or(1);
+            // The markers are drawn on the edge columns, padded to the width of the character
+            let marker = if markers && i == 0 && offset > 0 {
+                Some(format!("<{}", " ".repeat(width - 1)))
+            } else if continues_right && rx + width >= end {
+                Some(format!("{}>", " ".repeat(end.saturating_sub(rx).max(1) - 1)))
+            } else {
+                None
+            };
+            if let Some(marker) = marker {
+                let (color, restore) = (theme.line_number_escape(), theme.escape(current_hl_type));
+                buffer.push_str(&format!("{}{}{}", color, marker, restore));
+                rx += width;
+                if marker.ends_with('>') {
+                    break;
+                }
+                continue;
+            }
+            if c.is_ascii_control() {
+                let rendered_char = if (c as u8) <= 26 { (b'@' + c as u8) as char } else { '?' };
+                let reset = format!("{}{}", RESET_FMT, background);
+                buffer.push_str(&format!("{}{}{}", REVERSE_VIDEO, rendered_char, reset));
+                underlined = false;
+                // Restore previous color
+                if current_hl_type != HlType::Normal {
+                    buffer.push_str(&theme.escape(current_hl_type));
+                }
+            } else {
+                if ruler.is_some_and(|col| rx >= col) {
+                    hl_type = &HlType::Overflow;
+                }
+                if rx >= trailing_rx {
+                    hl_type = &HlType::TrailingSpace;
+                }
+                if self.search_matches.iter().any(|m| m.contains(&rx)) {
+                    hl_type = &HlType::OtherMatch;
+                }
+                if self.match_segment.as_ref().is_some_and(|m| m.contains(&rx)) {
+                    // Set the highlight type to Match, i.e. set the background to cyan
+                    hl_type = &HlType::Match;
+                }
+                if self.bracket_rx.contains(&rx) {
+                    hl_type = &HlType::Bracket;
+                }
+                if current_hl_type.is_background() && !hl_type.is_background() {
+                    // Reset the background after a highlighted match or bracket
+                    buffer.push_str(RESET_FMT);
+                    buffer.push_str(background);
+                    (current_hl_type, underlined) = (HlType::Normal, false);
+                }
+                if current_hl_type != *hl_type {
+                    buffer.push_str(&theme.escape(*hl_type));
+                    current_hl_type = *hl_type;
+                }
+                let in_word = self.word_matches.iter().flatten().any(|m| m.contains(&rx));
+                if in_word != underlined {
+                    buffer.push_str(if in_word { UNDERLINE } else { NO_UNDERLINE });
+                    underlined = in_word;
+                }
+                if rx < indent_rx && rx % self.tab == 0 && !hl_type.is_background() {
+                    // \u{2502}: pipe "│"
+                    let (color, restore) = (theme.line_number_escape(), theme.escape(*hl_type));
+                    buffer.push_str(&format!("{}\u{2502}{}", color, restore));
+                } else {
+                    let glyph = if show_whitespace { self.whitespace_glyph(rx) } else { None };
+                    buffer.push(glyph.unwrap_or(c));
+                }
+            }
+            rx += width;
+        }
+        buffer.push_str(RESET_FMT);
+        buffer.push_str(background);
+        let mut rx = rx.max(start);
+        if let Some(col) = ruler.filter(|col| (rx..end).contains(col)) {
+            // \u{2502}: pipe "│"
+            let (padding, color) = (" ".repeat(col - rx), theme.line_number_escape());
+            buffer.push_str(&format!("{}{}\u{2502}{}{}", padding, color, RESET_FMT, background));
+            rx = col + 1;
+        }
+        if !background.is_empty() {
+            buffer.push_str(&format!("{}{}", " ".repeat(end.saturating_sub(rx)), RESET_FMT));
+        }
+    }
+
+    /// Return the glyph used to show the whitespace rendered at position `rx`, if any: `·` for a
+    /// space, and `→` for the first rendered column of a tab. The glyphs have the same width as a
+    /// space, so that the `cx2rx` and `rx2cx` mappings are unchanged.
+    fn whitespace_glyph(&self, rx: usize) -> Option<char> {
+        let cx = self.rx2cx[rx];
+        match self.chars.get(cx) {
+            Some(b' ') => Some('\u{b7}'),
+            Some(b'\t') if self.cx2rx[cx] == rx => Some('\u{2192}'),
+            _ => None,
+        }
+    }
+}
+
+/// Return the number of trailing spaces and tabs in `chars`.
+pub fn trailing_whitespace_len(chars: &[u8]) -> usize {
+    chars.iter().rev().take_while(|c| matches!(c, b' ' | b'\t')).count()
+}
+
+/// Return the highlight type and the length of the link or emphasized text starting at byte `i` of
+/// `line`, if any. Emphasis must start after a separator, and the delimiters must not be
+/// surrounded by whitespace on the inner side, so that list bullets are not highlighted.
+fn markup_span(syntax: &SyntaxConf, line: &[u8], i: usize) -> Option<(HlType, usize)> {
+    let (rest, find) = (&line[i..], |s: &[u8], n: &[u8]| s.windows(n.len()).position(|w| w == n));
+    if syntax.highlight_links && rest.starts_with(b"[") {
+        let text_len = find(rest, b"](")?;
+        let url_len = find(&rest[text_len + 2..], b")")?;
+        return Some((HlType::Link, text_len + url_len + 3));
+    }
+    if i > 0 && !is_sep(line[i - 1]) {
+        return None;
+    }
+    for delim in syntax.emphasis_delims.iter().map(String::as_bytes) {
+        if !rest.starts_with(delim) || rest.get(delim.len()).is_none_or(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let inner = &rest[delim.len()..];
+        let closing = (1..inner.len()).find(|j| {
+            inner[*j..].starts_with(delim) && !inner[j - 1].is_ascii_whitespace()
+        });
+        if let Some(j) = closing {
+            return Some((HlType::Emphasis, j + 2 * delim.len()));
+        }
+    }
+    None
+}
+
+/// Return the length of the number starting at byte `i` of `line`, if any. Hexadecimal (`0x1F`),
+/// octal (`0o17`) and binary (`0b101`) integers, floats (`3.14`, `.5`, `1e-9`), underscore
+/// separators (`1_000`) and type suffixes (`8u8`) are part of a single token. A fraction needs a
+/// digit after the dot, so that ranges (`1..2`) and method calls (`1.max(2)`) are not included.
+/// The caller must check that the number is preceded by a separator.
+fn number_len(line: &[u8], i: usize) -> Option<usize> {
+    let prev = |n: usize| i.checked_sub(n).map(|k| line[k]);
+    // A digit after a single dot is a field access (`a.1`), while a digit after `..` is not
+    if prev(1) == Some(b'.') && prev(2) != Some(b'.') {
+        return None;
+    }
+    let digits = |j: usize, is_digit: fn(&u8) -> bool| {
+        j + line[j..].iter().take_while(|c| is_digit(c) || **c == b'_').count()
+    };
+    let is_digit_at = |j: usize| line.get(j).is_some_and(u8::is_ascii_digit);
+    let mut j = match (line[i], line.get(i + 1).map(u8::to_ascii_lowercase)) {
+        (b'0', Some(b'x')) => digits(i + 2, u8::is_ascii_hexdigit),
+        (b'0', Some(b'o')) => digits(i + 2, |c| (b'0'..=b'7').contains(c)),
+        (b'0', Some(b'b')) => digits(i + 2, |c| matches!(c, b'0' | b'1')),
+        (b'.', _) if matches!(prev(1), Some(b')' | b']')) => return None,
+        (c, _) if c.is_ascii_digit() || (c == b'.' && is_digit_at(i + 1)) => {
+            let mut j = digits(i, u8::is_ascii_digit);
+            if line.get(j) == Some(&b'.') && is_digit_at(j + 1) {
+                j = digits(j + 1, u8::is_ascii_digit);
+            }
+            let sign_len = usize::from(matches!(line.get(j + 1), Some(b'+' | b'-')));
+            if matches!(line.get(j), Some(b'e' | b'E')) && is_digit_at(j + 1 + sign_len) {
+                j = digits(j + 1 + sign_len, u8::is_ascii_digit);
+            }
+            j
+        }
+        _ => return None,
+    };
+    j += line[j..].iter().take_while(|c| c.is_ascii_alphanumeric() || **c == b'_').count();
+    Some(j - i)
+}
+
+/// Return the index where the key of a row written as `key = value` may start: after the
+/// indentation and the list markers (`- `) of the row.
+fn key_start(line: &[u8]) -> usize {
+    let mut start = line.iter().take_while(|c| **c == b' ').count();
+    while line[start..].starts_with(b"- ") {
+        start += 2 + line[start + 2..].iter().take_while(|c| **c == b' ').count();
+    }
+    start
+}
+
+/// Return the length of the key of a row written as `key = value`, where `=` is the separator
+/// `sep`. The key is made of alphanumeric characters, `_`, `-` and `.`, and the separator must be
+/// followed by whitespace or end the row, so that values such as URLs are not taken as keys.
+fn key_len(line: &[u8], sep: &str) -> Option<usize> {
+    let start = key_start(line);
+    let is_key_char = |c: &u8| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'.');
+    let len = line[start..].iter().take_while(|c| is_key_char(c)).count();
+    let spaces = line[start + len..].iter().take_while(|c| **c == b' ').count();
+    let after_sep = line[start + len + spaces..].strip_prefix(sep.as_bytes())?;
+    (len > 0 && after_sep.first().is_none_or(u8::is_ascii_whitespace)).then_some(len)
+}
+
+/// Return whether a row is a heading starting with the token `start`. In languages with keys, the
+/// token must be followed by a key, possibly after spaces and other occurrences of the token, e.g.
+/// `[table]` or `[[array]]` in TOML, so that the rows of multi-line arrays are not headings. Keys
+/// starting with a digit are not recognized, since such rows are usually arrays of numbers.
+fn is_heading(line: &[u8], start: &str, has_keys: bool) -> bool {
+    let Some(mut rest) = line.strip_prefix(start.as_bytes()) else { return false };
+    while let Some(after) = rest.trim_ascii_start().strip_prefix(start.as_bytes()) {
+        rest = after;
+    }
+    let is_key_start = |c: &u8| c.is_ascii_alphabetic() || matches!(c, b'_' | b'-' | b'"' | b'\'');
+    !has_keys || rest.trim_ascii_start().first().is_some_and(is_key_start)
+}
+
+/// Return whether `c` is an ASCII separator.
+fn is_sep(c: u8) -> bool {
+    c.is_ascii_whitespace() || c == b'\0' || (c.is_ascii_punctuation() && c != b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_utf8() {
+        let mut row = Row::new(b"a\xff\xc3b\xc3\xa9".to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        assert_eq!(row.render, "a\\xFF\\xC3b\u{e9}");
+        assert_eq!(row.cx2rx, [0, 1, 5, 9, 10, 10, 11]);
+        assert_eq!(row.rx2cx.len(), 12);
+        assert_eq!(row.char_boundaries(), [0, 1, 2, 3, 4, 6]);
+    }
+
+    #[test]
+    fn char_boundaries() {
+        // An emoji, a letter followed by a combining acute accent, and a CJK character
+        let mut row = Row::new("\u{1f600}e\u{301}\u{4e2d}\t".as_bytes().to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        assert_eq!(row.char_boundaries(), [0, 4, 7, 10, 11]);
+        // Wide characters advance the rendered column by two, combining marks by zero
+        assert_eq!(row.cx2rx, [0, 0, 0, 0, 2, 3, 3, 3, 3, 3, 5, 8]);
+        assert_eq!(row.rx2cx, [0, 0, 4, 7, 7, 10, 10, 10, 11]);
+        assert_eq!((row.previous_boundary(7), row.previous_boundary(5)), (4, 4));
+        assert_eq!((row.next_boundary(4), row.next_boundary(11)), (7, 11));
+
+        // A combining mark at the start of the row is not merged with the next character
+        let mut row = Row::new("\u{301}a".as_bytes().to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        assert_eq!(row.char_boundaries(), [0, 2, 3]);
+        assert_eq!(Row::new(Vec::new()).char_boundaries(), [0]);
+    }
+
+    #[test]
+    fn wrap_columns() {
+        let mut row = Row::new("lorem ipsum dolor\tsit amet_consectetur".as_bytes().to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        assert_eq!(row.wrap_columns(80), [0]);
+        assert_eq!(row.wrap_columns(14), [0, 12, 24, 38]);
+        assert_eq!(row.wrap_columns(4), [0, 4, 6, 10, 12, 16, 20, 24, 28, 32, 36]);
+
+        let mut row = Row::new("a\u{4e2d}\u{4e2d}".as_bytes().to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        assert_eq!(row.wrap_columns(4), [0, 3]);
+        assert_eq!(Row::new(Vec::new()).wrap_columns(4), [0]);
+    }
+
+    #[test]
+    fn expanded_tabs() {
+        let mut row = Row::new("\t\u{e9}\tx\u{4e2d}\t".as_bytes().to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        assert_eq!(row.expanded_tabs(), "    \u{e9}   x\u{4e2d} ".as_bytes());
+    }
+
+    #[test]
+    fn draw_trailing_whitespace() {
+        let mut row = Row::new(b"let a; \t".to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+
+        let (mut buffer, mut config) = (String::new(), Config::default());
+        row.draw(0, 80, &config, "", &mut buffer);
+        assert!(!buffer.contains(&HlType::TrailingSpace.to_string()));
+
+        buffer.clear();
+        config.highlight_trailing_whitespace = true;
+        row.draw(0, 80, &config, "", &mut buffer);
+        assert!(buffer.ends_with(&format!("let a;{}  {}", HlType::TrailingSpace, RESET_FMT)));
+    }
+
+    #[test]
+    fn draw_ruler() {
+        let config = Config { ruler_column: 4, ..Config::default() };
+        let (mut short_row, mut long_row) = (Row::new(b"ab".to_vec()), Row::new(b"abcdef".into()));
+        short_row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        long_row.update(&SyntaxConf::default(), HlState::Normal, 4);
+
+        let mut buffer = String::new();
+        short_row.draw(0, 80, &config, "", &mut buffer);
+        let ruler = format!("{}\u{2502}{}", config.theme.line_number_escape(), RESET_FMT);
+        assert_eq!(buffer, format!("ab{}  {}", RESET_FMT, ruler));
+        buffer.clear();
+        short_row.draw(5, 80, &config, "", &mut buffer);
+        assert_eq!(buffer, RESET_FMT);
+
+        buffer.clear();
+        long_row.draw(0, 80, &config, "", &mut buffer);
+        assert_eq!(buffer, format!("abcd{}ef{}", HlType::Overflow, RESET_FMT));
+    }
+
+    #[test]
+    fn draw_whitespace() {
+        let mut row = Row::new(b"\ta b\t".to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        let config = Config { show_whitespace: true, ..Config::default() };
+        let mut buffer = String::new();
+        row.draw(0, 80, &config, "", &mut buffer);
+        assert_eq!(buffer, format!("\u{2192}   a\u{b7}b\u{2192}{}", RESET_FMT));
+        assert_eq!(row.cx2rx, [0, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn draw_indent_guides() {
+        let mut row = Row::new(b"  \t  a\tb".to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        let mut config = Config { indent_guides: true, ..Config::default() };
+        let draw = |offset, config: &Config| {
+            let mut buffer = String::new();
+            row.draw(offset, 80, config, "", &mut buffer);
+            buffer
+        };
+        let (color, normal) = (config.theme.line_number_escape(), HlType::Normal);
+        let guide = format!("{}\u{2502}{}", color, normal);
+        assert_eq!(draw(0, &config), format!("{0}   {0} a b{1}", guide, RESET_FMT));
+        // The guides stay on the tab stops when scrolling horizontally
+        let marker = format!("{}<{}", color, normal);
+        assert_eq!(draw(2, &config), format!("{} {} a b{}", marker, guide, RESET_FMT));
+        assert_eq!(row.cx2rx, [0, 1, 2, 4, 5, 6, 7, 8, 9]);
+
+        config.indent_guides = false;
+        assert_eq!(draw(0, &config), format!("      a b{}", RESET_FMT));
+    }
+
+    #[test]
+    fn draw_with_background() {
+        let mut row = Row::new(b"a\x01b".to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        row.bracket_rx = vec![2];
+        let config = Config { ruler_column: 5, ..Config::default() };
+        let (mut buffer, bg) = (String::new(), "\x1b[48;5;236m");
+        row.draw(0, 7, &config, bg, &mut buffer);
+        let ruler = format!("{}\u{2502}{}", config.theme.line_number_escape(), RESET_FMT);
+        let ctrl_a = format!("{}A{}", REVERSE_VIDEO, RESET_FMT);
+        let (bracket, reset) = (HlType::Bracket, RESET_FMT);
+        let expected = format!("{bg}a{ctrl_a}{bg}{bracket}b{reset}{bg}  {ruler}{bg} ");
+        assert_eq!(buffer, expected + reset);
+    }
+
+    #[test]
+    fn draw_scroll_markers() {
+        let mut row = Row::new("ab\u{4e2d}cdef\u{6587}".as_bytes().to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        let mut config = Config::default();
+        let (color, normal) = (config.theme.line_number_escape(), HlType::Normal);
+        let draw = |offset, max_len, config: &Config| {
+            let mut buffer = String::new();
+            row.draw(offset, max_len, config, "", &mut buffer);
+            buffer.strip_suffix(RESET_FMT).map(String::from).unwrap()
+        };
+        assert_eq!(draw(0, 10, &config), "ab\u{4e2d}cdef\u{6587}");
+        assert_eq!(draw(0, 4, &config), format!("ab{} >{}", color, normal));
+        // The markers replace wide characters entirely, and keep the rendered width
+        assert_eq!(draw(1, 3, &config), format!("{0}<{1}{0} >{1}", color, normal));
+        assert_eq!(draw(2, 5, &config), format!("{0}< {1}cd{0}>{1}", color, normal));
+        // The markers are placed on the edge columns after wide characters
+        let mut wide_row = Row::new("\u{4e2d}\u{4e2d}abcdefgh".as_bytes().to_vec());
+        wide_row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        let mut buffer = String::new();
+        wide_row.draw(2, 6, &config, "", &mut buffer);
+        assert_eq!(buffer, format!("{0}<{1}bcde{0}>{1}{2}", color, normal, RESET_FMT));
+
+        config.scroll_markers = false;
+        assert_eq!(draw(1, 3, &config), "b\u{4e2d}c");
+        config.scroll_markers = true;
+        config.wrap = true;
+        assert_eq!(draw(1, 3, &config), "b\u{4e2d}c");
+    }
+
+    #[test]
+    fn draw_search_matches() {
+        let mut row = Row::new(b"ab ab ab".to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        row.search_matches = vec![0..2, 6..8];
+        row.match_segment = Some(3..5);
+        let (mut buffer, config) = (String::new(), Config::default());
+        row.draw(0, 80, &config, "", &mut buffer);
+        let (other, active) = (HlType::OtherMatch, HlType::Match);
+        let expected = format!("{0}ab{2} {1}ab{2} {0}ab{2}", other, active, RESET_FMT);
+        assert_eq!(buffer, expected);
+    }
+
+    #[test]
+    fn draw_word_matches() {
+        let mut row = Row::new(b"ab ab ab".to_vec());
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        row.word_matches = Some(vec![0..2, 3..5, 6..8]);
+        row.match_segment = Some(3..5);
+        let (mut buffer, config) = (String::new(), Config::default());
+        row.draw(0, 80, &config, "", &mut buffer);
+        // The underline is restored after the background of the match is reset
+        let (u, nu, m, r) = (UNDERLINE, NO_UNDERLINE, HlType::Match, RESET_FMT);
+        assert_eq!(buffer, format!("{u}ab{nu} {m}{u}ab{r} {u}ab{r}"));
+
+        // Rendering the row again clears the matches
+        row.update(&SyntaxConf::default(), HlState::Normal, 4);
+        assert_eq!(row.word_matches, None);
+    }
+
+    /// Load a syntax configuration bundled in the `syntax.d` directory, checking its extensions.
+    fn bundled_syntax(file_name: &str, expected_extensions: &str) -> SyntaxConf {
+        let path = std::path::Path::new("syntax.d").join(file_name);
+        let (syntax, extensions) = SyntaxConf::from_file(&path).unwrap();
+        assert_eq!(extensions.join(","), expected_extensions);
+        syntax
+    }
+
+    /// Highlight a row starting in state `hl_state`, and return its highlight types as one letter
+    /// per rendered character, followed by its final state.
+    fn highlight(syntax: &SyntaxConf, text: &str, hl_state: HlState) -> (String, HlState) {
+        let mut row = Row::new(text.as_bytes().to_vec());
+        let hl_state = row.update(syntax, hl_state, 4);
+        let hl_types = row.hl.iter().map(|t| match t {
+            HlType::Heading => 'h',
+            HlType::Emphasis => 'e',
+            HlType::Link => 'l',
+            HlType::String | HlType::MlString => 's',
+            HlType::Comment | HlType::MlComment => 'c',
+            HlType::Number => 'n',
+            HlType::Keyword1 => 'k',
+            HlType::Keyword2 => 'K',
+            _ => '.',
+        });
+        (hl_types.collect(), hl_state)
+    }
+
+    #[test]
+    fn number_highlighting() {
+        let mut syntax = SyntaxConf { highlight_numbers: true, ..SyntaxConf::default() };
+        let hl = |syntax: &SyntaxConf, text| highlight(syntax, text, HlState::Normal).0;
+        assert_eq!(hl(&syntax, "0x1F + 0b101 - 0o17"), "nnnn...nnnnn...nnnn");
+        assert_eq!(hl(&syntax, "3.14 1e9 2.5E-3 1_000u32"), "nnnn.nnn.nnnnnn.nnnnnnnn");
+        assert_eq!(hl(&syntax, "a.1 x[0].5 b1 c_2"), "......n..........");
+        assert_eq!(hl(&syntax, "1..2 1..=2"), "n..n.n...n");
+        assert_eq!(hl(&syntax, "1.max(.5)"), "n.....nn.");
+        syntax.highlight_numbers = false;
+        assert_eq!(hl(&syntax, "0x1F 3.14"), ".........");
+    }
+
+    #[test]
+    fn multi_line_strings() {
+        let delims = vec![String::from("\"\"\""), String::from("'''")];
+        let python = SyntaxConf { ml_string_delims: delims, ..SyntaxConf::default() };
+        let (normal, triple_double) = (HlState::Normal, HlState::MultiLineString(0));
+        assert_eq!(highlight(&python, "x = \"\"\"a", normal), ("....ssss".into(), triple_double));
+        // A delimiter only closes the strings it has opened
+        let triple_single = "b ''' c";
+        let hl = highlight(&python, triple_single, triple_double);
+        assert_eq!(hl, ("sssssss".into(), triple_double));
+        assert_eq!(highlight(&python, "\"\"\" + y", triple_double), ("sss....".into(), normal));
+
+        let prefixes = vec![String::from("r"), String::from("br")];
+        let quotes = vec!['"'];
+        let rust = SyntaxConf { raw_string_prefixes: prefixes, sl_string_quotes: quotes, ..python };
+        let raw = HlState::RawString(1);
+        assert_eq!(highlight(&rust, "s = r#\"a\"b", normal), ("....ssssss".into(), raw));
+        let closed = highlight(&rust, "\"\"# + br\"c\" \"d\"", raw);
+        assert_eq!(closed, ("sss...sssss.sss".into(), normal));
+        assert_eq!(highlight(&rust, "bar\"x\" r##", normal), ("...sss....".into(), normal));
+    }
+
+    #[test]
+    fn markdown_syntax() {
+        let syntax = bundled_syntax("markdown.ini", "md,markdown");
+        let hl = |text, hl_state| highlight(&syntax, text, hl_state);
+        let (normal, code) = (HlState::Normal, HlState::CodeBlock);
+        assert_eq!(hl("## Title", normal), ("hhhhhhhh".into(), normal));
+        assert_eq!(hl("a **b** `c` *d*", normal).0, "..eeeee.sss.eee");
+        assert_eq!(hl("* a_b_c [x](y)", normal).0, "........llllll");
+        assert_eq!(hl("```rust", normal), ("sssssss".into(), code));
+        assert_eq!(hl("# *", code), ("sss".into(), code));
+        assert_eq!(hl("```", code), ("sss".into(), normal));
+    }
+
+    #[test]
+    fn toml_syntax() {
+        let syntax = bundled_syntax("toml.ini", "toml");
+        let hl = |text| highlight(&syntax, text, HlState::Normal).0;
+        assert_eq!(hl("[package]"), "hhhhhhhhh");
+        assert_eq!(hl("name = \"kibi\" # x"), "kkkk...ssssss.ccc");
+        assert_eq!(hl("a.b = [1, true]"), "kkk....n..KKKK.");
+        assert_eq!(hl("  [2, 3],"), "...n..n..");
+        // Rows of multi-line arrays are not headings, even at the start of the row
+        assert_eq!(hl("[2, 3],"), ".n..n..");
+        assert_eq!(hl("[[bin]]"), "hhhhhhh");
+        assert_eq!(hl("[ \"a b\" ]"), "hhhhhhhhh");
+        assert_eq!(highlight(&syntax, "s = '''", HlState::Normal).1, HlState::MultiLineString(1));
+    }
+
+    #[test]
+    fn yaml_syntax() {
+        let syntax = bundled_syntax("yaml.ini", "yml,yaml");
+        let hl = |text| highlight(&syntax, text, HlState::Normal).0;
+        assert_eq!(hl("key: 'v' # c"), "kkk..sss.ccc");
+        assert_eq!(hl("  - name: null"), "....kkkk..KKKK");
+        assert_eq!(hl("  - 12"), "....nn");
+        assert_eq!(hl("- http://x"), "..........");
+    }
+}
@@ -0,0 +1,151 @@
+//! # Snippets
+//!
+//! Templates inserted in place of a trigger word. Snippets are loaded from `snippets.ini` files
+//! located in the configuration directories, with lines such as `todo = // TODO({date}): `.
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::process_ini_file;
+use crate::{sys, Error};
+
+/// The number of seconds in a day.
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Snippets, as a list of triggers and templates.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Snippets {
+    /// The triggers and their templates, with escape sequences already processed.
+    templates: Vec<(String, String)>,
+}
+
+impl Snippets {
+    /// Load the snippets from the `snippets.ini` files located in the configuration directories.
+    /// The user-level file takes precedence over the system-wide one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if one of the snippet files cannot be parsed properly.
+    pub fn load() -> Result<Self, Error> {
+        let mut snippets = Self::default();
+        let paths: Vec<_> =
+            sys::conf_dirs().iter().map(|d| Path::new(d).join("snippets.ini")).collect();
+        for path in paths.iter().filter(|p| p.is_file()).rev() {
+            process_ini_file(path, &mut |key, value| snippets.set(key, value))?;
+        }
+        Ok(snippets)
+    }
+
+    /// Set the template of a trigger, given as an INI key and value. The space following `=` is
+    /// ignored, but trailing spaces are kept. `\n`, `\t` and `\\` stand for a new line, a tab and
+    /// a backslash.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if key.is_empty() || !key.bytes().all(is_trigger_byte) {
+            return Err(format!("Invalid trigger: {}", key));
+        }
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        let (mut template, mut chars) = (String::new(), value.chars());
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                template.push(c);
+                continue;
+            }
+            template.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('\\') => '\\',
+                c => return Err(format!("Invalid escape sequence: \\{}", c.unwrap_or(' '))),
+            });
+        }
+        self.templates.retain(|(t, _)| t != key);
+        self.templates.push((String::from(key), template));
+        Ok(())
+    }
+
+    /// Return the template of the given trigger, with `{date}` replaced with the current date
+    /// formatted with `date_format`.
+    pub fn expand(&self, trigger: &str, date_format: &str) -> Option<String> {
+        let (_, template) = self.templates.iter().find(|(t, _)| t == trigger)?;
+        Some(template.replace("{date}", &format_date(date_format, SystemTime::now())))
+    }
+}
+
+/// Return whether a byte may be part of the trigger of a snippet.
+pub fn is_trigger_byte(b: u8) -> bool { b.is_ascii_alphanumeric() || b == b'_' }
+
+/// Format a time (in UTC) with a `strftime`-like format string. The supported conversions are
+/// `%Y`, `%m`, `%d`, `%H`, `%M`, `%S` and `%%`; other characters are copied as is.
+pub fn format_date(format: &str, time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days(secs / SECONDS_PER_DAY);
+    let secs_of_day = secs % SECONDS_PER_DAY;
+    let (hour, minute, second) = (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+    let (mut date, mut chars) = (String::new(), format.chars());
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            date.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => date.push_str(&year.to_string()),
+            Some('m') => date.push_str(&format!("{:02}", month)),
+            Some('d') => date.push_str(&format!("{:02}", day)),
+            Some('H') => date.push_str(&format!("{:02}", hour)),
+            Some('M') => date.push_str(&format!("{:02}", minute)),
+            Some('S') => date.push_str(&format!("{:02}", second)),
+            Some('%') => date.push('%'),
+            Some(c) => date.extend(['%', c]),
+            None => date.push('%'),
+        }
+    }
+    date
+}
+
+/// Convert a number of days since 1970-01-01 into a (year, month, day) date of the proleptic
+/// Gregorian calendar, using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Shift the epoch to 0000-03-01, so that leap days end the 400-year eras
+    let days = days + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153; // 0 for March
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    (era * 400 + year_of_era + u64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn dates() {
+        let format = "%Y-%m-%d %H:%M:%S %% %q";
+        let date = |secs| format_date(format, UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(date(0), "1970-01-01 00:00:00 % %q");
+        assert_eq!(date(951_782_400), "2000-02-29 00:00:00 % %q");
+        assert_eq!(date(1_767_225_599), "2025-12-31 23:59:59 % %q");
+        assert_eq!(date(1_772_368_245), "2026-03-01 12:30:45 % %q");
+    }
+
+    #[test]
+    fn snippet_templates() {
+        let mut snippets = Snippets::default();
+        snippets.set("todo", " // TODO({date}): ").unwrap();
+        snippets.set("fn_main", "fn main() {\\n\\t\\\\\\n}").unwrap();
+        assert_eq!(snippets.expand("fn_main", ""), Some(String::from("fn main() {\n\t\\\n}")));
+        assert_eq!(snippets.expand("main", ""), None);
+        let today = format_date("%d/%m/%Y", SystemTime::now());
+        assert_eq!(snippets.expand("todo", "%d/%m/%Y"), Some(format!("// TODO({}): ", today)));
+
+        snippets.set("todo", "FIXME").unwrap();
+        assert_eq!(snippets.expand("todo", ""), Some(String::from("FIXME")));
+        assert_eq!(snippets.set("to do", "x"), Err(String::from("Invalid trigger: to do")));
+        let err = Err(String::from("Invalid escape sequence: \\x"));
+        assert_eq!(snippets.set("todo", "a\\x"), err);
+    }
+}
@@ -0,0 +1,190 @@
+//! # State
+//!
+//! Utilities to persist the state of the editor across sessions, in files located in the user
+//! configuration directory.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{parse_values, process_ini_file};
+use crate::{sys, Error};
+
+/// The name of the state file storing the last cursor position for each file.
+pub const CURSOR_POSITIONS: &str = "cursor_positions.ini";
+
+/// The maximum number of cursor positions stored in the state file. When this number is reached,
+/// the least recently stored positions are dropped.
+const MAX_CURSOR_POSITIONS: usize = 256;
+
+/// The name of the state file storing the recently opened files.
+pub const RECENT_FILES: &str = "recent_files.ini";
+
+/// The name of the state file storing the queries of the Find prompt.
+pub const FIND_HISTORY: &str = "find_history.ini";
+
+/// A file path and the cursor position (row, column) stored for that file.
+type CursorPosition = (String, (usize, usize));
+
+/// Return the directory where the state files are located, i.e. the user configuration directory.
+pub fn state_dir() -> Option<PathBuf> { sys::conf_dirs().into_iter().next().map(PathBuf::from) }
+
+/// Read the cursor positions stored in a state file. Each line of the file has the format
+/// `path=row,col`, where `row` and `col` are 0-indexed. Since the path may contain `=`, the line
+/// is split at the last one. Invalid lines are ignored. If the file does not exist, return an
+/// empty `Vec`.
+pub fn read_cursor_positions(path: &Path) -> Result<Vec<CursorPosition>, Error> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)?;
+    let positions = content.lines().filter_map(|line| {
+        let (file_path, value) = line.rsplit_once('=')?;
+        match parse_values(value).ok()?[..] {
+            [row, col] => Some((String::from(file_path), (row, col))),
+            _ => None,
+        }
+    });
+    Ok(positions.collect())
+}
+
+/// Return the cursor position stored for `file_path` in the state file, if any.
+pub fn load_cursor_position(path: &Path, file_path: &str) -> Result<Option<(usize, usize)>, Error> {
+    let positions = read_cursor_positions(path)?;
+    Ok(positions.into_iter().find(|(p, _)| p == file_path).map(|(_, pos)| pos))
+}
+
+/// Store the cursor position for `file_path` in the state file, replacing any existing entry for
+/// that file.
+pub fn store_cursor_position(
+    path: &Path, file_path: &str, pos: (usize, usize),
+) -> Result<(), Error> {
+    let mut positions = read_cursor_positions(path)?;
+    positions.retain(|(p, _)| p != file_path);
+    positions.push((String::from(file_path), pos));
+    let n_dropped = positions.len().saturating_sub(MAX_CURSOR_POSITIONS);
+    let content: String = (positions.iter().skip(n_dropped))
+        .map(|(p, (row, col))| format!("{}={},{}\n", p, row, col))
+        .collect();
+    write_state_file(path, &content)
+}
+
+/// Read the recently opened files stored in a state file, from the least to the most recent.
+/// Each line of the file has the format `file=path`. If the file does not exist, return an empty
+/// `Vec`.
+pub fn read_recent_files(path: &Path) -> Result<Vec<String>, Error> {
+    let mut files = Vec::new();
+    if !path.is_file() {
+        return Ok(files);
+    }
+    process_ini_file(path, &mut |key, value| {
+        match key {
+            "file" => files.push(String::from(value)),
+            _ => return Err(format!("Invalid key: {}", key)),
+        }
+        Ok(())
+    })?;
+    Ok(files)
+}
+
+/// Store `file_path` as the most recently opened file in the state file. Only the `max_files`
+/// most recent files are kept.
+pub fn store_recent_file(path: &Path, file_path: &str, max_files: usize) -> Result<(), Error> {
+    let mut files = read_recent_files(path)?;
+    files.retain(|f| f != file_path);
+    files.push(String::from(file_path));
+    let n_dropped = files.len().saturating_sub(max_files);
+    let content: String = files.iter().skip(n_dropped).map(|f| format!("file={}\n", f)).collect();
+    write_state_file(path, &content)
+}
+
+/// Read the queries stored in a state file, from the oldest to the most recent. Each line of the
+/// file has the format `query=text`, where the text is not trimmed. If the file does not exist,
+/// return an empty `Vec`.
+pub fn read_history(path: &Path) -> Result<Vec<String>, Error> {
+    let mut queries = Vec::new();
+    if !path.is_file() {
+        return Ok(queries);
+    }
+    process_ini_file(path, &mut |key, value| {
+        match key {
+            "query" => queries.push(String::from(value)),
+            _ => return Err(format!("Invalid key: {}", key)),
+        }
+        Ok(())
+    })?;
+    Ok(queries)
+}
+
+/// Store the queries of a prompt in the state file, replacing the existing ones.
+pub fn store_history(path: &Path, queries: &[String]) -> Result<(), Error> {
+    let content: String = queries.iter().map(|q| format!("query={}\n", q)).collect();
+    write_state_file(path, &content)
+}
+
+/// Write a state file, creating the user configuration directory if needed.
+fn write_state_file(path: &Path, content: &str) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(fs::write(path, content)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn cursor_positions_round_trip() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("state").join(CURSOR_POSITIONS);
+        assert_eq!(load_cursor_position(&path, "/a.txt").unwrap(), None);
+
+        store_cursor_position(&path, "/a.txt", (3, 4)).unwrap();
+        store_cursor_position(&path, "/b.txt", (5, 6)).unwrap();
+        store_cursor_position(&path, "/a.txt", (7, 8)).unwrap();
+        store_cursor_position(&path, "/tmp/a=b.txt", (9, 10)).unwrap();
+
+        assert_eq!(load_cursor_position(&path, "/a.txt").unwrap(), Some((7, 8)));
+        assert_eq!(load_cursor_position(&path, "/b.txt").unwrap(), Some((5, 6)));
+        assert_eq!(load_cursor_position(&path, "/tmp/a=b.txt").unwrap(), Some((9, 10)));
+        let content = "/b.txt=5,6\n/a.txt=7,8\n/tmp/a=b.txt=9,10\n";
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn recent_files_round_trip() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("state").join(RECENT_FILES);
+        assert!(read_recent_files(&path).unwrap().is_empty());
+
+        for file_path in ["/a.txt", "/b=c.txt", "/d.txt", "/a.txt"] {
+            store_recent_file(&path, file_path, 3).unwrap();
+        }
+        assert_eq!(read_recent_files(&path).unwrap(), ["/b=c.txt", "/d.txt", "/a.txt"]);
+        store_recent_file(&path, "/e.txt", 3).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "file=/d.txt\nfile=/a.txt\nfile=/e.txt\n");
+    }
+
+    #[test]
+    fn history_round_trip() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("state").join(FIND_HISTORY);
+        assert!(read_history(&path).unwrap().is_empty());
+
+        let queries = [String::from("fn main"), String::from(" a = b ")];
+        store_history(&path, &queries).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "query=fn main\nquery= a = b \n");
+        assert_eq!(read_history(&path).unwrap(), queries);
+    }
+
+    #[test]
+    fn invalid_cursor_positions() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join(CURSOR_POSITIONS);
+        fs::write(&path, "/a.txt=1,2\n/b.txt=3\n# c.txt\n/d.txt=x,4\n/e.txt=5,6\n").unwrap();
+        let positions = read_cursor_positions(&path).unwrap();
+        assert_eq!(positions, [(String::from("/a.txt"), (1, 2)), (String::from("/e.txt"), (5, 6))]);
+    }
+}
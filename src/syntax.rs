@@ -1,7 +1,9 @@
 use std::fmt::{self, Display, Formatter};
-use std::path::{Path, PathBuf};
+use std::ops::Range;
+use std::path::Path;
 
 use crate::config::{self, parse_value as pv, parse_values as pvs};
+use crate::row::{HlState, Row};
 use crate::{sys, Error};
 
 /// Type of syntax highlighting for a single rendered character.
@@ -9,17 +11,29 @@ use crate::{sys, Error};
 /// Each `HLType` is associated with a color, via its discriminant. The ANSI color is equal
 /// to the discriminant, modulo 100. The colors are described here:
 /// <https://en.wikipedia.org/wiki/ANSI_escape_code#Colors>
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum HlType {
-    Normal = 39,     // Default foreground color
-    Number = 31,     // Red
-    Match = 46,      // Cyan
-    String = 32,     // Green
-    MlString = 132,  // Green
-    Comment = 34,    // Blue
-    MlComment = 134, // Blue
-    Keyword1 = 33,   // Yellow
-    Keyword2 = 35,   // Magenta
+    Normal = 39,        // Default foreground color
+    Number = 31,        // Red
+    Match = 46,         // Cyan
+    OtherMatch = 44,    // Blue
+    Bracket = 43,       // Yellow
+    TrailingSpace = 41, // Red
+    String = 32,        // Green
+    MlString = 132,     // Green
+    Comment = 34,       // Blue
+    MlComment = 134,    // Blue
+    Keyword1 = 33,      // Yellow
+    Keyword2 = 35,      // Magenta
+    Heading = 135,      // Magenta
+    Emphasis = 133,     // Yellow
+    Link = 36,          // Cyan
+    Overflow = 91,      // Bright red
+}
+
+impl HlType {
+    /// Return whether the highlight type sets the background color, rather than the foreground.
+    pub fn is_background(self) -> bool { (40..50).contains(&((self as u32) % 100)) }
 }
 
 impl Display for HlType {
@@ -27,6 +41,24 @@ impl Display for HlType {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result { write!(f, "\x1b[{}m", (*self as u32) % 100) }
 }
 
+/// A range of rendered bytes of a line, and its highlight type.
+pub type HlSpan = (HlType, Range<usize>);
+
+/// Highlight `text` with the syntax configuration `conf`, like the editor does but without any
+/// escape sequence. Each line is rendered with tabs expanded to `tab_stop` columns, and returned
+/// with the spans of its rendered bytes that share the same highlight type. The spans cover the
+/// whole rendered line. Multi-line comments and strings carry over to the following lines.
+pub fn highlight(text: &str, conf: &Conf, tab_stop: usize) -> Vec<(String, Vec<HlSpan>)> {
+    let mut hl_state = HlState::Normal;
+    let highlight_line = |line: &str| {
+        let mut row = Row::new(line.as_bytes().to_vec());
+        hl_state = row.update(conf, hl_state, tab_stop.max(1));
+        let (render, spans) = row.hl_spans();
+        (String::from(render), spans)
+    };
+    text.lines().map(highlight_line).collect()
+}
+
 /// Configuration for syntax highlighting.
 #[derive(Clone, Default)]
 pub struct Conf {
@@ -40,19 +72,61 @@ pub struct Conf {
     pub sl_comment_start: Vec<String>,
     /// The tokens that start and end a multi-line comment, e.g. ("/*", "*/").
     pub ml_comment_delims: Option<(String, String)>,
-    /// The token that start and end a multi-line strings, e.g. "\"\"\"" for Python.
-    pub ml_string_delim: Option<String>,
+    /// The tokens that start and end multi-line strings, e.g. "\"\"\"" and "'''" for Python.
+    pub ml_string_delims: Vec<String>,
+    /// The prefixes of raw strings, e.g. "r" and "br" for Rust. Raw strings start with a prefix
+    /// followed by any number of `#` and a quote, and end with a quote followed by as many `#`.
+    pub raw_string_prefixes: Vec<String>,
     /// Keywords to highlight and there corresponding HLType (typically
     /// HLType::Keyword1 or HLType::Keyword2)
     pub keywords: Vec<(HlType, Vec<String>)>,
+    /// The token that starts a heading when it begins a row, e.g. "#" for Markdown. If the language
+    /// has a `key_separator`, the token must be followed by a key, e.g. "[table]" for TOML.
+    pub heading_start: Option<String>,
+    /// The token that opens and closes a fenced code block when it begins a row, e.g. "```".
+    pub code_fence: Option<String>,
+    /// The tokens that delimit emphasized text, e.g. "**" and "_". Longer tokens must come first.
+    pub emphasis_delims: Vec<String>,
+    /// Whether to highlight links written as `[text](url)`.
+    pub highlight_links: bool,
+    /// The token that separates a key from its value, e.g. "=" for TOML. Keys at the start of a
+    /// row, possibly after list markers, are highlighted as `HLType::Keyword1`.
+    pub key_separator: Option<String>,
+    /// The tokens that start the definition of a symbol, e.g. "fn" and "struct" for Rust. Rows
+    /// defining symbols are listed by the go-to-symbol prompt.
+    pub symbol_prefixes: Vec<String>,
+    /// The tab stop preferred for the language, overriding the `tab_stop` of the configuration.
+    pub tab_stop: Option<usize>,
 }
 
 impl Conf {
     /// Return the syntax configuration corresponding to the given file extension, if a matching
-    /// INI file is found in a config directory.
+    /// INI file is found in the `syntax.d` subdirectory of a configuration directory (languages
+    /// added by the user) or of a data directory (languages installed with the editor). The
+    /// configuration directories take precedence, so that a bundled language can be overridden.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a syntax file cannot be read or parsed properly.
     pub fn get(ext: &str) -> Result<Option<Self>, Error> {
-        for conf_dir in sys::data_dirs() {
-            match PathBuf::from(conf_dir).join("syntax.d").read_dir() {
+        let mut dirs = sys::conf_dirs();
+        dirs.extend(sys::data_dirs());
+        dirs.dedup();
+        Self::get_from_dirs(ext, &dirs)
+    }
+
+    /// Return the syntax configuration corresponding to the given file extension, if a matching
+    /// INI file is found in the `syntax.d` subdirectory of one of `dirs`. Directories that come
+    /// first take precedence; missing directories are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a syntax file cannot be read or parsed properly, including files for
+    /// other extensions, so that invalid definitions are not silently ignored. The error contains
+    /// the path of the file and the number of the invalid line.
+    pub fn get_from_dirs<D: AsRef<Path>>(ext: &str, dirs: &[D]) -> Result<Option<Self>, Error> {
+        for conf_dir in dirs {
+            match conf_dir.as_ref().join("syntax.d").read_dir() {
                 Ok(dir_entries) =>
                     for dir_entry in dir_entries {
                         let (sc, extensions) = Self::from_file(&dir_entry?.path())?;
@@ -66,6 +140,42 @@ impl Conf {
         }
         Ok(None)
     }
+
+    /// Return the name of the file type shown in the status bar, or `no ft` if the file type is
+    /// unknown.
+    pub fn file_type(&self) -> &str { if self.name.is_empty() { "no ft" } else { &self.name } }
+
+    /// Return the token used to comment a line, i.e. the first single-line comment start, if any.
+    pub fn comment_token(&self) -> Option<&str> {
+        self.sl_comment_start.first().map(String::as_str)
+    }
+
+    /// Return whether `line` defines a symbol, i.e. starts with one of `symbol_prefixes` after
+    /// leading whitespace. Prefixes made of words, such as `fn`, may also follow modifiers such as
+    /// `pub(crate)`, and must not be followed by a letter, a digit or `_`.
+    pub fn is_symbol(&self, line: &str) -> bool {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+        let (mut rest, mut after_modifier) = (line.trim_start(), false);
+        loop {
+            let matches = |p: &String| match rest.strip_prefix(p.as_str()) {
+                _ if after_modifier && !p.ends_with(is_word_char) => false,
+                Some(after) => !(p.ends_with(is_word_char) && after.starts_with(is_word_char)),
+                None => false,
+            };
+            if self.symbol_prefixes.iter().any(matches) {
+                return true;
+            }
+            // Skip a modifier followed by whitespace
+            let modifier_len = rest.find(|c| !(is_word_char(c) || c == '(' || c == ')'));
+            let modifier_len = modifier_len.unwrap_or(rest.len());
+            match rest[modifier_len..].strip_prefix(char::is_whitespace) {
+                Some(after) if modifier_len > 0 => rest = after.trim_start(),
+                _ => return false,
+            }
+            after_modifier = true;
+        }
+    }
+
     /// Load a `SyntaxConf` from file.
     pub fn from_file(path: &Path) -> Result<(Self, Vec<String>), Error> {
         let (mut sc, mut extensions) = (Self::default(), Vec::new());
@@ -81,9 +191,21 @@ impl Conf {
                         [v1, v2] => Some((pv(v1)?, pv(v2)?)),
                         d => return Err(format!("Expected 2 delimiters, got {}", d.len())),
                     },
-                "multiline_string_delim" => sc.ml_string_delim = Some(pv(val)?),
+                "multiline_string_delim" | "multiline_string_delims" =>
+                    sc.ml_string_delims = pvs(val)?,
+                "raw_string_prefixes" => sc.raw_string_prefixes = pvs(val)?,
                 "keywords_1" => sc.keywords.push((HlType::Keyword1, pvs(val)?)),
                 "keywords_2" => sc.keywords.push((HlType::Keyword2, pvs(val)?)),
+                "heading_start" => sc.heading_start = Some(pv(val)?),
+                "code_fence" => sc.code_fence = Some(pv(val)?),
+                "emphasis_delims" => sc.emphasis_delims = pvs(val)?,
+                "highlight_links" => sc.highlight_links = pv(val)?,
+                "key_separator" => sc.key_separator = Some(pv(val)?),
+                "symbol_prefixes" => sc.symbol_prefixes = pvs(val)?,
+                "tab_stop" => match pv(val)? {
+                    0 => return Err("tab_stop must be > 0".into()),
+                    tab_stop => sc.tab_stop = Some(tab_stop),
+                },
                 _ => return Err(format!("Invalid key: {}", key)),
             }
             Ok(())
@@ -115,6 +237,69 @@ mod tests {
         assert_eq!(file_count, syntax_names.len());
     }
 
+    #[test]
+    fn symbols() {
+        let conf = Conf { symbol_prefixes: pvs("fn, struct, #").unwrap(), ..Conf::default() };
+        assert!(conf.is_symbol("fn main() {"));
+        assert!(conf.is_symbol("    pub(crate) async fn run() {"));
+        assert!(conf.is_symbol("pub struct Editor {"));
+        assert!(conf.is_symbol("# Heading"));
+        assert!(!conf.is_symbol("fnord()"));
+        assert!(!conf.is_symbol("let f = fn_ptr;"));
+        assert!(!conf.is_symbol("issue #12"));
+        assert!(!conf.is_symbol("// fn main"));
+        assert!(!Conf::default().is_symbol("fn main() {"));
+    }
+
+    #[test]
+    fn rust_symbols() {
+        let conf = Conf::get_from_dirs("rs", &["."]).unwrap().unwrap();
+        assert_eq!(conf.file_type(), "Rust");
+        assert!(conf.is_symbol("pub fn new() -> Self {"));
+        assert!(conf.is_symbol("impl<T> Display for Wrapper<T> {"));
+        assert!(conf.is_symbol("    const MAX: u8 = 1;"));
+        assert!(conf.is_symbol("macro_rules! set_status {"));
+        assert!(conf.is_symbol("pub(crate) enum Key {"));
+        assert!(!conf.is_symbol("let implementation = fn_ptr;"));
+        assert!(!conf.is_symbol("    self.type_name()"));
+    }
+
+    #[test]
+    fn highlight_rust_snippet() {
+        let conf = Conf {
+            highlight_numbers: true,
+            sl_string_quotes: vec!['"'],
+            sl_comment_start: vec![String::from("//")],
+            ml_comment_delims: Some((String::from("/*"), String::from("*/"))),
+            keywords: vec![
+                (HlType::Keyword1, pvs("fn, let").unwrap()),
+                (HlType::Keyword2, pvs("i32").unwrap()),
+            ],
+            ..Conf::default()
+        };
+        let text = "fn main() {\n\tlet x: i32 = 42; // answer\n/* \"multi\nline */ \"s\"\n}\n";
+        let lines = highlight(text, &conf, 4);
+        let (render, spans): (Vec<_>, Vec<_>) = lines.into_iter().unzip();
+        assert_eq!(render[..2], ["fn main() {", "    let x: i32 = 42; // answer"]);
+        assert_eq!(render[2..], ["/* \"multi", "line */ \"s\"", "}"]);
+        assert_eq!(spans[0], [(HlType::Keyword1, 0..2), (HlType::Normal, 2..11)]);
+        let expected = [
+            (HlType::Normal, 0..4),
+            (HlType::Keyword1, 4..7),
+            (HlType::Normal, 7..11),
+            (HlType::Keyword2, 11..14),
+            (HlType::Normal, 14..17),
+            (HlType::Number, 17..19),
+            (HlType::Normal, 19..21),
+            (HlType::Comment, 21..30),
+        ];
+        assert_eq!(spans[1], expected);
+        assert_eq!(spans[2], [(HlType::MlComment, 0..9)]);
+        let expected = [(HlType::MlComment, 0..7), (HlType::Normal, 7..8), (HlType::String, 8..11)];
+        assert_eq!(spans[3], expected);
+        assert_eq!(spans[4], [(HlType::Normal, 0..1)]);
+    }
+
     #[test]
     fn conf_from_invalid_path() {
         let tmp_dir = TempDir::new().expect("Could not create temporary directory");
@@ -125,4 +310,43 @@ mod tests {
             Err(e) => panic!("Unexpected error {:?}", e),
         }
     }
+
+    #[test]
+    fn user_syntax_file() {
+        let (user_dir, data_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let dirs = [user_dir.path(), data_dir.path()];
+        for dir in dirs {
+            fs::create_dir(dir.join("syntax.d")).unwrap();
+        }
+        let ini = "name = Fake\nextensions = fk, fake\nsingleline_comment_start = --\n\
+                   multiline_comment_delims = {-, -}\nsingleline_string_quotes = \"\n\
+                   keywords_1 = let, in\nkeywords_2 = Int\ntab_stop = 2\n";
+        fs::write(user_dir.path().join("syntax.d/fake.ini"), ini).unwrap();
+        let other_ini = "name = Other\nextensions = fk";
+        fs::write(data_dir.path().join("syntax.d/fake.ini"), other_ini).unwrap();
+        assert!(Conf::get_from_dirs("rs", &dirs).unwrap().is_none());
+        // The first directory takes precedence
+        let conf = Conf::get_from_dirs("fk", &dirs).unwrap().unwrap();
+        assert_eq!(conf.file_type(), "Fake");
+        assert_eq!(conf.comment_token(), Some("--"));
+        assert!(!conf.is_symbol("let x = 1"));
+        assert_eq!(conf.tab_stop, Some(2));
+
+        let mut row = crate::row::Row::new(b"let x = \"a\" in x -- c".to_vec());
+        row.update(&conf, crate::row::HlState::Normal, 4);
+        let mut buffer = String::new();
+        row.draw(0, 80, &crate::Config::default(), "", &mut buffer);
+        let (k1, s, c, n) = (HlType::Keyword1, HlType::String, HlType::Comment, HlType::Normal);
+        let reset = crate::ansi_escape::RESET_FMT;
+        let expected = format!("{k1}let{n} x = {s}\"a\"{n} {k1}in{n} x {c}-- c{reset}");
+        assert_eq!(buffer, expected);
+
+        // A malformed file is reported with the number of the invalid line
+        let path = user_dir.path().join("syntax.d/broken.ini");
+        fs::write(&path, "name = Broken\nextensions = br\nkeywords_3 = x\n").unwrap();
+        match Conf::get_from_dirs("md", &dirs) {
+            Err(Error::Config(p, 3, msg)) if p == path && msg == "Invalid key: keywords_3" => (),
+            r => panic!("Unexpected result {:?}", r.map(|c| c.map(|c| c.name))),
+        }
+    }
 }
@@ -10,7 +10,7 @@ use crate::{ansi_escape::DEVICE_STATUS_REPORT, ansi_escape::REPOSITION_CURSOR_EN
 ///
 /// It is used as an alternative method if `sys::get_window_size()` returns an error.
 pub fn get_window_size_using_cursor() -> Result<(usize, usize), Error> {
-    let mut stdin = sys::stdin();
+    let mut stdin = sys::stdin()?;
     print!("{}{}", REPOSITION_CURSOR_END, DEVICE_STATUS_REPORT);
     io::stdout().flush()?;
     let mut prefix_buffer = [0_u8; 2];
@@ -18,11 +18,11 @@ pub fn get_window_size_using_cursor() -> Result<(usize, usize), Error> {
     if prefix_buffer != [b'\x1b', b'['] {
         return Err(Error::CursorPosition);
     }
-    Ok((read_value_util(b';')?, read_value_until(b'R')?))
+    Ok((read_value_until(b';')?, read_value_until(b'R')?))
 }
 
 /// Read value until a certain stop byte is reached, and parse the result (pre-stop byte).
-fn read_value_util<T: std::str::FromStr>(stop_byte: u8) -> Result<T, Error> {
+fn read_value_until<T: std::str::FromStr>(stop_byte: u8) -> Result<T, Error> {
     let mut buf = Vec::new();
     io::stdin().lock().read_until(stop_byte, &mut buf)?;
     // Check that we have reached `stop_byte`, not EOF.
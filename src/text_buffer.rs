@@ -0,0 +1,520 @@
+//! # Text buffer
+//!
+//! The rows of a document, the cursor, and the editing operations applied to them. A `TextBuffer`
+//! never interacts with the terminal, so it can be used on its own to edit text programmatically;
+//! `Editor` wraps one for each open file and adds the terminal user interface.
+
+use std::iter;
+use std::ops::Range;
+
+use crate::row::{HlState, Row};
+use crate::{syntax::Conf as SyntaxConf, Config};
+
+/// The line ending style of a file.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum LineEnding {
+    /// Unix-style line endings (`\n`)
+    #[default]
+    Lf,
+    /// Windows-style line endings (`\r\n`)
+    CrLf,
+}
+
+impl LineEnding {
+    /// Return the bytes used to separate lines.
+    pub(crate) fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::CrLf => b"\r\n",
+        }
+    }
+
+    /// Return the name of the line ending style, as shown in the status bar.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct CursorState {
+    /// x position (indexing the characters, not the columns)
+    pub(crate) x: usize,
+    /// y position (row number, 0-indexed)
+    pub(crate) y: usize,
+    /// Row offset
+    pub(crate) roff: usize,
+    /// Column offset
+    pub(crate) coff: usize,
+    /// The rendered column the cursor returns to when moving up or down, by rows or by pages, even
+    /// after crossing shorter rows. It is updated by the horizontal movements and by edits.
+    pub(crate) desired_rx: usize,
+}
+
+impl CursorState {
+    pub(crate) fn move_to_next_line(&mut self) {
+        self.y += 1;
+        self.x = 0;
+    }
+}
+
+/// `slice_find` returns the index of `needle` in slice `s` if `needle` is a subslice of `s`,
+/// otherwise returns `None`.
+fn slice_find<T: PartialEq>(s: &[T], needle: &[T]) -> Option<usize> {
+    (0..(s.len() + 1).saturating_sub(needle.len())).find(|&i| s[i..].starts_with(needle))
+}
+
+/// The text of a document, the cursor, and the state of the syntax highlighting.
+pub struct TextBuffer {
+    /// The collection of rows, including the content and the syntax highlighting information.
+    pub(crate) rows: Vec<Row>,
+    /// The current state of the cursor.
+    pub(crate) cursor: CursorState,
+    /// The file name. If None, the user will be prompted for a file name the first time they try to
+    /// save.
+    // TODO: It may be better to store a PathBuf instead
+    pub(crate) file_name: Option<String>,
+    /// The syntax configuration corresponding to the current file's extension.
+    pub(crate) syntax: SyntaxConf,
+    /// Whether the document has been modified since it was open.
+    pub(crate) dirty: bool,
+    /// The number of bytes contained in `rows`. This excludes new lines.
+    pub(crate) n_bytes: u64,
+    /// The line ending style, detected when loading the file and used when saving it.
+    pub(crate) line_ending: LineEnding,
+    /// The number of rows, starting from the first one, whose syntax highlighting is up to date.
+    /// The following rows are highlighted lazily, when they are scrolled into view.
+    pub(crate) highlighted_up_to: usize,
+    /// The ranges of folded rows. Each folded block is shown as a single summary line, below the
+    /// row it belongs to. Folds are dropped when their rows or the rows above them are modified.
+    pub(crate) folds: Vec<Range<usize>>,
+    /// The number of columns used to render a tab character.
+    pub(crate) tab_stop: usize,
+    /// The additional cursors, placed on other rows to type the same text on each of them. Only
+    /// their position is used.
+    pub(crate) extra_cursors: Vec<CursorState>,
+}
+
+impl Default for TextBuffer {
+    fn default() -> Self { Self::new(&Config::default()) }
+}
+
+impl TextBuffer {
+    /// Create an empty text buffer, rendering tabs with the tab stop of `config`.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            rows: Vec::new(),
+            cursor: CursorState::default(),
+            file_name: None,
+            syntax: SyntaxConf::default(),
+            dirty: false,
+            n_bytes: 0,
+            line_ending: LineEnding::default(),
+            highlighted_up_to: 0,
+            folds: Vec::new(),
+            tab_stop: config.tab_stop,
+            extra_cursors: Vec::new(),
+        }
+    }
+
+    /// Replace the content of the buffer with `text` and move the cursor to the beginning. Line
+    /// endings are detected as when loading a file, and the buffer is not considered modified.
+    pub fn set_text(&mut self, text: &str) {
+        self.load_rows(text.as_bytes());
+        self.finish_loading();
+        self.update_all_rows();
+        (self.cursor, self.dirty) = (CursorState::default(), false);
+    }
+
+    /// Return the content of the buffer, with rows separated by the detected line ending. Bytes
+    /// that are not valid UTF-8 are replaced with `U+FFFD`.
+    pub fn text(&self) -> String {
+        let rows: Vec<_> = self.rows.iter().map(|row| row.chars.as_slice()).collect();
+        String::from_utf8_lossy(&rows.join(self.line_ending.as_bytes())).into_owned()
+    }
+
+    /// Return the content of each row, without line endings.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.rows.iter().map(|row| row.chars.as_slice())
+    }
+
+    /// Return the position of the cursor, as (row, byte in the row).
+    pub fn cursor(&self) -> (usize, usize) { (self.cursor.y, self.cursor.x) }
+
+    /// Move the cursor to the given row and byte. The position is clamped to the end of the
+    /// document and to the end of the row; it may point after the last row, to append text.
+    pub fn set_cursor(&mut self, y: usize, x: usize) {
+        self.cursor.y = y.min(self.rows.len());
+        let len = self.current_row().map_or(0, |row| row.chars.len());
+        self.cursor.x = x.min(len);
+    }
+
+    /// Return whether the document has been modified since it was loaded.
+    pub fn is_dirty(&self) -> bool { self.dirty }
+
+    /// Return whether the document is empty or not. If there is more than one row, even if all the
+    /// rows are empty, `is_empty` returns `false`, since the text contains new lines.
+    pub fn is_empty(&self) -> bool { self.rows.len() <= 1 && self.n_bytes == 0 }
+
+    /// Return the current row if the cursor points to an existing row, `None` otherwise.
+    pub(crate) fn current_row(&self) -> Option<&Row> { self.rows.get(self.cursor.y) }
+
+    /// Return the position of the cursor, in terms of rendered characters (as opposed to
+    /// `self.cursor.x`, which is the position of the cursor in terms of bytes).
+    pub(crate) fn rx(&self) -> usize { self.current_row().map_or(0, |r| r.cx2rx[self.cursor.x]) }
+
+    /// Replace the rows with the lines of `text`. `finish_loading` must be called afterwards.
+    pub(crate) fn load_rows(&mut self, text: &[u8]) {
+        // Contrary to `BufRead::split`, `slice::split` yields an empty line if the text is empty or
+        // ends with a newline, which is exactly the row we need to append in this case.
+        self.rows = text.split(|b| *b == b'\n').map(|line| Row::new(line.to_vec())).collect();
+    }
+
+    /// Update the state of the buffer after rows have been loaded: folds, additional cursors, line
+    /// endings and number of bytes. The rows still need to be rendered.
+    pub(crate) fn finish_loading(&mut self) {
+        self.folds.clear();
+        self.extra_cursors.clear();
+        self.detect_line_ending();
+        self.n_bytes = self.rows.iter().map(|row| row.chars.len() as u64).sum();
+    }
+
+    /// Detect the dominant line ending style of the rows that have just been loaded. If most lines
+    /// end with `\r\n`, the trailing `\r` is removed from the rows and restored when saving.
+    fn detect_line_ending(&mut self) {
+        // The last row is not followed by a line ending
+        let n_terminated = self.rows.len().saturating_sub(1);
+        let rows = &mut self.rows[..n_terminated];
+        let n_crlf = rows.iter().filter(|row| row.chars.last() == Some(&b'\r')).count();
+        let is_crlf = 2 * n_crlf > n_terminated;
+        self.line_ending = if is_crlf { LineEnding::CrLf } else { LineEnding::Lf };
+        if is_crlf {
+            for row in rows.iter_mut().filter(|row| row.chars.last() == Some(&b'\r')) {
+                row.chars.pop();
+            }
+        }
+    }
+
+    /// Update a row, given its index. If `ignore_following_rows` is `false` and the highlight state
+    /// has changed during the update (for instance, it is now in "multi-line comment" state, keep
+    /// updating the next rows
+    pub(crate) fn update_row(&mut self, y: usize, ignore_following_rows: bool) {
+        // The folds below the row may not match the rows anymore
+        self.folds.retain(|fold| fold.end <= y);
+        if y >= self.highlighted_up_to {
+            // The row will be highlighted when it is scrolled into view
+            if let Some(row) = self.rows.get_mut(y) {
+                row.update_render(self.tab_stop);
+            }
+            return;
+        }
+        let mut hl_state = if y > 0 { self.rows[y - 1].hl_state } else { HlState::Normal };
+        // Rows after the watermark don't need to be updated: they will be highlighted from the
+        // state of the previous row when they are scrolled into view.
+        for row in self.rows.iter_mut().take(self.highlighted_up_to).skip(y) {
+            let previous_hl_state = row.hl_state;
+            hl_state = row.update(&self.syntax, hl_state, self.tab_stop);
+            if ignore_following_rows || hl_state == previous_hl_state {
+                return;
+            }
+            // If the state has changed (for instance, a multi-line comment started in this row),
+            // continue updating the following rows
+        }
+    }
+
+    /// Render all the rows. Their syntax highlighting is reset; rows are highlighted when they are
+    /// scrolled into view.
+    pub(crate) fn update_all_rows(&mut self) {
+        for row in &mut self.rows {
+            row.update_render(self.tab_stop);
+        }
+        self.highlighted_up_to = 0;
+    }
+
+    /// Highlight the rows located between the watermark `highlighted_up_to` and `end` (excluded),
+    /// then move the watermark to `end`.
+    pub(crate) fn highlight_rows(&mut self, end: usize) {
+        let (start, end) = (self.highlighted_up_to, end.min(self.rows.len()));
+        let mut hl_state = if start > 0 { self.rows[start - 1].hl_state } else { HlState::Normal };
+        for row in self.rows.iter_mut().take(end).skip(start) {
+            hl_state = row.update_syntax(&self.syntax, hl_state);
+        }
+        self.highlighted_up_to = start.max(end);
+    }
+
+    /// Move the watermark `highlighted_up_to` down after `n` rows have been inserted before the
+    /// row `y`.
+    pub(crate) fn rows_inserted(&mut self, y: usize, n: usize) {
+        if y < self.highlighted_up_to {
+            self.highlighted_up_to += n;
+        }
+    }
+
+    /// Move the watermark `highlighted_up_to` up after the `n` rows starting from the row `y` have
+    /// been removed. If the watermark was among the removed rows, it is moved to `y`.
+    pub(crate) fn rows_removed(&mut self, y: usize, n: usize) {
+        if y < self.highlighted_up_to {
+            self.highlighted_up_to = y.max(self.highlighted_up_to.saturating_sub(n));
+        }
+    }
+
+    /// Insert a byte at the current cursor position. If there is no row at the current cursor
+    /// position, add a new row and insert the byte.
+    pub fn insert_byte(&mut self, c: u8) {
+        if let Some(row) = self.rows.get_mut(self.cursor.y) {
+            row.chars.insert(self.cursor.x, c);
+        } else {
+            self.rows.push(Row::new(vec![c]));
+        }
+        self.update_row(self.cursor.y, false);
+        self.cursor.x += 1;
+        self.n_bytes += 1;
+        self.dirty = true;
+    }
+
+    /// Insert a new line at the current cursor position and move the cursor to the start of the new
+    /// line. If the cursor is in the middle of a row, split off that row. If `auto_indent` is
+    /// true, the new line starts with the leading whitespace located before the cursor.
+    pub fn insert_new_line(&mut self, auto_indent: bool) {
+        let (position, new_row_chars, indent_len) = if self.cursor.x == 0 {
+            (self.cursor.y, Vec::new(), 0)
+        } else {
+            // self.rows[self.cursor.y] must exist, since cursor.x = 0 for any cursor.y ≥ row.len()
+            let row = &mut self.rows[self.cursor.y];
+            let split_chars = row.chars.split_off(self.cursor.x);
+            let mut new_chars = Vec::new();
+            if auto_indent {
+                new_chars.extend(row.chars.iter().take_while(|c| matches!(c, b' ' | b'\t')));
+            }
+            let indent_len = new_chars.len();
+            new_chars.extend(split_chars);
+            self.update_row(self.cursor.y, false);
+            (self.cursor.y + 1, new_chars, indent_len)
+        };
+        self.rows.insert(position, Row::new(new_row_chars));
+        self.rows_inserted(position, 1);
+        self.update_row(position, false);
+        self.cursor.move_to_next_line();
+        self.cursor.x = indent_len;
+        self.n_bytes += indent_len as u64;
+        self.dirty = true;
+    }
+
+    /// Insert text at the current cursor position, as is: unlike `insert_new_line`, the new lines
+    /// are not auto-indented. `\r\n` and `\r` are inserted as new lines, since terminals send the
+    /// new lines of pasted text as `\r`. The rows are updated once, after the whole text has been
+    /// inserted, and the cursor is moved to the end of the inserted text.
+    pub fn insert_text(&mut self, text: &[u8]) {
+        let mut lines = vec![Vec::new()];
+        let mut bytes = text.iter().peekable();
+        while let Some(c) = bytes.next() {
+            match c {
+                b'\r' if bytes.peek() == Some(&&b'\n') => (),
+                b'\r' | b'\n' => lines.push(Vec::new()),
+                c => lines.last_mut().expect("lines is not empty").push(*c),
+            }
+        }
+        let (y, n_lines) = (self.cursor.y, lines.len());
+        if y == self.rows.len() {
+            self.rows.push(Row::new(Vec::new()));
+        }
+        self.n_bytes += lines.iter().map(|line| line.len() as u64).sum::<u64>();
+        let tail = self.rows[y].chars.split_off(self.cursor.x);
+        let last_line = lines.last_mut().expect("lines is not empty");
+        self.cursor.x = last_line.len() + if n_lines == 1 { self.cursor.x } else { 0 };
+        last_line.extend(tail);
+        let mut lines = lines.into_iter();
+        self.rows[y].chars.extend(lines.next().expect("lines is not empty"));
+        self.rows.splice(y + 1..y + 1, lines.map(Row::new));
+        // The following rows have moved down, and so has the watermark
+        self.rows_inserted(y + 1, n_lines - 1);
+        for i in y..y + n_lines {
+            self.update_row(i, i + 1 < y + n_lines);
+        }
+        self.cursor.y += n_lines - 1;
+        self.dirty = true;
+    }
+
+    /// Delete a character at the current cursor position. If the cursor is located at the beginning
+    /// of a row that is not the first or last row, merge the current row and the previous row. If
+    /// the cursor is located after the last row, move up to the last character of the previous row.
+    pub fn delete_char(&mut self) {
+        if self.cursor.x > 0 {
+            let row = &mut self.rows[self.cursor.y];
+            // Obtain the number of bytes to be removed: the size of the UTF-8 character, including
+            // the combining marks that follow it.
+            let n_bytes_to_remove = self.cursor.x - row.previous_boundary(self.cursor.x);
+            row.chars.splice(self.cursor.x - n_bytes_to_remove..self.cursor.x, iter::empty());
+            self.update_row(self.cursor.y, false);
+            self.cursor.x -= n_bytes_to_remove;
+            self.n_bytes -= n_bytes_to_remove as u64;
+            self.dirty = if self.is_empty() { self.file_name.is_some() } else { true };
+        } else if self.cursor.y < self.rows.len() && self.cursor.y > 0 {
+            let row = self.rows.remove(self.cursor.y);
+            // The following rows have moved up, and so has the watermark
+            self.rows_removed(self.cursor.y, 1);
+            let previous_row = &mut self.rows[self.cursor.y - 1];
+            self.cursor.x = previous_row.chars.len();
+            previous_row.chars.extend(&row.chars);
+            self.update_row(self.cursor.y - 1, true);
+            self.update_row(self.cursor.y, false);
+            self.dirty = true;
+            self.cursor.y -= 1;
+        } else if self.cursor.y == self.rows.len() && self.cursor.y > 0 {
+            // If the cursor is located after the last row, pressing backspace is equivalent to
+            // pressing the left arrow key.
+            self.cursor.y -= 1;
+            self.cursor.x = self.rows[self.cursor.y].chars.len();
+        }
+    }
+
+    /// Replace the `len` bytes located at row `y`, byte `x` with `replacement`. The row is updated
+    /// afterwards, so that `cx2rx` and the syntax highlighting reflect the new content.
+    pub(crate) fn replace_at(&mut self, y: usize, x: usize, len: usize, replacement: &[u8]) {
+        self.rows[y].chars.splice(x..x + len, replacement.iter().copied());
+        self.update_row(y, false);
+        self.n_bytes = self.n_bytes + replacement.len() as u64 - len as u64;
+        self.dirty = true;
+    }
+
+    /// Find the next occurrence of `query`, starting at row `y`, byte `x` and searching forward
+    /// until the end of the document. Return the position `(y, x)` of the match, if any.
+    pub fn find_from(&self, query: &[u8], y: usize, x: usize) -> Option<(usize, usize)> {
+        self.rows.iter().enumerate().skip(y).find_map(|(i, row)| {
+            let start = if i == y { x.min(row.chars.len()) } else { 0 };
+            slice_find(&row.chars[start..], query).map(|cx| (i, start + cx))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_buffer_editing() {
+        let mut buffer = TextBuffer::default();
+        buffer.set_text("fn main() {\r\n}\r\n");
+        assert_eq!((buffer.line_ending, buffer.rows().count()), (LineEnding::CrLf, 3));
+        assert!(!buffer.is_dirty());
+
+        buffer.set_cursor(0, 11);
+        buffer.insert_new_line(true);
+        for c in b"  x();" {
+            buffer.insert_byte(*c);
+        }
+        assert_eq!(buffer.cursor(), (1, 6));
+        buffer.delete_char();
+        assert_eq!(buffer.text(), "fn main() {\r\n  x()\r\n}\r\n");
+        assert_eq!((buffer.n_bytes, buffer.is_dirty()), (17, true));
+
+        // Deleting at the beginning of a row merges it with the previous row
+        buffer.set_cursor(2, 0);
+        buffer.delete_char();
+        assert_eq!(buffer.rows().collect::<Vec<_>>(), [&b"fn main() {"[..], b"  x()}", b""]);
+        assert_eq!(buffer.cursor(), (1, 5));
+        assert_eq!(buffer.find_from(b"x", 0, 0), Some((1, 2)));
+        assert_eq!(buffer.find_from(b"x", 1, 3), None);
+
+        // Inserted text is not auto-indented
+        buffer.set_cursor(1, 2);
+        buffer.insert_text(b"y\r\n  z\r");
+        let rows = [&b"fn main() {"[..], b"  y", b"  z", b"x()}", b""];
+        assert_eq!(buffer.rows().collect::<Vec<_>>(), rows);
+        assert_eq!((buffer.cursor(), buffer.n_bytes), ((3, 0), 21));
+
+        buffer.set_cursor(10, 10);
+        assert_eq!(buffer.cursor(), (5, 0));
+        buffer.set_text("");
+        assert!(buffer.text().is_empty() && buffer.is_empty());
+        assert_eq!(buffer.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn text_buffer_utf8() {
+        let mut buffer = TextBuffer::default();
+        buffer.set_text("x\u{301}\u{1f600}\u{4e2d}");
+        buffer.set_cursor(0, 10);
+        buffer.delete_char();
+        assert_eq!((buffer.text().as_str(), buffer.cursor()), ("x\u{301}\u{1f600}", (0, 7)));
+        buffer.delete_char();
+        assert_eq!((buffer.text().as_str(), buffer.cursor()), ("x\u{301}", (0, 3)));
+        // The combining mark is deleted with the letter it belongs to
+        buffer.delete_char();
+        assert_eq!((buffer.text().as_str(), buffer.cursor(), buffer.n_bytes), ("", (0, 0), 0));
+        for b in "\u{4e2d}".bytes() {
+            buffer.insert_byte(b);
+        }
+        assert_eq!((buffer.cursor(), buffer.rx()), ((0, 3), 2));
+    }
+
+    #[test]
+    fn text_buffer_utf8_rows() {
+        let mut buffer = TextBuffer::default();
+        buffer.set_text("\u{e9}\n\u{fc}\u{1f600}");
+        // Merging the rows keeps the cursor between the two multi-byte characters
+        buffer.set_cursor(1, 0);
+        buffer.delete_char();
+        assert_eq!((buffer.text().as_str(), buffer.cursor()), ("\u{e9}\u{fc}\u{1f600}", (0, 2)));
+        assert_eq!(buffer.n_bytes, 8);
+        buffer.delete_char();
+        assert_eq!((buffer.text().as_str(), buffer.cursor()), ("\u{fc}\u{1f600}", (0, 0)));
+        assert_eq!(buffer.n_bytes, 6);
+        buffer.set_cursor(0, 6);
+        buffer.delete_char();
+        assert_eq!((buffer.cursor(), buffer.n_bytes, buffer.is_dirty()), ((0, 2), 2, true));
+
+        // Emptying a buffer without a file name makes it unmodified again, but not with a file
+        buffer.delete_char();
+        assert_eq!((buffer.text().as_str(), buffer.n_bytes, buffer.is_dirty()), ("", 0, false));
+        buffer.set_text("\u{e9}");
+        buffer.file_name = Some(String::from("a.txt"));
+        buffer.set_cursor(0, 2);
+        buffer.delete_char();
+        assert!(buffer.is_empty() && buffer.is_dirty());
+    }
+
+    #[test]
+    fn text_buffer_raw_string() {
+        let mut buffer = TextBuffer::default();
+        let raw_string_prefixes = vec![String::from("r")];
+        buffer.syntax = SyntaxConf { raw_string_prefixes, ..SyntaxConf::default() };
+        buffer.set_text("s = r#\"\na\"b\n\"#;\nc");
+        buffer.highlight_rows(4);
+        let is_code = |buf: &TextBuffer| buf.rows.iter().map(|r| r.is_code(0)).collect::<Vec<_>>();
+        assert_eq!(is_code(&buffer), [true, false, false, true]);
+
+        // Removing the hash closes the string on the second row
+        buffer.set_cursor(0, 6);
+        buffer.delete_char();
+        assert_eq!(is_code(&buffer), [true, false, true, true]);
+        assert_eq!(buffer.rows[1].hl_state, HlState::Normal);
+        buffer.insert_byte(b'#');
+        assert_eq!(is_code(&buffer), [true, false, false, true]);
+        assert_eq!(buffer.rows[1].hl_state, HlState::RawString(1));
+    }
+
+    #[test]
+    fn text_buffer_code_fence() {
+        let mut buffer = TextBuffer::default();
+        let code_fence = Some(String::from("```"));
+        buffer.syntax = SyntaxConf { code_fence, ..SyntaxConf::default() };
+        buffer.set_text("a\n```\nb\n\nc");
+        buffer.highlight_rows(5);
+        let hl_states = |buf: &TextBuffer| buf.rows.iter().map(|r| r.hl_state).collect::<Vec<_>>();
+        let (normal, code) = (HlState::Normal, HlState::CodeBlock);
+        // An unterminated fence keeps the rest of the file in a code block
+        assert_eq!(hl_states(&buffer), Vec::from([normal, code, code, code, code]));
+
+        buffer.set_cursor(3, 0);
+        for c in b"```" {
+            buffer.insert_byte(*c);
+        }
+        assert_eq!(hl_states(&buffer), Vec::from([normal, code, code, normal, normal]));
+        buffer.set_cursor(1, 1);
+        buffer.delete_char();
+        assert_eq!(hl_states(&buffer), Vec::from([normal, normal, normal, code, code]));
+    }
+}
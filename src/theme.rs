@@ -0,0 +1,192 @@
+//! # Theme
+//!
+//! True-color themes, mapping highlight types to RGB colors. A theme is loaded from `theme.ini`
+//! files located in the configuration directories, with lines such as `keyword1 = #c678dd`.
+
+use std::env;
+use std::path::Path;
+
+use crate::config::process_ini_file;
+use crate::syntax::HlType;
+use crate::{sys, Error};
+
+/// An RGB color.
+type Rgb = (u8, u8, u8);
+
+/// The 256-color escape sequence used for line numbers when no theme color is set (dark grey).
+const DEFAULT_LINE_NUMBER_COLOR: &str = "\x1b[38;5;240m";
+
+/// The 256-color escape sequence used for the background of the current line when no theme color
+/// is set (darker grey).
+const DEFAULT_CURSORLINE_COLOR: &str = "\x1b[48;5;236m";
+
+/// The escape sequences used for warnings and errors in the message bar when no theme color is
+/// set (yellow and red).
+const DEFAULT_WARNING_COLOR: &str = "\x1b[33m";
+const DEFAULT_ERROR_COLOR: &str = "\x1b[31m";
+
+/// A true-color theme. Highlight types that are not set in the theme keep their ANSI colors.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Theme {
+    /// The RGB color of each highlight type overridden by the theme.
+    colors: Vec<(HlType, Rgb)>,
+    /// The RGB color of the line numbers and of the divider between split panes, if overridden.
+    line_number: Option<Rgb>,
+    /// The RGB background color of the current line, if overridden.
+    cursorline: Option<Rgb>,
+    /// The RGB colors of warnings and errors in the message bar, if overridden.
+    warning: Option<Rgb>,
+    error: Option<Rgb>,
+}
+
+impl Theme {
+    /// Load the theme from the `theme.ini` files located in the configuration directories. The
+    /// user-level file takes precedence over the system-wide one. If the terminal does not
+    /// advertise true-color support via `$COLORTERM`, return the default theme, which uses the
+    /// 256-color palette.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if one of the theme files cannot be parsed properly.
+    pub fn load() -> Result<Self, Error> {
+        let mut theme = Self::default();
+        if !env::var("COLORTERM").is_ok_and(|v| v == "truecolor" || v == "24bit") {
+            return Ok(theme);
+        }
+        let paths: Vec<_> =
+            sys::conf_dirs().iter().map(|d| Path::new(d).join("theme.ini")).collect();
+        for path in paths.iter().filter(|p| p.is_file()).rev() {
+            process_ini_file(path, &mut |key, value| theme.set(key, value))?;
+        }
+        Ok(theme)
+    }
+
+    /// Set the color of a highlight category, given as an INI key and value, for instance
+    /// `comment` and `#5c6370`.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let rgb = parse_rgb(value)?;
+        let hl_types: &[HlType] = match key {
+            "line_number" => {
+                self.line_number = Some(rgb);
+                return Ok(());
+            }
+            "cursorline" => {
+                self.cursorline = Some(rgb);
+                return Ok(());
+            }
+            "warning" => {
+                self.warning = Some(rgb);
+                return Ok(());
+            }
+            "error" => {
+                self.error = Some(rgb);
+                return Ok(());
+            }
+            "normal" => &[HlType::Normal],
+            "number" => &[HlType::Number],
+            "match" => &[HlType::Match],
+            "other_match" => &[HlType::OtherMatch],
+            "bracket" => &[HlType::Bracket],
+            "trailing_space" => &[HlType::TrailingSpace],
+            "string" => &[HlType::String, HlType::MlString],
+            "comment" => &[HlType::Comment, HlType::MlComment],
+            "keyword1" => &[HlType::Keyword1],
+            "keyword2" => &[HlType::Keyword2],
+            "heading" => &[HlType::Heading],
+            "emphasis" => &[HlType::Emphasis],
+            "link" => &[HlType::Link],
+            "overflow" => &[HlType::Overflow],
+            _ => return Err(format!("Invalid key: {}", key)),
+        };
+        self.colors.retain(|(t, _)| !hl_types.contains(t));
+        self.colors.extend(hl_types.iter().map(|t| (*t, rgb)));
+        Ok(())
+    }
+
+    /// Return the escape sequence setting the color of the given highlight type. Highlight types
+    /// with a colored background (e.g. `Match`) set the background color.
+    pub fn escape(&self, hl_type: HlType) -> String {
+        match self.colors.iter().find(|(t, _)| *t == hl_type) {
+            Some((_, (r, g, b))) => {
+                let layer = if hl_type.is_background() { 48 } else { 38 };
+                format!("\x1b[{};2;{};{};{}m", layer, r, g, b)
+            }
+            None => hl_type.to_string(),
+        }
+    }
+
+    /// Return the escape sequence setting the color of the line numbers.
+    pub fn line_number_escape(&self) -> String {
+        match self.line_number {
+            Some((r, g, b)) => format!("\x1b[38;2;{};{};{}m", r, g, b),
+            None => String::from(DEFAULT_LINE_NUMBER_COLOR),
+        }
+    }
+
+    /// Return the escape sequence setting the background color of the current line.
+    pub fn cursorline_escape(&self) -> String {
+        match self.cursorline {
+            Some((r, g, b)) => format!("\x1b[48;2;{};{};{}m", r, g, b),
+            None => String::from(DEFAULT_CURSORLINE_COLOR),
+        }
+    }
+
+    /// Return the escape sequence setting the color of warnings in the message bar.
+    pub fn warning_escape(&self) -> String {
+        match self.warning {
+            Some((r, g, b)) => format!("\x1b[38;2;{};{};{}m", r, g, b),
+            None => String::from(DEFAULT_WARNING_COLOR),
+        }
+    }
+
+    /// Return the escape sequence setting the color of errors in the message bar.
+    pub fn error_escape(&self) -> String {
+        match self.error {
+            Some((r, g, b)) => format!("\x1b[38;2;{};{};{}m", r, g, b),
+            None => String::from(DEFAULT_ERROR_COLOR),
+        }
+    }
+}
+
+/// Parse a color written as `#rrggbb`.
+fn parse_rgb(value: &str) -> Result<Rgb, String> {
+    let value = value.trim();
+    let hex = value.strip_prefix('#').filter(|h| h.len() == 6 && h.is_ascii());
+    let component = |i: usize| hex.and_then(|h| u8::from_str_radix(&h[i..i + 2], 16).ok());
+    match (component(0), component(2), component(4)) {
+        (Some(r), Some(g), Some(b)) => Ok((r, g, b)),
+        _ => Err(format!("Invalid color: {} (expected #rrggbb)", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_colors() {
+        let mut theme = Theme::default();
+        assert_eq!(theme.escape(HlType::Comment), "\x1b[34m");
+        assert_eq!(theme.line_number_escape(), DEFAULT_LINE_NUMBER_COLOR);
+
+        theme.set("comment", " #5c6370").unwrap();
+        theme.set("match", "#FF0080").unwrap();
+        theme.set("line_number", "#010203").unwrap();
+        assert_eq!(theme.escape(HlType::MlComment), "\x1b[38;2;92;99;112m");
+        assert_eq!(theme.escape(HlType::Match), "\x1b[48;2;255;0;128m");
+        assert_eq!(theme.escape(HlType::String), "\x1b[32m");
+        assert_eq!(theme.line_number_escape(), "\x1b[38;2;1;2;3m");
+        assert_eq!(theme.cursorline_escape(), DEFAULT_CURSORLINE_COLOR);
+        theme.set("cursorline", "#2c313c").unwrap();
+        assert_eq!(theme.cursorline_escape(), "\x1b[48;2;44;49;60m");
+        assert_eq!(theme.warning_escape(), DEFAULT_WARNING_COLOR);
+        assert_eq!(theme.error_escape(), DEFAULT_ERROR_COLOR);
+        theme.set("error", "#e06c75").unwrap();
+        assert_eq!(theme.error_escape(), "\x1b[38;2;224;108;117m");
+
+        let err = |s: &str| Err(String::from(s));
+        assert_eq!(theme.set("comment", "#5c637"), err("Invalid color: #5c637 (expected #rrggbb)"));
+        assert_eq!(theme.set("comment", "5c6370"), err("Invalid color: 5c6370 (expected #rrggbb)"));
+        assert_eq!(theme.set("unknown", "#000000"), err("Invalid key: unknown"));
+    }
+}
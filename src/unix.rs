@@ -2,12 +2,14 @@
 //!
 //! UNIX-specific structs and functions. Will be imported as `sys` on UNIX systems.
 
-use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering::Relaxed};
 
 // On UNIX systems, termios represents the terminal mode.
 pub use libc::termios as TermMode;
 use libc::{c_int, c_void, sigaction, sighandler_t, siginfo_t, winsize};
-use libc::{SA_SIGINFO, STDIN_FILENO, STDOUT_FILENO, TCSADRAIN, TIOCGWINSZ, VMIN, VTIME};
+use libc::{SA_SIGINFO, SIGINT, SIGTERM, STDIN_FILENO, STDOUT_FILENO, TCSADRAIN, TIOCGWINSZ};
+use libc::{VMIN, VTIME};
 
 pub use crate::xdg::*;
 use crate::Error;
@@ -41,24 +43,58 @@ extern "C" fn handle_wsize(_: c_int, _: *mut siginfo_t, _: *mut c_void) { WSC.st
 /// Register a signal handler that sets a global variable when the window size changes.
 /// After calling this function, use `has_window_size_changed` to query the global variable.
 pub fn register_winsize_change_signal_handler() -> Result<(), Error> {
+    register_signal_handler(libc::SIGWINCH, handle_wsize)
+}
+
+/// Check if the windows size has changed since the last call to this function.
+/// The `register_winsize_change_signal_handler` needs to be called before this function.
+pub fn has_window_size_changed() -> bool { WSC.swap(false, Relaxed) }
+
+/// Stores the last termination signal received (`SIGTERM` or `SIGINT`), or 0 if there is none.
+static TERMINATION_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Handle a termination signal.
+extern "C" fn handle_termination(sig: c_int, _: *mut siginfo_t, _: *mut c_void) {
+    TERMINATION_SIGNAL.store(sig, Relaxed)
+}
+
+/// Register a signal handler that sets a global variable when `SIGTERM` or `SIGINT` is received,
+/// instead of terminating the process right away, so that the terminal can be restored. After
+/// calling this function, use `has_termination_signal` to query the global variable.
+pub fn register_termination_signal_handler() -> Result<(), Error> {
+    register_signal_handler(SIGTERM, handle_termination)?;
+    register_signal_handler(SIGINT, handle_termination)
+}
+
+/// Check if a termination signal has been received.
+/// The `register_termination_signal_handler` needs to be called before this function.
+pub fn has_termination_signal() -> bool { TERMINATION_SIGNAL.load(Relaxed) != 0 }
+
+/// Forget a `SIGINT` signal received so far. This is used after running a command in the
+/// terminal, since pressing Ctrl-C to interrupt the command also sends the signal to the editor.
+pub fn clear_interrupt_signal() {
+    let _ = TERMINATION_SIGNAL.compare_exchange(SIGINT, 0, Relaxed, Relaxed);
+}
+
+/// A signal handler, as registered with `SA_SIGINFO`.
+type SigHandler = extern "C" fn(c_int, *mut siginfo_t, *mut c_void);
+
+/// Register `handler` as the handler of signal `sig`.
+fn register_signal_handler(sig: c_int, handler: SigHandler) -> Result<(), Error> {
     unsafe {
         let mut maybe_sa = std::mem::MaybeUninit::<sigaction>::uninit();
         cerr(libc::sigemptyset(&mut (*maybe_sa.as_mut_ptr()).sa_mask))?;
         // We could use sa_handler here, however, sigaction defined in libc does not have
         // sa_handler field, so we use sa_sigaction instead.
         (*maybe_sa.as_mut_ptr()).sa_flags = SA_SIGINFO;
-        (*maybe_sa.as_mut_ptr()).sa_sigaction = handle_wsize as sighandler_t;
-        cerr(libc::sigaction(libc::SIGWINCH, maybe_sa.as_ptr(), std::ptr::null_mut()))
+        (*maybe_sa.as_mut_ptr()).sa_sigaction = handler as sighandler_t;
+        cerr(libc::sigaction(sig, maybe_sa.as_ptr(), std::ptr::null_mut()))
     }
 }
 
-/// Check if the windows size has changed since the last call to this function.
-/// The `register_winsize_change_signal_handler` needs to be called before this function.
-pub fn has_window_size_changed() -> bool { WSC.swap(false, Relaxed) }
-
 /// Set the terminal mode.
 pub fn set_term_mode(term: &TermMode) -> Result<(), Error> {
-    cerr(unsafe { lubc::tcsetattr(STDIN_FILENO, TCSADRAIN, term) })
+    cerr(unsafe { libc::tcsetattr(STDIN_FILENO, TCSADRAIN, term) })
 }
 
 /// Setup the termios to enable raw mode, and return the original termios.
@@ -81,6 +117,22 @@ pub fn enable_raw_mode() -> Result<TermMode, Error> {
 #[allow(clippy::unnecessary_wraps)] // Result required on other platforms
 pub fn stdin() -> std::io::Result<std::io::Stdin> { Ok(std::io::stdin()) }
 
+/// Reopen the controlling terminal (`/dev/tty`) as the standard input. This is needed when text
+/// has been piped to the standard input, so that key presses can still be read.
+pub fn reopen_stdin_from_tty() -> Result<(), Error> {
+    let tty = std::fs::File::open("/dev/tty")?;
+    cerr(unsafe { libc::dup2(tty.as_raw_fd(), STDIN_FILENO) })
+}
+
 pub fn path(filename: &str) -> std::path::PathBuf { std::path::PathBuf::from(filename) }
 
+/// Return a command running `command` with the shell of the user (`$SHELL`, or `/bin/sh` if it is
+/// not set).
+pub fn shell_command(command: &str) -> std::process::Command {
+    let shell = std::env::var_os("SHELL").filter(|s| !s.is_empty()).unwrap_or("/bin/sh".into());
+    let mut cmd = std::process::Command::new(shell);
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
 
@@ -5,6 +5,7 @@
 pub use crate::xdg::*;
 use crate::Error;
 
+#[derive(Clone, Copy)]
 pub struct TermMode {}
 
 /// Return the current window size as (rows, columns).
@@ -20,6 +21,17 @@ pub fn register_winsize_change_signal_handler() -> Result<(), Error> { Ok(()) }
 /// this always return false.
 pub fn has_window_size_changed() -> bool { false }
 
+/// Register a signal handler that sets a global variable when a termination signal is received.
+/// On WASI platforms, this does nothing.
+#[allow(clippy::unnecessary_wraps)] // Result required on other platforms
+pub fn register_termination_signal_handler() -> Result<(), Error> { Ok(()) }
+
+/// Check if a termination signal has been received. On WASI platforms, this always return false.
+pub fn has_termination_signal() -> bool { false }
+
+/// Forget an interrupt signal received so far. On WASI platforms, this does nothing.
+pub fn clear_interrupt_signal() {}
+
 /// Set the terminal mode. On WASI platforms, this does nothing.
 #[allow(clippy::unnecessary_wraps)] // Result required on other platforms
 pub fn set_term_mode(_term: &TermMode) -> Result<(), Error> { Ok(()) }
@@ -30,6 +42,19 @@ pub fn enable_raw_mode() -> Result<TermMode, Error> { Ok(TermMode {}) }
 
 pub fn stdin() -> std::io::Result<std::fs::File> { std::fs::File::open("/dev/tty") }
 
+/// Reopen the controlling terminal as the standard input. On WASI platforms, this does nothing,
+/// since `stdin` always opens `/dev/tty`.
+#[allow(clippy::unnecessary_wraps)] // Result required on other platforms
+pub fn reopen_stdin_from_tty() -> Result<(), Error> { Ok(()) }
+
+/// Return a command running `command` with `sh`. WASI does not support spawning processes yet, so
+/// running the command fails with an I/O error.
+pub fn shell_command(command: &str) -> std::process::Command {
+    let mut cmd = std::process::Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
 pub fn path(filename: &str) -> std::path::PathBuf {
     // If the filename is absolute then it starts with a forward slash and we
     // can just open the file however if it lacks a forwrad slash then its
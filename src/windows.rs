@@ -4,7 +4,8 @@
 
 #![allow(clippy::wildcard_imports)]
 
-use std::{convert::TryInto, env::var, io};
+use std::sync::{Mutex, PoisonError};
+use std::{convert::TryInto, env::var, io, mem};
 
 use winapi::um::wincon::*;
 use winapi_util::{console as cons, HandleRef};
@@ -15,8 +16,10 @@ use crate::Error;
 // for stdout).
 pub type TermMode = (u32, u32);
 
-/// Return configuration directories for Windows systems
-pub fn conf_dirs() -> Vec<String> { var("APPDATA").map(|d| d + "/Kibi").into_iter().collect() }
+/// Return configuration directories for Windows systems, i.e. `%APPDATA%\rust-text-editor`.
+pub fn conf_dirs() -> Vec<String> {
+    var("APPDATA").map(|d| d + "\\rust-text-editor").into_iter().collect()
+}
 
 /// Return data directories for Windows systems
 pub fn data_dirs() -> Vec<String> { conf_dirs() }
@@ -30,10 +33,31 @@ pub fn get_window_size() -> Result<(usize, usize), Error> {
     }
 }
 
+/// The window size returned by the last call to `has_window_size_changed`, if any.
+static LAST_WINDOW_SIZE: Mutex<Option<(usize, usize)>> = Mutex::new(None);
+
+/// Record the current window size. Windows has no signal for window size changes, so
+/// `has_window_size_changed` compares the window size with the recorded one instead.
+#[allow(clippy::unnecessary_wraps)] // Result required on other platforms
+pub fn register_winsize_change_signal_handler() -> Result<(), Error> {
+    *LAST_WINDOW_SIZE.lock().unwrap_or_else(PoisonError::into_inner) = get_window_size().ok();
+    Ok(())
+}
+
+/// Check if the window size has changed since the last call to this function, by querying the
+/// console screen buffer. If the size cannot be obtained, it is considered unchanged.
+pub fn has_window_size_changed() -> bool {
+    let Ok(wsize) = get_window_size() else { return false };
+    let mut last_wsize = LAST_WINDOW_SIZE.lock().unwrap_or_else(PoisonError::into_inner);
+    mem::replace(&mut *last_wsize, Some(wsize)).is_some_and(|last_wsize| last_wsize != wsize)
+}
+
 #[allow(clippy::unnecessary_wraps)] // Result required on other platforms
-pub fn register_winsize_change_signal_handler() -> Result<(), Error> { Ok(()) }
+pub fn register_termination_signal_handler() -> Result<(), Error> { Ok(()) }
+
+pub fn has_termination_signal() -> bool { false }
 
-pub fn has_window_size_changed() -> bool { false }
+pub fn clear_interrupt_signal() {}
 
 /// Set the terminal mode.
 #[allow(clippy::trivially_copy_pass_by_ref)]
@@ -63,4 +87,19 @@ pub fn enable_raw_mode() -> Result<TermMode, Error> {
 #[allow(clippy::unnecessary_wraps)] // Result required on other platforms
 pub fn stdin() -> std::io::Result<std::io::Stdin> { Ok(std::io::stdin()) }
 
+/// Reopen the console as the standard input. This is not supported on Windows yet.
+pub fn reopen_stdin_from_tty() -> Result<(), Error> {
+    let msg = "Reading from the standard input is not supported on Windows";
+    Err(io::Error::new(io::ErrorKind::Unsupported, msg).into())
+}
+
 pub fn path(filename: &str) -> std::path::PathBuf { std::path::PathBuf::from(filename) }
+
+/// Return a command running `command` with the command interpreter (`%COMSPEC%`, or `cmd.exe` if
+/// it is not set).
+pub fn shell_command(command: &str) -> std::process::Command {
+    let shell = var("COMSPEC").unwrap_or_else(|_| String::from("cmd.exe"));
+    let mut cmd = std::process::Command::new(shell);
+    cmd.arg("/C").arg(command);
+    cmd
+}
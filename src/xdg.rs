@@ -8,20 +8,34 @@ use std::env::var;
 /// <https://specifications.freedesktop.org/basedir-spec/basedir-spec-latest.html>
 pub(crate) fn xdg_dirs(xdg_type: &str, def_home_suffix: &str, def_dirs: &str) -> Vec<String> {
     let (home_key, dirs_key) = (format!("XDG_{}_HOME", xdg_type), format!("XDG_{}_DIRS", xdg_type));
+    let (xdg_home, home, xdg_dirs) = (var(home_key).ok(), var("HOME").ok(), var(dirs_key).ok());
+    XdgEnv { xdg_home, home, xdg_dirs }.dirs(def_home_suffix, def_dirs)
+}
+
+/// The values of the environment variables used to locate the XDG directories of a given type,
+/// e.g. `$XDG_CONFIG_HOME`, `$HOME` and `$XDG_CONFIG_DIRS`.
+pub(crate) struct XdgEnv {
+    pub xdg_home: Option<String>,
+    pub home: Option<String>,
+    pub xdg_dirs: Option<String>,
+}
 
-    let mut dirs = Vec::new();
+impl XdgEnv {
+    /// Return the directories, the user-level one first, then the system-wide ones.
+    pub(crate) fn dirs(self, def_home_suffix: &str, def_dirs: &str) -> Vec<String> {
+        let mut dirs = Vec::new();
 
-    // If environment variable `home_key` (e.g. `$XDG_CONFIG_HOME`) is set, add its value to `dirs`.
-    // Otherwise, if environment variable `$HOME` is set, add `$HOME{def_home_suffix}`
-    // (e.g. `$HOME/.config`) to `dirs`.
-    dirs.extend(var(home_key).or_else(|_| var("HOME").map(|d| d + def_home_suffix)).into_iter());
+        // If `xdg_home` (e.g. `$XDG_CONFIG_HOME`) is set, add its value to `dirs`. Otherwise, if
+        // `$HOME` is set, add `$HOME{def_home_suffix}` (e.g. `$HOME/.config`) to `dirs`.
+        dirs.extend(self.xdg_home.or_else(|| self.home.map(|d| d + def_home_suffix)));
 
-    // If environment variable `dirs_key` (e.g. `XDG_CONFIG_DIRS`) is set, split by `:` and add the
-    // parts to `dirs`.
-    // Otherwise, add the split `def_dirs` (e.g. `/etc/xdg:/etc`) and add the parts to `dirs`.
-    dirs.extend(var(dirs_key).unwrap_or_else(|_| def_dirs.into()).split(':').map(String::from));
+        // If `xdg_dirs` (e.g. `XDG_CONFIG_DIRS`) is set, split by `:` and add the parts to `dirs`.
+        // Otherwise, add the split `def_dirs` (e.g. `/etc/xdg:/etc`) and add the parts to `dirs`.
+        let xdg_dirs = self.xdg_dirs.unwrap_or_else(|| def_dirs.into());
+        dirs.extend(xdg_dirs.split(':').map(String::from));
 
-    dirs.into_iter().map(|p| p + "/kibi").collect()
+        dirs.into_iter().map(|p| p + "/rust-text-editor").collect()
+    }
 }
 
 /// Return configuration directories for UNIX systems
@@ -31,3 +45,29 @@ pub fn conf_dirs() -> Vec<String> { xdg_dirs("CONFIG", "/.config", "/etc/xdg:/et
 pub fn data_dirs() -> Vec<String> {
     xdg_dirs("DATA", "/.local/share", "/usr/local/share/:/usr/share/")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xdg_config_dirs() {
+        let env = |xdg_home: Option<&str>, home: Option<&str>, xdg_dirs: Option<&str>| XdgEnv {
+            xdg_home: xdg_home.map(String::from),
+            home: home.map(String::from),
+            xdg_dirs: xdg_dirs.map(String::from),
+        };
+        let dirs = |env: XdgEnv| env.dirs("/.config", "/etc/xdg:/etc");
+        assert_eq!(dirs(env(Some("/x"), Some("/home/u"), None)), [
+            "/x/rust-text-editor",
+            "/etc/xdg/rust-text-editor",
+            "/etc/rust-text-editor"
+        ]);
+        assert_eq!(dirs(env(None, Some("/home/u"), Some("/opt/conf"))), [
+            "/home/u/.config/rust-text-editor",
+            "/opt/conf/rust-text-editor"
+        ]);
+        let expected = ["/a/rust-text-editor", "/b/rust-text-editor"];
+        assert_eq!(dirs(env(None, None, Some("/a:/b"))), expected);
+    }
+}